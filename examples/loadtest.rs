@@ -0,0 +1,35 @@
+//! Opens connections to a running server and prints what they received.
+//!
+//! ```text
+//! cargo run --release --features loadtest --example loadtest -- ws://127.0.0.1:3012 20000 30
+//! ```
+//!
+//! The arguments are the url of the server, the number of connections and how many seconds to
+//! spread opening them over. The connections stay open for another minute.
+
+use std::time::Duration;
+
+use pushevent::loadtest::LoadTest;
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let url = args
+        .next()
+        .unwrap_or_else(|| "ws://127.0.0.1:3012".to_string());
+    let connections = args
+        .next()
+        .map_or(1000, |x| x.parse().expect("invalid connection count"));
+    let ramp = args.next().map_or(10, |x| x.parse().expect("invalid ramp"));
+
+    let report = LoadTest::new(url)
+        .connections(connections)
+        .resources("/events/{}")
+        .resource_count(10)
+        .ramp(Duration::from_secs(ramp))
+        .duration(Duration::from_secs(60))
+        .run()
+        .await;
+
+    println!("{}", report);
+}
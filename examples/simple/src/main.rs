@@ -1,8 +1,7 @@
-use pushevent::server::Server;
+use pushevent::server::ServerBuilder;
 use pushevent::Event;
 use pushevent::SerializableEvent;
 use serde::Serialize;
-use std::thread;
 use std::time;
 
 /// Basic event struct which serializes with serde to json.
@@ -19,30 +18,37 @@ impl SerializableEvent for SimplePushEvent {
     }
 }
 
-fn main() {
+#[tokio::main]
+async fn main() -> Result<(), pushevent::Error> {
     // Server is started on localhost with port 3012
-    let server = Server::new("127.0.0.1:3012");
+    let server = ServerBuilder::new().addr("127.0.0.1:3012").start().await?;
     let tx = server.get_tx();
 
-    loop {
-        // We create a new boxed instance of our SimplePushEvent struct with whatever message
-        // inside.
-        let msg = Box::new(SimplePushEvent {
-            message: "Hello world".to_string(),
-        });
-
-        // The previous message event is encapsulated in our Event struct to which we supply two
-        // arguments, the path/resource subscribers we would like to target ("/hello_world") and
-        // our message event struct instance which implements SerializableEvent.
-        let event = Event::new("/hello_world".to_string(), msg);
-
-        // The event is then sent over the tx channel provided by our server instance
-        match tx.send(event) {
-            Ok(_) => {}
-            Err(x) => println!("Err {:?}", x),
-        };
-
-        let ten_millis = time::Duration::from_millis(100);
-        thread::sleep(ten_millis);
-    }
+    tokio::spawn(async move {
+        loop {
+            // We create a new boxed instance of our SimplePushEvent struct with whatever message
+            // inside.
+            let msg = Box::new(SimplePushEvent {
+                message: "Hello world".to_string(),
+            });
+
+            // The previous message event is encapsulated in our Event struct to which we supply
+            // two arguments, the path/resource subscribers we would like to target
+            // ("/hello_world") and our message event struct instance which implements
+            // SerializableEvent.
+            let event = Event::new("/hello_world", msg);
+
+            // The event is then sent over the tx channel provided by our server instance, which
+            // fails once the server has shut down.
+            if let Err(x) = tx.send(event) {
+                println!("Err {:?}", x);
+                break;
+            }
+
+            tokio::time::sleep(time::Duration::from_millis(100)).await;
+        }
+    });
+
+    // Serve clients until Ctrl-C, then tell them to reconnect and close their connections.
+    server.run_until_shutdown().await
 }
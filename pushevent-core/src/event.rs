@@ -0,0 +1,398 @@
+use alloc::{borrow::Cow, boxed::Box, string::String};
+use core::{convert::TryFrom, fmt, str::Utf8Error};
+
+use bytes::Bytes;
+use bytestring::ByteString;
+
+/// SerializableEvent denotes structs that are able to serialize to some String.
+/// This is used as mainly a marker trait, underneath serialize you most likely would want to use
+/// serde.
+pub trait SerializableEvent: Sync + Send + 'static {
+    /// Returns a String of the serialized object
+    fn serialize(&self) -> String;
+
+    /// Returns the serialized object as UTF-8 bytes, defaults to the bytes of
+    /// [`serialize`](Self::serialize).
+    ///
+    /// Events whose payload is already serialized into a shared buffer, such as a cached
+    /// thumbnail, can return it here so that it is sent to every client without being copied.
+    /// Events are always sent as text frames, there are no binary events: payloads that aren't
+    /// valid UTF-8 are refused when the event is published, see [`Event::payload_error`].
+    fn serialize_bytes(&self) -> Bytes {
+        Bytes::from(self.serialize())
+    }
+}
+
+impl<T: SerializableEvent + ?Sized> SerializableEvent for Box<T> {
+    fn serialize(&self) -> String {
+        (**self).serialize()
+    }
+
+    fn serialize_bytes(&self) -> Bytes {
+        (**self).serialize_bytes()
+    }
+}
+
+/// Where an event entered the server, see [`Event::origin`].
+///
+/// Bridges forwarding events between a server and a message broker tag the events they receive
+/// with their name, so that the server doesn't send them back through the same bridge.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum Origin {
+    /// Published by the application itself.
+    #[default]
+    Local,
+    /// Received from the bridge with this name.
+    Bridge(String),
+    /// Received from the node of a cluster with this id.
+    Peer(String),
+}
+
+/// Base Event struct which can be sent across a channel provided by `Server::get_tx`.
+/// This struct encapsulates a inner trait object and res which is the resource we want to target.
+///
+/// The serialized payload is reference counted, so cloning a event (for example to publish it to
+/// several servers) doesn't copy it, and neither does sending it to many clients. Two events are
+/// equal if they target the same resource and carry the same payload.
+///
+/// Every event is a text event with the same priority, so there is no `is_binary` or `priority`
+/// to inspect: [`Event::res`], [`Event::payload`] and [`Event::payload_bytes`] are all there is.
+///
+/// # Example
+/// ```
+/// use pushevent_core::{Event, SerializableEvent};
+/// struct Message;
+///
+/// impl SerializableEvent for Message {
+///     fn serialize(&self) -> String {
+///         "x".repeat(100)
+///     }
+/// }
+///
+/// let event = Event::new("/events/message", Message);
+/// let copy = event.clone();
+///
+/// assert_eq!(event, copy);
+/// assert_ne!(event, Event::new("/events/other", Message));
+/// assert_eq!(
+///     format!("{:?}", event),
+///     format!(
+///         r#"Event {{ res: "/events/message", payload: "{}..." (100 bytes) }}"#,
+///         "x".repeat(64)
+///     ),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Event {
+    res: String,
+    /// Empty if the serialized payload wasn't valid UTF-8.
+    inner: ByteString,
+    /// Why the serialized payload was discarded, see [`Event::payload_error`].
+    payload_error: Option<Utf8Error>,
+    /// Assigned by the server when the event is published, sent to clients in the envelope.
+    id: Option<u64>,
+    origin: Origin,
+    partition_key: Option<String>,
+}
+
+impl Event {
+    /// Returns a Event instance.
+    /// # Arguments
+    ///
+    /// * `res` - A string slice that holds the resource we want to target
+    /// * `inner` - A object, possibly boxed, that can serialize to a string.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::{Event, SerializableEvent};
+    /// struct Message;
+    ///
+    /// impl SerializableEvent for Message {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("Hello world")
+    ///     }
+    /// }
+    ///
+    /// let message = Box::new(Message);
+    /// let new_event = Event::new("/events/message", message);
+    ///
+    /// assert_eq!(new_event.get_res(), String::from("/events/message"));
+    /// assert_eq!(new_event.build(), String::from("Hello world"));
+    /// ```
+    pub fn new(res: impl Into<String>, inner: impl SerializableEvent) -> Self {
+        let (inner, payload_error) = utf8(inner.serialize_bytes());
+
+        Self {
+            res: res.into(),
+            inner,
+            payload_error,
+            id: None,
+            origin: Origin::Local,
+            partition_key: None,
+        }
+    }
+
+    /// Returns a Event whose payload is already serialized, taking ownership of it.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::Event;
+    ///
+    /// let event = Event::from_string("/events/message", String::from("Hello world"));
+    /// assert_eq!(event.payload(), "Hello world");
+    /// ```
+    pub fn from_string(res: impl Into<String>, payload: String) -> Self {
+        Self {
+            res: res.into(),
+            inner: ByteString::from(payload),
+            payload_error: None,
+            id: None,
+            origin: Origin::Local,
+            partition_key: None,
+        }
+    }
+
+    /// Returns the resource this event targets.
+    pub fn get_res(&self) -> String {
+        self.res.clone()
+    }
+
+    /// Returns the resource this event targets, without copying it like
+    /// [`get_res`](Self::get_res).
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::{Event, SerializableEvent};
+    /// struct Message;
+    ///
+    /// impl SerializableEvent for Message {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("Hello world")
+    ///     }
+    /// }
+    ///
+    /// let event = Event::new("/events/message", Message);
+    ///
+    /// assert_eq!(event.res(), "/events/message");
+    /// assert_eq!(event.payload(), "Hello world");
+    /// assert_eq!(event.payload().len(), 11);
+    /// ```
+    pub fn res(&self) -> &str {
+        &self.res
+    }
+
+    /// Returns the serialized payload, empty if it wasn't valid UTF-8.
+    pub fn payload(&self) -> &str {
+        &self.inner
+    }
+
+    /// Returns the serialized payload as the buffer it is shared through, which is always valid
+    /// UTF-8.
+    pub fn payload_bytes(&self) -> &Bytes {
+        self.inner.as_bytes()
+    }
+
+    /// Returns why the payload was discarded, if
+    /// [`SerializableEvent::serialize_bytes`] returned bytes that aren't valid UTF-8. Servers
+    /// refuse to publish such events, as text frames must be UTF-8 and replacing the invalid
+    /// sequences would corrupt the payload.
+    ///
+    /// # Example
+    /// ```
+    /// use bytes::Bytes;
+    /// use pushevent_core::{Event, SerializableEvent};
+    /// struct Thumbnail;
+    ///
+    /// impl SerializableEvent for Thumbnail {
+    ///     fn serialize(&self) -> String {
+    ///         unreachable!()
+    ///     }
+    ///
+    ///     fn serialize_bytes(&self) -> Bytes {
+    ///         Bytes::from_static(&[0x89, b'P', b'N', b'G'])
+    ///     }
+    /// }
+    ///
+    /// let event = Event::new("/thumbnails", Thumbnail);
+    /// assert!(event.payload_error().is_some());
+    /// assert_eq!(event.payload(), "");
+    /// ```
+    pub fn payload_error(&self) -> Option<Utf8Error> {
+        self.payload_error
+    }
+
+    /// Returns the id the server assigned to the event when it was published, `None` for events
+    /// that weren't published yet.
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
+    /// Returns the event as published with the id `id`. Servers give every event they publish
+    /// an id of their own this way, an id set beforehand is replaced and never reaches clients.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::Event;
+    ///
+    /// let event = Event::from_string("/prices", "42".into());
+    /// assert_eq!(event.id(), None);
+    ///
+    /// let event = event.with_id(7);
+    /// assert_eq!(event.id(), Some(7));
+    /// // The id doesn't change what the event is.
+    /// assert_eq!(event, Event::from_string("/prices", "42".into()));
+    /// ```
+    pub fn with_id(self, id: u64) -> Self {
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    /// Returns where the event entered the server, [`Origin::Local`] unless it was tagged with
+    /// [`with_origin`](Self::with_origin).
+    pub fn origin(&self) -> &Origin {
+        &self.origin
+    }
+
+    /// Returns the event tagged as coming from `origin`.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::{Event, Origin};
+    ///
+    /// let event = Event::from_string("/prices", "42".into());
+    /// assert_eq!(event.origin(), &Origin::Local);
+    ///
+    /// let event = event.with_origin(Origin::Bridge("redis".into()));
+    /// assert_eq!(event.origin(), &Origin::Bridge("redis".into()));
+    /// // The origin doesn't change what the event is.
+    /// assert_eq!(event, Event::from_string("/prices", "42".into()));
+    /// ```
+    pub fn with_origin(self, origin: Origin) -> Self {
+        Self { origin, ..self }
+    }
+
+    /// Returns the key the event was partitioned by with
+    /// [`with_partition_key`](Self::with_partition_key), if any.
+    pub fn partition_key(&self) -> Option<&str> {
+        self.partition_key.as_deref()
+    }
+
+    /// Returns the event partitioned by `key`: on routes handing every event to one subscriber,
+    /// the events with the same key go to the same one while it stays subscribed.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::Event;
+    ///
+    /// let event = Event::from_string("/jobs/transcode", "42".into());
+    /// assert_eq!(event.partition_key(), None);
+    ///
+    /// let event = event.with_partition_key("library-7");
+    /// assert_eq!(event.partition_key(), Some("library-7"));
+    /// // The key doesn't change what the event is.
+    /// assert_eq!(event, Event::from_string("/jobs/transcode", "42".into()));
+    /// ```
+    pub fn with_partition_key(self, key: impl Into<String>) -> Self {
+        Self {
+            partition_key: Some(key.into()),
+            ..self
+        }
+    }
+
+    /// Returns the event with its payload replaced by `payload`, keeping its resource, id and
+    /// origin.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::{Event, SerializableEvent};
+    /// struct Message;
+    ///
+    /// impl SerializableEvent for Message {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("Hello world")
+    ///     }
+    /// }
+    ///
+    /// let event = Event::new("/events/message", Message).with_payload("Bye world".to_string());
+    /// assert_eq!(event.res(), "/events/message");
+    /// assert_eq!(event.payload(), "Bye world");
+    /// ```
+    pub fn with_payload(self, payload: String) -> Self {
+        Self {
+            inner: ByteString::from(payload),
+            payload_error: None,
+            ..self
+        }
+    }
+
+    /// Returns the serialized event/message. The payload is serialized once when the event is
+    /// created, so this doesn't allocate.
+    /// # Example
+    /// ```
+    /// use pushevent_core::{Event, SerializableEvent};
+    /// struct Message;
+    ///
+    /// impl SerializableEvent for Message {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("Hello world")
+    ///     }
+    /// }
+    ///
+    /// let message = Box::new(Message);
+    /// let new_event = Event::new("/events/message", message);
+    /// assert_eq!(new_event.build(), "Hello world");
+    /// ```
+    pub fn build(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.payload())
+    }
+}
+
+/// Events are equal if they target the same resource with the same payload, wherever they come
+/// from.
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.res == other.res && self.inner == other.inner
+    }
+}
+
+impl Eq for Event {}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Payloads can be arbitrarily large, only this many bytes of it are printed.
+        const MAX_PAYLOAD: usize = 64;
+
+        let payload = self.payload();
+        if payload.len() <= MAX_PAYLOAD {
+            return f
+                .debug_struct("Event")
+                .field("res", &self.res)
+                .field("payload", &payload)
+                .finish();
+        }
+
+        let mut end = MAX_PAYLOAD;
+        while !payload.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        write!(
+            f,
+            "Event {{ res: {:?}, payload: \"{}...\" ({} bytes) }}",
+            self.res,
+            payload[..end].escape_debug(),
+            payload.len()
+        )
+    }
+}
+
+/// Returns `bytes` if they are valid UTF-8, else an empty payload and why they aren't.
+fn utf8(bytes: Bytes) -> (ByteString, Option<Utf8Error>) {
+    match ByteString::try_from(bytes) {
+        Ok(x) => (x, None),
+        Err(e) => (ByteString::new(), Some(e)),
+    }
+}
@@ -0,0 +1,124 @@
+//! The JSON frames of version 2 of the pushevent protocol, so that anything relaying events
+//! produces exactly what the server sends.
+//!
+//! Every event is wrapped in an envelope carrying its resource,
+//! `{"type":"event","id":7,"resource":"/alerts","payload":"..."}`, with the payload as a string.
+//! The id is only present once the server published the event.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::Event;
+
+/// Returns the envelope of `event`. Events sent again to a client that didn't acknowledge them
+/// are marked with `dup`.
+///
+/// # Example
+/// ```
+/// use pushevent_core::{format, Event, SerializableEvent};
+/// struct Message;
+///
+/// impl SerializableEvent for Message {
+///     fn serialize(&self) -> String {
+///         String::from(r#"{"temp":21.5}"#)
+///     }
+/// }
+///
+/// let event = Event::new("/sensors", Message);
+/// assert_eq!(
+///     format::envelope(&event, false),
+///     r#"{"type":"event","resource":"/sensors","payload":"{\"temp\":21.5}"}"#,
+/// );
+/// ```
+pub fn envelope(event: &Event, dup: bool) -> String {
+    let mut out = String::with_capacity(event.payload().len() + event.res().len() + 64);
+    push_envelope(&mut out, event, dup);
+    out
+}
+
+/// Returns the envelopes of `events` as a JSON array, which is how batched events are sent.
+pub fn batch<'a>(events: impl IntoIterator<Item = &'a Event>) -> String {
+    let mut out = String::from("[");
+
+    for (i, event) in events.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_envelope(&mut out, event, false);
+    }
+
+    out.push(']');
+    out
+}
+
+/// Returns the notice telling a subscriber of a round-robin route which partition keys of
+/// `res` it was assigned or lost, see [`Event::with_partition_key`].
+///
+/// # Example
+/// ```
+/// use pushevent_core::format;
+///
+/// assert_eq!(
+///     format::partitions("/jobs", &["a".into()], &[]),
+///     r#"{"type":"partitions","resource":"/jobs","assigned":["a"],"revoked":[]}"#,
+/// );
+/// ```
+pub fn partitions(res: &str, assigned: &[String], revoked: &[String]) -> String {
+    let mut out = String::from(r#"{"type":"partitions","resource":"#);
+    push_json_str(&mut out, res);
+    out.push_str(r#","assigned":"#);
+    push_json_strs(&mut out, assigned);
+    out.push_str(r#","revoked":"#);
+    push_json_strs(&mut out, revoked);
+    out.push('}');
+    out
+}
+
+fn push_envelope(out: &mut String, event: &Event, dup: bool) {
+    out.push_str(r#"{"type":"event","#);
+    if let Some(id) = event.id() {
+        // Writing to a string never fails.
+        let _ = write!(out, r#""id":{},"#, id);
+    }
+    if dup {
+        out.push_str(r#""dup":true,"#);
+    }
+    out.push_str(r#""resource":"#);
+    push_json_str(out, event.res());
+    out.push_str(r#","payload":"#);
+    push_json_str(out, event.payload());
+    out.push('}');
+}
+
+/// Appends `strs` to `out` as a JSON array of strings.
+fn push_json_strs(out: &mut String, strs: &[String]) {
+    out.push('[');
+    for (i, s) in strs.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_json_str(out, s);
+    }
+    out.push(']');
+}
+
+/// Appends `s` to `out` as a quoted and escaped JSON string.
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
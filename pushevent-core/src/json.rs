@@ -0,0 +1,73 @@
+use alloc::string::{String, ToString};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{Event, SerializableEvent};
+
+impl Event {
+    /// Returns a Event whose payload is `inner` serialized to JSON.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent_core::Event;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct ScanDone {
+    ///     library: u64,
+    /// }
+    ///
+    /// let event = Event::from_json("/events/library", &ScanDone { library: 5 }).unwrap();
+    /// assert_eq!(event.build(), r#"{"library":5}"#);
+    /// ```
+    pub fn from_json(res: impl Into<String>, inner: &impl Serialize) -> serde_json::Result<Self> {
+        Ok(Self::from_string(res, serde_json::to_string(inner)?))
+    }
+
+    /// Returns a Event whose payload is the JSON value `inner`. This is what the `event!` macro
+    /// of `pushevent` expands to.
+    pub fn from_value(res: impl Into<String>, inner: Value) -> Self {
+        Self::from_string(res, inner.to_string())
+    }
+}
+
+/// An event payload built at runtime, e.g. from database rows or user input, for when there is
+/// no type to implement [`SerializableEvent`] for.
+///
+/// # Example
+/// ```
+/// use pushevent_core::{DynamicEvent, Event};
+///
+/// let payload = DynamicEvent::try_from_str(r#"{ "kind": "scan_done", "library": 5 }"#).unwrap();
+/// let event = Event::new("/events/library", payload);
+/// assert_eq!(event.build(), r#"{"kind":"scan_done","library":5}"#);
+///
+/// assert!(DynamicEvent::try_from_str("{ kind: scan_done }").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicEvent(pub Value);
+
+impl DynamicEvent {
+    /// Returns the payload serializing to `v`.
+    pub fn from_value(v: Value) -> Self {
+        Self(v)
+    }
+
+    /// Parses `s` as JSON.
+    pub fn try_from_str(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s).map(Self)
+    }
+}
+
+impl From<Value> for DynamicEvent {
+    fn from(v: Value) -> Self {
+        Self(v)
+    }
+}
+
+impl SerializableEvent for DynamicEvent {
+    fn serialize(&self) -> String {
+        self.0.to_string()
+    }
+}
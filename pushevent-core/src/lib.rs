@@ -0,0 +1,16 @@
+//! The event types of [pushevent](https://docs.rs/pushevent), which only need `alloc`, so that
+//! firmware publishing to a pushevent server can share its event definitions with it.
+//!
+//! `pushevent` re-exports everything here, servers don't need to depend on this crate.
+#![no_std]
+
+extern crate alloc;
+
+mod event;
+pub mod format;
+#[cfg(feature = "serde")]
+mod json;
+
+pub use event::{Event, Origin, SerializableEvent};
+#[cfg(feature = "serde")]
+pub use json::DynamicEvent;
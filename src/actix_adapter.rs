@@ -0,0 +1,104 @@
+//! Delivers events to [actix](https://docs.rs/actix) actors as messages, so that actors can
+//! react to events without managing a websocket connection.
+//!
+//! Enabled by the `actix` feature.
+
+use actix::dev::ToEnvelope;
+use actix::{Actor, Addr, Handler, Message};
+use futures_util::StreamExt;
+use tokio::task::JoinHandle;
+
+use crate::server::Server;
+
+/// The payload of an event published to a resource an actor is subscribed to.
+#[derive(Debug, Clone, PartialEq, Eq, Message)]
+#[rtype(result = "()")]
+pub struct PushEventMessage(pub String);
+
+/// Subscribes actors to the resources of a server.
+///
+/// # Example
+/// ```
+/// use actix::{Actor, Context, Handler};
+/// use pushevent::actix_adapter::{EventActor, PushEventMessage};
+/// use pushevent::server::ServerBuilder;
+///
+/// struct Prices;
+///
+/// impl Actor for Prices {
+///     type Context = Context<Self>;
+/// }
+///
+/// impl Handler<PushEventMessage> for Prices {
+///     type Result = ();
+///
+///     fn handle(&mut self, msg: PushEventMessage, _: &mut Context<Self>) {
+///         println!("price changed: {}", msg.0);
+///     }
+/// }
+///
+/// #[actix::main]
+/// async fn main() {
+///     let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+///
+///     EventActor::new(&server).subscribe("/prices/*", Prices.start());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct EventActor {
+    server: Server,
+}
+
+impl EventActor {
+    /// Returns an adapter subscribing actors to the resources of `server`.
+    pub fn new(server: &Server) -> Self {
+        Self {
+            server: server.clone(),
+        }
+    }
+
+    /// Sends a [`PushEventMessage`] to `addr` for every event published to `res`, which may be
+    /// a pattern, see [`Server::subscribe_local`].
+    ///
+    /// The subscription ends when the server shuts down, when it is
+    /// [cancelled](ActorSubscription::cancel) or with the first event after the actor stopped.
+    pub fn subscribe<A>(&self, res: &str, addr: Addr<A>) -> ActorSubscription
+    where
+        A: Actor + Handler<PushEventMessage>,
+        A::Context: ToEnvelope<A, PushEventMessage>,
+    {
+        let mut events = self.server.subscribe_local(res);
+
+        let task = tokio::spawn(async move {
+            while let Some(payload) = events.next().await {
+                if !addr.connected() {
+                    break;
+                }
+
+                addr.do_send(PushEventMessage(payload));
+            }
+        });
+
+        ActorSubscription { task }
+    }
+}
+
+/// Forwards events to an actor, returned by [`EventActor::subscribe`].
+///
+/// Dropping it doesn't end the subscription.
+#[derive(Debug)]
+pub struct ActorSubscription {
+    task: JoinHandle<()>,
+}
+
+impl ActorSubscription {
+    /// Stops forwarding events to the actor.
+    pub fn cancel(self) {
+        self.task.abort();
+    }
+
+    /// Returns whether the subscription ended.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
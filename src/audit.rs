@@ -0,0 +1,210 @@
+//! A record of who connected to which resource and when, see
+//! [`ServerBuilder::audit`](crate::server::ServerBuilder::audit).
+
+use std::{net::SocketAddr, time::SystemTime};
+
+use crate::client::{ClientId, ClientMeta};
+
+/// The key of [`ClientMeta`] the identity of a client is read from. Authenticators and
+/// middleware insert it once they know who is connecting.
+pub const IDENTITY_KEY: &str = "identity";
+
+/// Receives an [`AuditEntry`] for every handshake, subscription change and disconnect.
+///
+/// The sink is called on the connection tasks and by the [`Server`](crate::server::Server)
+/// methods changing subscriptions, so it must not block. Sinks writing to files or the network
+/// should hand the entries to a thread of their own, like the `JsonLinesSink` of the
+/// `audit-jsonl` feature does.
+///
+/// # Example
+/// ```
+/// use pushevent::audit::{AuditEntry, AuditSink};
+///
+/// struct Log;
+///
+/// impl AuditSink for Log {
+///     fn record(&self, entry: AuditEntry) {
+///         println!("{:?} {} {:?}", entry.kind, entry.resource, entry.identity);
+///     }
+/// }
+/// ```
+pub trait AuditSink: Send + Sync + 'static {
+    /// Records `entry`.
+    fn record(&self, entry: AuditEntry);
+}
+
+/// What happened to a connection, see [`AuditEntry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AuditKind {
+    /// The client completed the handshake and subscribed to the resource it connected to.
+    HandshakeAccepted,
+    /// The upgrade request was refused with the HTTP `status` and `reason`.
+    HandshakeRejected {
+        /// The HTTP status of the response.
+        status: u16,
+        /// The reason sent in the response body.
+        reason: String,
+    },
+    /// The client was subscribed to another resource, see
+    /// [`Server::subscribe`](crate::server::Server::subscribe).
+    Subscribe,
+    /// The client was unsubscribed from a resource, see
+    /// [`Server::unsubscribe`](crate::server::Server::unsubscribe).
+    Unsubscribe,
+    /// The connection ended.
+    Disconnect {
+        /// Why the connection ended, e.g. `closed by the client` or `slow consumer`.
+        reason: String,
+    },
+}
+
+impl AuditKind {
+    /// Returns the name of the kind in snake case, e.g. `handshake_accepted`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HandshakeAccepted => "handshake_accepted",
+            Self::HandshakeRejected { .. } => "handshake_rejected",
+            Self::Subscribe => "subscribe",
+            Self::Unsubscribe => "unsubscribe",
+            Self::Disconnect { .. } => "disconnect",
+        }
+    }
+}
+
+/// A single entry of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AuditEntry {
+    /// When it happened.
+    pub timestamp: SystemTime,
+    /// What happened.
+    pub kind: AuditKind,
+    /// The address of the remote peer.
+    pub addr: SocketAddr,
+    /// The resource the client connected to, or was subscribed to or unsubscribed from.
+    pub resource: String,
+    /// The id of the connection, `None` for rejected handshakes.
+    pub client: Option<ClientId>,
+    /// The identity of the client, see [`IDENTITY_KEY`].
+    pub identity: Option<String>,
+}
+
+impl AuditEntry {
+    pub(crate) fn new(
+        kind: AuditKind,
+        addr: SocketAddr,
+        resource: &str,
+        client: Option<ClientId>,
+        meta: &ClientMeta,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            kind,
+            addr,
+            resource: resource.to_string(),
+            client,
+            identity: meta.get(IDENTITY_KEY),
+        }
+    }
+}
+
+#[cfg(feature = "audit-jsonl")]
+pub use jsonl::JsonLinesSink;
+
+#[cfg(feature = "audit-jsonl")]
+mod jsonl {
+    use std::{
+        fs::OpenOptions,
+        io::{self, BufWriter, Write},
+        path::Path,
+        sync::mpsc,
+        thread,
+        time::UNIX_EPOCH,
+    };
+
+    use serde_json::{json, Value};
+
+    use super::{AuditEntry, AuditKind, AuditSink};
+
+    /// Writes every entry as a line of JSON, e.g.
+    ///
+    /// ```text
+    /// {"addr":"10.0.0.7:52114","client_id":"0190...","event":"handshake_accepted","identity":"alice","resource":"/orders","timestamp_ms":1700000000000}
+    /// {"addr":"10.0.0.7:52114","client_id":"0190...","event":"disconnect","identity":"alice","reason":"closed by the client","resource":"/orders","timestamp_ms":1700000004000}
+    /// ```
+    ///
+    /// Rejected handshakes carry the `status` and `reason` of the response. The entries are
+    /// written on a thread of their own through a buffer that is flushed whenever no more
+    /// entries are waiting, so recording never waits for the writer. Entries recorded before the
+    /// sink is dropped are still written.
+    pub struct JsonLinesSink {
+        tx: mpsc::Sender<AuditEntry>,
+    }
+
+    impl JsonLinesSink {
+        /// Appends the entries to the file at `path`, creating it if needed.
+        pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Self::from_writer(file)
+        }
+
+        /// Writes the entries to `writer`.
+        pub fn from_writer(writer: impl Write + Send + 'static) -> io::Result<Self> {
+            let (tx, rx) = mpsc::channel();
+            thread::Builder::new()
+                .name("pushevent-audit".to_string())
+                .spawn(move || write_entries(rx, BufWriter::new(writer)))?;
+
+            Ok(Self { tx })
+        }
+    }
+
+    impl AuditSink for JsonLinesSink {
+        fn record(&self, entry: AuditEntry) {
+            // Only fails once the writer gave up, which it logged.
+            let _ = self.tx.send(entry);
+        }
+    }
+
+    fn write_entries(rx: mpsc::Receiver<AuditEntry>, mut writer: impl Write) {
+        while let Ok(entry) = rx.recv() {
+            let written = std::iter::once(entry)
+                .chain(rx.try_iter())
+                .try_for_each(|x| writeln!(writer, "{}", to_json(&x)))
+                .and_then(|_| writer.flush());
+
+            if let Err(e) = written {
+                tracing::error!("failed to write the audit log, giving up: {}", e);
+                return;
+            }
+        }
+    }
+
+    fn to_json(entry: &AuditEntry) -> Value {
+        let timestamp = entry
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_millis() as u64);
+
+        let mut line = json!({
+            "timestamp_ms": timestamp,
+            "event": entry.kind.name(),
+            "client_id": entry.client.map(|x| x.to_string()),
+            "addr": entry.addr.to_string(),
+            "resource": entry.resource,
+            "identity": entry.identity,
+        });
+
+        match &entry.kind {
+            AuditKind::HandshakeRejected { status, reason } => {
+                line["status"] = json!(status);
+                line["reason"] = json!(reason);
+            }
+            AuditKind::Disconnect { reason } => line["reason"] = json!(reason),
+            _ => {}
+        }
+
+        line
+    }
+}
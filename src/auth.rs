@@ -0,0 +1,120 @@
+use std::fmt;
+
+use tungstenite::handshake::server::ErrorResponse;
+use tungstenite::http::{Response, StatusCode};
+
+pub use crate::Request;
+
+/// Decides whether a websocket upgrade request is allowed to connect.
+///
+/// The authenticator runs inside the handshake, before the client is subscribed to anything, so
+/// it must not block. Implementations that need remote data (key sets, session stores) should
+/// fetch it in the background and only consult a local cache here.
+///
+/// # Example
+/// ```
+/// use pushevent::auth::{Authenticator, Rejection, Request};
+///
+/// struct StaticToken(&'static str);
+///
+/// impl Authenticator for StaticToken {
+///     fn authenticate(&self, req: &Request) -> Result<(), Rejection> {
+///         match req.header("x-token") {
+///             Some(x) if x == self.0 => Ok(()),
+///             _ => Err(Rejection::unauthorized("missing or invalid token")),
+///         }
+///     }
+/// }
+///
+/// let req = Request::new("/events").with_header("x-token", "hunter2");
+/// assert!(StaticToken("hunter2").authenticate(&req).is_ok());
+/// ```
+pub trait Authenticator: Send + Sync + 'static {
+    /// Returns `Ok(())` if the client may connect, otherwise the rejection sent back as the HTTP
+    /// response to the upgrade request.
+    fn authenticate(&self, req: &Request) -> Result<(), Rejection>;
+}
+
+/// A refused upgrade request, carrying the HTTP status and reason returned to the client.
+///
+/// # Example
+/// ```
+/// use pushevent::auth::Rejection;
+///
+/// let rejection = Rejection::forbidden("not an admin");
+///
+/// assert_eq!(rejection.status(), 403);
+/// assert_eq!(rejection.clone(), rejection);
+/// assert_ne!(rejection, Rejection::unauthorized("not an admin"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    status: StatusCode,
+    reason: String,
+}
+
+impl Rejection {
+    /// Rejects the request with `400 Bad Request`.
+    pub fn bad_request(reason: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            reason: reason.into(),
+        }
+    }
+
+    /// Rejects the request with `401 Unauthorized`.
+    pub fn unauthorized(reason: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            reason: reason.into(),
+        }
+    }
+
+    /// Rejects the request with `403 Forbidden`.
+    pub fn forbidden(reason: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            reason: reason.into(),
+        }
+    }
+
+    /// Rejects the request with `429 Too Many Requests`.
+    pub fn too_many_requests(reason: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            reason: reason.into(),
+        }
+    }
+
+    /// Rejects the request with `414 URI Too Long`.
+    pub fn uri_too_long(reason: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::URI_TOO_LONG,
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns the HTTP status code of the rejection.
+    pub fn status(&self) -> u16 {
+        self.status.as_u16()
+    }
+
+    /// Returns the reason sent as the response body.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub(crate) fn into_response(self) -> ErrorResponse {
+        let mut res = Response::new(Some(self.reason));
+        *res.status_mut() = self.status;
+        res
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.reason)
+    }
+}
+
+impl std::error::Error for Rejection {}
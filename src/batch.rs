@@ -0,0 +1,160 @@
+//! Coalescing the events published to a route in a burst into one frame, see
+//! [`Server::set_route_batching`](crate::server::Server::set_route_batching).
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use tokio::{sync::Notify, time::Instant};
+
+use crate::Event;
+
+/// How the events published to a route are batched, see
+/// [`Server::set_route_batching`](crate::server::Server::set_route_batching). A batch is sent
+/// once `window` has passed since its first event or once it holds `max_events` events,
+/// whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    /// How long the first event of a batch waits for more.
+    pub window: Duration,
+    /// The number of events after which a batch is sent right away.
+    pub max_events: usize,
+}
+
+struct Batch {
+    events: Vec<Event>,
+    deadline: Instant,
+}
+
+/// The batches waiting for their window to end, one per resource, shared by the clients.
+#[derive(Default)]
+pub(crate) struct Batches {
+    pending: Mutex<HashMap<String, Batch>>,
+    /// Notified whenever a batch starts, so that the task sending them learns its deadline.
+    started: Notify,
+}
+
+impl Batches {
+    /// Adds `event` to the batch of its resource, returning the batch if it is full.
+    pub(crate) fn push(&self, event: Event, batching: Batching) -> Option<Vec<Event>> {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let batch = match pending.get_mut(event.res()) {
+            Some(x) => x,
+            None => {
+                self.started.notify_one();
+                pending.entry(event.get_res()).or_insert(Batch {
+                    events: Vec::new(),
+                    deadline: Instant::now() + batching.window,
+                })
+            }
+        };
+        batch.events.push(event);
+
+        if batch.events.len() < batching.max_events {
+            return None;
+        }
+
+        let res = batch.events[0].get_res();
+        pending.remove(&res).map(|x| x.events)
+    }
+
+    /// Returns when the next batch is due.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .map(|x| x.deadline)
+            .min()
+    }
+
+    /// Resolves once a batch started since the last call.
+    pub(crate) async fn started(&self) {
+        self.started.notified().await
+    }
+
+    /// Removes the batches due at `now`.
+    pub(crate) fn expired(&self, now: Instant) -> Vec<(String, Vec<Event>)> {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let due: Vec<_> = pending
+            .iter()
+            .filter(|(_, x)| x.deadline <= now)
+            .map(|(res, _)| res.clone())
+            .collect();
+
+        due.into_iter()
+            .filter_map(|res| {
+                let batch = pending.remove(&res)?;
+                Some((res, batch.events))
+            })
+            .collect()
+    }
+
+    /// Removes every batch, due or not.
+    pub(crate) fn take_all(&self) -> Vec<(String, Vec<Event>)> {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain()
+            .map(|(res, batch)| (res, batch.events))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SerializableEvent;
+
+    const BATCHING: Batching = Batching {
+        window: Duration::from_millis(20),
+        max_events: 3,
+    };
+
+    struct Text;
+
+    impl SerializableEvent for Text {
+        fn serialize(&self) -> String {
+            String::from("x")
+        }
+    }
+
+    fn event(res: &str) -> Event {
+        Event::new(res, Text)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batches_are_due_after_the_window_or_once_full() {
+        let batches = Batches::default();
+        let start = Instant::now();
+
+        assert!(batches.push(event("/a"), BATCHING).is_none());
+        assert!(batches.push(event("/b"), BATCHING).is_none());
+        assert!(batches.push(event("/a"), BATCHING).is_none());
+        assert_eq!(
+            batches.push(event("/a"), BATCHING).map(|x| x.len()),
+            Some(3)
+        );
+        assert_eq!(batches.next_deadline(), Some(start + BATCHING.window));
+
+        tokio::time::advance(Duration::from_millis(10)).await;
+        assert!(batches.push(event("/a"), BATCHING).is_none());
+        assert!(batches.expired(Instant::now()).is_empty());
+
+        tokio::time::advance(Duration::from_millis(10)).await;
+        let due = batches.expired(Instant::now());
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, "/b");
+
+        assert_eq!(
+            batches.next_deadline(),
+            Some(start + Duration::from_millis(30))
+        );
+        assert_eq!(batches.take_all().len(), 1);
+        assert_eq!(batches.next_deadline(), None);
+    }
+}
@@ -0,0 +1,181 @@
+//! A load generator publishing synthetic events to a running server and measuring how long they
+//! take to reach a client, for profiling without an external tool.
+//!
+//! Enabled by the `bench-harness` feature, see
+//! [`ServerBuilder::enable_bench_harness`](crate::server::ServerBuilder::enable_bench_harness).
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use tungstenite::Message;
+
+use crate::{Error, Event, EventTx, SerializableEvent};
+
+/// Payloads start with the time they were published at, in nanoseconds since the run started,
+/// padded to this many digits.
+const TIMESTAMP_LEN: usize = 20;
+
+/// How long to wait for the events still in flight once all were published.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What [`run`] publishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchConfig {
+    /// Events published per second.
+    pub rps: u32,
+    /// How long to publish events for.
+    pub duration: Duration,
+    /// The resource events are published to.
+    pub resource: String,
+    /// The size of every payload in bytes, at least 20.
+    pub payload_size: usize,
+}
+
+/// The results of a [`run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BenchReport {
+    /// The number of events published.
+    pub sent: u64,
+    /// The number of events the measuring client received.
+    pub delivered: u64,
+    /// The number of events that failed to queue or never arrived.
+    pub dropped: u64,
+    /// The average time from publishing an event until it was received.
+    pub avg_latency: Duration,
+    /// The 99th percentile of the time from publishing an event until it was received.
+    pub p99_latency: Duration,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} events, delivered {}, dropped {}, average latency {:?}, p99 latency {:?}",
+            self.sent, self.delivered, self.dropped, self.avg_latency, self.p99_latency
+        )
+    }
+}
+
+struct Payload(String);
+
+impl SerializableEvent for Payload {
+    fn serialize(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// Publishes events through `tx` as described by `config` and measures the latency with a client
+/// connected to the server listening on `addr`.
+///
+/// The client only receives what is published to `config.resource`, so other traffic on the
+/// server doesn't skew the results but does add to the load.
+pub async fn run(tx: EventTx, addr: SocketAddr, config: BenchConfig) -> Result<BenchReport, Error> {
+    let addr = match addr.ip() {
+        IpAddr::V4(x) if x.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port())
+        }
+        IpAddr::V6(x) if x.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), addr.port())
+        }
+        _ => addr,
+    };
+    let url = format!("ws://{}{}", addr, config.resource);
+    let (mut client, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(Error::handshake)?;
+
+    // The client is subscribed once it receives the first of these.
+    let subscribed = async {
+        loop {
+            tx.send(Event::new(&config.resource, Payload(String::new())))?;
+
+            let next = tokio::time::timeout(Duration::from_millis(10), client.next());
+            if let Ok(Some(Ok(Message::Text(_)))) = next.await {
+                return Ok(());
+            }
+        }
+    };
+    tokio::time::timeout(DRAIN_TIMEOUT, subscribed)
+        .await
+        .map_err(|_| Error::Handshake("the measuring client was never subscribed".into()))??;
+
+    let start = Instant::now();
+    let latencies = Arc::new(Mutex::new(Vec::new()));
+
+    let received = latencies.clone();
+    let mut receiver = tokio::spawn(async move {
+        while let Some(Ok(frame)) = client.next().await {
+            let payload = match frame {
+                Message::Text(x) => x,
+                _ => continue,
+            };
+
+            match payload
+                .get(..TIMESTAMP_LEN)
+                .and_then(|x| x.parse::<u64>().ok())
+            {
+                Some(sent) => {
+                    let latency = start.elapsed().saturating_sub(Duration::from_nanos(sent));
+                    received
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(latency);
+                }
+                // Published after every measured event.
+                None if payload == "done" => break,
+                // Left over from waiting for the subscription.
+                None => {}
+            }
+        }
+    });
+
+    let period = Duration::from_secs(1) / config.rps.max(1);
+    let mut interval = tokio::time::interval(period);
+    let mut sent = 0;
+
+    while start.elapsed() < config.duration {
+        interval.tick().await;
+
+        let mut payload = format!(
+            "{:0width$}",
+            start.elapsed().as_nanos(),
+            width = TIMESTAMP_LEN
+        );
+        payload.push_str(&"x".repeat(config.payload_size.saturating_sub(TIMESTAMP_LEN)));
+
+        // Events failing to queue are counted as dropped.
+        let _ = tx.send(Event::new(&config.resource, Payload(payload)));
+        sent += 1;
+    }
+
+    let _ = tx.send(Event::new(&config.resource, Payload("done".to_string())));
+    let _ = tokio::time::timeout(DRAIN_TIMEOUT, &mut receiver).await;
+    receiver.abort();
+
+    let mut latencies =
+        std::mem::take(&mut *latencies.lock().unwrap_or_else(PoisonError::into_inner));
+    latencies.sort_unstable();
+
+    let delivered = latencies.len() as u64;
+    let report = BenchReport {
+        sent,
+        delivered,
+        dropped: sent.saturating_sub(delivered),
+        avg_latency: match delivered {
+            0 => Duration::ZERO,
+            n => latencies.iter().sum::<Duration>() / n as u32,
+        },
+        p99_latency: latencies
+            .get((latencies.len() * 99 / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default(),
+    };
+
+    Ok(report)
+}
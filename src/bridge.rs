@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use crate::{Event, Origin, PublishTarget};
+
+/// The bridges events are forwarded to, see
+/// [`ServerBuilder::bridge`](crate::server::ServerBuilder::bridge).
+#[derive(Default)]
+pub(crate) struct Bridges {
+    bridges: Vec<(String, Arc<dyn PublishTarget>)>,
+}
+
+impl Bridges {
+    pub(crate) fn add(&mut self, name: String, target: Arc<dyn PublishTarget>) {
+        self.bridges.push((name, target));
+    }
+
+    pub(crate) fn names(&self) -> Vec<&str> {
+        self.bridges.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Publishes `event` to every bridge but the one it was received from.
+    pub(crate) fn forward(&self, event: &Event) {
+        for (name, target) in &self.bridges {
+            if matches!(event.origin(), Origin::Bridge(x) if x == name) {
+                continue;
+            }
+
+            if let Err(e) = target.publish(event.clone()) {
+                tracing::warn!(
+                    "failed to forward event for {} to bridge {}: {}",
+                    event.res(),
+                    name,
+                    e
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::{fmt, time::Duration};
+
+use futures_util::{future, pin_mut};
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{Error, Event, EventTx};
+
+/// A sender that coalesces events arriving in quick succession into batches, returned by
+/// [`EventTxExt::buffered`](crate::EventTxExt::buffered).
+///
+/// The sender is cheap to clone, all clones share the same buffer.
+#[derive(Clone)]
+pub struct BufferedEventTx {
+    tx: mpsc::UnboundedSender<Event>,
+    window: Duration,
+}
+
+impl BufferedEventTx {
+    pub(crate) fn new(inner: EventTx, window: Duration) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(flush_loop(inner, rx, window));
+
+        Self { tx, window }
+    }
+
+    /// Adds `event` to the current batch, starting a new one if there is none.
+    ///
+    /// Fails with [`Error::ChannelClosed`] once the task publishing the batches is gone, which
+    /// happens when the runtime it was spawned on shuts down. Errors publishing a batch are
+    /// logged and the batch is dropped.
+    pub fn send(&self, event: Event) -> Result<(), Error> {
+        self.tx.send(event).map_err(|_| Error::ChannelClosed)
+    }
+
+    /// Returns how long events are collected before they are published.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+impl fmt::Debug for BufferedEventTx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferedEventTx")
+            .field("window", &self.window)
+            .field("closed", &self.tx.is_closed())
+            .finish()
+    }
+}
+
+/// Collects events for `window` after the first one of a batch arrives, then publishes the batch.
+async fn flush_loop(tx: EventTx, mut rx: mpsc::UnboundedReceiver<Event>, window: Duration) {
+    while let Some(first) = rx.recv().await {
+        let deadline = tokio::time::sleep_until(Instant::now() + window);
+        pin_mut!(deadline);
+
+        let mut batch = vec![first];
+        loop {
+            let recv = rx.recv();
+            pin_mut!(recv);
+
+            match future::select(recv, deadline.as_mut()).await {
+                future::Either::Left((Some(event), _)) => batch.push(event),
+                // Either the window is over or every sender is gone, publish what we have.
+                _ => break,
+            }
+        }
+
+        let len = batch.len();
+        if let Err(e) = tx.publish_batch(batch) {
+            tracing::warn!("dropping {} buffered events: {}", len, e);
+        }
+    }
+}
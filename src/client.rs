@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::header::{HeaderName, HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+use uuid::Uuid;
+
+use crate::audit::{AuditEntry, AuditKind};
+use crate::auth::Rejection;
+use crate::middleware::{self, RequestMiddleware};
+use crate::protocol::{self, Encoding};
+use crate::replay;
+use crate::server::ServerInner;
+use crate::Request as UpgradeRequest;
+
+/// Opaque identifier assigned to every websocket connection accepted by the server.
+///
+/// Ids are version 7 UUIDs, unique across the nodes of a cluster and never reused. The ids of
+/// the clients of one server are ordered by when they connected.
+///
+/// # Sticky sessions
+/// The upgrade response carries the id in the `X-Pushevent-Client-Id` header, which load
+/// balancers can learn and route on, so that a client sending the header back when
+/// reconnecting lands on the node that knows it. With HAProxy:
+///
+/// ```text
+/// backend pushevent
+///     stick-table type string len 36 size 1m expire 1h
+///     stick on req.hdr(X-Pushevent-Client-Id)
+///     stick store-response res.hdr(X-Pushevent-Client-Id)
+///     server a 10.0.0.1:3012
+///     server b 10.0.0.2:3012
+/// ```
+///
+/// With nginx, where learning a session from a response header requires the commercial
+/// `sticky` directive:
+///
+/// ```text
+/// upstream pushevent {
+///     server 10.0.0.1:3012;
+///     server 10.0.0.2:3012;
+///     sticky learn
+///         create=$upstream_http_x_pushevent_client_id
+///         lookup=$http_x_pushevent_client_id
+///         zone=pushevent_clients:1m;
+/// }
+/// ```
+///
+/// See [`Server::find_client_by_id`](crate::server::Server::find_client_by_id) for looking a
+/// client up by the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientId(Uuid);
+
+impl ClientId {
+    /// Returns a fresh, never before seen id.
+    pub(crate) fn next() -> Self {
+        Self(Uuid::now_v7())
+    }
+
+    /// Parses an id in the format of the `X-Pushevent-Client-Id` header.
+    pub(crate) fn parse(id: &str) -> Option<Self> {
+        Uuid::try_parse(id.trim()).ok().map(Self)
+    }
+}
+
+/// Formats the id the way it is sent in the `X-Pushevent-Client-Id` header, as a hyphenated
+/// UUID.
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.hyphenated().fmt(f)
+    }
+}
+
+/// Key-value state attached to a connection by the application, for example the identity an
+/// [authenticator](crate::auth::Authenticator) extracted or a tenant computed when the client
+/// connected.
+///
+/// Clones share the same state, so values inserted through one clone, e.g. in the `on_connect`
+/// hook, are visible to all others, e.g. in the per-client filter or through
+/// [`Server::client_meta`](crate::server::Server::client_meta). The state is dropped together
+/// with the last clone, which the server holds until the client disconnects.
+///
+/// # Example
+/// ```
+/// use pushevent::ClientMeta;
+///
+/// let meta = ClientMeta::new();
+/// meta.clone().insert("tenant", "acme");
+///
+/// assert_eq!(meta.get("tenant").as_deref(), Some("acme"));
+/// assert_eq!(meta.get("user"), None);
+/// ```
+#[derive(Clone, Default)]
+pub struct ClientMeta(Arc<RwLock<HashMap<String, String>>>);
+
+impl ClientMeta {
+    /// Returns empty state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `key` to `value`, returning the previous value.
+    pub fn insert(&self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key.into(), value.into())
+    }
+
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key)
+            .cloned()
+    }
+
+    /// Removes `key`, returning its value.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(key)
+    }
+
+    /// Returns a copy of every key and value.
+    pub fn to_map(&self) -> HashMap<String, String> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl PartialEq for ClientMeta {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.to_map() == other.to_map()
+    }
+}
+
+impl Eq for ClientMeta {}
+
+impl fmt::Debug for ClientMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.to_map()).finish()
+    }
+}
+
+/// The upgrade response header carrying the id of the connection, see [`ClientId`].
+pub(crate) const CLIENT_ID_HEADER: &str = "x-pushevent-client-id";
+
+/// Public snapshot of a connected client, handed to the server hooks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The id of the connection.
+    pub id: ClientId,
+    /// The address of the remote peer.
+    pub addr: SocketAddr,
+    /// The resource the client subscribed to when connecting.
+    pub resource: String,
+    /// Query string parameters of the upgrade request, e.g. connecting to
+    /// `/events?user_id=42&org=acme` yields `user_id => 42` and `org => acme`. If a key is
+    /// repeated the last value wins.
+    pub metadata: HashMap<String, String>,
+    /// The version of the pushevent protocol negotiated through `Sec-WebSocket-Protocol`, see
+    /// [`ServerBuilder::max_protocol_version`](crate::server::ServerBuilder::max_protocol_version).
+    pub protocol_version: u8,
+    /// The encoding of the events sent to the client, negotiated through
+    /// `Sec-WebSocket-Protocol` or the `Accept` header.
+    pub encoding: Encoding,
+    /// The subprotocol selected from the ones offered through `Sec-WebSocket-Protocol`, either
+    /// one of the pushevent protocols or one declared with
+    /// [`ServerBuilder::subprotocols`](crate::server::ServerBuilder::subprotocols).
+    pub subprotocol: Option<String>,
+    /// The `User-Agent` header of the upgrade request, if the client sent one.
+    pub user_agent: Option<String>,
+    /// State attached to the connection by the application, shared by every copy of this
+    /// snapshot.
+    pub meta: ClientMeta,
+    /// The tags set with [`Server::tag_client`](crate::server::Server::tag_client), shared by
+    /// every copy of this snapshot. Kept apart from [`meta`](Self::meta), so that tags can't
+    /// overwrite the state set by the authenticator, such as the audited identity.
+    pub tags: ClientMeta,
+}
+
+/// Per-connection state collected while the websocket handshake is in progress.
+pub(crate) struct Client {
+    pub(crate) id: ClientId,
+    pub(crate) addr: SocketAddr,
+    /// The path of the upgrade request, which is the resource this client subscribes to.
+    pub(crate) resource: String,
+    /// The query string parameters of the upgrade request.
+    pub(crate) metadata: HashMap<String, String>,
+    /// The pushevent protocol version events are encoded with for this client.
+    pub(crate) protocol_version: u8,
+    /// The encoding events are serialized with for this client.
+    pub(crate) encoding: Encoding,
+    /// The subprotocol echoed in the handshake response.
+    pub(crate) subprotocol: Option<String>,
+    /// The `User-Agent` header of the upgrade request.
+    pub(crate) user_agent: Option<String>,
+    /// The session token the client identified itself with, see [`replay::SESSION_HEADER`].
+    pub(crate) session: Option<String>,
+    /// The state attached by the middleware and authenticator.
+    pub(crate) meta: ClientMeta,
+}
+
+impl Client {
+    pub(crate) fn new(addr: SocketAddr) -> Self {
+        Self {
+            id: ClientId::next(),
+            addr,
+            resource: String::new(),
+            metadata: HashMap::new(),
+            protocol_version: 1,
+            encoding: Encoding::Json,
+            subprotocol: None,
+            user_agent: None,
+            session: None,
+            meta: ClientMeta::new(),
+        }
+    }
+
+    pub(crate) fn info(&self) -> ClientInfo {
+        ClientInfo {
+            id: self.id,
+            addr: self.addr,
+            resource: self.resource.clone(),
+            metadata: self.metadata.clone(),
+            protocol_version: self.protocol_version,
+            encoding: self.encoding,
+            subprotocol: self.subprotocol.clone(),
+            user_agent: self.user_agent.clone(),
+            meta: self.meta.clone(),
+            tags: ClientMeta::new(),
+        }
+    }
+
+    /// Inspects the upgrade request, rejecting it if the server's authenticator refuses it, and
+    /// selects the protocol version offered by the client.
+    // The error response type is dictated by tungstenite.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn on_request(
+        &mut self,
+        server: &ServerInner,
+        req: &Request,
+        mut res: Response,
+    ) -> Result<Response, ErrorResponse> {
+        let req = UpgradeRequest::from_handshake(req).with_remote_addr(self.addr);
+        // Shared with the request, so that what the middleware attaches ends up here even if the
+        // request is rejected.
+        self.meta = req.meta().clone();
+        server
+            .limits
+            .check_len(req.path())
+            .map_err(Rejection::into_response)?;
+
+        let accepted = server
+            .middleware
+            .handle(&req, &|req| {
+                match &server.authenticator {
+                    Some(x) if server.route(req.path()).authenticate => x.authenticate(req)?,
+                    _ => {}
+                }
+
+                Ok(middleware::Response::new())
+            })
+            .map_err(Rejection::into_response)?;
+
+        for (name, value) in accepted.headers() {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    res.headers_mut().append(name, value);
+                }
+                _ => tracing::debug!("{}: skipping invalid response header {}", self.addr, name),
+            }
+        }
+
+        self.resource = req.path().to_string();
+        self.user_agent = req.header("user-agent").map(str::to_string);
+        self.session = req.header(replay::SESSION_HEADER).map(str::to_string);
+
+        if let Some(query) = req.query() {
+            self.metadata = url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect();
+        }
+
+        server
+            .limits
+            .check(&self.info(), &self.resource, 0)
+            .map_err(Rejection::into_response)?;
+
+        // Only one subprotocol can be selected, binary encodings take precedence over the
+        // protocol versions, which take precedence over the application's subprotocols.
+        if let Some((encoding, name)) =
+            protocol::negotiate_encoding(&req).and_then(|x| Some((x, x.name()?)))
+        {
+            self.encoding = encoding;
+            self.subprotocol = Some(name.to_string());
+        } else if let Some((version, name)) = protocol::negotiate(&req, server.max_protocol_version)
+        {
+            self.protocol_version = version;
+            self.subprotocol = Some(name.to_string());
+        } else {
+            if let Some(version) = self
+                .metadata
+                .get("protocol")
+                .and_then(|x| protocol::from_query(x, server.max_protocol_version))
+            {
+                self.protocol_version = version;
+            }
+
+            self.subprotocol = protocol::offered(&req)
+                .find(|x| server.subprotocols.iter().any(|y| y == x))
+                .map(str::to_string);
+        }
+
+        // Clients that can't offer subprotocols may still ask for a binary encoding.
+        if self.encoding == Encoding::Json {
+            if let Some(encoding) = protocol::accepted_encoding(&req) {
+                self.encoding = encoding;
+            }
+        }
+
+        match &self.subprotocol {
+            // Offered by the client, so it is a valid header value.
+            Some(name) => {
+                if let Ok(value) = HeaderValue::from_str(name) {
+                    res.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+                }
+            }
+            None if server.reject_unsupported_subprotocols
+                && protocol::offered(&req).next().is_some() =>
+            {
+                return Err(Rejection::bad_request(
+                    "none of the offered subprotocols is supported",
+                )
+                .into_response());
+            }
+            None => {}
+        }
+
+        // A hyphenated UUID is always a valid header value.
+        if let Ok(value) = HeaderValue::from_str(&self.id.to_string()) {
+            res.headers_mut().insert(CLIENT_ID_HEADER, value);
+        }
+
+        Ok(res)
+    }
+}
+
+/// Hands the upgrade request of a connection to [`Client::on_request`].
+pub(crate) struct OnRequest<'a> {
+    pub(crate) client: &'a mut Client,
+    pub(crate) server: &'a ServerInner,
+}
+
+impl Callback for OnRequest<'_> {
+    fn on_request(self, req: &Request, res: Response) -> Result<Response, ErrorResponse> {
+        let res = self.client.on_request(self.server, req, res);
+
+        if let (Err(rejection), Some(audit)) = (&res, &self.server.audit) {
+            let kind = AuditKind::HandshakeRejected {
+                status: rejection.status().as_u16(),
+                reason: rejection.body().clone().unwrap_or_default(),
+            };
+            audit.record(AuditEntry::new(
+                kind,
+                self.client.addr,
+                req.uri().path(),
+                None,
+                &self.client.meta,
+            ));
+        }
+
+        res
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use rustc_hash::FxHasher;
+
+use crate::client::ClientId;
+use crate::pattern;
+use crate::registry::Registry;
+
+/// A [`Registry`] split into shards by resource, each behind its own lock, so that publishing to
+/// and subscribing to unrelated resources doesn't contend on a single lock.
+///
+/// Subscriptions to patterns, which may match resources of any shard, are kept in a shard of
+/// their own that is consulted for every event.
+///
+/// Hooks run while a shard is locked may panic, which leaves the registry itself intact, so
+/// poisoned locks are used as is.
+pub(crate) struct Demultiplexer<T> {
+    /// The shards of the resources, followed by the shard of the patterns.
+    shards: Box<[RwLock<Registry<T>>]>,
+}
+
+/// The shards that may hold subscribers of a resource, locked for reading.
+pub(crate) struct Recipients<'a, T> {
+    shard: RwLockReadGuard<'a, Registry<T>>,
+    /// The shard of the patterns, unless `shard` already is that shard.
+    patterns: Option<RwLockReadGuard<'a, Registry<T>>>,
+}
+
+impl<T> Demultiplexer<T> {
+    /// Returns a demultiplexer with `shards` shards, at least one, for resources.
+    pub(crate) fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1) + 1)
+                .map(|_| RwLock::new(Registry::new()))
+                .collect(),
+        }
+    }
+
+    fn index(&self, res: &str) -> usize {
+        let patterns = self.shards.len() - 1;
+        if pattern::is_pattern(res) {
+            return patterns;
+        }
+
+        let mut hasher = FxHasher::default();
+        res.hash(&mut hasher);
+
+        hasher.finish() as usize % patterns
+    }
+
+    /// Locks the shards holding the subscribers of `res` for reading, always in the same order.
+    pub(crate) fn read(&self, res: &str) -> Recipients<'_, T> {
+        let index = self.index(res);
+        let patterns = self.shards.len() - 1;
+        let lock = |index: usize| {
+            self.shards[index]
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+        };
+
+        Recipients {
+            shard: lock(index),
+            patterns: (index != patterns).then(|| lock(patterns)),
+        }
+    }
+
+    /// Locks the shard holding `res` for writing.
+    pub(crate) fn write(&self, res: &str) -> RwLockWriteGuard<'_, Registry<T>> {
+        self.shards[self.index(res)]
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks every shard for writing, always in the same order.
+    pub(crate) fn write_all(&self) -> Vec<RwLockWriteGuard<'_, Registry<T>>> {
+        self.shards
+            .iter()
+            .map(|x| x.write().unwrap_or_else(PoisonError::into_inner))
+            .collect()
+    }
+
+    /// Removes `id` from every resource it is subscribed to. Returns whether the client was known.
+    pub(crate) fn remove_client(&self, id: ClientId) -> bool {
+        let mut removed = false;
+
+        for shard in self.shards.iter() {
+            removed |= shard
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove_client(id);
+        }
+
+        removed
+    }
+
+    /// Returns the handle of one of the subscriptions of `id`.
+    pub(crate) fn get(&self, id: ClientId) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.shards.iter().find_map(|x| {
+            x.read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(id)
+                .cloned()
+        })
+    }
+
+    /// Returns the resources `id` is subscribed to.
+    pub(crate) fn subscriptions(&self, id: ClientId) -> Vec<String> {
+        self.shards
+            .iter()
+            .flat_map(|x| {
+                x.read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .subscriptions(id)
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Returns every client with the handle of one of its subscriptions and all resources it is
+    /// subscribed to. The shards are locked one at a time.
+    pub(crate) fn snapshot(&self) -> HashMap<ClientId, (T, Vec<String>)>
+    where
+        T: Clone,
+    {
+        let mut clients = HashMap::<ClientId, (T, Vec<String>)>::new();
+
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap_or_else(PoisonError::into_inner);
+
+            for (id, handle) in shard.clients() {
+                clients
+                    .entry(id)
+                    .or_insert_with(|| (handle.clone(), Vec::new()))
+                    .1
+                    .extend(shard.subscriptions(id).map(str::to_string));
+            }
+        }
+
+        clients
+    }
+
+    /// Returns every client once, with the handle of one of its subscriptions. The shards are
+    /// locked one at a time.
+    pub(crate) fn all(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let mut seen = HashSet::new();
+        let mut clients = Vec::new();
+
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap_or_else(PoisonError::into_inner);
+            clients.extend(
+                shard
+                    .clients()
+                    .filter(|(id, _)| seen.insert(*id))
+                    .map(|(_, handle)| handle.clone()),
+            );
+        }
+
+        clients
+    }
+
+    /// Returns the number of clients with at least one subscription.
+    pub(crate) fn client_count(&self) -> usize {
+        let shards = self
+            .shards
+            .iter()
+            .map(|x| x.read().unwrap_or_else(PoisonError::into_inner))
+            .collect::<Vec<_>>();
+
+        count_clients(shards.iter().map(|x| &**x))
+    }
+
+    /// Empties every shard, returning the previous contents.
+    pub(crate) fn take(&self) -> Vec<Registry<T>> {
+        self.write_all()
+            .into_iter()
+            .map(|mut x| std::mem::replace(&mut *x, Registry::new()))
+            .collect()
+    }
+}
+
+impl<T> Recipients<'_, T> {
+    /// Returns the clients that should receive an event published to `res`, each once.
+    pub(crate) fn subscribers<'a>(
+        &'a self,
+        res: &'a str,
+    ) -> impl Iterator<Item = (ClientId, &'a T)> + 'a {
+        let patterns = self.patterns.as_ref().filter(|x| x.has_patterns());
+        // A client can only match through both shards if it is subscribed to a pattern.
+        let mut seen = patterns.is_some().then(HashSet::new);
+
+        self.shard
+            .subscribers(res)
+            .chain(patterns.into_iter().flat_map(move |x| x.subscribers(res)))
+            .filter(move |(id, _)| seen.as_mut().is_none_or(|x| x.insert(*id)))
+    }
+}
+
+/// Returns the number of distinct clients across `shards`.
+pub(crate) fn count_clients<'a, T: 'a>(shards: impl IntoIterator<Item = &'a Registry<T>>) -> usize {
+    shards
+        .into_iter()
+        .flat_map(Registry::client_ids)
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_span_shards() {
+        let demux = Demultiplexer::new(8);
+        let a = ClientId::next();
+        let b = ClientId::next();
+
+        for res in ["/a", "/b", "/c", "/d", "/e", "/f", "*"] {
+            demux.write(res).add(res, a, ());
+        }
+        demux.write("/a").add("/a", b, ());
+
+        assert_eq!(demux.client_count(), 2);
+        assert_eq!(demux.subscriptions(a).len(), 7);
+        assert_eq!(demux.read("/a").subscribers("/a").count(), 2);
+        assert_eq!(demux.read("/g").subscribers("/g").count(), 1);
+        assert_eq!(demux.read("*").subscribers("*").count(), 1);
+
+        assert!(demux.remove_client(a));
+        assert!(!demux.remove_client(a));
+        assert!(demux.subscriptions(a).is_empty());
+        assert_eq!(demux.client_count(), 1);
+
+        assert_eq!(count_clients(&demux.take()), 1);
+        assert_eq!(demux.client_count(), 0);
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+
+/// Boxed error type used to carry errors of dependencies without exposing them in the public API.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors returned by the public API of this crate.
+///
+/// Errors of the websocket backend are carried as opaque sources rather than being exposed
+/// directly:
+/// ```compile_fail
+/// let _: pushevent::Error = tungstenite::Error::ConnectionClosed.into();
+/// ```
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The listener could not be bound to the requested address.
+    #[error("failed to bind listener: {0}")]
+    Bind(#[source] io::Error),
+    /// The websocket handshake with a client failed.
+    #[error("websocket handshake failed: {0}")]
+    Handshake(#[source] BoxError),
+    /// The server is gone and no longer receives events.
+    #[error("event channel is closed")]
+    ChannelClosed,
+    /// A event could not be serialized.
+    #[error("failed to serialize event: {0}")]
+    Serialization(#[source] BoxError),
+    /// No client with the requested id is connected.
+    #[error("client not found")]
+    ClientNotFound,
+    /// No session with the requested id is open.
+    #[error("session not found")]
+    SessionNotFound,
+    /// The client may not subscribe to the resource, see
+    /// [`ServerBuilder::validate_resource`](crate::server::ServerBuilder::validate_resource).
+    #[error("subscription rejected: {0}")]
+    ResourceRejected(#[source] crate::auth::Rejection),
+    /// The event queue is bounded and currently at capacity.
+    #[error("event channel is at capacity")]
+    QueueFull,
+    /// The payload of the event is larger than the server accepts, see
+    /// [`ServerBuilder::max_payload`](crate::server::ServerBuilder::max_payload).
+    #[error("the payload of {size} bytes exceeds the limit of {max} bytes")]
+    PayloadTooLarge {
+        /// The size of the payload in bytes.
+        size: usize,
+        /// The largest payload accepted for the resource, in bytes.
+        max: usize,
+    },
+    /// A task of the server panicked.
+    #[error("a server task panicked")]
+    TaskPanicked,
+    /// The server tasks didn't finish in time.
+    #[error("timed out waiting for the server to finish")]
+    JoinTimeout,
+    /// The server didn't start accepting connections in time.
+    #[error("timed out waiting for the server to accept connections")]
+    NotReady,
+    /// The operation isn't supported by the configured broadcast backend.
+    #[error("not supported by the broadcast backend")]
+    Unsupported,
+    /// A JSON Schema registered with
+    /// [`ServerBuilder::schema`](crate::server::ServerBuilder::schema) is invalid.
+    #[error("{0}")]
+    InvalidSchema(#[source] BoxError),
+    /// A thread of the server, such as a broadcast worker, could not be started.
+    #[error("failed to spawn a server thread: {0}")]
+    Spawn(#[source] io::Error),
+    /// The server was started outside a tokio runtime without one to run on, see
+    /// [`ServerBuilder::runtime`](crate::server::ServerBuilder::runtime).
+    #[error("no tokio runtime to run the server on")]
+    NoRuntime,
+    /// The handlers for the shutdown signals could not be installed.
+    #[error("failed to listen for shutdown signals: {0}")]
+    Signal(#[source] io::Error),
+}
+
+impl Error {
+    pub(crate) fn handshake(e: tungstenite::Error) -> Self {
+        Self::Handshake(Box::new(e))
+    }
+}
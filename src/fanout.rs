@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tungstenite::Message;
+
+use crate::client::ClientInfo;
+use crate::pattern;
+use crate::protocol;
+use crate::server::ServerInner;
+use crate::Event;
+
+/// How the broadcast loop hands events to the connections, see
+/// [`ServerBuilder::broadcast_backend`](crate::server::ServerBuilder::broadcast_backend).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum BroadcastBackend {
+    /// Every client has its own unbounded queue. Events are delivered to every client that stays
+    /// connected, no matter how far it falls behind.
+    #[default]
+    PerClient,
+    /// Every resource has a [`tokio::sync::broadcast`] channel holding the last `capacity`
+    /// events. Clients that fall further behind skip the events they missed and are sent a
+    /// `{"type":"lagged","missed":N}` notice instead, if they speak protocol version 2.
+    TokioBroadcast {
+        /// The number of events a client may fall behind before it starts missing events.
+        capacity: usize,
+    },
+}
+
+/// One broadcast channel per resource or pattern with at least one subscriber.
+pub(crate) struct Channels {
+    capacity: usize,
+    senders: Mutex<Senders>,
+}
+
+#[derive(Default)]
+struct Senders {
+    resources: HashMap<String, broadcast::Sender<Event>>,
+    patterns: HashMap<String, broadcast::Sender<Event>>,
+}
+
+impl Senders {
+    fn get_mut(&mut self, res: &str) -> &mut HashMap<String, broadcast::Sender<Event>> {
+        if pattern::is_pattern(res) {
+            &mut self.patterns
+        } else {
+            &mut self.resources
+        }
+    }
+}
+
+impl Channels {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            senders: Mutex::default(),
+        }
+    }
+
+    /// Returns a receiver for the events published to `res`, or to the resources matching it if
+    /// it is a pattern.
+    pub(crate) fn subscribe(&self, res: &str) -> broadcast::Receiver<Event> {
+        self.senders
+            .lock()
+            .unwrap()
+            .get_mut(res)
+            .entry(res.to_string())
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+
+    /// Publishes `event` to the subscribers of its resource and of the patterns matching it.
+    pub(crate) fn publish(&self, event: Event) {
+        let senders = self.senders.lock().unwrap();
+
+        let patterns = senders
+            .patterns
+            .iter()
+            .filter(|(pattern, _)| pattern::matches(pattern, event.res()));
+        for (_, tx) in patterns {
+            let _ = tx.send(event.clone());
+        }
+
+        if let Some(tx) = senders.resources.get(event.res()) {
+            let _ = tx.send(event);
+        }
+    }
+
+    /// Removes the channel of `res` once its last receiver is gone.
+    pub(crate) fn release(&self, res: &str) {
+        let mut senders = self.senders.lock().unwrap();
+        let senders = senders.get_mut(res);
+
+        if senders.get(res).is_some_and(|x| x.receiver_count() == 0) {
+            senders.remove(res);
+        }
+    }
+}
+
+/// Turns a broadcast receiver into the frames sent to `client`, applying the server's
+/// per-client filter and the client's protocol version. Lagging behind yields a notice with the
+/// number of skipped events.
+pub(crate) fn frames(
+    rx: broadcast::Receiver<Event>,
+    server: Arc<ServerInner>,
+    client: Arc<ClientInfo>,
+) -> impl Stream<Item = Message> {
+    stream::unfold(rx, move |mut rx| {
+        let server = server.clone();
+        let client = client.clone();
+
+        async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) if server.accepts(&client, &event) => {
+                        server
+                            .taps
+                            .record(&client, event.res(), event.payload(), true);
+                        let frame = protocol::encode(&client, &event);
+                        return Some((frame, rx));
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::debug!("{}: lagging behind, skipped {} events", client.addr, n);
+                        if !protocol::receives_notices(&client) {
+                            continue;
+                        }
+
+                        let notice = format!(r#"{{"type":"lagged","missed":{}}}"#, n);
+                        return Some((Message::Text(notice.into()), rx));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,111 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::client::ClientId;
+
+/// The groups clients were put in by the application, see
+/// [`Server::join_group`](crate::server::Server::join_group).
+///
+/// Groups only exist while they have members, and clients leave every group when they
+/// disconnect.
+#[derive(Default)]
+pub(crate) struct Groups {
+    /// group -> its members.
+    members: HashMap<String, HashSet<ClientId>>,
+    /// client -> the groups it is a member of, used to remove a client in one go.
+    joined: HashMap<ClientId, HashSet<String>>,
+}
+
+impl Groups {
+    /// Adds `id` to `group`. Returns `false` if it already was a member.
+    pub(crate) fn join(&mut self, id: ClientId, group: &str) -> bool {
+        if !self
+            .members
+            .entry(group.to_string())
+            .or_default()
+            .insert(id)
+        {
+            return false;
+        }
+
+        self.joined.entry(id).or_default().insert(group.to_string());
+        true
+    }
+
+    /// Removes `id` from `group`. Returns whether it was a member.
+    pub(crate) fn leave(&mut self, id: ClientId, group: &str) -> bool {
+        let left = match self.members.get_mut(group) {
+            Some(members) => {
+                let left = members.remove(&id);
+                if members.is_empty() {
+                    self.members.remove(group);
+                }
+                left
+            }
+            None => false,
+        };
+
+        if let Some(groups) = self.joined.get_mut(&id) {
+            groups.remove(group);
+            if groups.is_empty() {
+                self.joined.remove(&id);
+            }
+        }
+
+        left
+    }
+
+    /// Removes `id` from every group it is a member of.
+    pub(crate) fn remove_client(&mut self, id: ClientId) {
+        for group in self.joined.remove(&id).unwrap_or_default() {
+            if let Some(members) = self.members.get_mut(&group) {
+                members.remove(&id);
+                if members.is_empty() {
+                    self.members.remove(&group);
+                }
+            }
+        }
+    }
+
+    /// Returns the members of `group`, in no particular order.
+    pub(crate) fn members(&self, group: &str) -> Vec<ClientId> {
+        self.members
+            .get(group)
+            .map(|x| x.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the groups `id` is a member of, in no particular order.
+    pub(crate) fn groups(&self, id: ClientId) -> Vec<String> {
+        self.joined
+            .get(&id)
+            .map(|x| x.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_groups_are_removed() {
+        let (a, b) = (ClientId::next(), ClientId::next());
+        let mut groups = Groups::default();
+
+        assert!(groups.join(a, "admins"));
+        assert!(!groups.join(a, "admins"));
+        assert!(groups.join(a, "game-42"));
+        assert!(groups.join(b, "game-42"));
+        assert_eq!(groups.members("admins"), [a]);
+
+        assert!(groups.leave(a, "admins"));
+        assert!(!groups.leave(a, "admins"));
+        assert!(!groups.members.contains_key("admins"));
+
+        groups.remove_client(a);
+        assert_eq!(groups.members("game-42"), [b]);
+        assert!(groups.groups(a).is_empty());
+        groups.remove_client(b);
+        assert!(groups.members.is_empty() && groups.joined.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How long a server task may spend on a single event or connection by default before it is
+/// reported as stalled, see
+/// [`ServerBuilder::stall_timeout`](crate::server::ServerBuilder::stall_timeout).
+pub(crate) const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A snapshot of the state of a server, see
+/// [`Server::health_report`](crate::server::Server::health_report).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HealthReport {
+    /// Whether the server accepts new connections, `false` once it started shutting down.
+    pub accepting: bool,
+    /// Whether the task accepting connections is running and not stuck.
+    pub listener_alive: bool,
+    /// Whether the broadcast loop is running and not stuck delivering an event.
+    pub broadcast_loop_alive: bool,
+    /// The number of events published and not yet taken out of the queue.
+    pub event_queue_depth: usize,
+    /// When the last connection was accepted.
+    pub last_accepted_at: Option<SystemTime>,
+    /// When an event was last delivered.
+    pub last_delivered_at: Option<SystemTime>,
+}
+
+impl HealthReport {
+    /// Returns whether the server should receive traffic, meant for readiness probes.
+    pub fn is_ready(&self) -> bool {
+        self.accepting && self.listener_alive && self.broadcast_loop_alive
+    }
+}
+
+/// Tracks whether a server task is running, and since when it is busy with a single item.
+pub(crate) struct Heartbeat {
+    started: Instant,
+    /// Nanoseconds since `started` plus one when the task started its current item, `0` while
+    /// it is waiting.
+    busy_since: AtomicU64,
+    running: AtomicBool,
+}
+
+impl Heartbeat {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            busy_since: AtomicU64::new(0),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Marks the task as running until the returned guard is dropped, also when it panics.
+    pub(crate) fn run(&self) -> Running<'_> {
+        self.running.store(true, Ordering::Release);
+        Running(self)
+    }
+
+    /// Returns whether the task is running, busy or not.
+    pub(crate) fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Marks the task as busy until the returned guard is dropped.
+    pub(crate) fn busy(&self) -> Busy<'_> {
+        let now = self.started.elapsed().as_nanos() as u64 + 1;
+        self.busy_since.store(now, Ordering::Release);
+        Busy(self)
+    }
+
+    /// Returns whether the task is running and not busy with a single item for longer than
+    /// `stall_timeout`.
+    pub(crate) fn is_alive(&self, stall_timeout: Duration) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+
+        match self.busy_since.load(Ordering::Acquire) {
+            0 => true,
+            since => {
+                let now = self.started.elapsed().as_nanos() as u64 + 1;
+                Duration::from_nanos(now.saturating_sub(since)) < stall_timeout
+            }
+        }
+    }
+}
+
+pub(crate) struct Running<'a>(&'a Heartbeat);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::Release);
+    }
+}
+
+pub(crate) struct Busy<'a>(&'a Heartbeat);
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.busy_since.store(0, Ordering::Release);
+    }
+}
+
+/// A point in time that is updated concurrently, `None` until it is first set.
+#[derive(Default)]
+pub(crate) struct Timestamp(AtomicU64);
+
+impl Timestamp {
+    pub(crate) fn set_now(&self) {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_millis() as u64);
+        self.0.store(millis.max(1), Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> Option<SystemTime> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+}
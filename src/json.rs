@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+use crate::{Error, Event, EventTx};
+
+impl EventTx {
+    /// Serializes `inner` to JSON and publishes it to `res`, sparing one-off notifications a
+    /// dedicated [`SerializableEvent`](crate::SerializableEvent) impl.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct ScanDone {
+    ///     library: u64,
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = pushevent::build().await.unwrap();
+    /// tx.publish_json("/events/library", &ScanDone { library: 5 }).unwrap();
+    /// # }
+    /// ```
+    pub fn publish_json(
+        &self,
+        res: impl Into<String>,
+        inner: &impl Serialize,
+    ) -> Result<(), Error> {
+        let event = Event::from_json(res, inner).map_err(|e| Error::Serialization(Box::new(e)))?;
+
+        self.send(event)
+    }
+}
+
+/// Builds an [`Event`] from a resource and a JSON literal, using the same syntax as
+/// [`serde_json::json!`].
+///
+/// The resource must be string-like (anything that is `Into<String>`), anything else is rejected
+/// at compile time.
+///
+/// # Example
+/// ```
+/// use pushevent::event;
+///
+/// let id = 5;
+/// let event = event!("/events/library", { "kind": "scan_done", "library": id });
+///
+/// assert_eq!(event.get_res(), "/events/library");
+/// assert_eq!(event.build(), r#"{"kind":"scan_done","library":5}"#);
+/// ```
+///
+/// ```compile_fail
+/// use pushevent::event;
+///
+/// let event = event!(5, { "kind": "scan_done" });
+/// ```
+#[macro_export]
+macro_rules! event {
+    ($res:expr, $($json:tt)+) => {
+        $crate::Event::from_value($res, $crate::__private::serde_json::json!($($json)+))
+    };
+}
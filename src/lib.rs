@@ -1,141 +1,116 @@
-use std::{
-    collections::HashMap,
-    env,
-    io::Error as IoError,
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-};
-
-use futures_channel::mpsc::{unbounded, UnboundedSender};
-use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
-
-use tokio::net::{TcpListener, TcpStream};
-use tungstenite::protocol::Message;
-
-/// SerializableEvent denotes structs that are able to serialize to some String.
-/// This is used as mainly a marker trait, underneath serialize you most likely would want to use
-/// serde.
-pub trait SerializableEvent: Sync + Send + 'static {
-    /// Returns a String of the serialized object
-    fn serialize(&self) -> String;
+#[cfg(feature = "actix")]
+pub mod actix_adapter;
+pub mod audit;
+pub mod auth;
+mod batch;
+#[cfg(feature = "bench-harness")]
+pub mod bench_harness;
+mod bridge;
+mod buffered;
+mod client;
+mod demux;
+mod error;
+mod fanout;
+mod group;
+mod health;
+#[cfg(feature = "serde")]
+mod json;
+mod limits;
+#[cfg(feature = "loadtest")]
+pub mod loadtest;
+mod local;
+mod message;
+mod metered;
+pub mod middleware;
+mod multi;
+pub mod noop;
+#[cfg(feature = "oauth")]
+pub mod oauth;
+mod partition;
+mod pattern;
+mod pipe;
+mod protocol;
+mod qos;
+mod registry;
+mod replay;
+mod request;
+mod retry;
+mod route;
+mod routing;
+#[cfg(feature = "schema")]
+mod schema;
+mod sequence;
+pub mod server;
+pub mod session;
+mod socket;
+mod stream;
+mod tap;
+mod task;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+mod transform;
+mod tx;
+
+pub use buffered::BufferedEventTx;
+pub use client::{ClientId, ClientInfo, ClientMeta};
+pub use error::{BoxError, Error};
+pub use local::LocalSubscription;
+pub use message::{CloseReason, Payload};
+pub use metered::{MeteredEventTx, TxMetrics};
+pub use multi::{MultiPublishError, MultiPublisher, PublishTarget};
+pub use protocol::Encoding;
+#[cfg(feature = "serde")]
+pub use pushevent_core::DynamicEvent;
+pub use pushevent_core::{Event, Origin, SerializableEvent};
+pub use request::Request;
+pub use retry::{RetriesExhausted, RetryingEventTx};
+pub use stream::StreamEvent;
+pub use transform::{Transform, Transformer};
+pub use tx::{EventTx, EventTxExt};
+
+use server::ServerBuilder;
+
+/// The types most applications need, `use pushevent::prelude::*` imports all of them.
+///
+/// # Example
+/// ```
+/// use pushevent::prelude::*;
+///
+/// struct Hello;
+///
+/// impl SerializableEvent for Hello {
+///     fn serialize(&self) -> String {
+///         String::from("hello")
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let server: Server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+/// let tx: EventTx = server.get_tx();
+///
+/// tx.send(Event::new("/events", Hello)).unwrap();
+/// # }
+/// ```
+pub mod prelude {
+    pub use crate::server::{Server, ServerBuilder};
+    pub use crate::{ClientId, ClientInfo, Event, EventTx, EventTxExt, SerializableEvent};
 }
 
-/// Base Event struct which can be sent across a channel provided by
-/// [`Server::get_tx`](server::Server::get_tx).
-/// This struct encapsulates a inner trait object and res which is the resource we want to target.
-pub struct Event {
-    inner: String,
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
 }
 
-impl Event {
-    /// Returns a Event instance.
-    /// # Arguments
-    ///
-    /// * `res` - A string slice that holds the resource we want to target
-    /// * `inner` - A boxed trait object that can serialize to a string.
-    ///
-    /// # Example
-    /// ```
-    /// use pushevent::{Event, SerializableEvent};
-    /// struct Message;
-    ///
-    /// impl SerializableEvent for Message {
-    ///     fn serialize(&self) -> String {
-    ///         String::from("Hello world")
-    ///     }
-    /// }
-    ///
-    /// let message = Box::new(Message);
-    /// let new_event = Event::new("/events/message", message);
-    ///
-    /// assert_eq!(new_event.get_res(), String::from("/events/message"));
-    /// assert_eq!(new_event.build(), String::from("Hello world"));
-    /// ```
-    pub fn new(inner: impl SerializableEvent) -> Self {
-        Self {
-            inner: inner.serialize(),
-        }
-    }
-
-    /// Serializes and returns the inner event/message.
-    /// # Example
-    /// ```
-    /// use pushevent::{Event, SerializableEvent};
-    /// struct Message;
-    ///
-    /// impl SerializableEvent for Message {
-    ///     fn serialize(&self) -> String {
-    ///         String::from("Hello world")
-    ///     }
-    /// }
-    ///
-    /// let message = Box::new(Message);
-    /// let new_event = Event::new("/events/message", message);
-    /// assert_eq!(new_event.build(), String::from("Hello world"));
-    /// ```
-    pub fn build(&self) -> String {
-        self.inner.clone()
-    }
+/// Starts the server on `127.0.0.1:3012` and returns a sender for publishing events to the
+/// connected clients. The event queue is unbounded.
+pub async fn build() -> Result<EventTx, Error> {
+    ServerBuilder::new().build().await
 }
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<SocketAddr, Tx>>>;
-
-pub type EventTx = UnboundedSender<Event>;
-
-async fn handle_connection(peer_map: PeerMap, raw_stream: TcpStream, addr: SocketAddr) {
-    let ws_stream = tokio_tungstenite::accept_async(raw_stream)
-        .await
-        .expect("Error during the websocket handshake occurred");
-
-    // Insert the write part of this peer to the peer map.
-    let (tx, rx) = unbounded();
-    peer_map.lock().unwrap().insert(addr, tx);
-
-    let (outgoing, incoming) = ws_stream.split();
-
-    let broadcast_incoming = incoming.try_for_each(|msg| {
-        future::ok(())
-    });
-
-    let receive_from_others = rx.map(Ok).forward(outgoing);
-
-    pin_mut!(broadcast_incoming, receive_from_others);
-    future::select(broadcast_incoming, receive_from_others).await;
-
-    peer_map.lock().unwrap().remove(&addr);
-}
-
-pub async fn build() -> Result<UnboundedSender<Event>, ()> {
-    let addr = "127.0.0.1:3012".to_string();
-    let state = PeerMap::new(Mutex::new(HashMap::new()));
-    let (tx, rx) = unbounded();
-
-    let listener = TcpListener::bind(&addr).await.expect("failed to bind");
-
-    let state_clone = state.clone();
-
-    tokio::spawn(async move {
-        while let Ok((stream, addr)) = listener.accept().await {
-            tokio::spawn(handle_connection(state_clone.clone(), stream, addr));
-        }
-    });
-
-    let broadcast_incoming = rx.for_each(move |msg: Event| {
-        let peers = state.lock().unwrap();
-
-        // We want to broadcast the message to everyone except ourselves.
-        let broadcast_recipients =
-            peers.iter().map(|(_, ws_sink)| ws_sink);
-
-        for recp in broadcast_recipients {
-            recp.unbounded_send(Message::text(msg.build()));
-        }
-
-        future::ready(())
-    });
-
-    tokio::spawn(broadcast_incoming);
-
-    Ok(tx)
+/// Same as [`build`] but the event queue holds at most `capacity` events, after which
+/// [`EventTx::send`] fails with [`Error::QueueFull`] until the broadcast loop catches up.
+pub async fn build_bounded(capacity: usize) -> Result<EventTx, Error> {
+    ServerBuilder::new().capacity(capacity).build().await
 }
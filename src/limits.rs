@@ -0,0 +1,148 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, PoisonError,
+};
+
+use crate::auth::Rejection;
+use crate::client::ClientInfo;
+use crate::pattern;
+use crate::route::{self, Routes};
+use crate::{Error, Event};
+
+/// Resources starting with this prefix are reserved for clients allowed by
+/// [`ServerBuilder::allow_reserved`](crate::server::ServerBuilder::allow_reserved).
+pub(crate) const RESERVED_PREFIX: &str = "/_pushevent/";
+
+/// The default of
+/// [`ServerBuilder::max_resource_len`](crate::server::ServerBuilder::max_resource_len).
+pub(crate) const DEFAULT_MAX_RESOURCE_LEN: usize = 1024;
+
+/// The default of [`ServerBuilder::max_payload`](crate::server::ServerBuilder::max_payload).
+pub(crate) const DEFAULT_MAX_PAYLOAD: usize = 1024 * 1024;
+
+pub(crate) type ClientPredicate = Arc<dyn Fn(&ClientInfo) -> bool + Send + Sync>;
+pub(crate) type ResourceValidator = Arc<dyn Fn(&ClientInfo, &str) -> bool + Send + Sync>;
+
+/// Which resources clients may subscribe to.
+#[derive(Clone)]
+pub(crate) struct ResourceLimits {
+    pub(crate) max_len: usize,
+    /// The number of resources a client may be subscribed to at once.
+    pub(crate) max_subscriptions: usize,
+    /// The clients that may subscribe to reserved resources, none if unset.
+    pub(crate) allow_reserved: Option<ClientPredicate>,
+    /// Whether clients may subscribe to patterns such as `/orders/*`.
+    pub(crate) allow_patterns: bool,
+    pub(crate) validator: Option<ResourceValidator>,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_len: DEFAULT_MAX_RESOURCE_LEN,
+            max_subscriptions: usize::MAX,
+            allow_reserved: None,
+            allow_patterns: false,
+            validator: None,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Checks the length of `res`, which is done before the upgrade request is authenticated.
+    pub(crate) fn check_len(&self, res: &str) -> Result<(), Rejection> {
+        if res.len() > self.max_len {
+            return Err(Rejection::uri_too_long(format!(
+                "resources are limited to {} bytes",
+                self.max_len
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether `client`, currently subscribed to `subscribed` resources, may subscribe to
+    /// `res` as well.
+    pub(crate) fn check(
+        &self,
+        client: &ClientInfo,
+        res: &str,
+        subscribed: usize,
+    ) -> Result<(), Rejection> {
+        self.check_len(res)?;
+
+        if subscribed >= self.max_subscriptions {
+            return Err(Rejection::too_many_requests(format!(
+                "clients are limited to {} subscriptions",
+                self.max_subscriptions
+            )));
+        }
+
+        if res.starts_with(RESERVED_PREFIX)
+            && !self.allow_reserved.as_ref().is_some_and(|f| f(client))
+        {
+            return Err(Rejection::forbidden(format!("{} is reserved", res)));
+        }
+
+        if !self.allow_patterns && pattern::is_pattern(res) {
+            return Err(Rejection::forbidden(format!(
+                "{} is a pattern, which isn't allowed",
+                res
+            )));
+        }
+
+        if !self.validator.as_ref().is_none_or(|f| f(client, res)) {
+            return Err(Rejection::forbidden(format!("{} is not allowed", res)));
+        }
+
+        Ok(())
+    }
+}
+
+/// How large the payloads of events may be, checked before they are queued and again before
+/// they are delivered, see [`ServerBuilder::max_payload`](crate::server::ServerBuilder::max_payload).
+pub(crate) struct PayloadLimit {
+    max: usize,
+    /// The routes of the server, which may override `max`.
+    routes: Routes,
+    /// The number of events rejected for being too large.
+    rejected: AtomicU64,
+}
+
+impl PayloadLimit {
+    pub(crate) fn new(max: usize, routes: Routes) -> Self {
+        Self {
+            max,
+            routes,
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Checks the size of the payload of `event`, counting it if it is too large. Also refuses
+    /// payloads that weren't valid UTF-8, see [`Event::payload_error`].
+    pub(crate) fn check(&self, event: &Event) -> Result<(), Error> {
+        if let Some(e) = event.payload_error() {
+            return Err(Error::Serialization(Box::new(e)));
+        }
+
+        let max = route::route(
+            &self.routes.read().unwrap_or_else(PoisonError::into_inner),
+            event.res(),
+        )
+        .and_then(|x| x.max_payload)
+        .unwrap_or(self.max);
+
+        let size = event.payload().len();
+        if size <= max {
+            return Ok(());
+        }
+
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        Err(Error::PayloadTooLarge { size, max })
+    }
+
+    /// Returns the number of events rejected for being too large.
+    pub(crate) fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,333 @@
+//! A synthetic load test opening many websocket connections to a server and measuring what
+//! they receive, for finding out how many subscribers a deployment holds before putting it in
+//! front of real clients.
+//!
+//! Enabled by the `loadtest` feature. The connections only subscribe, the events are whatever
+//! the server under test publishes in the meantime.
+//!
+//! Connections ask for version 2 of the protocol with `?protocol=2`, so that events arrive in
+//! envelopes carrying their id. Events that reached some connections of a resource but not
+//! others are counted as missed, see [`LoadReport::missed`].
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use futures_util::StreamExt;
+use tokio::time::Instant;
+use tungstenite::Message;
+
+/// How many ids a connection collects before adding them to the ids of its resource.
+const ID_BATCH: usize = 256;
+
+/// How long a single handshake may take before the connection counts as failed.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A load test, configured with the builder methods and started with [`run`](Self::run).
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+/// use pushevent::loadtest::LoadTest;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let report = LoadTest::new("ws://127.0.0.1:3012")
+///     .connections(20_000)
+///     .resources("/prices/{}")
+///     .resource_count(100)
+///     .ramp(Duration::from_secs(30))
+///     .duration(Duration::from_secs(60))
+///     .run()
+///     .await;
+///
+/// println!("{}", report);
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadTest {
+    url: String,
+    connections: usize,
+    resources: String,
+    resource_count: usize,
+    ramp: Duration,
+    duration: Duration,
+}
+
+impl LoadTest {
+    /// Returns a load test against the server listening on `url`, e.g. `ws://10.0.0.7:3012`,
+    /// opening 100 connections to `/` at once and keeping them open for 10 seconds.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            connections: 100,
+            resources: "/".to_string(),
+            resource_count: 1,
+            ramp: Duration::ZERO,
+            duration: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets the number of connections to open.
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    /// Sets the resource the connections subscribe to. A `{}` in `pattern` is replaced by the
+    /// index of the connection modulo [`resource_count`](Self::resource_count), which spreads
+    /// the connections over that many resources.
+    pub fn resources(mut self, pattern: impl Into<String>) -> Self {
+        self.resources = pattern.into();
+        self
+    }
+
+    /// Sets the number of resources the connections are spread over, see
+    /// [`resources`](Self::resources). Defaults to 1.
+    pub fn resource_count(mut self, count: usize) -> Self {
+        self.resource_count = count.max(1);
+        self
+    }
+
+    /// Spreads opening the connections evenly over `ramp` instead of opening all of them at
+    /// once.
+    pub fn ramp(mut self, ramp: Duration) -> Self {
+        self.ramp = ramp;
+        self
+    }
+
+    /// Sets how long the connections are kept open once the ramp ended.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Opens the connections, receives events until the ramp and the duration passed, then
+    /// closes them and reports what happened. Connections are tasks on the current runtime,
+    /// a multi-threaded one spreads them over its workers.
+    pub async fn run(self) -> LoadReport {
+        let start = Instant::now();
+        let end = start + self.ramp + self.duration;
+        let ids: Arc<Mutex<HashMap<String, BTreeSet<u64>>>> = Arc::default();
+
+        let tasks: Vec<_> = (0..self.connections)
+            .map(|i| {
+                let resource = self
+                    .resources
+                    .replace("{}", &(i % self.resource_count).to_string());
+                let query = if resource.contains('?') { '&' } else { '?' };
+                let url = format!("{}{}{}protocol=2", self.url, resource, query);
+                let connect_at = start + self.ramp.mul_f64(i as f64 / self.connections as f64);
+
+                tokio::spawn(connection(url, resource, connect_at, end, ids.clone()))
+            })
+            .collect();
+
+        let mut report = LoadReport::default();
+        let mut handshakes = Vec::with_capacity(self.connections);
+        let mut received = Vec::with_capacity(self.connections);
+        for task in tasks {
+            match task.await {
+                Ok(Some(x)) => {
+                    handshakes.push(x.handshake);
+                    report.messages += x.messages;
+                    received.push(x);
+                }
+                // Panicked or failed to connect.
+                _ => report.failed += 1,
+            }
+        }
+        report.connected = handshakes.len() as u64;
+
+        let ids = ids.lock().unwrap_or_else(PoisonError::into_inner);
+        report.missed = received
+            .iter()
+            .filter_map(|x| Some((ids.get(&x.resource)?, x.ids?, x.events)))
+            .map(|(all, (first, last), events)| {
+                (all.range(first..=last).count() as u64).saturating_sub(events)
+            })
+            .sum();
+
+        handshakes.sort_unstable();
+        let percentile = |p: usize| {
+            handshakes
+                .get((handshakes.len() * p / 100).min(handshakes.len().saturating_sub(1)))
+                .copied()
+                .unwrap_or_default()
+        };
+        report.handshake_p50 = percentile(50);
+        report.handshake_p90 = percentile(90);
+        report.handshake_p99 = percentile(99);
+        report.messages_per_sec = report.messages as f64 / start.elapsed().as_secs_f64();
+
+        report
+    }
+}
+
+/// The results of a [`LoadTest`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct LoadReport {
+    /// The number of connections that completed the handshake.
+    pub connected: u64,
+    /// The number of connections that couldn't be opened.
+    pub failed: u64,
+    /// The median time the handshakes took.
+    pub handshake_p50: Duration,
+    /// The 90th percentile of the time the handshakes took.
+    pub handshake_p90: Duration,
+    /// The 99th percentile of the time the handshakes took.
+    pub handshake_p99: Duration,
+    /// The number of events received by all connections together.
+    pub messages: u64,
+    /// The number of events received per second by all connections together.
+    pub messages_per_sec: f64,
+    /// The number of events a connection didn't receive although other connections to the
+    /// same resource did, while it was open. Events no connection received aren't noticed,
+    /// and neither are events sent to clients speaking protocol version 1, which carry no id.
+    pub missed: u64,
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} connected, {} failed, handshake p50 {:?} p90 {:?} p99 {:?}, \
+             {} events received ({:.1}/s), {} missed",
+            self.connected,
+            self.failed,
+            self.handshake_p50,
+            self.handshake_p90,
+            self.handshake_p99,
+            self.messages,
+            self.messages_per_sec,
+            self.missed
+        )
+    }
+}
+
+/// What a single connection received.
+struct Received {
+    resource: String,
+    handshake: Duration,
+    /// Every frame carrying events, counting the events of a batch one by one.
+    messages: u64,
+    /// The number of events that carried an id.
+    events: u64,
+    /// The first and last id received.
+    ids: Option<(u64, u64)>,
+}
+
+/// Connects to `url` at `connect_at` and receives events until `end`. Returns `None` if the
+/// connection couldn't be opened.
+async fn connection(
+    url: String,
+    resource: String,
+    connect_at: Instant,
+    end: Instant,
+    ids: Arc<Mutex<HashMap<String, BTreeSet<u64>>>>,
+) -> Option<Received> {
+    tokio::time::sleep_until(connect_at).await;
+
+    let started = Instant::now();
+    let (mut client, _) =
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, tokio_tungstenite::connect_async(url))
+            .await
+            .ok()?
+            .ok()?;
+
+    let mut received = Received {
+        resource,
+        handshake: started.elapsed(),
+        messages: 0,
+        events: 0,
+        ids: None,
+    };
+    let mut batch = Vec::with_capacity(ID_BATCH);
+    let flush = |batch: &mut Vec<u64>, resource: &str| {
+        ids.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(resource.to_string())
+            .or_default()
+            .extend(batch.drain(..));
+    };
+
+    let receive = async {
+        while let Some(Ok(frame)) = client.next().await {
+            let frame = match &frame {
+                Message::Text(x) => x.as_str(),
+                _ => continue,
+            };
+
+            let (messages, frame_ids) = parse_frame(frame);
+            received.messages += messages;
+            for id in frame_ids {
+                received.events += 1;
+                received.ids = Some(match received.ids {
+                    Some((first, _)) => (first, id),
+                    None => (id, id),
+                });
+                batch.push(id);
+            }
+
+            if batch.len() >= ID_BATCH {
+                flush(&mut batch, &received.resource);
+            }
+        }
+    };
+    let _ = tokio::time::timeout_at(end, receive).await;
+    flush(&mut batch, &received.resource);
+
+    let _ = client.close(None).await;
+    Some(received)
+}
+
+/// Returns the number of events in `frame`, and the ids of those that carry one. Frames that
+/// aren't events, such as the draining notice, count as none.
+fn parse_frame(frame: &str) -> (u64, Vec<u64>) {
+    const ENVELOPE: &str = r#"{"type":"event","#;
+
+    // Payloads are escaped inside an envelope, so this only matches envelopes.
+    let ids: Vec<_> = frame
+        .match_indices(ENVELOPE)
+        .filter_map(|(i, _)| {
+            let rest = frame[i + ENVELOPE.len()..].strip_prefix(r#""id":"#)?;
+            let end = rest.find(|c: char| !c.is_ascii_digit())?;
+            rest[..end].parse().ok()
+        })
+        .collect();
+
+    match frame.matches(ENVELOPE).count() {
+        // A version 1 payload, or a notice.
+        0 if frame.starts_with(r#"{"type":"#) => (0, ids),
+        0 => (1, ids),
+        n => (n as u64, ids),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_counted_by_their_events() {
+        assert_eq!(
+            parse_frame(r#"{"type":"event","id":7,"resource":"/a","payload":"x"}"#),
+            (1, vec![7])
+        );
+        assert_eq!(
+            parse_frame(
+                r#"[{"type":"event","id":8,"resource":"/a","payload":"{\"type\":\"event\",\"id\":1}"},{"type":"event","id":9,"resource":"/a","payload":"y"}]"#
+            ),
+            (2, vec![8, 9])
+        );
+        assert_eq!(
+            parse_frame(r#"{"type":"draining","reconnect_after_ms":0}"#),
+            (0, vec![])
+        );
+        assert_eq!(parse_frame("raw payload"), (1, vec![]));
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, PoisonError, RwLock, Weak,
+    },
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::sync::mpsc;
+
+use crate::{pattern, Event};
+
+/// How many payloads a local subscription holds while it isn't read, newer ones are dropped.
+const LOCAL_CAPACITY: usize = 1024;
+
+/// Subscribers living in the same process as the server, see
+/// [`Server::subscribe_local`](crate::server::Server::subscribe_local).
+#[derive(Default)]
+pub(crate) struct LocalSubscribers {
+    next_id: AtomicU64,
+    /// (id, resource or pattern, queue) of every subscriber.
+    subscribers: RwLock<Vec<(u64, String, mpsc::Sender<String>)>>,
+}
+
+impl LocalSubscribers {
+    pub(crate) fn subscribe(self: &Arc<Self>, res: &str) -> LocalSubscription {
+        let (tx, rx) = mpsc::channel(LOCAL_CAPACITY);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((id, res.to_string(), tx));
+
+        LocalSubscription {
+            rx,
+            id,
+            subscribers: Arc::downgrade(self),
+        }
+    }
+
+    /// Hands the payload of `event` to the subscribers of its resource. Subscribers that don't
+    /// keep up miss the events published while their queue is full.
+    pub(crate) fn publish(&self, event: &Event) {
+        for (_, _, tx) in self
+            .subscribers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(_, res, _)| pattern::matches(res, event.res()))
+        {
+            // Full while the subscription isn't read, closed while it is being dropped.
+            let _ = tx.try_send(event.payload().to_string());
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        self.subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(x, _, _)| *x != id);
+    }
+
+    /// Ends every subscription, once the server shut down.
+    pub(crate) fn close(&self) {
+        self.subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+/// A stream of the payloads of the events published to a resource, returned by
+/// [`Server::subscribe_local`](crate::server::Server::subscribe_local).
+///
+/// The stream ends once the server shut down. Dropping it unsubscribes.
+#[derive(Debug)]
+pub struct LocalSubscription {
+    rx: mpsc::Receiver<String>,
+    id: u64,
+    subscribers: Weak<LocalSubscribers>,
+}
+
+impl Stream for LocalSubscription {
+    type Item = String;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<String>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for LocalSubscription {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_subscriptions_are_removed_right_away() {
+        let subscribers = Arc::new(LocalSubscribers::default());
+        let prices = subscribers.subscribe("/prices/*");
+        let orders = subscribers.subscribe("/orders");
+
+        drop(prices);
+        let remaining = subscribers.subscribers.read().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].1, "/orders");
+        drop(remaining);
+
+        drop(orders);
+        assert!(subscribers.subscribers.read().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_subscriptions_drop_new_payloads() {
+        use futures_util::StreamExt;
+
+        let subscribers = Arc::new(LocalSubscribers::default());
+        let mut prices = subscribers.subscribe("/prices/*");
+
+        for i in 0..LOCAL_CAPACITY + 1 {
+            subscribers.publish(&Event::from_string("/prices/btc", i.to_string()));
+        }
+        subscribers.close();
+
+        assert_eq!(prices.next().await.as_deref(), Some("0"));
+        assert_eq!(prices.count().await, LOCAL_CAPACITY - 1);
+    }
+}
@@ -0,0 +1,133 @@
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, Message};
+
+/// A single websocket data frame, as sent to or received from a client.
+///
+/// # Example
+/// ```
+/// use pushevent::Payload;
+///
+/// assert_eq!(Payload::from("hello"), Payload::Text("hello".to_string()));
+/// assert_eq!(Payload::from(vec![1, 2]), Payload::Binary(vec![1, 2]));
+/// assert_eq!(Payload::from("hello").len(), 5);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// A UTF-8 text frame.
+    Text(String),
+    /// A binary frame.
+    Binary(Vec<u8>),
+}
+
+impl Payload {
+    /// Returns the size of the payload in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Text(x) => x.len(),
+            Self::Binary(x) => x.len(),
+        }
+    }
+
+    /// Returns whether the payload is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the payload of a data frame, `None` for control frames.
+    pub(crate) fn from_message(message: Message) -> Option<Self> {
+        match message {
+            Message::Text(x) => Some(Self::Text(x.as_str().to_string())),
+            Message::Binary(x) => Some(Self::Binary(x.to_vec())),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for Payload {
+    fn from(x: String) -> Self {
+        Self::Text(x)
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(x: &str) -> Self {
+        Self::Text(x.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(x: Vec<u8>) -> Self {
+        Self::Binary(x)
+    }
+}
+
+/// Why a connection is being closed, sent to the client in the close frame.
+///
+/// # Example
+/// ```
+/// use pushevent::CloseReason;
+///
+/// let reason = CloseReason::policy("too many subscriptions");
+///
+/// assert_eq!(reason.code(), 1008);
+/// assert_eq!(reason.reason(), "too many subscriptions");
+/// assert_eq!(CloseReason::new(4000, "custom").code(), 4000);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    code: u16,
+    reason: String,
+}
+
+impl CloseReason {
+    /// Returns a close reason with an arbitrary status code, see RFC 6455 section 7.4 for the
+    /// meaning of the predefined codes. Applications should use codes in the 4000-4999 range.
+    pub fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// 1000, the connection fulfilled its purpose.
+    pub fn normal() -> Self {
+        Self::new(1000, "")
+    }
+
+    /// 1001, the server is going away.
+    pub fn going_away() -> Self {
+        Self::new(1001, "")
+    }
+
+    /// 1008, the client violated a policy of the server.
+    pub fn policy(reason: impl Into<String>) -> Self {
+        Self::new(1008, reason)
+    }
+
+    /// 1008, the client doesn't read the events sent to it fast enough, see
+    /// [`ServerBuilder::write_timeout`](crate::server::ServerBuilder::write_timeout).
+    pub fn slow_consumer() -> Self {
+        Self::policy("slow consumer")
+    }
+
+    /// 1012, the server is restarting and the client should reconnect.
+    pub fn service_restart() -> Self {
+        Self::new(1012, "service restart")
+    }
+
+    /// Returns the status code sent in the close frame.
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// Returns the human readable reason sent in the close frame.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub(crate) fn into_message(self) -> Message {
+        Message::Close(Some(CloseFrame {
+            code: CloseCode::from(self.code),
+            reason: self.reason.into(),
+        }))
+    }
+}
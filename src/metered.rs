@@ -0,0 +1,120 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{Error, Event, EventTx};
+
+/// A sender counting the events it publishes, returned by
+/// [`EventTxExt::meter`](crate::EventTxExt::meter).
+///
+/// The sender is cheap to clone, all clones share the same counters.
+#[derive(Clone)]
+pub struct MeteredEventTx {
+    tx: EventTx,
+    name: Arc<str>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    events_sent: AtomicU64,
+    events_failed: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+/// The counters of a [`MeteredEventTx`], see [`MeteredEventTx::report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TxMetrics {
+    /// The name the sender was created with.
+    pub name: String,
+    /// The number of events queued.
+    pub events_sent: u64,
+    /// The number of events that failed to queue.
+    pub events_failed: u64,
+    /// The size of the payloads of the events queued in bytes.
+    pub bytes_sent: u64,
+}
+
+impl MeteredEventTx {
+    pub(crate) fn new(tx: EventTx, name: &str) -> Self {
+        Self {
+            tx,
+            name: name.into(),
+            counters: Arc::default(),
+        }
+    }
+
+    /// Queues an event for broadcast like [`EventTx::send`], counting it.
+    pub fn send(&self, event: Event) -> Result<(), Error> {
+        let len = event.payload().len() as u64;
+        let sent = self.tx.send(event);
+        self.count(&sent, 1, len);
+
+        sent
+    }
+
+    /// Queues several events at once like [`EventTx::publish_batch`], counting them.
+    pub fn publish_batch(&self, events: Vec<Event>) -> Result<(), Error> {
+        let count = events.len() as u64;
+        let len = events.iter().map(|x| x.payload().len() as u64).sum();
+        let sent = self.tx.publish_batch(events);
+        self.count(&sent, count, len);
+
+        sent
+    }
+
+    /// Returns the name the sender was created with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the current value of every counter.
+    pub fn report(&self) -> TxMetrics {
+        TxMetrics {
+            name: self.name.to_string(),
+            events_sent: self.counters.events_sent.load(Ordering::Relaxed),
+            events_failed: self.counters.events_failed.load(Ordering::Relaxed),
+            bytes_sent: self.counters.bytes_sent.load(Ordering::Relaxed),
+        }
+    }
+
+    fn count(&self, sent: &Result<(), Error>, events: u64, bytes: u64) {
+        match sent {
+            Ok(()) => {
+                self.counters
+                    .events_sent
+                    .fetch_add(events, Ordering::Relaxed);
+                self.counters.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.counters
+                    .events_failed
+                    .fetch_add(events, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MeteredEventTx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MeteredEventTx")
+            .field("name", &self.name)
+            .field("metrics", &self.report())
+            .finish()
+    }
+}
+
+impl fmt::Display for TxMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: sent {} events ({} bytes), {} failed",
+            self.name, self.events_sent, self.bytes_sent, self.events_failed
+        )
+    }
+}
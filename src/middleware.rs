@@ -0,0 +1,404 @@
+//! Composable handlers for the websocket upgrade request.
+//!
+//! Middleware runs inside the handshake, in the order it was added with
+//! [`ServerBuilder::middleware`](crate::server::ServerBuilder::middleware), before the
+//! server's [authenticator](crate::auth::Authenticator). Each middleware either rejects the
+//! request or calls the rest of the stack through `next`, and may add headers to the response it
+//! gets back. Middleware is also told when clients connect and disconnect and when events are
+//! broadcast, which does nothing unless it overrides the corresponding methods.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::auth::{Authenticator, Rejection};
+use crate::{ClientInfo, Request};
+
+/// Handles a websocket upgrade request, see the [module documentation](self).
+///
+/// # Example
+/// ```
+/// use pushevent::auth::Rejection;
+/// use pushevent::middleware::{RequestMiddleware, Response};
+/// use pushevent::Request;
+///
+/// /// Tells clients which instance they are connected to.
+/// struct Instance(&'static str);
+///
+/// impl RequestMiddleware for Instance {
+///     fn handle(
+///         &self,
+///         req: &Request,
+///         next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+///     ) -> Result<Response, Rejection> {
+///         next(req).map(|res| res.with_header("x-instance", self.0))
+///     }
+/// }
+///
+/// let res = Instance("eu-1")
+///     .handle(&Request::new("/events"), &|_| Ok(Response::new()))
+///     .unwrap();
+/// assert_eq!(res.header("x-instance"), Some("eu-1"));
+/// ```
+pub trait RequestMiddleware: Send + Sync + 'static {
+    /// Handles `req`, calling `next` with it to run the rest of the stack. Returning an error
+    /// rejects the request with the rejection's status and reason.
+    fn handle(
+        &self,
+        req: &Request,
+        next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+    ) -> Result<Response, Rejection>;
+
+    /// Called once `client` completed the handshake and is subscribed, on the connection's
+    /// task, so it should return quickly.
+    fn on_connect(&self, client: &ClientInfo) {
+        let _ = client;
+    }
+
+    /// Called once `client` disconnected, `connected_for` after it connected.
+    fn on_disconnect(&self, client: &ClientInfo, connected_for: Duration) {
+        let _ = (client, connected_for);
+    }
+
+    /// Called once an event published to `res` was handed to its `subscribers`, `bytes` being
+    /// the size of the frames sent. Runs on the broadcast loop, so it should return quickly.
+    fn on_broadcast(&self, res: &str, subscribers: usize, bytes: u64) {
+        let _ = (res, subscribers, bytes);
+    }
+}
+
+/// The headers added to the response of an accepted upgrade request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Response {
+    /// (lowercase name, value) in the order they were added.
+    headers: Vec<(String, String)>,
+}
+
+impl Response {
+    /// Returns a response without any headers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a header to the response.
+    pub fn with_header(mut self, name: impl AsRef<str>, value: impl Into<String>) -> Self {
+        self.headers
+            .push((name.as_ref().to_ascii_lowercase(), value.into()));
+        self
+    }
+
+    /// Returns the value of the first header called `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all headers as (lowercase name, value) pairs.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// Middleware run in order, itself usable as a single middleware.
+#[derive(Clone, Default)]
+pub struct MiddlewareStack {
+    stack: Vec<Arc<dyn RequestMiddleware>>,
+}
+
+impl MiddlewareStack {
+    /// Returns an empty stack, which accepts every request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `middleware` to the end of the stack.
+    pub fn with(mut self, middleware: impl RequestMiddleware) -> Self {
+        self.push(middleware);
+        self
+    }
+
+    /// Adds `middleware` to the end of the stack.
+    pub fn push(&mut self, middleware: impl RequestMiddleware) {
+        self.stack.push(Arc::new(middleware));
+    }
+
+    /// Returns the number of middleware in the stack.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns whether the stack is empty.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+fn run(
+    stack: &[Arc<dyn RequestMiddleware>],
+    req: &Request,
+    end: &dyn Fn(&Request) -> Result<Response, Rejection>,
+) -> Result<Response, Rejection> {
+    match stack.split_first() {
+        Some((first, rest)) => first.handle(req, &|req| run(rest, req, end)),
+        None => end(req),
+    }
+}
+
+impl RequestMiddleware for MiddlewareStack {
+    fn handle(
+        &self,
+        req: &Request,
+        next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+    ) -> Result<Response, Rejection> {
+        run(&self.stack, req, next)
+    }
+
+    fn on_connect(&self, client: &ClientInfo) {
+        for middleware in &self.stack {
+            middleware.on_connect(client);
+        }
+    }
+
+    fn on_disconnect(&self, client: &ClientInfo, connected_for: Duration) {
+        for middleware in &self.stack {
+            middleware.on_disconnect(client, connected_for);
+        }
+    }
+
+    fn on_broadcast(&self, res: &str, subscribers: usize, bytes: u64) {
+        for middleware in &self.stack {
+            middleware.on_broadcast(res, subscribers, bytes);
+        }
+    }
+}
+
+impl fmt::Debug for MiddlewareStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareStack")
+            .field("len", &self.stack.len())
+            .finish()
+    }
+}
+
+/// Only lets browsers on the given origins connect.
+///
+/// Browsers send the page's origin in the `Origin` header of the upgrade request, and unlike
+/// for HTTP requests don't enforce any cross-origin policy for websockets themselves. Requests
+/// without an `Origin` header, which come from clients other than browsers, are let through.
+///
+/// # Example
+/// ```
+/// use pushevent::middleware::{CorsMiddleware, RequestMiddleware, Response};
+/// use pushevent::Request;
+///
+/// let cors = CorsMiddleware::new(["https://example.com"]);
+/// let next = |_: &Request| Ok(Response::new());
+///
+/// let req = Request::new("/events").with_header("origin", "https://example.com");
+/// let res = cors.handle(&req, &next).unwrap();
+/// assert_eq!(res.header("access-control-allow-origin"), Some("https://example.com"));
+///
+/// let req = Request::new("/events").with_header("origin", "https://evil.example");
+/// assert_eq!(cors.handle(&req, &next).unwrap_err().status(), 403);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CorsMiddleware {
+    origins: HashSet<String>,
+}
+
+impl CorsMiddleware {
+    /// Allows the origins in `origins`, e.g. `https://example.com`.
+    pub fn new(origins: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            origins: origins.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl RequestMiddleware for CorsMiddleware {
+    fn handle(
+        &self,
+        req: &Request,
+        next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+    ) -> Result<Response, Rejection> {
+        let origin = match req.header("origin") {
+            Some(x) => x,
+            None => return next(req),
+        };
+
+        if !self.origins.contains(origin) {
+            return Err(Rejection::forbidden(format!(
+                "origin {} is not allowed",
+                origin
+            )));
+        }
+
+        next(req).map(|res| res.with_header("access-control-allow-origin", origin))
+    }
+}
+
+/// Runs an [`Authenticator`] as part of the middleware stack, for when it has to run before or
+/// after other middleware.
+#[derive(Debug, Clone)]
+pub struct AuthMiddleware<A> {
+    authenticator: A,
+}
+
+impl<A: Authenticator> AuthMiddleware<A> {
+    /// Returns middleware rejecting the requests refused by `authenticator`.
+    pub fn new(authenticator: A) -> Self {
+        Self { authenticator }
+    }
+}
+
+impl<A: Authenticator> RequestMiddleware for AuthMiddleware<A> {
+    fn handle(
+        &self,
+        req: &Request,
+        next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+    ) -> Result<Response, Rejection> {
+        self.authenticator.authenticate(req)?;
+        next(req)
+    }
+}
+
+/// Limits how many connections a single IP address may open, rejecting the rest with
+/// `429 Too Many Requests`.
+///
+/// Requests are counted in fixed windows: every address may open up to `max` connections per
+/// window, the counts are reset when a new window starts.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use pushevent::middleware::{RateLimitMiddleware, RequestMiddleware, Response};
+/// use pushevent::Request;
+///
+/// let limit = RateLimitMiddleware::new(2, Duration::from_secs(60));
+/// let next = |_: &Request| Ok(Response::new());
+/// let req = Request::new("/events").with_remote_addr("10.0.0.1:5000".parse().unwrap());
+///
+/// assert!(limit.handle(&req, &next).is_ok());
+/// assert!(limit.handle(&req, &next).is_ok());
+/// assert_eq!(limit.handle(&req, &next).unwrap_err().status(), 429);
+/// ```
+#[derive(Debug)]
+pub struct RateLimitMiddleware {
+    max: u32,
+    window: Duration,
+    state: Mutex<RateLimitWindow>,
+}
+
+#[derive(Debug)]
+struct RateLimitWindow {
+    started: Instant,
+    /// Requests without a remote address share the `None` count.
+    counts: HashMap<Option<IpAddr>, u32>,
+}
+
+impl RateLimitMiddleware {
+    /// Allows every IP address `max` connections per `window`.
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            state: Mutex::new(RateLimitWindow {
+                started: Instant::now(),
+                counts: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl RequestMiddleware for RateLimitMiddleware {
+    fn handle(
+        &self,
+        req: &Request,
+        next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+    ) -> Result<Response, Rejection> {
+        {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.started.elapsed() >= self.window {
+                state.started = Instant::now();
+                state.counts.clear();
+            }
+
+            let count = state
+                .counts
+                .entry(req.remote_addr().map(|x| x.ip()))
+                .or_default();
+            if *count >= self.max {
+                return Err(Rejection::too_many_requests("too many connection attempts"));
+            }
+            *count += 1;
+        }
+
+        next(req)
+    }
+}
+
+/// Logs every upgrade request and whether it was accepted, and the connections and broadcasts
+/// that follow as structured [`tracing`] events: `client connected` and `client disconnected` at
+/// the info level, and `event broadcast` at the debug level.
+#[derive(Debug, Clone, Default)]
+pub struct LoggingMiddleware;
+
+impl LoggingMiddleware {
+    /// Returns a new logging middleware.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl RequestMiddleware for LoggingMiddleware {
+    fn handle(
+        &self,
+        req: &Request,
+        next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+    ) -> Result<Response, Rejection> {
+        let addr = req
+            .remote_addr()
+            .map_or_else(|| String::from("unknown address"), |x| x.to_string());
+        let res = next(req);
+
+        match &res {
+            Ok(_) => tracing::info!("{}: accepted upgrade request for {}", addr, req.path()),
+            Err(e) => tracing::info!(
+                "{}: rejected upgrade request for {}: {}",
+                addr,
+                req.path(),
+                e
+            ),
+        }
+
+        res
+    }
+
+    fn on_connect(&self, client: &ClientInfo) {
+        tracing::info!(
+            client_id = %client.id,
+            addr = %client.addr,
+            resource = %client.resource,
+            "client connected"
+        );
+    }
+
+    fn on_disconnect(&self, client: &ClientInfo, connected_for: Duration) {
+        tracing::info!(
+            client_id = %client.id,
+            duration_secs = connected_for.as_secs_f64(),
+            "client disconnected"
+        );
+    }
+
+    fn on_broadcast(&self, res: &str, subscribers: usize, bytes: u64) {
+        tracing::debug!(resource = res, subscribers, bytes, "event broadcast");
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fmt, sync::Arc};
+
+use crate::{Error, Event, EventTx};
+
+/// Something events can be published to, such as the [`EventTx`] of a server or a bridge to a
+/// message broker.
+///
+/// # Example
+/// ```
+/// use pushevent::{Error, Event, PublishTarget};
+/// use std::sync::Mutex;
+///
+/// /// Keeps every published event, e.g. to forward them to another system later.
+/// #[derive(Default)]
+/// struct Recorder(Mutex<Vec<Event>>);
+///
+/// impl PublishTarget for Recorder {
+///     fn publish(&self, event: Event) -> Result<(), Error> {
+///         self.0.lock().unwrap().push(event);
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait PublishTarget: Send + Sync + 'static {
+    /// Publishes `event`, returning an error if it could not be delivered.
+    fn publish(&self, event: Event) -> Result<(), Error>;
+}
+
+impl PublishTarget for EventTx {
+    fn publish(&self, event: Event) -> Result<(), Error> {
+        self.send(event)
+    }
+}
+
+/// Publishes every event to several targets, for example to servers listening on different
+/// addresses.
+///
+/// Events are published to the targets in the order they were added. A failing target doesn't
+/// prevent the event from being published to the remaining ones, its error is reported in the
+/// [`MultiPublishError`] returned once all targets were tried.
+///
+/// # Example
+/// ```
+/// use pushevent::server::ServerBuilder;
+/// use pushevent::{Event, MultiPublisher, SerializableEvent};
+///
+/// struct Message;
+///
+/// impl SerializableEvent for Message {
+///     fn serialize(&self) -> String {
+///         String::from("Hello world")
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let public = ServerBuilder::new().addr("127.0.0.1:0").build().await.unwrap();
+/// let internal = ServerBuilder::new().addr("127.0.0.1:0").build().await.unwrap();
+///
+/// let publisher = MultiPublisher::new().with_target(public).with_target(internal);
+///
+/// assert_eq!(publisher.len(), 2);
+/// assert!(publisher.publish(Event::new("/events", Message)).is_ok());
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct MultiPublisher {
+    targets: Vec<Arc<dyn PublishTarget>>,
+}
+
+impl MultiPublisher {
+    /// Returns a publisher without any targets.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a target events are published to.
+    pub fn with_target(mut self, target: impl PublishTarget) -> Self {
+        self.add_target(target);
+        self
+    }
+
+    /// Adds a target events are published to.
+    pub fn add_target(&mut self, target: impl PublishTarget) {
+        self.targets.push(Arc::new(target));
+    }
+
+    /// Returns the number of targets.
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    /// Returns whether there are no targets.
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Publishes `event` to all targets. Publishing to no targets at all succeeds.
+    pub fn publish(&self, event: Event) -> Result<(), MultiPublishError> {
+        let errors = self
+            .targets
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, target)| target.publish(event.clone()).err().map(|e| (idx, e)))
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(MultiPublishError {
+                targets: self.targets.len(),
+                errors,
+            })
+        }
+    }
+}
+
+impl fmt::Debug for MultiPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiPublisher")
+            .field("targets", &self.targets.len())
+            .finish()
+    }
+}
+
+/// The targets a [`MultiPublisher`] failed to publish an event to.
+#[derive(Debug)]
+pub struct MultiPublishError {
+    targets: usize,
+    errors: Vec<(usize, Error)>,
+}
+
+impl MultiPublishError {
+    /// Returns the index of every failed target, in the order the targets were added, together
+    /// with its error.
+    pub fn errors(&self) -> &[(usize, Error)] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for MultiPublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to publish to {} of {} targets",
+            self.errors.len(),
+            self.targets
+        )?;
+
+        for (idx, e) in &self.errors {
+            write!(f, "; target {}: {}", idx, e)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for MultiPublishError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.errors.first().map(|(_, e)| e as _)
+    }
+}
@@ -0,0 +1,43 @@
+//! Senders that aren't connected to a server, for tests and configurations with push events
+//! disabled.
+//!
+//! Both return a regular [`EventTx`], so code publishing events doesn't need to special case a
+//! missing server. They differ in what publishing reports:
+//!
+//! * [`null_tx`] behaves like the sender of a server that is gone, every send fails with
+//!   [`Error::ChannelClosed`](crate::Error::ChannelClosed). Use it to exercise error handling.
+//! * [`sink_tx`] accepts and discards every event, every send succeeds. Use it to turn push
+//!   events off without any of the publishing code noticing.
+//!
+//! # Example
+//! ```
+//! use pushevent::{noop, Error, Event, SerializableEvent};
+//!
+//! struct Message;
+//!
+//! impl SerializableEvent for Message {
+//!     fn serialize(&self) -> String {
+//!         String::from("Hello world")
+//!     }
+//! }
+//!
+//! assert!(noop::sink_tx().send(Event::new("/events", Message)).is_ok());
+//! assert!(matches!(
+//!     noop::null_tx().send(Event::new("/events", Message)),
+//!     Err(Error::ChannelClosed)
+//! ));
+//! ```
+
+use crate::tx::{self, EventTx};
+
+/// Returns a sender whose receiver has already been dropped, every send fails with
+/// [`Error::ChannelClosed`](crate::Error::ChannelClosed).
+pub fn null_tx() -> EventTx {
+    let (tx, _) = tx::unbounded();
+    tx
+}
+
+/// Returns a sender that accepts every event and discards it.
+pub fn sink_tx() -> EventTx {
+    tx::sink()
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, DecodingKey, Validation};
+use serde::Deserialize;
+
+use crate::auth::{Authenticator, Rejection, Request};
+
+/// How often the key set is downloaded again.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long a successfully validated token is trusted without checking its signature again.
+const TOKEN_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Authenticator validating OAuth2 bearer tokens (JWTs) sent in the `Authorization` header of the
+/// websocket upgrade request.
+///
+/// The signing keys are downloaded from `jwks_url` when the authenticator is created and
+/// refreshed every hour in the background. Tokens must be signed by one of those keys, must not
+/// be expired and must carry the configured audience and issuer, otherwise the request is
+/// rejected with `401 Unauthorized`. Validated tokens are cached for 60 seconds.
+///
+/// # Example
+/// ```no_run
+/// use pushevent::oauth::OAuthAuthenticator;
+/// use pushevent::server::ServerBuilder;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let authenticator = OAuthAuthenticator::new(
+///     "https://auth.example.com/.well-known/jwks.json",
+///     "pushevent",
+///     "https://auth.example.com/",
+/// )
+/// .await
+/// .unwrap();
+///
+/// let tx = ServerBuilder::new()
+///     .authenticator(authenticator)
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub struct OAuthAuthenticator {
+    jwks_url: String,
+    audience: String,
+    issuer: String,
+    keys: Arc<RwLock<JwkSet>>,
+    /// token -> instant until which it is considered valid without re-validation.
+    cache: Mutex<HashMap<String, Instant>>,
+}
+
+/// Error returned when the key set can't be downloaded.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to download jwks: {0}")]
+pub struct OAuthError(#[source] reqwest::Error);
+
+#[derive(Deserialize)]
+struct Claims {
+    exp: u64,
+}
+
+impl OAuthAuthenticator {
+    /// Downloads the key set from `jwks_url` and returns an authenticator accepting tokens for
+    /// `audience` issued by `issuer`. Must be called from within a tokio runtime, which the hourly
+    /// refresh task is spawned on.
+    pub async fn new(
+        jwks_url: impl Into<String>,
+        audience: impl Into<String>,
+        issuer: impl Into<String>,
+    ) -> Result<Self, OAuthError> {
+        let jwks_url = jwks_url.into();
+        let client = reqwest::Client::new();
+        let keys = Arc::new(RwLock::new(fetch_jwks(&client, &jwks_url).await?));
+
+        tokio::spawn(refresh_loop(
+            client,
+            jwks_url.clone(),
+            Arc::downgrade(&keys),
+        ));
+
+        Ok(Self {
+            jwks_url,
+            audience: audience.into(),
+            issuer: issuer.into(),
+            keys,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the url the key set is downloaded from.
+    pub fn jwks_url(&self) -> &str {
+        &self.jwks_url
+    }
+
+    /// Validates `token`, returning its expiry time as a unix timestamp.
+    fn validate(&self, token: &str) -> Result<u64, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+        let keys = self.keys.read().unwrap();
+
+        let jwk = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None => keys.keys.first(),
+        }
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.audience]);
+        validation.set_issuer(&[&self.issuer]);
+
+        let data = decode::<Claims>(token, &DecodingKey::from_jwk(jwk)?, &validation)?;
+
+        Ok(data.claims.exp)
+    }
+
+    fn is_cached(&self, token: &str) -> bool {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(token)
+            .is_some_and(|x| *x > Instant::now())
+    }
+
+    fn cache(&self, token: &str, exp: u64) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        // Never trust a cached token past its own expiry.
+        let ttl = TOKEN_CACHE_TTL.min(Duration::from_secs(exp.saturating_sub(now)));
+        let now = Instant::now();
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, x| *x > now);
+        cache.insert(token.to_string(), now + ttl);
+    }
+}
+
+impl Authenticator for OAuthAuthenticator {
+    fn authenticate(&self, req: &Request) -> Result<(), Rejection> {
+        let token = req
+            .header("authorization")
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .ok_or_else(|| Rejection::unauthorized("missing bearer token"))?;
+
+        if self.is_cached(token) {
+            return Ok(());
+        }
+
+        let exp = self
+            .validate(token)
+            .map_err(|e| Rejection::unauthorized(format!("invalid bearer token: {}", e)))?;
+
+        self.cache(token, exp);
+
+        Ok(())
+    }
+}
+
+async fn fetch_jwks(client: &reqwest::Client, url: &str) -> Result<JwkSet, OAuthError> {
+    client
+        .get(url)
+        .send()
+        .await
+        .and_then(|x| x.error_for_status())
+        .map_err(OAuthError)?
+        .json()
+        .await
+        .map_err(OAuthError)
+}
+
+/// Periodically replaces the key set until the authenticator is dropped.
+async fn refresh_loop(client: reqwest::Client, url: String, keys: Weak<RwLock<JwkSet>>) {
+    loop {
+        tokio::time::sleep(JWKS_REFRESH_INTERVAL).await;
+
+        let keys = match keys.upgrade() {
+            Some(x) => x,
+            None => return,
+        };
+
+        match fetch_jwks(&client, &url).await {
+            Ok(x) => *keys.write().unwrap() = x,
+            Err(e) => tracing::warn!("failed to refresh jwks from {}: {}", url, e),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use crate::client::ClientId;
+
+/// A partition key that changed hands after the subscribers of its resource changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Move {
+    pub(crate) key: String,
+    pub(crate) from: ClientId,
+    /// `None` once nobody is subscribed anymore.
+    pub(crate) to: Option<ClientId>,
+}
+
+/// The subscriber every partition key of the round-robin routes was last assigned to, see
+/// [`Event::with_partition_key`](crate::Event::with_partition_key).
+///
+/// Keys are assigned by rendezvous hashing: every client gets a score for a key, and the one
+/// with the highest score receives its events. A client joining only takes the keys it scores
+/// highest on, and the keys of a client leaving are spread over the others, so the remaining
+/// keys stay where they are.
+#[derive(Default)]
+pub(crate) struct Partitions {
+    /// resource -> partition key -> the client its events go to.
+    owners: HashMap<String, HashMap<String, ClientId>>,
+}
+
+impl Partitions {
+    /// Records that the events of `res` with `key` went to `owner`.
+    pub(crate) fn assigned(&mut self, res: &str, key: &str, owner: ClientId) {
+        self.owners
+            .entry(res.to_string())
+            .or_default()
+            .insert(key.to_string(), owner);
+    }
+
+    /// Returns the resources with partition keys.
+    pub(crate) fn resources(&self) -> impl Iterator<Item = &str> {
+        self.owners.keys().map(String::as_str)
+    }
+
+    /// Assigns the keys of `res` to `clients`, its subscribers now, returning the keys that
+    /// moved sorted by key. Forgets the keys once nobody is subscribed.
+    pub(crate) fn rebalance(&mut self, res: &str, clients: &[ClientId]) -> Vec<Move> {
+        let owners = match self.owners.get_mut(res) {
+            Some(x) => x,
+            None => return Vec::new(),
+        };
+
+        let mut moves = Vec::new();
+        for (key, owner) in owners.iter_mut() {
+            let to = pick(key, clients.iter().copied());
+            if to != Some(*owner) {
+                moves.push(Move {
+                    key: key.clone(),
+                    from: *owner,
+                    to,
+                });
+                if let Some(to) = to {
+                    *owner = to;
+                }
+            }
+        }
+
+        if clients.is_empty() {
+            self.owners.remove(res);
+        }
+        moves.sort_by(|a, b| a.key.cmp(&b.key));
+        moves
+    }
+}
+
+/// Returns the client the events with `key` go to among `clients`.
+pub(crate) fn pick(key: &str, clients: impl IntoIterator<Item = ClientId>) -> Option<ClientId> {
+    clients.into_iter().max_by_key(|x| score(key, *x))
+}
+
+/// Returns how much `client` wants `key`, the same in every process.
+pub(crate) fn score(key: &str, client: ClientId) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    client.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_keys_of_changed_clients_move() {
+        let clients: Vec<_> = (0..4).map(|_| ClientId::next()).collect();
+        let keys: Vec<_> = (0..100).map(|x| format!("library-{}", x)).collect();
+        let mut partitions = Partitions::default();
+
+        for key in &keys {
+            let owner = pick(key, clients[..3].iter().copied()).unwrap();
+            partitions.assigned("/jobs", key, owner);
+        }
+        assert_eq!(partitions.rebalance("/jobs", &clients[..3]), []);
+
+        // A client joining only takes keys.
+        let joined = partitions.rebalance("/jobs", &clients);
+        assert!(!joined.is_empty());
+        assert!(joined.iter().all(|x| x.to == Some(clients[3])));
+
+        // The keys of a client leaving go to the others, everything else stays.
+        let left = partitions.rebalance("/jobs", &clients[1..]);
+        assert!(left.iter().all(|x| x.from == clients[0] && x.to.is_some()));
+        for key in &keys {
+            let owner = partitions.owners["/jobs"][key];
+            assert_eq!(Some(owner), pick(key, clients[1..].iter().copied()));
+        }
+
+        let gone = partitions.rebalance("/jobs", &[]);
+        assert_eq!(gone.len(), keys.len());
+        assert!(partitions.resources().next().is_none());
+    }
+}
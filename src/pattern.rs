@@ -0,0 +1,44 @@
+/// Returns whether the resource pattern `pattern` matches `res`.
+///
+/// `*` matches every resource and a pattern ending in `/*` matches every resource below that
+/// prefix, e.g. `/orders/*` matches `/orders/1` and `/orders/1/items` but not `/orders`. Any
+/// other pattern only matches itself.
+pub(crate) fn matches(pattern: &str, res: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    match pattern.strip_suffix('*') {
+        Some(prefix) if prefix.ends_with('/') => {
+            res.len() > prefix.len() && res.starts_with(prefix)
+        }
+        _ => pattern == res,
+    }
+}
+
+/// Returns whether `res` is a pattern matching other resources, see [`matches`].
+pub(crate) fn is_pattern(res: &str) -> bool {
+    res == "*" || res.ends_with("/*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", "/anything"));
+        assert!(matches("/orders", "/orders"));
+        assert!(!matches("/orders", "/orders/1"));
+        assert!(matches("/orders/*", "/orders/1"));
+        assert!(matches("/orders/*", "/orders/1/items"));
+        assert!(!matches("/orders/*", "/orders/"));
+        assert!(!matches("/orders/*", "/orders"));
+        assert!(!matches("/orders/*", "/ordersx/1"));
+        assert!(!matches("/orders*", "/orders1"));
+
+        assert!(is_pattern("*"));
+        assert!(is_pattern("/orders/*"));
+        assert!(!is_pattern("/orders*"));
+    }
+}
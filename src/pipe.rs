@@ -0,0 +1,111 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, PoisonError, RwLock,
+};
+
+use crate::{pattern, Event, Transformer};
+
+/// The routes events are copied between, see [`Server::pipe`](crate::server::Server::pipe).
+#[derive(Default)]
+pub(crate) struct Pipes {
+    next_id: AtomicU64,
+    pipes: RwLock<Vec<Pipe>>,
+}
+
+struct Pipe {
+    id: u64,
+    /// The resource or pattern events are copied from.
+    from: String,
+    to: String,
+    transformer: Option<Arc<dyn Transformer>>,
+}
+
+impl Pipes {
+    pub(crate) fn add(
+        self: &Arc<Self>,
+        from: &str,
+        to: &str,
+        transformer: Option<Arc<dyn Transformer>>,
+    ) -> PipeHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.pipes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Pipe {
+                id,
+                from: from.to_string(),
+                to: to.to_string(),
+                transformer,
+            });
+
+        PipeHandle {
+            id,
+            pipes: self.clone(),
+        }
+    }
+
+    /// Returns the copies of `event` for the routes it is piped to, dropping the ones whose
+    /// transformer panicked.
+    pub(crate) fn pipe(&self, event: &Event) -> Vec<Event> {
+        let pipes = self.pipes.read().unwrap_or_else(PoisonError::into_inner);
+        let mut piped = Vec::new();
+
+        for pipe in pipes
+            .iter()
+            .filter(|pipe| pattern::matches(&pipe.from, event.res()))
+        {
+            let payload = match &pipe.transformer {
+                Some(transformer) => {
+                    let transform = AssertUnwindSafe(|| {
+                        transformer.transform_event(event, event.payload().to_string())
+                    });
+                    match panic::catch_unwind(transform) {
+                        Ok(x) => x,
+                        Err(_) => {
+                            tracing::error!(
+                                "transformer of pipe from {} to {} panicked, dropping event",
+                                pipe.from,
+                                pipe.to
+                            );
+                            continue;
+                        }
+                    }
+                }
+                None => event.payload().to_string(),
+            };
+
+            piped.push(
+                Event::from_string(pipe.to.as_str(), payload).with_origin(event.origin().clone()),
+            );
+        }
+
+        piped
+    }
+}
+
+/// A pipe copying the events of a route to another, returned by
+/// [`Server::pipe`](crate::server::Server::pipe).
+///
+/// Dropping the handle keeps the pipe, [`remove`](Self::remove) deletes it.
+pub struct PipeHandle {
+    id: u64,
+    pipes: Arc<Pipes>,
+}
+
+impl PipeHandle {
+    /// Deletes the pipe, events published afterwards aren't copied anymore.
+    pub fn remove(self) {
+        self.pipes
+            .pipes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|pipe| pipe.id != self.id);
+    }
+}
+
+impl std::fmt::Debug for PipeHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PipeHandle").field("id", &self.id).finish()
+    }
+}
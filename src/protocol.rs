@@ -0,0 +1,296 @@
+//! Negotiation and encoding of the `pushevent-v*` websocket subprotocols, see
+//! [`ServerBuilder::max_protocol_version`](crate::server::ServerBuilder::max_protocol_version),
+//! and of the binary encodings.
+
+use tungstenite::{Message, Utf8Bytes};
+
+use crate::{ClientInfo, Event, Request};
+
+/// The most recent protocol version the server knows how to speak.
+pub(crate) const LATEST: u8 = 2;
+
+/// How events are serialized for a client, negotiated through `Sec-WebSocket-Protocol`, or
+/// through the `Accept` header of the upgrade request for clients that don't offer an encoding,
+/// and available as [`ClientInfo::encoding`].
+///
+/// Binary encodings always carry the version 2 envelope. Payloads that are valid JSON are
+/// transcoded, so `{"type":"event","resource":"/events","payload":{"id":1}}` is sent as a map
+/// with a nested map, other payloads are sent as strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// JSON text frames, shaped by the negotiated protocol version.
+    #[default]
+    Json,
+    /// MessagePack binary frames, negotiated with the `pushevent.msgpack` subprotocol or
+    /// `Accept: application/msgpack`.
+    #[cfg(feature = "msgpack")]
+    MsgPack,
+    /// CBOR binary frames, negotiated with the `pushevent.cbor` subprotocol or
+    /// `Accept: application/cbor`.
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Encoding {
+    /// Returns the subprotocol name of a binary encoding.
+    pub(crate) fn name(self) -> Option<&'static str> {
+        match self {
+            Self::Json => None,
+            #[cfg(feature = "msgpack")]
+            Self::MsgPack => Some("pushevent.msgpack"),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => Some("pushevent.cbor"),
+        }
+    }
+}
+
+/// Returns the subprotocols offered in the `Sec-WebSocket-Protocol` headers of `req`, in the
+/// order the client prefers them.
+pub(crate) fn offered(req: &Request) -> impl Iterator<Item = &str> {
+    req.headers()
+        .filter(|(k, _)| *k == "sec-websocket-protocol")
+        .flat_map(|(_, v)| v.split(','))
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+}
+
+/// Picks the highest version offered in the `Sec-WebSocket-Protocol` headers of `req` that is no
+/// higher than `max_version`, with the name it was offered as. Every version is offered either
+/// as `pushevent-vN` or as `pushevent.vN`. Returns `None` if the client didn't offer any
+/// pushevent version.
+pub(crate) fn negotiate(req: &Request, max_version: u8) -> Option<(u8, &str)> {
+    offered(req)
+        .filter_map(|x| match x {
+            "pushevent-v1" | "pushevent.v1" => Some((1, x)),
+            "pushevent-v2" | "pushevent.v2" => Some((2, x)),
+            _ => None,
+        })
+        .filter(|(version, _)| *version <= max_version)
+        .max_by_key(|(version, _)| *version)
+}
+
+/// Returns the version requested with the `protocol` query string parameter, e.g.
+/// `/events?protocol=2` for clients that can't set `Sec-WebSocket-Protocol`, capped at
+/// `max_version`.
+pub(crate) fn from_query(value: &str, max_version: u8) -> Option<u8> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|x| *x >= 1)
+        .map(|x| x.min(max_version))
+}
+
+/// Returns whether `client` understands messages other than events, such as the draining and
+/// lagging notices. Clients speaking version 1 only expect raw payloads.
+pub(crate) fn receives_notices(client: &ClientInfo) -> bool {
+    client.protocol_version >= 2 || client.encoding != Encoding::Json
+}
+
+/// Returns whether `client` receives the events of [batched](crate::server::Batching) routes
+/// in batches. Clients speaking version 1 or a binary encoding receive them one by one.
+pub(crate) fn receives_batches(client: &ClientInfo) -> bool {
+    client.encoding == Encoding::Json && client.protocol_version >= 2
+}
+
+/// Returns the first binary encoding offered in the `Sec-WebSocket-Protocol` headers of `req`.
+/// Returns `None` if the client didn't offer any the server supports.
+pub(crate) fn negotiate_encoding(req: &Request) -> Option<Encoding> {
+    offered(req).find_map(|x| match x {
+        #[cfg(feature = "msgpack")]
+        "pushevent.msgpack" => Some(Encoding::MsgPack),
+        #[cfg(feature = "cbor")]
+        "pushevent.cbor" => Some(Encoding::Cbor),
+        _ => None,
+    })
+}
+
+/// Returns the first binary encoding listed in the `Accept` headers of `req`, for clients that
+/// can't offer subprotocols, e.g. `Accept: application/cbor`. Media ranges with `q=0` are
+/// skipped. Returns `None` if the client didn't list any the server supports.
+pub(crate) fn accepted_encoding(req: &Request) -> Option<Encoding> {
+    req.headers()
+        .filter(|(k, _)| *k == "accept")
+        .flat_map(|(_, v)| v.split(','))
+        .filter_map(|x| {
+            let mut params = x.split(';').map(str::trim);
+            let media_type = params.next()?;
+            let refused = params.any(|x| {
+                x.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            (!refused).then_some(media_type)
+        })
+        .find_map(|x| match x.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "msgpack")]
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Encoding::MsgPack)
+            }
+            #[cfg(feature = "cbor")]
+            "application/cbor" => Some(Encoding::Cbor),
+            _ => None,
+        })
+}
+
+/// A control frame sent by a client, `{"action":"pause"}` or `{"action":"resume"}`, see
+/// [`ServerBuilder::pause_policy`](crate::server::ServerBuilder::pause_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Control {
+    Pause,
+    Resume,
+}
+
+/// Returns the control frame `frame` is, if it is one.
+pub(crate) fn parse_control(frame: &str) -> Option<Control> {
+    let (key, value) = frame
+        .trim()
+        .strip_prefix('{')?
+        .strip_suffix('}')?
+        .split_once(':')?;
+    if key.trim() != r#""action""# {
+        return None;
+    }
+
+    match value.trim() {
+        r#""pause""# => Some(Control::Pause),
+        r#""resume""# => Some(Control::Resume),
+        _ => None,
+    }
+}
+
+/// Marks an event clients acknowledge by its id, see [`QoS`](crate::server::QoS).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ack {
+    /// Whether the event was sent before, on exactly once routes.
+    pub(crate) dup: bool,
+}
+
+/// Serializes `event` as sent to `client`.
+pub(crate) fn encode(client: &ClientInfo, event: &Event) -> Message {
+    encode_with(client, event, None)
+}
+
+/// Serializes `event` as sent to `client`, always in an envelope if it is to be acknowledged.
+///
+/// Version 1 frames share the buffer of the event, so they are cheap to clone.
+pub(crate) fn encode_with(client: &ClientInfo, event: &Event, ack: Option<Ack>) -> Message {
+    match client.encoding {
+        Encoding::Json => Message::Text(encode_json(client.protocol_version, event, ack)),
+        #[cfg(feature = "msgpack")]
+        Encoding::MsgPack => Message::Binary(
+            rmp_serde::to_vec(&envelope(event, ack))
+                .expect("JSON values always serialize")
+                .into(),
+        ),
+        #[cfg(feature = "cbor")]
+        Encoding::Cbor => {
+            let mut out = Vec::new();
+            ciborium::into_writer(&envelope(event, ack), &mut out)
+                .expect("JSON values always serialize");
+            Message::Binary(out.into())
+        }
+    }
+}
+
+/// Encodes one event for many clients, at most once per encoding and protocol version. The
+/// clients then share the encoded frames.
+pub(crate) struct Frames<'a> {
+    event: &'a Event,
+    ack: Option<Ack>,
+    encoded: Vec<((Encoding, u8), Message)>,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(event: &'a Event, ack: Option<Ack>) -> Self {
+        Self {
+            event,
+            ack,
+            encoded: Vec::new(),
+        }
+    }
+
+    /// Returns `event` as sent to `client`.
+    pub(crate) fn get(&mut self, client: &ClientInfo) -> Message {
+        // Binary encodings don't depend on the version.
+        let version = match client.encoding {
+            Encoding::Json => client.protocol_version,
+            #[allow(unreachable_patterns)]
+            _ => 0,
+        };
+        let key = (client.encoding, version);
+
+        if let Some((_, frame)) = self.encoded.iter().find(|(x, _)| *x == key) {
+            return frame.clone();
+        }
+
+        let frame = encode_with(client, self.event, self.ack);
+        self.encoded.push((key, frame.clone()));
+        frame
+    }
+}
+
+/// Serializes `events` as one version 2 frame, a JSON array of their envelopes.
+pub(crate) fn encode_batch<'a>(events: impl IntoIterator<Item = &'a Event>) -> Message {
+    Message::Text(pushevent_core::format::batch(events).into())
+}
+
+/// Returns the version 2 envelope of `event` with the payload parsed, for the binary encodings.
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+fn envelope(event: &Event, ack: Option<Ack>) -> serde_json::Value {
+    let payload = serde_json::from_str(event.payload())
+        .unwrap_or_else(|_| serde_json::Value::String(event.payload().to_string()));
+
+    let mut envelope = serde_json::json!({
+        "type": "event",
+        "resource": event.res(),
+        "payload": payload,
+    });
+    if let Some(id) = event.id() {
+        envelope["id"] = id.into();
+    }
+    if ack.is_some_and(|x| x.dup) {
+        envelope["dup"] = true.into();
+    }
+
+    envelope
+}
+
+/// Serializes `event` as a JSON text frame for a client speaking `version`. Events clients
+/// acknowledge are always sent in the version 2 envelope.
+fn encode_json(version: u8, event: &Event, ack: Option<Ack>) -> Utf8Bytes {
+    match (version, ack) {
+        (2, _) | (_, Some(_)) => {
+            pushevent_core::format::envelope(event, ack.is_some_and(|x| x.dup)).into()
+        }
+        _ => payload(event),
+    }
+}
+
+/// Returns the payload of `event` as the payload of a text frame, sharing its buffer.
+///
+/// Not exposed as a conversion of events to frames: sharing the buffer means returning
+/// tungstenite's `Utf8Bytes`, which is kept out of the public API, and returning an owned
+/// `Payload` instead would copy every payload.
+pub(crate) fn payload(event: &Event) -> Utf8Bytes {
+    // SAFETY: events keep their payload as a `ByteString`, which only holds valid UTF-8.
+    unsafe { Utf8Bytes::from_bytes_unchecked(event.payload_bytes().clone()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_frames_are_parsed() {
+        assert_eq!(parse_control(r#"{"action":"pause"}"#), Some(Control::Pause));
+        assert_eq!(
+            parse_control(r#" { "action" : "resume" } "#),
+            Some(Control::Resume)
+        );
+        assert_eq!(parse_control(r#"{"action":"stop"}"#), None);
+        assert_eq!(parse_control(r#"{"type":"pause"}"#), None);
+        assert_eq!(parse_control(r#"{"action":"pause","id":1}"#), None);
+        assert_eq!(parse_control("pause"), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Delivery guarantees of a route, see
+//! [`Server::set_route_qos`](crate::server::Server::set_route_qos).
+//!
+//! Events published to a route with a guarantee above [`QoS::AtMostOnce`] carry an id in their
+//! envelope, `{"type":"event","id":7,"resource":"/alerts","payload":"..."}`, which clients
+//! acknowledge by sending the text frame `{"type":"ack","id":7}`. Events that aren't
+//! acknowledged in time are sent again until they are or the client disconnects.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::client::ClientId;
+use crate::Event;
+
+/// The number of unacknowledged events kept per client, older ones are given up on.
+pub(crate) const MAX_UNACKED: usize = 1024;
+
+/// The number of acknowledged ids remembered per client on [`QoS::ExactlyOnce`] routes.
+const ACKED_HISTORY: usize = 1024;
+
+/// How an event published to a route is delivered, modelled after the MQTT levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum QoS {
+    /// Every event is sent once and lost if the connection drops before it is written.
+    #[default]
+    AtMostOnce,
+    /// Events are sent again until the client acknowledges them, so a client may receive an
+    /// event more than once.
+    AtLeastOnce,
+    /// Like [`AtLeastOnce`](Self::AtLeastOnce), but events sent again carry `"dup":true` and
+    /// the server stops resending an event once it was acknowledged, so that a client
+    /// discarding the ids it already processed handles every event exactly once.
+    ExactlyOnce,
+}
+
+/// An event waiting for a client to acknowledge it.
+struct Unacked {
+    event: Event,
+    qos: QoS,
+    sent_at: Instant,
+}
+
+#[derive(Default)]
+struct ClientAcks {
+    unacked: BTreeMap<u64, Unacked>,
+    /// The ids acknowledged last, on exactly once routes.
+    acked: VecDeque<u64>,
+    acked_set: HashSet<u64>,
+}
+
+/// The events sent to clients that they haven't acknowledged yet.
+#[derive(Default)]
+pub(crate) struct Acks {
+    clients: Mutex<HashMap<ClientId, ClientAcks>>,
+}
+
+impl Acks {
+    /// Records that the event `id` was sent to `client`.
+    pub(crate) fn sent(&self, client: ClientId, id: u64, event: Event, qos: QoS) {
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        let acks = clients.entry(client).or_default();
+
+        if acks.unacked.len() >= MAX_UNACKED {
+            if let Some((oldest, _)) = acks.unacked.pop_first() {
+                tracing::warn!(
+                    "{:?} never acknowledged event {}, giving up",
+                    client,
+                    oldest
+                );
+            }
+        }
+
+        acks.unacked.insert(
+            id,
+            Unacked {
+                event,
+                qos,
+                sent_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Records that `client` acknowledged the event `id`. Returns `false` if it wasn't waiting
+    /// for an acknowledgement, e.g. because it was acknowledged before.
+    pub(crate) fn ack(&self, client: ClientId, id: u64) -> bool {
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        let acks = match clients.get_mut(&client) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        match acks.unacked.remove(&id) {
+            Some(unacked) => {
+                if unacked.qos == QoS::ExactlyOnce {
+                    if acks.acked.len() >= ACKED_HISTORY {
+                        if let Some(x) = acks.acked.pop_front() {
+                            acks.acked_set.remove(&x);
+                        }
+                    }
+                    acks.acked.push_back(id);
+                    acks.acked_set.insert(id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether `client` acknowledged the exactly once event `id` recently.
+    pub(crate) fn is_acked(&self, client: ClientId, id: u64) -> bool {
+        self.clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&client)
+            .is_some_and(|x| x.acked_set.contains(&id))
+    }
+
+    /// Forgets the events sent to a client that disconnected, returning the ones it didn't
+    /// acknowledge.
+    pub(crate) fn remove_client(&self, client: ClientId) -> Vec<Event> {
+        self.clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&client)
+            .map(|x| x.unacked.into_values().map(|x| x.event).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the events sent at least `timeout` ago that still aren't acknowledged, marking
+    /// them as sent again now.
+    pub(crate) fn expired(&self, timeout: Duration) -> Vec<(ClientId, u64, Event, QoS)> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut expired = Vec::new();
+        for (client, acks) in clients.iter_mut() {
+            for (id, unacked) in acks.unacked.iter_mut() {
+                if now.duration_since(unacked.sent_at) >= timeout {
+                    unacked.sent_at = now;
+                    expired.push((*client, *id, unacked.event.clone(), unacked.qos));
+                }
+            }
+        }
+
+        expired
+    }
+}
+
+/// Returns the id of an acknowledgement frame, `{"type":"ack","id":N}`.
+pub(crate) fn parse_ack(frame: &str) -> Option<u64> {
+    let rest = frame.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut id = None;
+    let mut is_ack = false;
+
+    for field in rest.split(',') {
+        let (key, value) = field.split_once(':')?;
+        match (key.trim(), value.trim()) {
+            (r#""type""#, r#""ack""#) => is_ack = true,
+            (r#""id""#, x) => id = Some(x.parse().ok()?),
+            _ => return None,
+        }
+    }
+
+    id.filter(|_| is_ack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acks_are_parsed() {
+        assert_eq!(parse_ack(r#"{"type":"ack","id":7}"#), Some(7));
+        assert_eq!(parse_ack(r#" { "id": 7, "type": "ack" } "#), Some(7));
+        assert_eq!(parse_ack(r#"{"type":"ack"}"#), None);
+        assert_eq!(parse_ack(r#"{"type":"event","id":7}"#), None);
+        assert_eq!(parse_ack(r#"{"type":"ack","id":"7"}"#), None);
+        assert_eq!(parse_ack("hello"), None);
+    }
+}
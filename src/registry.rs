@@ -0,0 +1,304 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::client::ClientId;
+use crate::pattern;
+use crate::routing::ResourceTrie;
+
+/// Keeps track of which clients are subscribed to which resources.
+///
+/// The registry knows nothing about websockets, `T` is whatever handle the caller uses to reach
+/// a client (usually the sending half of its outgoing channel). Routes without any subscribers
+/// are removed eagerly so that the set of resources always reflects live subscriptions.
+///
+/// Clients can subscribe to [patterns](pattern::matches) such as `/orders/*`, an event is still
+/// delivered once to a client matching it through several subscriptions.
+pub(crate) struct Registry<T> {
+    /// resource -> subscribers of that resource.
+    routes: ResourceTrie<HashMap<ClientId, T>>,
+    /// client -> resources it is subscribed to, used to tear down a client in one go.
+    clients: HashMap<ClientId, HashSet<String>>,
+    /// The number of routes that are patterns.
+    patterns: usize,
+}
+
+impl<T> Registry<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            routes: ResourceTrie::new(),
+            clients: HashMap::new(),
+            patterns: 0,
+        }
+    }
+
+    /// Subscribes `id` to `res`. Returns `false` if the client was already subscribed, in which
+    /// case the existing handle is kept.
+    pub(crate) fn add(&mut self, res: &str, id: ClientId, handle: T) -> bool {
+        let subscribers = self.routes.get_or_insert_with(res, HashMap::new);
+
+        if subscribers.contains_key(&id) {
+            return false;
+        }
+
+        if subscribers.is_empty() && pattern::is_pattern(res) {
+            self.patterns += 1;
+        }
+
+        subscribers.insert(id, handle);
+        self.clients.entry(id).or_default().insert(res.to_string());
+
+        true
+    }
+
+    /// Unsubscribes `id` from `res`. Returns whether the client was subscribed.
+    pub(crate) fn remove(&mut self, res: &str, id: ClientId) -> bool {
+        let removed = match self.routes.get_mut(res) {
+            Some(subscribers) => {
+                let removed = subscribers.remove(&id).is_some();
+
+                if subscribers.is_empty() {
+                    self.remove_route(res);
+                }
+
+                removed
+            }
+            None => false,
+        };
+
+        if let Some(resources) = self.clients.get_mut(&id) {
+            resources.remove(res);
+
+            if resources.is_empty() {
+                self.clients.remove(&id);
+            }
+        }
+
+        removed
+    }
+
+    /// Removes `id` from every resource it is subscribed to. Returns whether the client was known.
+    pub(crate) fn remove_client(&mut self, id: ClientId) -> bool {
+        let resources = match self.clients.remove(&id) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        for res in resources {
+            if let Some(subscribers) = self.routes.get_mut(&res) {
+                subscribers.remove(&id);
+
+                if subscribers.is_empty() {
+                    self.remove_route(&res);
+                }
+            }
+        }
+
+        true
+    }
+
+    fn remove_route(&mut self, res: &str) {
+        if self.routes.remove(res).is_some() && pattern::is_pattern(res) {
+            self.patterns -= 1;
+        }
+    }
+
+    /// Returns the clients that should receive an event published to `res`, each once. A client
+    /// subscribed through several matching routes is returned with the handle of one of them.
+    pub(crate) fn subscribers<'a>(
+        &'a self,
+        res: &'a str,
+    ) -> impl Iterator<Item = (ClientId, &'a T)> + 'a {
+        let patterns = (self.patterns > 0)
+            .then(|| self.routes.patterns_matching(res).map(|(_, x)| x))
+            .into_iter()
+            .flatten();
+        // Only needed when a client can match through more than one route.
+        let mut seen = (self.patterns > 0).then(HashSet::new);
+
+        self.routes
+            .get(res)
+            .into_iter()
+            .chain(patterns)
+            .flat_map(|x| x.iter().map(|(id, handle)| (*id, handle)))
+            .filter(move |(id, _)| seen.as_mut().is_none_or(|x| x.insert(*id)))
+    }
+
+    /// Returns whether any client is subscribed to a pattern.
+    pub(crate) fn has_patterns(&self) -> bool {
+        self.patterns > 0
+    }
+
+    /// Returns the handle of one of the subscriptions of `id`.
+    pub(crate) fn get(&self, id: ClientId) -> Option<&T> {
+        let res = self.clients.get(&id)?.iter().next()?;
+        self.routes.get(res)?.get(&id)
+    }
+
+    /// Returns every client once, together with the handle of one of its subscriptions.
+    pub(crate) fn clients(&self) -> impl Iterator<Item = (ClientId, &T)> {
+        self.clients
+            .keys()
+            .filter_map(move |id| Some((*id, self.get(*id)?)))
+    }
+
+    /// Returns the resources `id` is subscribed to.
+    pub(crate) fn subscriptions(&self, id: ClientId) -> impl Iterator<Item = &str> {
+        self.clients
+            .get(&id)
+            .into_iter()
+            .flat_map(|x| x.iter().map(String::as_str))
+    }
+
+    /// Returns the number of clients subscribed to `res`.
+    #[allow(dead_code)]
+    pub(crate) fn subscriber_count(&self, res: &str) -> usize {
+        self.routes.get(res).map_or(0, HashMap::len)
+    }
+
+    /// Returns every resource with at least one subscriber.
+    #[allow(dead_code)]
+    pub(crate) fn resources(&self) -> impl Iterator<Item = &str> {
+        self.routes.keys()
+    }
+
+    /// Returns every client with at least one subscription.
+    pub(crate) fn client_ids(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients.keys().copied()
+    }
+
+    /// Returns the number of clients with at least one subscription.
+    #[allow(dead_code)]
+    pub(crate) fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const ROUTES: &[&str] = &["/a", "/b", "/c/d", "/", "/c/*", "*"];
+    /// Resources events are published to, including some only reached through patterns.
+    const PUBLISH: &[&str] = &["/a", "/b", "/c/d", "/", "/c/*", "*", "/c/e", "/x"];
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Connect(usize),
+        Subscribe(usize, usize),
+        Unsubscribe(usize, usize),
+        Disconnect(usize),
+        Broadcast(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        let route = 0..ROUTES.len();
+        let client = 0..16usize;
+
+        prop_oneof![
+            route.clone().prop_map(Op::Connect),
+            (client.clone(), route.clone()).prop_map(|(c, r)| Op::Subscribe(c, r)),
+            (client.clone(), route.clone()).prop_map(|(c, r)| Op::Unsubscribe(c, r)),
+            client.prop_map(Op::Disconnect),
+            (0..PUBLISH.len()).prop_map(Op::Broadcast),
+        ]
+    }
+
+    type Model = HashMap<String, HashSet<ClientId>>;
+
+    /// The clients an event published to `res` should reach according to `model`.
+    fn recipients(model: &Model, res: &str) -> HashSet<ClientId> {
+        model
+            .iter()
+            .filter(|(route, _)| pattern::matches(route, res))
+            .flat_map(|(_, x)| x.iter().copied())
+            .collect()
+    }
+
+    fn check(registry: &Registry<ClientId>, model: &Model) {
+        for res in PUBLISH {
+            let expected = recipients(model, res);
+            let actual: HashSet<_> = registry.subscribers(res).map(|(id, _)| id).collect();
+
+            assert_eq!(actual, expected, "subscribers of {}", res);
+            assert_eq!(
+                registry.subscriber_count(res),
+                model.get(*res).map_or(0, HashSet::len)
+            );
+        }
+
+        let mut expected: Vec<_> = model
+            .iter()
+            .filter(|(_, x)| !x.is_empty())
+            .map(|(res, _)| res.as_str())
+            .collect();
+        let mut actual: Vec<_> = registry.resources().collect();
+        expected.sort_unstable();
+        actual.sort_unstable();
+
+        assert_eq!(actual, expected);
+
+        let clients: HashSet<_> = model.values().flatten().collect();
+        assert_eq!(registry.client_count(), clients.len());
+    }
+
+    proptest! {
+        #[test]
+        fn registry_matches_model(ops in proptest::collection::vec(op(), 1..64)) {
+            let mut registry = Registry::new();
+            let mut model = Model::new();
+            let mut live: Vec<ClientId> = Vec::new();
+
+            for op in ops {
+                match op {
+                    Op::Connect(r) => {
+                        let id = ClientId::next();
+                        live.push(id);
+
+                        assert!(registry.add(ROUTES[r], id, id));
+                        model.entry(ROUTES[r].to_string()).or_default().insert(id);
+                    }
+                    Op::Subscribe(c, r) if !live.is_empty() => {
+                        let id = live[c % live.len()];
+                        let new = model.entry(ROUTES[r].to_string()).or_default().insert(id);
+
+                        assert_eq!(registry.add(ROUTES[r], id, id), new);
+                    }
+                    Op::Unsubscribe(c, r) if !live.is_empty() => {
+                        let id = live[c % live.len()];
+                        let existed = model
+                            .get_mut(ROUTES[r])
+                            .is_some_and(|x| x.remove(&id));
+
+                        assert_eq!(registry.remove(ROUTES[r], id), existed);
+                    }
+                    Op::Disconnect(c) if !live.is_empty() => {
+                        let id = live.remove(c % live.len());
+                        let mut existed = false;
+
+                        for subscribers in model.values_mut() {
+                            existed |= subscribers.remove(&id);
+                        }
+
+                        assert_eq!(registry.remove_client(id), existed);
+                    }
+                    Op::Broadcast(r) => {
+                        let expected = recipients(&model, PUBLISH[r]);
+                        let recipients: Vec<_> = registry
+                            .subscribers(PUBLISH[r])
+                            .map(|(id, handle)| {
+                                assert_eq!(id, *handle);
+                                id
+                            })
+                            .collect();
+
+                        assert_eq!(recipients.len(), expected.len(), "duplicate delivery");
+                        assert_eq!(recipients.into_iter().collect::<HashSet<_>>(), expected);
+                    }
+                    _ => {}
+                }
+
+                check(&registry, &model);
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{pattern, Event};
+
+/// How long the session of a disconnected client can be resumed.
+pub(crate) const SESSION_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// The header reconnecting clients identify their session with.
+pub(crate) const SESSION_HEADER: &str = "x-pushevent-session";
+
+/// Recent events and the sessions of recently disconnected clients, see
+/// [`ServerBuilder::replay_on_reconnect`](crate::server::ServerBuilder::replay_on_reconnect).
+pub(crate) struct Replay {
+    history: Mutex<History>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// The last `capacity` events published, numbered in the order they were published.
+struct History {
+    capacity: usize,
+    /// The sequence number of the next event.
+    next_seq: u64,
+    events: VecDeque<(u64, Event)>,
+}
+
+struct Session {
+    resource: String,
+    /// The sequence number of the first event the client missed.
+    resume_from: u64,
+    disconnected_at: Instant,
+}
+
+impl Replay {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            history: Mutex::new(History {
+                capacity,
+                next_seq: 0,
+                events: VecDeque::with_capacity(capacity),
+            }),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds `event` to the history, evicting the oldest event if it is full.
+    pub(crate) fn record(&self, event: &Event) {
+        let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        let seq = history.next_seq;
+        history.next_seq += 1;
+
+        if history.capacity == 0 {
+            return;
+        }
+
+        if history.events.len() == history.capacity {
+            history.events.pop_front();
+        }
+        history.events.push_back((seq, event.clone()));
+    }
+
+    /// Remembers that the client with the session `token` left `resource`, having received
+    /// every event published so far.
+    pub(crate) fn disconnected(&self, token: String, resource: &str) {
+        let resume_from = self
+            .history
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .next_seq;
+
+        self.sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                token,
+                Session {
+                    resource: resource.to_string(),
+                    resume_from,
+                    disconnected_at: Instant::now(),
+                },
+            );
+    }
+
+    /// Resumes the session `token` of a client reconnecting to `resource`, returning the events
+    /// published to `resource` since it disconnected that are still in the history. Returns
+    /// nothing for unknown or expired sessions and sessions of another resource.
+    pub(crate) fn resume(&self, token: &str, resource: &str) -> Vec<Event> {
+        let session = match self
+            .sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(token)
+        {
+            Some(x) if x.resource == resource && x.disconnected_at.elapsed() < SESSION_TTL => x,
+            _ => return Vec::new(),
+        };
+
+        let history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((oldest, _)) = history.events.front() {
+            if *oldest > session.resume_from {
+                tracing::debug!(
+                    "{} events missed by session {} are no longer in the history",
+                    oldest - session.resume_from,
+                    token
+                );
+            }
+        }
+
+        history
+            .events
+            .iter()
+            .filter(|(seq, event)| {
+                *seq >= session.resume_from && pattern::matches(resource, event.res())
+            })
+            .map(|(_, event)| event.clone())
+            .collect()
+    }
+
+    /// Forgets the sessions of clients that disconnected more than [`SESSION_TTL`] ago.
+    pub(crate) fn expire(&self) {
+        self.sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, x| x.disconnected_at.elapsed() < SESSION_TTL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SerializableEvent;
+
+    struct Text(&'static str);
+
+    impl SerializableEvent for Text {
+        fn serialize(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    #[test]
+    fn resumes_from_the_disconnect() {
+        let replay = Replay::new(3);
+        replay.record(&Event::new("/a", Text("before")));
+        replay.disconnected("token".to_string(), "/a");
+
+        for payload in ["one", "two", "three"] {
+            replay.record(&Event::new("/a", Text(payload)));
+        }
+        replay.record(&Event::new("/b", Text("other")));
+
+        assert!(replay.resume("unknown", "/a").is_empty());
+
+        // "one" was evicted from the history.
+        let events = replay.resume("token", "/a");
+        assert_eq!(
+            events.iter().map(Event::build).collect::<Vec<_>>(),
+            ["two", "three"]
+        );
+
+        // A session is resumed once.
+        assert!(replay.resume("token", "/a").is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+
+use tungstenite::handshake::server;
+
+use crate::ClientMeta;
+
+/// The HTTP upgrade request a client sent to open its websocket connection.
+///
+/// Header names are matched case-insensitively. Headers whose value isn't valid UTF-8 are left
+/// out.
+///
+/// # Example
+/// ```
+/// use pushevent::Request;
+///
+/// let req = Request::new("/events?user_id=42").with_header("X-Token", "hunter2");
+///
+/// assert_eq!(req.path(), "/events");
+/// assert_eq!(req.query(), Some("user_id=42"));
+/// assert_eq!(req.header("x-token"), Some("hunter2"));
+/// assert_eq!(req.header("authorization"), None);
+/// ```
+///
+/// The request type of the websocket backend is deliberately not part of the public API:
+/// ```compile_fail
+/// use pushevent::auth::Authenticator;
+///
+/// fn check(auth: &dyn Authenticator, req: &tungstenite::handshake::server::Request) {
+///     let _ = auth.authenticate(req);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    uri: String,
+    /// (lowercase name, value) in the order they were received.
+    headers: Vec<(String, String)>,
+    remote_addr: Option<SocketAddr>,
+    meta: ClientMeta,
+}
+
+impl Request {
+    /// Returns a request for `uri` (path and optional query string) without any headers.
+    pub fn new(uri: impl Into<String>) -> Self {
+        Self {
+            uri: uri.into(),
+            headers: Vec::new(),
+            remote_addr: None,
+            meta: ClientMeta::new(),
+        }
+    }
+
+    /// Adds a header to the request.
+    pub fn with_header(mut self, name: impl AsRef<str>, value: impl Into<String>) -> Self {
+        self.headers
+            .push((name.as_ref().to_ascii_lowercase(), value.into()));
+        self
+    }
+
+    /// Sets the address the request was received from.
+    pub fn with_remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.remote_addr = Some(addr);
+        self
+    }
+
+    /// Returns the address the request was received from. Always set for requests received by
+    /// the server.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Returns the state attached to the connection. Values inserted while handling the request,
+    /// e.g. by an [authenticator](crate::auth::Authenticator), stay available as
+    /// [`ClientInfo::meta`](crate::ClientInfo::meta) once the client is connected.
+    pub fn meta(&self) -> &ClientMeta {
+        &self.meta
+    }
+
+    /// Returns the path of the request, which is the resource the client subscribes to.
+    pub fn path(&self) -> &str {
+        self.uri.split('?').next().unwrap_or_default()
+    }
+
+    /// Returns the query string of the request, if any.
+    pub fn query(&self) -> Option<&str> {
+        self.uri.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Returns the value of the first header called `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all headers as (lowercase name, value) pairs.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub(crate) fn from_handshake(req: &server::Request) -> Self {
+        let uri = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.uri().path().to_string(), |x| x.to_string());
+
+        Self {
+            uri,
+            headers: req
+                .headers()
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+                .collect(),
+            remote_addr: None,
+            meta: ClientMeta::new(),
+        }
+    }
+}
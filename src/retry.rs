@@ -0,0 +1,178 @@
+use std::{fmt, time::Duration};
+
+use crate::{Error, Event, EventTx};
+
+/// A sender retrying events the queue is too full for, returned by
+/// [`EventTxExt::retrying`](crate::EventTxExt::retrying).
+///
+/// A bounded queue rejects events with [`Error::QueueFull`] while the broadcast loop is behind.
+/// Rather than failing right away, [`send_with_retry`](Self::send_with_retry) backs off
+/// exponentially, waiting `base_delay`, then twice as long, and so on, for up to `max_retries`
+/// retries.
+#[derive(Clone)]
+pub struct RetryingEventTx {
+    inner: EventTx,
+    max_retries: u32,
+    base_delay: Duration,
+}
+
+impl RetryingEventTx {
+    pub(crate) fn new(inner: EventTx, max_retries: u32, base_delay: Duration) -> Self {
+        Self {
+            inner,
+            max_retries,
+            base_delay,
+        }
+    }
+
+    /// Queues `event` like [`EventTx::send`], retrying while the queue is full.
+    ///
+    /// Fails once the queue was still full after the last retry, or right away with any other
+    /// error such as [`Error::ChannelClosed`], which retrying can't fix. The event is handed
+    /// back in the error.
+    pub async fn send_with_retry(&self, event: Event) -> Result<(), RetriesExhausted> {
+        let mut attempt = 0;
+
+        loop {
+            let error = match self.inner.send(event.clone()) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            if !matches!(error, Error::QueueFull) || attempt >= self.max_retries {
+                return Err(RetriesExhausted {
+                    attempts: attempt + 1,
+                    event,
+                    error,
+                });
+            }
+
+            tokio::time::sleep(self.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Returns how long to wait before retry number `attempt`, counting from zero.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.checked_pow(attempt).unwrap_or(u32::MAX))
+    }
+
+    /// Returns the number of times an event is retried.
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// Returns how long the first retry waits.
+    pub fn base_delay(&self) -> Duration {
+        self.base_delay
+    }
+}
+
+impl fmt::Debug for RetryingEventTx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryingEventTx")
+            .field("max_retries", &self.max_retries)
+            .field("base_delay", &self.base_delay)
+            .finish()
+    }
+}
+
+/// The error of [`RetryingEventTx::send_with_retry`], carrying the event that couldn't be
+/// queued.
+#[derive(Debug)]
+pub struct RetriesExhausted {
+    attempts: u32,
+    event: Event,
+    error: Error,
+}
+
+impl RetriesExhausted {
+    /// Returns how many times queueing the event was tried.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Returns the error of the last attempt.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// Returns the event that couldn't be queued.
+    pub fn into_event(self) -> Event {
+        self.event
+    }
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gave up after {} attempts: {}",
+            self.attempts, self.error
+        )
+    }
+}
+
+impl std::error::Error for RetriesExhausted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::tx::bounded;
+    use crate::SerializableEvent;
+
+    struct Tick;
+
+    impl SerializableEvent for Tick {
+        fn serialize(&self) -> String {
+            String::from("tick")
+        }
+    }
+
+    fn tick() -> Event {
+        Event::new("/ticks", Tick)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_back_off_exponentially() {
+        let (tx, mut rx) = bounded(1);
+        tx.send(tick()).unwrap();
+
+        let retrying = RetryingEventTx::new(tx, 3, Duration::from_millis(10));
+        let start = Instant::now();
+        let e = retrying.send_with_retry(tick()).await.unwrap_err();
+
+        assert_eq!(e.attempts(), 4);
+        assert!(matches!(e.error(), Error::QueueFull));
+        assert_eq!(start.elapsed(), Duration::from_millis(10 + 20 + 40));
+
+        // Room freed up while backing off is used by the next retry.
+        let sending = tokio::spawn(async move { retrying.send_with_retry(tick()).await });
+        tokio::time::sleep(Duration::from_millis(15)).await;
+        rx.recv().await.unwrap();
+        sending.await.unwrap().unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(70 + 30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closed_queues_are_not_retried() {
+        let (tx, rx) = bounded(1);
+        drop(rx);
+
+        let retrying = RetryingEventTx::new(tx, 3, Duration::from_secs(1));
+        let start = Instant::now();
+        let e = retrying.send_with_retry(tick()).await.unwrap_err();
+
+        assert_eq!(e.attempts(), 1);
+        assert!(matches!(e.error(), Error::ChannelClosed));
+        assert_eq!(e.into_event(), tick());
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,175 @@
+//! Settings of single resources or patterns overriding the ones of the server, see
+//! [`ServerBuilder::route`](crate::server::ServerBuilder::route).
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::batch::Batching;
+use crate::qos::QoS;
+
+/// The configured resources and patterns, shared by the server with its senders.
+pub(crate) type Routes = Arc<RwLock<HashMap<String, RouteConfig>>>;
+
+/// The configuration of a resource or pattern, see
+/// [`ServerBuilder::route`](crate::server::ServerBuilder::route). Anything not set keeps the
+/// behavior of the server.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use pushevent::server::{QoS, RouteConfig};
+///
+/// let progress = RouteConfig::new()
+///     .coalesce(Duration::from_millis(100))
+///     .replay(16);
+/// let alerts = RouteConfig::new().qos(QoS::AtLeastOnce);
+/// let public = RouteConfig::new().skip_auth();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteConfig {
+    pub(crate) qos: QoS,
+    pub(crate) delivery: DeliveryMode,
+    pub(crate) batching: Option<Batching>,
+    pub(crate) replay: Option<usize>,
+    pub(crate) authenticate: bool,
+    pub(crate) max_payload: Option<usize>,
+}
+
+impl RouteConfig {
+    /// Returns a configuration changing nothing.
+    pub fn new() -> Self {
+        Self {
+            qos: QoS::AtMostOnce,
+            delivery: DeliveryMode::Broadcast,
+            batching: None,
+            replay: None,
+            authenticate: true,
+            max_payload: None,
+        }
+    }
+
+    /// Delivers the events published to the route with `qos`, see
+    /// [`Server::set_route_qos`](crate::server::Server::set_route_qos).
+    pub fn qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Delivers the events published to the route with `mode`, see
+    /// [`Server::set_route_delivery`](crate::server::Server::set_route_delivery).
+    pub fn delivery(mut self, mode: DeliveryMode) -> Self {
+        self.delivery = mode;
+        self
+    }
+
+    /// Batches the events published to the route, see
+    /// [`Server::set_route_batching`](crate::server::Server::set_route_batching).
+    pub fn batching(mut self, batching: Batching) -> Self {
+        self.batching = Some(batching);
+        self
+    }
+
+    /// Batches the events published to the route within `window` of each other, however many
+    /// there are.
+    pub fn coalesce(self, window: Duration) -> Self {
+        self.batching(Batching {
+            window,
+            max_events: usize::MAX,
+        })
+    }
+
+    /// Replays at most the last `events` events a client resuming its session on the route
+    /// missed, `0` disables replaying them. Events are only kept with
+    /// [`ServerBuilder::replay_on_reconnect`](crate::server::ServerBuilder::replay_on_reconnect),
+    /// whose history is shared by every route.
+    pub fn replay(mut self, events: usize) -> Self {
+        self.replay = Some(events);
+        self
+    }
+
+    /// Limits the payloads of the events published to the route to `bytes`, see
+    /// [`ServerBuilder::max_payload`](crate::server::ServerBuilder::max_payload).
+    pub fn max_payload(mut self, bytes: usize) -> Self {
+        self.max_payload = Some(bytes);
+        self
+    }
+
+    /// Lets clients connect to the route without passing the
+    /// [authenticator](crate::server::ServerBuilder::authenticator). The middleware still runs.
+    pub fn skip_auth(mut self) -> Self {
+        self.authenticate = false;
+        self
+    }
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which subscribers of a route receive its events, see
+/// [`Server::set_route_delivery`](crate::server::Server::set_route_delivery).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum DeliveryMode {
+    /// Every subscriber receives every event.
+    #[default]
+    Broadcast,
+    /// Every event goes to a single subscriber, taking turns, like jobs handed to a pool of
+    /// workers. Subscribers with more than `max_queue_depth` frames waiting to be written are
+    /// skipped, unless all of them are. Events with a partition key stick to one subscriber
+    /// instead.
+    RoundRobin {
+        /// The number of frames waiting for a subscriber above which it is skipped.
+        max_queue_depth: usize,
+    },
+}
+
+/// Returns the configuration of `res`: the one of the resource itself, else the one of the
+/// longest pattern matching it.
+pub(crate) fn route<'a>(
+    routes: &'a HashMap<String, RouteConfig>,
+    res: &str,
+) -> Option<&'a RouteConfig> {
+    routes.get(res).or_else(|| {
+        routes
+            .iter()
+            .filter(|(pattern, _)| crate::pattern::matches(pattern, res))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, config)| config)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_longest_pattern_wins() {
+        let mut routes = HashMap::new();
+        routes.insert("*".to_string(), RouteConfig::new().qos(QoS::AtLeastOnce));
+        routes.insert(
+            "/alerts/*".to_string(),
+            RouteConfig::new().qos(QoS::ExactlyOnce),
+        );
+        routes.insert("/alerts/eu/*".to_string(), RouteConfig::new().skip_auth());
+        routes.insert("/alerts/low".to_string(), RouteConfig::new());
+
+        let qos = |res| route(&routes, res).map(|x| x.qos);
+        assert_eq!(qos("/news"), Some(QoS::AtLeastOnce));
+        assert_eq!(qos("/alerts/high"), Some(QoS::ExactlyOnce));
+        assert_eq!(qos("/alerts/low"), Some(QoS::AtMostOnce));
+
+        // Configurations aren't merged, the longest pattern alone applies.
+        let eu = route(&routes, "/alerts/eu/paris").unwrap();
+        assert_eq!(eu.qos, QoS::AtMostOnce);
+        assert!(!eu.authenticate);
+
+        routes.remove("*");
+        assert_eq!(route(&routes, "/news"), None);
+    }
+}
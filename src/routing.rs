@@ -0,0 +1,342 @@
+use crate::pattern;
+
+/// Maps resources to values like a `HashMap<String, V>`, but keeps them in a compressed radix
+/// trie. Looking up a resource takes time linear in its length, and the
+/// [patterns](pattern::matches) matching a resource are all found in the same walk down the trie
+/// instead of by testing every pattern.
+pub(crate) struct ResourceTrie<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+struct Node<V> {
+    /// The bytes of the keys between the parent and this node, only empty for the root.
+    label: Vec<u8>,
+    /// The key ending at this node and its value, if any.
+    entry: Option<(Box<str>, V)>,
+    /// Sorted by the first byte of their label, which siblings never share.
+    children: Vec<Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new(label: &[u8]) -> Self {
+        Self {
+            label: label.to_vec(),
+            entry: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        self.children.binary_search_by_key(&byte, |x| x.label[0])
+    }
+
+    fn child(&self, byte: u8) -> Option<&Self> {
+        let i = self.child_index(byte).ok()?;
+        Some(&self.children[i])
+    }
+
+    fn value(&self) -> Option<(&str, &V)> {
+        self.entry.as_ref().map(|(k, v)| (&**k, v))
+    }
+}
+
+impl<V> ResourceTrie<V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            root: Node::new(&[]),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of keys.
+    #[allow(dead_code)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the node `key` ends at, if the trie has one.
+    fn find(&self, key: &str) -> Option<&Node<V>> {
+        let mut node = &self.root;
+        let mut rest = key.as_bytes();
+
+        while let Some(byte) = rest.first() {
+            node = node.child(*byte)?;
+            rest = rest.strip_prefix(node.label.as_slice())?;
+        }
+
+        Some(node)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&V> {
+        self.find(key)?.value().map(|(_, v)| v)
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let mut node = &mut self.root;
+        let mut rest = key.as_bytes();
+
+        while let Some(byte) = rest.first() {
+            let i = node.child_index(*byte).ok()?;
+            node = &mut node.children[i];
+            rest = rest.strip_prefix(node.label.as_slice())?;
+        }
+
+        node.entry.as_mut().map(|(_, v)| v)
+    }
+
+    /// Returns the value of `key`, inserting the one returned by `f` if there is none.
+    pub(crate) fn get_or_insert_with(&mut self, key: &str, f: impl FnOnce() -> V) -> &mut V {
+        let mut node = &mut self.root;
+        let mut rest = key.as_bytes();
+
+        while let Some(byte) = rest.first() {
+            let i = match node.child_index(*byte) {
+                Ok(i) => i,
+                Err(i) => {
+                    node.children.insert(i, Node::new(rest));
+                    node = &mut node.children[i];
+                    break;
+                }
+            };
+
+            let child = &mut node.children[i];
+            let common = child
+                .label
+                .iter()
+                .zip(rest)
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            // The key leaves the edge halfway, split it so that a node ends where they part.
+            if common < child.label.len() {
+                let mut suffix = std::mem::replace(child, Node::new(&rest[..common]));
+                suffix.label.drain(..common);
+                child.children.push(suffix);
+            }
+
+            node = child;
+            rest = &rest[common..];
+        }
+
+        if node.entry.is_none() {
+            self.len += 1;
+        }
+
+        &mut node.entry.get_or_insert_with(|| (key.into(), f())).1
+    }
+
+    /// Removes `key`, returning its value.
+    pub(crate) fn remove(&mut self, key: &str) -> Option<V> {
+        let removed = remove(&mut self.root, key.as_bytes());
+        if removed.is_some() {
+            self.len -= 1;
+        }
+
+        removed
+    }
+
+    /// Returns every key and its value, in no particular order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        let mut stack = vec![&self.root];
+
+        std::iter::from_fn(move || loop {
+            let node = stack.pop()?;
+            stack.extend(&node.children);
+
+            if let Some(x) = node.value() {
+                return Some(x);
+            }
+        })
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// Returns the keys that are patterns matching `res`, other than `res` itself, and their
+    /// values.
+    pub(crate) fn patterns_matching<'a>(
+        &'a self,
+        res: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a V)> + 'a {
+        let bytes = res.as_bytes();
+
+        // The nodes of the keys that are prefixes of `res`.
+        let path = std::iter::successors(Some((&self.root, 0)), move |(node, depth)| {
+            let rest = &bytes[*depth..];
+            let child = node.child(*rest.first()?)?;
+            rest.starts_with(&child.label)
+                .then(|| (child, depth + child.label.len()))
+        });
+
+        path.flat_map(move |(node, depth)| {
+            let rest = &bytes[depth..];
+            // A pattern may also end on an edge the walk leaves, e.g. `/orders/*` for `/orders/1`
+            // when no other key starts with `/orders/`.
+            let next = rest.first().copied().filter(|x| *x != b'*');
+            let off_path = Some(b'*')
+                .into_iter()
+                .chain(next)
+                .filter_map(move |x| node.child(x))
+                .filter(move |x| !rest.starts_with(&x.label));
+
+            node.value()
+                .into_iter()
+                .chain(off_path.filter_map(Node::value))
+        })
+        .filter(move |(key, _)| {
+            *key != res && pattern::is_pattern(key) && pattern::matches(key, res)
+        })
+    }
+}
+
+/// Removes `rest` below `node`, merging the nodes left without a key into their only child.
+fn remove<V>(node: &mut Node<V>, rest: &[u8]) -> Option<V> {
+    let byte = match rest.first() {
+        Some(x) => *x,
+        None => return node.entry.take().map(|(_, v)| v),
+    };
+
+    let i = node.child_index(byte).ok()?;
+    let child = &mut node.children[i];
+    let removed = remove(child, rest.strip_prefix(child.label.as_slice())?)?;
+
+    if child.entry.is_none() {
+        match child.children.len() {
+            0 => {
+                node.children.remove(i);
+            }
+            1 => {
+                let mut only = child.children.pop().expect("one child");
+                let mut label = std::mem::take(&mut child.label);
+                label.extend_from_slice(&only.label);
+                only.label = label;
+                *child = only;
+            }
+            _ => {}
+        }
+    }
+
+    Some(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Instant;
+
+    const KEYS: &[&str] = &[
+        "", "*", "/", "/*", "/a", "/a/*", "/a/b", "/a/b/*", "/ab", "/ab/*", "/a/*/b", "/é", "/è",
+    ];
+
+    fn node_count<V>(node: &Node<V>) -> usize {
+        1 + node.children.iter().map(node_count).sum::<usize>()
+    }
+
+    proptest! {
+        #[test]
+        fn trie_matches_hashmap(ops in proptest::collection::vec((any::<bool>(), 0..KEYS.len()), 1..64)) {
+            let mut trie = ResourceTrie::new();
+            let mut model = HashMap::new();
+
+            for (insert, key) in ops {
+                let key = KEYS[key];
+                if insert {
+                    *trie.get_or_insert_with(key, || 0) += 1;
+                    *model.entry(key).or_insert(0) += 1;
+                } else {
+                    assert_eq!(trie.remove(key), model.remove(key));
+                }
+
+                assert_eq!(trie.len(), model.len());
+                for key in KEYS {
+                    assert_eq!(trie.get(key), model.get(key));
+
+                    let mut expected: Vec<_> = model
+                        .keys()
+                        .filter(|x| *x != key && pattern::is_pattern(x) && pattern::matches(x, key))
+                        .copied()
+                        .collect();
+                    let mut actual: Vec<_> = trie.patterns_matching(key).map(|(k, _)| k).collect();
+                    expected.sort_unstable();
+                    actual.sort_unstable();
+                    assert_eq!(actual, expected, "patterns matching {:?}", key);
+                }
+
+                let mut keys: Vec<_> = trie.keys().collect();
+                keys.sort_unstable();
+                let mut expected: Vec<_> = model.keys().copied().collect();
+                expected.sort_unstable();
+                assert_eq!(keys, expected);
+
+                // Removed keys don't leave empty nodes behind.
+                assert!(node_count(&trie.root) <= 2 * model.len() + 1);
+            }
+        }
+    }
+
+    /// Compares lookups against a `HashMap` holding the same 10k routes, with the patterns kept
+    /// aside the way the registry used to, run with
+    /// `cargo test --release routing -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_against_hashmap() {
+        const ROUTES: usize = 10_000;
+        const LOOKUPS: usize = 100;
+        const ROUNDS: usize = 1_000;
+
+        let mut trie = ResourceTrie::new();
+        let mut map = HashMap::new();
+        let mut patterns = HashSet::new();
+        for i in 0..ROUTES {
+            let route = format!("/library/{}/items/{}", i % 100, i);
+            *trie.get_or_insert_with(&route, || 0) += 1;
+            map.insert(route, 1);
+        }
+        for i in 0..100 {
+            let route = format!("/library/{}/*", i);
+            trie.get_or_insert_with(&route, || 1);
+            map.insert(route.clone(), 1);
+            patterns.insert(route);
+        }
+
+        let lookups: Vec<_> = (0..LOOKUPS)
+            .map(|i| format!("/library/{}/items/{}", i % 100, i * 97 % ROUTES))
+            .collect();
+
+        let start = Instant::now();
+        let mut found = 0;
+        for _ in 0..ROUNDS {
+            for res in &lookups {
+                found += trie.get(res).copied().unwrap_or(0);
+                found += trie.patterns_matching(res).count();
+            }
+        }
+        let trie_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut expected = 0;
+        for _ in 0..ROUNDS {
+            for res in &lookups {
+                expected += map.get(res).copied().unwrap_or(0);
+                expected += patterns
+                    .iter()
+                    .filter(|x| *x != res && pattern::matches(x, res))
+                    .count();
+            }
+        }
+        let map_time = start.elapsed();
+
+        assert_eq!(found, expected);
+        println!(
+            "{} lookups in {} routes: trie {:?}, hashmap {:?}",
+            LOOKUPS * ROUNDS,
+            ROUTES,
+            trie_time,
+            map_time
+        );
+    }
+}
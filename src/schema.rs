@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::{pattern, BoxError, Event};
+
+pub(crate) type OnValidationError = Arc<dyn Fn(&str, &str, &str) + Send + Sync>;
+
+/// The JSON Schemas events are validated against before they are delivered, see
+/// [`ServerBuilder::schema`](crate::server::ServerBuilder::schema).
+#[derive(Default)]
+pub(crate) struct Schemas {
+    /// The compiled schemas with the patterns they are registered for.
+    schemas: Vec<(String, jsonschema::Validator)>,
+    on_error: Option<OnValidationError>,
+}
+
+impl Schemas {
+    /// Compiles `schemas`, failing on the first invalid schema.
+    pub(crate) fn compile(
+        schemas: Vec<(String, Value)>,
+        on_error: Option<OnValidationError>,
+    ) -> Result<Self, BoxError> {
+        let schemas = schemas
+            .into_iter()
+            .map(|(pattern, schema)| {
+                jsonschema::validator_for(&schema)
+                    .map(|x| (pattern, x))
+                    .map_err(|e| format!("invalid schema: {}", e))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { schemas, on_error })
+    }
+
+    /// Returns whether the payload of `event` is valid according to every schema matching its
+    /// resource. Reports invalid payloads to the validation error callback.
+    pub(crate) fn check(&self, event: &Event) -> bool {
+        let mut matching = self
+            .schemas
+            .iter()
+            .filter(|(pattern, _)| pattern::matches(pattern, event.res()))
+            .peekable();
+
+        // Only parsed when there is something to validate.
+        if matching.peek().is_none() {
+            return true;
+        }
+
+        let error = match serde_json::from_str::<Value>(event.payload()) {
+            Ok(payload) => matching
+                .find_map(|(_, schema)| schema.validate(&payload).err().map(|e| e.to_string())),
+            Err(e) => Some(format!("payload is not JSON: {}", e)),
+        };
+
+        let error = match error {
+            Some(x) => x,
+            None => return true,
+        };
+
+        tracing::debug!("dropping invalid event for {}: {}", event.res(), error);
+        if let Some(on_error) = &self.on_error {
+            on_error(event.res(), event.payload(), &error);
+        }
+
+        false
+    }
+}
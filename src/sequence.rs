@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use crate::client::ClientId;
+use crate::pattern;
+
+/// Events a client should have been offered but wasn't, see
+/// [`ServerBuilder::detect_sequence_gaps`](crate::server::ServerBuilder::detect_sequence_gaps).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Gap {
+    pub(crate) resource: String,
+    /// The id of the last event of the resource the client was offered.
+    pub(crate) from: u64,
+    /// The id of the last event of the resource the client missed.
+    pub(crate) to: u64,
+}
+
+/// Remembers the last event of every resource, and the last one offered to every client, so
+/// that the fan-out skipping a client is noticed on the next event.
+///
+/// An event counts as offered once the fan-out considered the client for it, also if the
+/// per-client filter then rejected it.
+#[derive(Default)]
+pub(crate) struct Sequences {
+    /// resource -> the id of its last event.
+    resources: HashMap<String, u64>,
+    /// client -> resource -> the id of the last event of the resource it was offered.
+    clients: HashMap<ClientId, HashMap<String, u64>>,
+}
+
+impl Sequences {
+    /// Records `id` as the last event of `res`, returning the id of the event before it.
+    pub(crate) fn published(&mut self, res: &str, id: u64) -> Option<u64> {
+        self.resources.insert(res.to_string(), id)
+    }
+
+    /// Records that `client` was offered the event `id` of `res`, whose previous event was
+    /// `previous`. Returns the events it missed in between, if any.
+    pub(crate) fn offered(
+        &mut self,
+        client: ClientId,
+        res: &str,
+        previous: Option<u64>,
+        id: u64,
+    ) -> Option<Gap> {
+        let last = self
+            .clients
+            .entry(client)
+            .or_default()
+            .insert(res.to_string(), id);
+
+        // Clients that just subscribed haven't been offered anything yet.
+        match (last, previous) {
+            (Some(from), Some(to)) if from != to => Some(Gap {
+                resource: res.to_string(),
+                from,
+                to,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Forgets what `client` was offered through its subscription to `res`, which may be a
+    /// pattern, so that subscribing to it again doesn't count as a gap.
+    pub(crate) fn unsubscribed(&mut self, client: ClientId, res: &str) {
+        if let Some(resources) = self.clients.get_mut(&client) {
+            resources.retain(|x, _| !pattern::matches(res, x));
+        }
+    }
+
+    pub(crate) fn remove_client(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_events_are_noticed_on_the_next_one() {
+        let (a, b) = (ClientId::next(), ClientId::next());
+        let mut sequences = Sequences::default();
+
+        let publish = |sequences: &mut Sequences, id, offered_to: &[ClientId]| {
+            let previous = sequences.published("/orders", id);
+            offered_to
+                .iter()
+                .filter_map(|x| sequences.offered(*x, "/orders", previous, id))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(publish(&mut sequences, 1, &[a]), []);
+        // `b` subscribes.
+        assert_eq!(publish(&mut sequences, 2, &[a, b]), []);
+        // The fan-out skips `a`, which is noticed when it gets the next event.
+        assert_eq!(publish(&mut sequences, 3, &[b]), []);
+        assert_eq!(
+            publish(&mut sequences, 4, &[a, b]),
+            [Gap {
+                resource: "/orders".to_string(),
+                from: 2,
+                to: 3,
+            }]
+        );
+        assert_eq!(publish(&mut sequences, 5, &[a, b]), []);
+
+        sequences.unsubscribed(a, "/orders/*");
+        sequences.unsubscribed(a, "/orders");
+        assert_eq!(publish(&mut sequences, 6, &[b]), []);
+        assert_eq!(publish(&mut sequences, 7, &[a, b]), []);
+    }
+}
@@ -0,0 +1,4631 @@
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    future::Future,
+    hash::{Hash, Hasher},
+    io,
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use futures_channel::mpsc::{unbounded, UnboundedSender};
+use futures_util::{
+    future::{self, BoxFuture, FutureExt},
+    pin_mut,
+    stream::{self, PollNext, Stream, TryStreamExt},
+    Sink, SinkExt, StreamExt,
+};
+
+use rustc_hash::FxHasher;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, watch, Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tungstenite::{Message, Utf8Bytes};
+
+use crate::audit::{AuditEntry, AuditKind, AuditSink};
+use crate::auth::Authenticator;
+use crate::batch::Batches;
+use crate::bridge::Bridges;
+use crate::client::{Client, ClientId, ClientInfo, ClientMeta, OnRequest};
+use crate::demux::{self, Demultiplexer};
+use crate::fanout::{self, Channels};
+use crate::group::Groups;
+use crate::health::{self, Heartbeat, Timestamp};
+use crate::limits::{self, PayloadLimit, ResourceLimits};
+use crate::local::{LocalSubscribers, LocalSubscription};
+use crate::middleware::{MiddlewareStack, RequestMiddleware};
+use crate::partition::{self, Move, Partitions};
+use crate::pipe::Pipes;
+use crate::protocol::{self, Ack, Control};
+use crate::qos::{self, Acks};
+use crate::replay::{self, Replay};
+use crate::route::{self, Routes};
+#[cfg(feature = "schema")]
+use crate::schema::{OnValidationError, Schemas};
+use crate::sequence::Sequences;
+use crate::session::{SessionManager, Sessions};
+use crate::socket::SocketOptions;
+use crate::tap::Taps;
+use crate::task::TaskGroup;
+use crate::transform::{self, Infallible, Transform, Transformer, Transforms};
+use crate::tx::{self, EventRx, EventTx, QueueState, Queued};
+use crate::{CloseReason, Error, Event, Payload, PublishTarget};
+
+pub use crate::batch::Batching;
+pub use crate::fanout::BroadcastBackend;
+pub use crate::health::HealthReport;
+pub use crate::pipe::PipeHandle;
+pub use crate::qos::QoS;
+pub use crate::route::{DeliveryMode, RouteConfig};
+pub use crate::tap::{TapFilter, TapOutcome, TapRecord};
+/// The settings of the websocket connections, see [`ServerBuilder::websocket_config`]. Not
+/// covered by semver.
+#[cfg(feature = "unstable-websocket-config")]
+pub use tungstenite::protocol::WebSocketConfig;
+
+/// How many events are kept for a paused route, see [`Server::pause_route`].
+const PAUSED_ROUTE_CAPACITY: usize = 1024;
+
+/// How many events are kept for a round-robin route without subscribers, see
+/// [`DeliveryMode::RoundRobin`].
+const UNASSIGNED_CAPACITY: usize = 1024;
+
+/// How many frames are kept for a paused client by default, see [`PausePolicy`].
+const PAUSED_CLIENT_CAPACITY: usize = 1024;
+
+/// How many frames are kept for a client until its snapshot was sent, see
+/// [`ServerBuilder::snapshot`]. Clients with more frames are disconnected.
+const SNAPSHOT_HOLD_CAPACITY: usize = 4096;
+
+type Tx = UnboundedSender<Message>;
+type OnConnect = Arc<dyn Fn(&ClientInfo) + Send + Sync>;
+type OnMessage = Arc<dyn Fn(&ClientInfo, Payload) + Send + Sync>;
+type ClientFilter = Arc<dyn Fn(&ClientInfo, &Event) -> bool + Send + Sync>;
+type SnapshotHook =
+    Arc<dyn Fn(&str, &ClientInfo) -> BoxFuture<'static, Option<Vec<Event>>> + Send + Sync>;
+
+/// A subscribed client as stored in the registry.
+#[derive(Clone)]
+pub(crate) struct Peer {
+    pub(crate) tx: Tx,
+    pub(crate) info: Arc<ClientInfo>,
+    /// The number of frames in `tx` the connection hasn't taken out yet.
+    pub(crate) queued: Arc<AtomicUsize>,
+    pub(crate) connected_at: SystemTime,
+    /// The frames held back while the client is paused.
+    pub(crate) hold: Arc<Hold>,
+    /// When the client last sent a ping frame.
+    pub(crate) last_ping: Arc<Timestamp>,
+}
+
+/// The frames held back for a client while it is paused, see [`Server::pause_client`], or its
+/// snapshot wasn't sent yet, see [`ServerBuilder::snapshot`].
+pub(crate) struct Hold {
+    /// Whether frames are held, checked before locking `held`.
+    holding: AtomicBool,
+    held: Mutex<Held>,
+    /// How many frames are held while the client is paused, `0` drops them.
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct Held {
+    frames: VecDeque<Message>,
+    /// Whether the client is paused.
+    paused: bool,
+    /// The number of snapshots taken for the client that weren't sent yet.
+    snapshots: usize,
+}
+
+impl Hold {
+    fn new(policy: PausePolicy) -> Self {
+        Self {
+            holding: AtomicBool::new(false),
+            held: Mutex::default(),
+            capacity: policy.capacity(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Held> {
+        self.held.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Peer {
+    /// Queues `frame` to be sent to the client, or holds it back while the client is paused or
+    /// its snapshot wasn't sent yet. Returns `false` if the connection is closed.
+    fn send(&self, frame: Message) -> bool {
+        if self.hold.holding.load(Ordering::Acquire) {
+            let mut held = self.hold.lock();
+
+            // Nothing is dropped before the snapshot, it would miss the event.
+            if held.snapshots > 0 {
+                if self.tx.is_closed() {
+                    return false;
+                }
+                if held.frames.len() == SNAPSHOT_HOLD_CAPACITY {
+                    tracing::warn!(
+                        "{}: disconnecting, too many frames held until the snapshot is sent",
+                        self.info.addr
+                    );
+                    held.frames.clear();
+                    self.send_now(CloseReason::new(1013, "snapshot not ready").into_message());
+                    self.tx.close_channel();
+                    return false;
+                }
+                held.frames.push_back(frame);
+                return true;
+            }
+
+            // Resumed while waiting for the lock otherwise.
+            if held.paused {
+                if self.hold.capacity == 0 {
+                    return !self.tx.is_closed();
+                }
+                if held.frames.len() == self.hold.capacity {
+                    tracing::warn!(
+                        "{}: dropping the oldest frame held for the paused client",
+                        self.info.addr
+                    );
+                    held.frames.pop_front();
+                }
+                held.frames.push_back(frame);
+                return !self.tx.is_closed();
+            }
+        }
+
+        self.send_now(frame)
+    }
+
+    /// Queues `frame` to be sent to the client, even if it is paused. Returns `false` if the
+    /// connection is closed.
+    fn send_now(&self, frame: Message) -> bool {
+        // Counted first, so that the connection never takes out a frame that isn't counted yet.
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if self.tx.unbounded_send(frame).is_err() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+
+        true
+    }
+
+    fn connection_info(&self, mut resources: Vec<String>) -> ConnectionInfo {
+        resources.sort_unstable();
+
+        ConnectionInfo {
+            id: self.info.id,
+            addr: self.info.addr,
+            connected_at: self.connected_at,
+            resources,
+            queue_depth: self.queued.load(Ordering::Relaxed),
+            paused: self.hold.lock().paused,
+            last_ping_at: self.last_ping.get(),
+            metadata: self.info.metadata.clone(),
+            meta: self.info.meta.to_map(),
+            tags: self.info.tags.to_map(),
+        }
+    }
+
+    /// Holds back the frames sent to the client from now on. Returns `false` if it already was
+    /// paused.
+    fn pause(&self) -> bool {
+        let mut held = self.hold.lock();
+        if held.paused {
+            return false;
+        }
+
+        held.paused = true;
+        self.hold.holding.store(true, Ordering::Release);
+        true
+    }
+
+    /// Queues the frames held back while the client was paused, unless a snapshot is still
+    /// pending, in which case they are sent after it. Returns `false` if it wasn't paused.
+    fn resume(&self) -> bool {
+        let mut held = self.hold.lock();
+        if !held.paused {
+            return false;
+        }
+
+        held.paused = false;
+        if held.snapshots == 0 {
+            self.release(&mut held);
+        }
+        true
+    }
+
+    /// Holds back the frames sent to the client from now on until its snapshot was sent with
+    /// [`send_snapshot`](Self::send_snapshot). Returns where the snapshot goes among the held
+    /// frames.
+    fn hold_for_snapshot(&self) -> usize {
+        let mut held = self.hold.lock();
+        held.snapshots += 1;
+        self.hold.holding.store(true, Ordering::Release);
+        held.frames.len()
+    }
+
+    /// Sends the snapshot of the client before the frames held back since
+    /// [`hold_for_snapshot`](Self::hold_for_snapshot) returned `position`, then lets the frames
+    /// through unless the client is paused or another snapshot is pending.
+    fn send_snapshot(&self, position: usize, frames: Vec<Message>) {
+        let mut held = self.hold.lock();
+        held.snapshots -= 1;
+
+        // Frames are only added while snapshots are pending, an earlier snapshot put before
+        // `position` only moves this one ahead of more frames.
+        let position = position.min(held.frames.len());
+        let later = held.frames.split_off(position);
+        held.frames.extend(frames);
+        held.frames.extend(later);
+
+        if held.snapshots > 0 {
+            return;
+        }
+        if !held.paused {
+            return self.release(&mut held);
+        }
+
+        let excess = held.frames.len().saturating_sub(self.hold.capacity);
+        if excess > 0 {
+            tracing::warn!(
+                "{}: dropping the {} oldest frames held for the paused client",
+                self.info.addr,
+                excess
+            );
+            held.frames.drain(..excess);
+        }
+    }
+
+    /// Queues the held frames and stops holding them.
+    fn release(&self, held: &mut Held) {
+        // Queued while locked, so that frames sent concurrently can't overtake them.
+        for frame in held.frames.drain(..) {
+            self.send_now(frame);
+        }
+        self.hold.holding.store(false, Ordering::Release);
+    }
+
+    /// Tells the client to reconnect after `reconnect_after` and closes the connection.
+    fn drain(&self, reconnect_after: Duration) {
+        let event = format!(
+            r#"{{"type":"draining","reconnect_after_ms":{}}}"#,
+            reconnect_after.as_millis()
+        );
+
+        if protocol::receives_notices(&self.info) {
+            let _ = self.send_now(Message::Text(event.into()));
+        }
+        let _ = self.send_now(CloseReason::service_restart().into_message());
+    }
+}
+
+/// A handle sending frames to a single client, bypassing the event queue, returned by
+/// [`Server::get_client_tx`].
+///
+/// The handle is cheap to clone and doesn't keep the connection open.
+#[derive(Clone)]
+pub struct ClientTx {
+    peer: Peer,
+    taps: Arc<Taps>,
+}
+
+impl ClientTx {
+    /// Returns the id of the client.
+    pub fn id(&self) -> ClientId {
+        self.peer.info.id
+    }
+
+    /// Sends `payload` to the client as a text frame, as is rather than in an event envelope.
+    /// The frame is held back while the client is [paused](Server::pause_client). Fails once
+    /// the client disconnected.
+    pub fn send(&self, payload: String) -> Result<(), ClientGone> {
+        let info = &self.peer.info;
+        self.taps
+            .record(info, &info.resource, &payload, !self.peer.tx.is_closed());
+
+        match self.peer.send(Message::Text(payload.into())) {
+            true => Ok(()),
+            false => Err(ClientGone),
+        }
+    }
+
+    /// Returns whether the client disconnected.
+    pub fn is_closed(&self) -> bool {
+        self.peer.tx.is_closed()
+    }
+}
+
+impl fmt::Debug for ClientTx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTx")
+            .field("id", &self.peer.info.id)
+            .finish()
+    }
+}
+
+/// A route paused with [`Server::pause_route`].
+#[derive(Default)]
+pub(crate) struct PausedRoute {
+    /// The events published to the route since it was paused.
+    held: VecDeque<Event>,
+    /// Whether [`Server::resume_route`] was called, the route is resumed once the broadcast loop
+    /// reaches the events queued until then.
+    resuming: bool,
+}
+
+/// The error of [`ClientTx::send`], the client disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientGone;
+
+impl fmt::Display for ClientGone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the client disconnected")
+    }
+}
+
+impl std::error::Error for ClientGone {}
+
+/// Configures and starts a pushevent server.
+///
+/// # Example
+/// ```no_run
+/// use pushevent::server::ServerBuilder;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let tx = ServerBuilder::new()
+///     .addr("0.0.0.0:3012")
+///     .capacity(1024)
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub struct ServerBuilder {
+    addr: String,
+    capacity: Option<usize>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    middleware: MiddlewareStack,
+    on_connect: Option<OnConnect>,
+    on_disconnect: Option<OnConnect>,
+    on_message: Option<OnMessage>,
+    per_client_filter: Option<ClientFilter>,
+    transforms: Transforms,
+    snapshots: Vec<(String, SnapshotHook)>,
+    max_protocol_version: u8,
+    subprotocols: Vec<String>,
+    reject_unsupported_subprotocols: bool,
+    ack_timeout: Duration,
+    ping_interval: Option<Duration>,
+    write_timeout: Option<Duration>,
+    handshake_timeout: Duration,
+    max_pending_handshakes: usize,
+    websocket_config: Option<tungstenite::protocol::WebSocketConfig>,
+    shutdown_grace: Duration,
+    shutdown_timeout: Duration,
+    warm_up: Option<(usize, Duration)>,
+    stall_timeout: Duration,
+    socket: SocketOptions,
+    backend: BroadcastBackend,
+    restart_on_panic: bool,
+    detect_sequence_gaps: bool,
+    shard_count: usize,
+    broadcast_workers: usize,
+    worker_stack_size: Option<usize>,
+    runtime: Option<Handle>,
+    audit: Option<Arc<dyn AuditSink>>,
+    bridges: Bridges,
+    load_shedder: Option<LoadShedder>,
+    pause_policy: PausePolicy,
+    replay_history: Option<usize>,
+    routes: HashMap<String, RouteConfig>,
+    max_payload: usize,
+    limits: ResourceLimits,
+    #[cfg(feature = "schema")]
+    schemas: Vec<(String, serde_json::Value)>,
+    #[cfg(feature = "schema")]
+    on_validation_error: Option<OnValidationError>,
+    #[cfg(feature = "bench-harness")]
+    bench: Option<crate::bench_harness::BenchConfig>,
+}
+
+/// State shared between the accept loop, the connection tasks and the broadcast loop.
+pub(crate) struct ServerInner {
+    pub(crate) clients: Demultiplexer<Peer>,
+    pub(crate) authenticator: Option<Arc<dyn Authenticator>>,
+    pub(crate) middleware: MiddlewareStack,
+    pub(crate) on_connect: Option<OnConnect>,
+    pub(crate) on_disconnect: Option<OnConnect>,
+    pub(crate) on_message: Option<OnMessage>,
+    pub(crate) per_client_filter: Option<ClientFilter>,
+    /// Run in order on every event before it is delivered.
+    pub(crate) transforms: Transforms,
+    /// The hooks returning the state sent to new clients, with the patterns they are
+    /// registered for.
+    pub(crate) snapshots: Vec<(String, SnapshotHook)>,
+    pub(crate) max_protocol_version: u8,
+    /// The subprotocols of the application clients may select.
+    pub(crate) subprotocols: Vec<String>,
+    /// Whether clients offering only unsupported subprotocols are rejected.
+    pub(crate) reject_unsupported_subprotocols: bool,
+    /// The configuration of the resources and patterns, see [`ServerBuilder::route`].
+    pub(crate) routes: Routes,
+    /// The size of payloads accepted, shared with the senders.
+    pub(crate) payload_limit: Arc<PayloadLimit>,
+    /// The events of batched routes waiting to be sent, see [`Server::set_route_batching`].
+    pub(crate) batches: Batches,
+    /// The id of the next event published, see [`ServerInner::next_event_id`].
+    pub(crate) event_ids: AtomicU64,
+    /// The events clients haven't acknowledged yet.
+    pub(crate) acks: Acks,
+    /// How long clients have to acknowledge an event before it is sent again.
+    pub(crate) ack_timeout: Duration,
+    /// How long writing a frame to a client may take before it is disconnected.
+    pub(crate) write_timeout: Option<Duration>,
+    /// How long clients have to complete the websocket handshake.
+    pub(crate) handshake_timeout: Duration,
+    /// The settings of the websocket connections, tungstenite's defaults unless set.
+    pub(crate) websocket_config: Option<tungstenite::protocol::WebSocketConfig>,
+    /// The handshakes that may be in progress at once, a permit each.
+    pub(crate) pending_handshakes: Arc<Semaphore>,
+    /// The handshakes that timed out since the server started.
+    pub(crate) handshake_timeouts: AtomicU64,
+    /// The connections dropped since the server started because too many handshakes were in
+    /// progress.
+    pub(crate) refused_handshakes: AtomicU64,
+    /// Set to `true` once the server shuts down, which stops the accept and broadcast loops.
+    pub(crate) shutdown: watch::Sender<bool>,
+    /// Set to `true` once both the accept and the broadcast loop are running, see
+    /// [`Server::wait_ready`] and [`ServerInner::loop_started`].
+    pub(crate) ready: watch::Sender<bool>,
+    /// The number of clients to wait for before broadcasting and for how long at most, see
+    /// [`ServerBuilder::warm_up`].
+    pub(crate) warm_up: Option<(usize, Duration)>,
+    /// Set to `true` once the broadcast loop takes events out of the queue.
+    pub(crate) warmed_up: watch::Sender<bool>,
+    /// How long a server task may be busy before it is reported as stalled.
+    pub(crate) stall_timeout: Duration,
+    /// Whether the accept loop is running.
+    pub(crate) listener: Heartbeat,
+    /// Whether the broadcast loop is running, and since when it delivers the current event.
+    pub(crate) broadcaster: Heartbeat,
+    pub(crate) last_accepted: Timestamp,
+    pub(crate) last_delivered: Timestamp,
+    /// Set to `true` by [`Server::shutdown`], which closes every connection right away.
+    pub(crate) closing: watch::Sender<bool>,
+    /// Notified every time a client disconnects.
+    pub(crate) disconnected: Notify,
+    /// How long [`Server::run_until_shutdown`] lets clients drain.
+    pub(crate) shutdown_grace: Duration,
+    /// The server tasks that are still running.
+    pub(crate) running: TaskGroup,
+    /// The broadcast loop and workers, while they are delivering events.
+    pub(crate) broadcasting: TaskGroup,
+    /// How long shutting down waits for the events being delivered.
+    pub(crate) shutdown_timeout: Duration,
+    /// Why a server task stopped unexpectedly, if one did.
+    pub(crate) failure: Mutex<Option<String>>,
+    /// Whether the broadcast loop carries on after delivering an event panicked.
+    pub(crate) restart_on_panic: bool,
+    /// The last event offered to every client, when looking for events the fan-out skipped.
+    pub(crate) sequences: Option<Mutex<Sequences>>,
+    /// The events the fan-out skipped a client for, see [`Server::sequence_gaps`].
+    pub(crate) sequence_gaps: AtomicU64,
+    /// The per-resource channels when using [`BroadcastBackend::TokioBroadcast`].
+    pub(crate) channels: Option<Channels>,
+    /// The event history and sessions when replaying events to reconnecting clients.
+    pub(crate) replay: Option<Replay>,
+    /// The sessions grouping the connections of a user, see [`Server::sessions`].
+    pub(crate) sessions: Mutex<Sessions>,
+    /// The groups the application put clients in, see [`Server::join_group`].
+    pub(crate) groups: Mutex<Groups>,
+    /// The paused routes with the events published to them since they were paused.
+    pub(crate) paused_routes: Mutex<HashMap<String, PausedRoute>>,
+    /// The client that received the last event of every resource of a round-robin route.
+    pub(crate) rotations: Mutex<HashMap<String, ClientId>>,
+    /// The client the events of every partition key of round-robin routes go to.
+    pub(crate) partitions: Mutex<Partitions>,
+    /// The events of round-robin routes held until a client subscribes.
+    pub(crate) unassigned: Mutex<HashMap<String, VecDeque<Event>>>,
+    /// What happens to the frames sent to paused clients.
+    pub(crate) pause_policy: PausePolicy,
+    /// The subscribers in the same process, see [`Server::subscribe_local`].
+    pub(crate) local: Arc<LocalSubscribers>,
+    /// The taps recording the frames sent to clients, see [`Server::tap`].
+    pub(crate) taps: Arc<Taps>,
+    /// The routes events are copied between, see [`Server::pipe`].
+    pub(crate) pipes: Arc<Pipes>,
+    /// The delivery statistics of every resource an event was published to.
+    pub(crate) stats: Mutex<HashMap<String, ResourceStats>>,
+    /// The payload of the last event delivered to every resource.
+    pub(crate) last_events: RwLock<HashMap<String, Utf8Bytes>>,
+    /// Which resources clients may subscribe to.
+    pub(crate) limits: ResourceLimits,
+    /// The schemas events are validated against before they are delivered.
+    #[cfg(feature = "schema")]
+    pub(crate) schemas: Schemas,
+    /// The state of the event channel.
+    pub(crate) queue: Arc<QueueState>,
+    /// The runtime the server tasks are spawned on.
+    pub(crate) runtime: Handle,
+    /// Where handshakes, subscription changes and disconnects are recorded.
+    pub(crate) audit: Option<Arc<dyn AuditSink>>,
+    /// Where delivered events are forwarded, see [`ServerBuilder::bridge`].
+    pub(crate) bridges: Bridges,
+}
+
+impl ServerInner {
+    /// Records that a server task stopped unexpectedly. Only the first failure is kept.
+    fn fail(&self, reason: String) {
+        tracing::error!("{}", reason);
+        self.failure
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_insert(reason);
+    }
+
+    /// Called by the accept and the broadcast loop once they run, marks the server as ready when
+    /// both do.
+    fn loop_started(&self) {
+        // Checked under the lock of `ready`, so that the loop starting last sees the other one.
+        self.ready.send_if_modified(|ready| {
+            let started = self.listener.is_running() && self.broadcaster.is_running();
+            let changed = started && !*ready;
+            *ready |= started;
+            changed
+        });
+    }
+
+    /// Returns the snapshot hook of `res`, the one registered for the longest pattern matching
+    /// it.
+    fn snapshot_hook(&self, res: &str) -> Option<&SnapshotHook> {
+        self.snapshots
+            .iter()
+            .filter(|(pattern, _)| crate::pattern::matches(pattern, res))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, hook)| hook)
+    }
+
+    /// Copies every event delivered to `from` to `to`, see [`Server::pipe`].
+    pub(crate) fn pipe(
+        &self,
+        from: &str,
+        to: &str,
+        transformer: Option<Box<dyn Transformer>>,
+    ) -> PipeHandle {
+        self.pipes.add(from, to, transformer.map(Arc::from))
+    }
+
+    /// Returns whether `client` should receive `event` according to the per-client filter.
+    pub(crate) fn accepts(&self, client: &ClientInfo, event: &Event) -> bool {
+        self.per_client_filter
+            .as_ref()
+            .is_none_or(|filter| filter(client, event))
+    }
+
+    /// Adds the delivery of an event to the statistics of `res`.
+    fn record(&self, res: &str, delivery: Delivery) {
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        let stats = match stats.get_mut(res) {
+            Some(x) => x,
+            None => stats.entry(res.to_string()).or_default(),
+        };
+
+        stats.events_sent += delivery.sent;
+        stats.bytes_sent += delivery.bytes;
+        stats.failed_sends += delivery.failed;
+        stats.subscriber_high_water = stats.subscriber_high_water.max(delivery.subscribers);
+        stats.last_event_at = Some(std::time::Instant::now());
+        self.last_delivered.set_now();
+        self.middleware
+            .on_broadcast(res, delivery.subscribers, delivery.bytes);
+    }
+
+    /// Sends every batch waiting for its window to end.
+    fn flush_batches(&self) {
+        for (res, events) in self.batches.take_all() {
+            deliver_batch(self, &res, events);
+        }
+    }
+
+    /// Records what happened to the connection of `info` in the audit log, if there is one.
+    pub(crate) fn audit(&self, kind: AuditKind, info: &ClientInfo, resource: &str) {
+        if let Some(audit) = &self.audit {
+            audit.record(AuditEntry::new(
+                kind,
+                info.addr,
+                resource,
+                Some(info.id),
+                &info.meta,
+            ));
+        }
+    }
+
+    /// Returns the configuration of `res`, see [`ServerBuilder::route`].
+    pub(crate) fn route(&self, res: &str) -> RouteConfig {
+        route::route(
+            &self.routes.read().unwrap_or_else(PoisonError::into_inner),
+            res,
+        )
+        .copied()
+        .unwrap_or_default()
+    }
+
+    /// Returns a fresh id for an event being sent, which clients receive in the envelope.
+    pub(crate) fn next_event_id(&self) -> u64 {
+        self.event_ids.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Sends `event` to the client `id` only, see [`Server::send_to`].
+    pub(crate) fn send_to(&self, id: ClientId, event: &Event) -> Result<(), Error> {
+        self.payload_limit.check(event)?;
+        let peer = self.clients.get(id).ok_or(Error::ClientNotFound)?;
+        let event = event.clone().with_id(self.next_event_id());
+        let frame = protocol::encode(&peer.info, &event);
+
+        let sent = peer.send(frame);
+        self.taps
+            .record(&peer.info, event.res(), event.payload(), sent);
+        match sent {
+            true => Ok(()),
+            false => Err(Error::ClientNotFound),
+        }
+    }
+
+    /// Sends `event` to the clients receiving the events published to its resource except
+    /// `exclude`, see [`Server::broadcast_except`].
+    pub(crate) fn broadcast_except(&self, exclude: ClientId, event: &Event) -> usize {
+        let res = event.res();
+        if let Err(e) = self.payload_limit.check(event) {
+            tracing::warn!("not sending event for {}: {}", res, e);
+            return 0;
+        }
+
+        #[cfg(feature = "schema")]
+        if !self.schemas.check(event) {
+            return 0;
+        }
+
+        // Holding it would send it to `exclude` too once the route is resumed.
+        if self
+            .paused_routes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(res)
+        {
+            tracing::warn!("not sending event for paused route {}", res);
+            return 0;
+        }
+
+        let event = event.clone().with_id(self.next_event_id());
+        let mut frames = protocol::Frames::new(&event, None);
+        let mut stats = Delivery::default();
+
+        let peers = self.clients.read(res);
+        for (id, peer) in peers.subscribers(res) {
+            if id == exclude {
+                continue;
+            }
+
+            stats.subscribers += 1;
+            if !self.accepts(&peer.info, &event) {
+                continue;
+            }
+
+            let frame = frames.get(&peer.info);
+            let len = frame.len() as u64;
+            let sent = peer.send(frame);
+            self.taps.record(&peer.info, res, event.payload(), sent);
+            if sent {
+                stats.sent += 1;
+                stats.bytes += len;
+            } else {
+                stats.failed += 1;
+            }
+        }
+
+        drop(peers);
+        let sent = stats.sent as usize;
+        self.record(res, stats);
+        sent
+    }
+
+    /// Returns the resources `id` is subscribed to, sorted.
+    pub(crate) fn subscriptions_for(&self, id: ClientId) -> Vec<String> {
+        let mut resources = self.clients.subscriptions(id);
+
+        resources.sort_unstable();
+        resources
+    }
+}
+
+/// The state of the server tasks, see [`Server::health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Health {
+    /// The server tasks are running, or have finished after a shutdown.
+    Running,
+    /// A server task panicked and stopped, with the panic message.
+    Failed(String),
+}
+
+/// A snapshot of a connected client, see [`Server::connections`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConnectionInfo {
+    /// The id of the connection.
+    pub id: ClientId,
+    /// The address of the remote peer.
+    pub addr: SocketAddr,
+    /// When the client connected.
+    pub connected_at: SystemTime,
+    /// The resources the client is subscribed to, sorted.
+    pub resources: Vec<String>,
+    /// The number of frames queued for the client that haven't been written yet. Events waiting
+    /// in the channels of [`BroadcastBackend::TokioBroadcast`] aren't counted.
+    pub queue_depth: usize,
+    /// Whether the client is paused, see [`Server::pause_client`].
+    pub paused: bool,
+    /// When the client last sent a ping frame, see [`ServerBuilder::ping_interval`].
+    pub last_ping_at: Option<SystemTime>,
+    /// The query string parameters of the upgrade request, see [`ClientInfo::metadata`].
+    pub metadata: HashMap<String, String>,
+    /// A copy of the state attached to the connection, see [`ClientInfo::meta`].
+    pub meta: HashMap<String, String>,
+    /// A copy of the tags of the connection, see [`Server::tag_client`].
+    pub tags: HashMap<String, String>,
+}
+
+/// Delivery statistics of a resource, see [`Server::resource_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ResourceStats {
+    /// The number of times an event was queued for a client, one per event and recipient.
+    pub events_sent: u64,
+    /// The number of bytes queued for clients, as encoded for each of them.
+    pub bytes_sent: u64,
+    /// The number of events that couldn't be queued because the client was disconnecting.
+    pub failed_sends: u64,
+    /// The largest number of subscribers an event was published to.
+    pub subscriber_high_water: usize,
+    /// When the last event was published to the resource.
+    pub last_event_at: Option<std::time::Instant>,
+}
+
+/// Drops new events while too many are waiting for the broadcast loop, see
+/// [`ServerBuilder::load_shedder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadShedder {
+    /// The number of queued events above which new events are dropped.
+    pub max_queue_depth: usize,
+    /// How often the queue depth is checked.
+    pub check_interval: Duration,
+}
+
+/// What happens to the frames sent to a paused client, see [`ServerBuilder::pause_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PausePolicy {
+    /// The frames are dropped.
+    Drop,
+    /// Up to `capacity` frames are held and sent once the client is resumed, older ones are
+    /// dropped.
+    Buffer {
+        /// The number of frames held.
+        capacity: usize,
+    },
+}
+
+impl PausePolicy {
+    /// Returns how many frames are held.
+    fn capacity(self) -> usize {
+        match self {
+            Self::Drop => 0,
+            Self::Buffer { capacity } => capacity,
+        }
+    }
+}
+
+impl Default for PausePolicy {
+    /// Holds up to 1024 frames.
+    fn default() -> Self {
+        Self::Buffer {
+            capacity: PAUSED_CLIENT_CAPACITY,
+        }
+    }
+}
+
+/// Handle to a running server, returned by [`ServerBuilder::start`].
+///
+/// The handle is cheap to clone, dropping it doesn't stop the server.
+#[derive(Clone)]
+pub struct Server {
+    inner: Arc<ServerInner>,
+    tx: EventTx,
+    local_addr: SocketAddr,
+    sessions: Arc<SessionManager>,
+}
+
+impl ServerBuilder {
+    /// Returns a builder for a server listening on `127.0.0.1:3012` with an unbounded event queue.
+    pub fn new() -> Self {
+        Self {
+            addr: "127.0.0.1:3012".to_string(),
+            capacity: None,
+            authenticator: None,
+            middleware: MiddlewareStack::new(),
+            on_connect: None,
+            on_disconnect: None,
+            on_message: None,
+            per_client_filter: None,
+            transforms: Vec::new(),
+            snapshots: Vec::new(),
+            max_protocol_version: 1,
+            subprotocols: Vec::new(),
+            reject_unsupported_subprotocols: false,
+            ack_timeout: Duration::from_secs(5),
+            ping_interval: None,
+            write_timeout: None,
+            handshake_timeout: Duration::from_secs(10),
+            max_pending_handshakes: 1024,
+            websocket_config: None,
+            shutdown_grace: Duration::from_secs(30),
+            shutdown_timeout: Duration::from_secs(5),
+            warm_up: None,
+            stall_timeout: health::DEFAULT_STALL_TIMEOUT,
+            socket: SocketOptions::default(),
+            backend: BroadcastBackend::PerClient,
+            restart_on_panic: true,
+            detect_sequence_gaps: false,
+            shard_count: thread::available_parallelism().map_or(1, usize::from),
+            broadcast_workers: 0,
+            worker_stack_size: None,
+            runtime: None,
+            audit: None,
+            bridges: Bridges::default(),
+            load_shedder: None,
+            pause_policy: PausePolicy::default(),
+            replay_history: None,
+            routes: HashMap::new(),
+            max_payload: limits::DEFAULT_MAX_PAYLOAD,
+            limits: ResourceLimits::default(),
+            #[cfg(feature = "schema")]
+            schemas: Vec::new(),
+            #[cfg(feature = "schema")]
+            on_validation_error: None,
+            #[cfg(feature = "bench-harness")]
+            bench: None,
+        }
+    }
+
+    /// Sets the address the server listens on, e.g. `0.0.0.0:3012`, `[::1]:3012` or
+    /// `localhost:3012`. The server binds to the first address the host name resolves to that
+    /// can be bound.
+    ///
+    /// The IPv6 wildcard address `[::]` also accepts IPv4 clients, whose
+    /// [address](ClientInfo::addr) is reported as IPv4, where the platform supports it.
+    pub fn addr(mut self, addr: impl Into<String>) -> Self {
+        self.addr = addr.into();
+        self
+    }
+
+    /// Limits the event queue to `capacity` events, after which [`EventTx::send`] fails with
+    /// [`Error::QueueFull`] until the broadcast loop catches up.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the authenticator every websocket upgrade request has to pass before the client is
+    /// subscribed.
+    pub fn authenticator(mut self, authenticator: impl Authenticator) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Adds middleware handling the websocket upgrade requests. Middleware runs in the order it
+    /// was added, before the [authenticator](Self::authenticator).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// use pushevent::middleware::{CorsMiddleware, LoggingMiddleware, RateLimitMiddleware};
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .middleware(LoggingMiddleware::new())
+    ///     .middleware(CorsMiddleware::new(["https://example.com"]))
+    ///     .middleware(RateLimitMiddleware::new(10, Duration::from_secs(60)))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn middleware(mut self, middleware: impl RequestMiddleware) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Sets a hook called every time a client has completed the handshake and is subscribed. The
+    /// hook runs on the connection's task, so it should return quickly.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .on_connect(|client| {
+    ///         // ws://127.0.0.1:3012/events?user_id=42
+    ///         println!("user {:?} connected", client.metadata.get("user_id"));
+    ///     })
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn on_connect(mut self, f: impl Fn(&ClientInfo) + Send + Sync + 'static) -> Self {
+        self.on_connect = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets a callback run once a client disconnected and was unsubscribed. The
+    /// [`ClientInfo::resource`] it is given is the resource the client was connected to.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .on_disconnect(|client| println!("client {:?} left {}", client.id, client.resource))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn on_disconnect(mut self, f: impl Fn(&ClientInfo) + Send + Sync + 'static) -> Self {
+        self.on_disconnect = Some(Arc::new(f));
+        self
+    }
+
+    /// Records every handshake, subscription change and disconnect in `sink`, for a log of who
+    /// connected to which resource and when. See [`audit`](crate::audit).
+    pub fn audit(mut self, sink: impl AuditSink) -> Self {
+        self.audit = Some(Arc::new(sink));
+        self
+    }
+
+    /// Forwards every event delivered by the server to `target`, e.g. a message broker other
+    /// servers receive events from. Can be called several times.
+    ///
+    /// Events received from the bridge should be published tagged with
+    /// [`Origin::Bridge`](crate::Origin::Bridge) and `name`, they are then delivered to the
+    /// clients and forwarded to the other bridges but not sent back through this one, so that
+    /// they don't loop. Events are forwarded as delivered, after the transforms ran, from the
+    /// broadcast loop, so publishing to `target` must not block.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, Origin};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let eu = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    /// // Events delivered in the US are also delivered to the clients in the EU.
+    /// let us = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .bridge("eu", eu.get_tx())
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// // Received from the EU, so not sent back there.
+    /// let event = Event::from_string("/prices", "42".into());
+    /// us.get_tx()
+    ///     .send(event.with_origin(Origin::Bridge("eu".into())))
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn bridge(mut self, name: impl Into<String>, target: impl PublishTarget) -> Self {
+        self.bridges.add(name.into(), Arc::new(target));
+        self
+    }
+
+    /// Sets a callback run on the connection task for every text or binary frame a client sends.
+    /// Frames are otherwise discarded, clients only receive events.
+    pub fn on_message(mut self, f: impl Fn(&ClientInfo, Payload) + Send + Sync + 'static) -> Self {
+        self.on_message = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets a filter deciding, for every subscriber of a resource, whether it receives a given
+    /// event. The filter is called with the client, the resource and the serialized payload, and
+    /// the client is skipped if it returns `false`.
+    ///
+    /// This allows fine-grained access control without a connection per user, for example only
+    /// delivering order updates to the user who placed the order. The filter runs on the
+    /// broadcast loop for every subscriber of every event, so it has to be cheap.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// // Clients connect to ws://127.0.0.1:3012/orders?user_id=42 and only receive their orders.
+    /// let tx = ServerBuilder::new()
+    ///     .per_client_filter(|client, _res, payload| {
+    ///         client
+    ///             .metadata
+    ///             .get("user_id")
+    ///             .is_some_and(|id| payload.contains(&format!(r#""user_id":{}"#, id)))
+    ///     })
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn per_client_filter(
+        self,
+        f: impl Fn(&ClientInfo, &str, &str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.per_client_event_filter(move |client, event| f(client, event.res(), event.payload()))
+    }
+
+    /// Like [`per_client_filter`](Self::per_client_filter), with the whole event, e.g. to look
+    /// at its [origin](Event::origin). Replaces the per-client filter set before.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::Origin;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// // Clients connecting with ?local_only=1 don't receive the events of other regions.
+    /// let tx = ServerBuilder::new()
+    ///     .per_client_event_filter(|client, event| {
+    ///         !client.metadata.contains_key("local_only") || *event.origin() == Origin::Local
+    ///     })
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn per_client_event_filter(
+        mut self,
+        f: impl Fn(&ClientInfo, &Event) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.per_client_filter = Some(Arc::new(f));
+        self
+    }
+
+    /// Registers a transform for the resources matching `pattern`, which rewrites or drops the
+    /// payload of every event before it is delivered. Transforms run in the order they were
+    /// registered, once per event.
+    ///
+    /// `pattern` is either a resource, `*` for every resource or a prefix ending in `/*` for
+    /// every resource below it, e.g. `/orders/*`. A transform that panics drops the event.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .transform("*", |_: &str, payload: String| (!payload.is_empty()).then_some(payload))
+    ///     .transform("/orders/*", |_: &str, payload: String| {
+    ///         Some(format!(r#"{{"region":"eu-west","order":{}}}"#, payload))
+    ///     })
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn transform(mut self, pattern: impl Into<String>, transform: impl Transform) -> Self {
+        self.transforms.push((pattern.into(), Arc::new(transform)));
+        self
+    }
+
+    /// Registers a transformer for the resources matching `pattern`, which rewrites the payload
+    /// of every event before it is delivered and before the
+    /// [per-client filter](Self::per_client_filter) sees it. Several transformers registered for
+    /// a resource are chained, they run in registration order together with the
+    /// [transforms](Self::transform).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .transformer("/events/prices", |_: &str, price: String| format!("${}", price))
+    ///     .transformer("/events/chat", |_: &str, text: String| text.replace('<', "&lt;"))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn transformer(
+        mut self,
+        pattern: impl Into<String>,
+        transformer: impl Transformer,
+    ) -> Self {
+        self.transforms
+            .push((pattern.into(), Arc::new(Infallible(transformer))));
+        self
+    }
+
+    /// Registers a hook returning the current state of the resources matching `pattern`, which
+    /// is sent to every client connecting to one of them before any other event, e.g. the
+    /// contents of a queue clients then receive the changes of. `pattern` is a resource or a
+    /// pattern like for [`transform`](Self::transform), the longest one matching applies.
+    ///
+    /// The hook is also called for clients [subscribed](Server::subscribe) to one of them later
+    /// on, which are sent the snapshot before the events published since. A client that is
+    /// [paused](Server::pause_client) at that time receives it once it is resumed, after the
+    /// frames held back so far. Pausing or resuming a client while the hook runs doesn't let any
+    /// frame through ahead of the snapshot.
+    ///
+    /// The hook is called once the client is subscribed. The events it returns are sent to that
+    /// client only, without running the transforms or the per-client filter, followed by the
+    /// events [replayed](Self::replay_on_reconnect) to it and then the events published since
+    /// it subscribed, so the snapshot may already reflect some of those.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::sync::{Arc, Mutex};
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::Event;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let queue = Arc::new(Mutex::new(vec!["song-1".to_string(), "song-2".to_string()]));
+    ///
+    /// let tx = ServerBuilder::new()
+    ///     .snapshot("/queue", move |res, _client| {
+    ///         let songs = queue.lock().unwrap().join(",");
+    ///         Some(vec![Event::from_string(res, songs)])
+    ///     })
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn snapshot(
+        self,
+        pattern: impl Into<String>,
+        hook: impl Fn(&str, &ClientInfo) -> Option<Vec<Event>> + Send + Sync + 'static,
+    ) -> Self {
+        self.snapshot_async(pattern, move |res, client| future::ready(hook(res, client)))
+    }
+
+    /// Like [`snapshot`](Self::snapshot), with a hook that may wait, e.g. for a database. The
+    /// events published meanwhile are held back for the client until the snapshot was sent, up to
+    /// 4096 frames. Clients with more are disconnected with the close code 1013 (try again
+    /// later), so that a slow hook on a busy route doesn't hold back frames without bound.
+    pub fn snapshot_async<F>(
+        mut self,
+        pattern: impl Into<String>,
+        hook: impl Fn(&str, &ClientInfo) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        F: Future<Output = Option<Vec<Event>>> + Send + 'static,
+    {
+        self.snapshots.push((
+            pattern.into(),
+            Arc::new(move |res: &str, client: &ClientInfo| hook(res, client).boxed()),
+        ));
+        self
+    }
+
+    /// Sets the highest version of the pushevent protocol clients may negotiate, defaults to 1.
+    ///
+    /// Clients list the versions they understand in the `Sec-WebSocket-Protocol` header of the
+    /// upgrade request (`pushevent-v1` or `pushevent.v1`, `pushevent-v2` or `pushevent.v2`) and
+    /// get the highest one the server allows. Clients that can't set the header may pass the
+    /// version in the query string instead, `/events?protocol=2`. Clients that don't ask for a
+    /// version get version 1.
+    ///
+    /// * Version 1 sends the serialized payload of every event as is, and nothing else.
+    /// * Version 2 wraps every event in a JSON envelope carrying its id and the resource it was
+    ///   published to, `{"type":"event","id":7,"resource":"/events","payload":"..."}`, with the
+    ///   serialized payload as a JSON string. System messages, such as the notice sent when the
+    ///   server drains, are only sent to version 2 clients.
+    ///
+    /// Raising the version doesn't affect clients that only know the old one, so clients can be
+    /// migrated to a new schema one by one. Versions above the latest one are treated as the
+    /// latest one.
+    pub fn max_protocol_version(mut self, version: u8) -> Self {
+        self.max_protocol_version = version.clamp(1, protocol::LATEST);
+        self
+    }
+
+    /// Declares subprotocols of the application clients may select through
+    /// `Sec-WebSocket-Protocol`, in addition to the pushevent protocols.
+    ///
+    /// The first subprotocol offered by the client that was declared is selected and echoed in
+    /// the handshake response, unless the client also offered a pushevent protocol, which takes
+    /// precedence. The selected subprotocol is available as [`ClientInfo::subprotocol`]. Clients
+    /// offering only unsupported subprotocols are accepted without one unless
+    /// [`reject_unsupported_subprotocols`](Self::reject_unsupported_subprotocols) is set.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .subprotocols(["chat.v2", "chat.v1"])
+    ///     .on_connect(|client| match client.subprotocol.as_deref() {
+    ///         Some("chat.v2") => println!("{:?} speaks the new chat protocol", client.id),
+    ///         _ => println!("{:?} speaks the old chat protocol", client.id),
+    ///     })
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn subprotocols(mut self, protocols: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.subprotocols
+            .extend(protocols.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets whether clients offering subprotocols of which none is supported are rejected with
+    /// `400 Bad Request`, defaults to `false`. Clients not offering any subprotocol are always
+    /// accepted.
+    pub fn reject_unsupported_subprotocols(mut self, reject: bool) -> Self {
+        self.reject_unsupported_subprotocols = reject;
+        self
+    }
+
+    /// Sets how long clients have to acknowledge an event published to a route with a
+    /// [`QoS`] above [`QoS::AtMostOnce`] before it is sent again, defaults to 5 seconds.
+    pub fn ack_timeout(mut self, timeout: Duration) -> Self {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    /// Disconnects clients that a frame can't be written to within `timeout`, disabled by
+    /// default.
+    ///
+    /// A client that stops reading lets the socket buffers fill up, after which writing to it
+    /// waits until the client reads again, if ever, while its queue keeps growing. With a
+    /// timeout such a client is sent a close frame with [`CloseReason::slow_consumer`], if the
+    /// socket still takes it, and disconnected.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends a ping frame every `interval` to the clients that didn't send one themselves within
+    /// that time, keeping idle connections and the NAT mappings on their way alive. Disabled by
+    /// default.
+    ///
+    /// Ping frames sent by clients are always answered with a pong, and recorded in
+    /// [`ConnectionInfo::last_ping_at`].
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval);
+        self
+    }
+
+    /// Drops connections whose websocket handshake doesn't complete within `timeout`, defaults
+    /// to 10 seconds, so that clients sending their upgrade request slowly can't hold on to a
+    /// connection. See [`Server::handshake_timeouts`].
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Drops new connections right away while `max` handshakes are in progress, defaults to
+    /// 1024. See [`Server::refused_handshakes`].
+    ///
+    /// Panics if `max` is 0.
+    pub fn max_pending_handshakes(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_pending_handshakes must be greater than 0");
+        self.max_pending_handshakes = max;
+        self
+    }
+
+    /// Sets the settings of tungstenite, the websocket implementation, for every connection,
+    /// such as the largest message clients may send or the size of the write buffers. Meant for
+    /// the knobs pushevent doesn't wrap, enabled with the `unstable-websocket-config` feature.
+    ///
+    /// **Not covered by semver:** the settings are the ones of the tungstenite version this
+    /// release depends on, and may change with any release.
+    ///
+    /// tungstenite 0.26 doesn't implement the `permessage-deflate` extension, so there is no
+    /// compression setting: the extension is never negotiated and frames are sent uncompressed,
+    /// also to clients offering it.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::{ServerBuilder, WebSocketConfig};
+    ///
+    /// let builder = ServerBuilder::new()
+    ///     .websocket_config(WebSocketConfig::default().max_message_size(Some(64 << 10)));
+    /// ```
+    #[cfg(feature = "unstable-websocket-config")]
+    pub fn websocket_config(mut self, config: WebSocketConfig) -> Self {
+        self.websocket_config = Some(config);
+        self
+    }
+
+    /// Sets `SO_REUSEADDR` on the listener, defaults to `true`. This allows restarting the
+    /// server on the same port while connections of the previous instance are still in
+    /// `TIME_WAIT`.
+    ///
+    /// Ignored on Windows, where the option would allow binding to a port another socket is
+    /// actively listening on and where rebinding works without it.
+    pub fn reuse_addr(mut self, reuse: bool) -> Self {
+        self.socket.reuse_addr = reuse;
+        self
+    }
+
+    /// Sets `SO_REUSEPORT` on the listener, defaults to `false`. This allows several processes
+    /// to listen on the same port, with the kernel distributing connections between them.
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.socket.reuse_port = reuse;
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on accepted connections, defaults to `true` so that small events
+    /// aren't held back by Nagle's algorithm.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.socket.nodelay = nodelay;
+        self
+    }
+
+    /// Enables TCP keepalive on accepted connections, probing idle connections after `time`.
+    /// Disabled by default.
+    pub fn keepalive(mut self, time: Duration) -> Self {
+        self.socket.keepalive = Some(time);
+        self
+    }
+
+    /// Sets how events are handed from the broadcast loop to the connections, defaults to
+    /// [`BroadcastBackend::PerClient`].
+    ///
+    /// With the per-client backend every client has its own unbounded queue, so every event is
+    /// delivered to every client that stays connected. A slow client isn't slowed down by others,
+    /// but its queue, and the memory it takes up, grows for as long as it can't keep up.
+    ///
+    /// With [`BroadcastBackend::TokioBroadcast`] every resource has a single channel holding the
+    /// last `capacity` events. Delivery is at-most-once: a client that falls more than `capacity`
+    /// events behind skips the events it missed and continues with the oldest one still held.
+    /// Memory use is bounded, at the cost of slow clients silently missing events. Events are
+    /// encoded and filtered on the connection tasks rather than on the broadcast loop.
+    ///
+    /// # Panics
+    /// [`start`](Self::start) panics if the capacity of [`BroadcastBackend::TokioBroadcast`] is
+    /// 0.
+    pub fn broadcast_backend(mut self, backend: BroadcastBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets whether the broadcast loop carries on when delivering an event panics, for example
+    /// in the [per-client filter](Self::per_client_filter), defaults to `true`. The event is
+    /// dropped and the panic is logged.
+    ///
+    /// Otherwise the broadcast loop stops, no more events are delivered and
+    /// [`Server::health`] reports the failure.
+    pub fn restart_on_panic(mut self, restart: bool) -> Self {
+        self.restart_on_panic = restart;
+        self
+    }
+
+    /// Sets whether the server checks that every subscriber of a resource is offered each of
+    /// its events, defaults to `false`. Meant for debugging reports of clients missing events.
+    ///
+    /// The server remembers the last event of every resource it offered to every client, which
+    /// costs a lock per recipient. A client skipped by the fan-out is noticed on the next event
+    /// of the resource, logged and counted by [`Server::sequence_gaps`]. Events rejected by the
+    /// per-client filter count as offered. Clients subscribed with
+    /// [`BroadcastBackend::TokioBroadcast`] take the events out of the channel themselves and
+    /// aren't checked.
+    pub fn detect_sequence_gaps(mut self, detect: bool) -> Self {
+        self.detect_sequence_gaps = detect;
+        self
+    }
+
+    /// Sets the number of shards subscriptions are split into by resource, defaults to the number
+    /// of CPU cores.
+    ///
+    /// Each shard has its own lock, so publishing to and subscribing to resources in different
+    /// shards doesn't contend. Counting or draining clients locks every shard.
+    pub fn shard_count(mut self, shards: usize) -> Self {
+        self.shard_count = shards;
+        self
+    }
+
+    /// Delivers events on `workers` threads, called `pushevent-broadcast-0` and so on, instead
+    /// of the broadcast loop itself, defaults to 0.
+    ///
+    /// Events are handed to the workers by resource, so the events of a resource are still
+    /// delivered in the order they were published, while the events of resources handled by
+    /// different workers are delivered in parallel. Worth it when hooks like the
+    /// [per-client filter](Self::per_client_filter) are expensive or resources have many
+    /// subscribers.
+    pub fn broadcast_workers(mut self, workers: usize) -> Self {
+        self.broadcast_workers = workers;
+        self
+    }
+
+    /// Sets the stack size of the [broadcast workers](Self::broadcast_workers) in bytes,
+    /// defaults to the default of [`std::thread`].
+    pub fn worker_stack_size(mut self, size: usize) -> Self {
+        self.worker_stack_size = Some(size);
+        self
+    }
+
+    /// Spawns the server tasks on the runtime of `handle` instead of the one
+    /// [`start`](Self::start) is called from, which then doesn't need to be called from a tokio
+    /// runtime at all.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let io = tokio::runtime::Builder::new_multi_thread()
+    ///     .worker_threads(2)
+    ///     .enable_all()
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let server = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .runtime(io.handle().clone())
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    /// # server.shutdown().await;
+    /// # io.shutdown_background();
+    /// # }
+    /// ```
+    pub fn runtime(mut self, handle: Handle) -> Self {
+        self.runtime = Some(handle);
+        self
+    }
+
+    /// Drops new events while more than [`LoadShedder::max_queue_depth`] events are waiting for
+    /// the broadcast loop, so that clients keep receiving the events already queued with a
+    /// bearable latency. Disabled by default.
+    ///
+    /// The queue depth is checked every [`LoadShedder::check_interval`], a warning is logged
+    /// whenever the server starts and stops dropping events. Dropped events aren't reported to
+    /// the publisher, [`EventTx::send`] succeeds.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use pushevent::server::{LoadShedder, ServerBuilder};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .load_shedder(LoadShedder {
+    ///         max_queue_depth: 10_000,
+    ///         check_interval: Duration::from_millis(100),
+    ///     })
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert_eq!(server.queue_depth(), 0);
+    /// # }
+    /// ```
+    pub fn load_shedder(mut self, shedder: LoadShedder) -> Self {
+        self.load_shedder = Some(shedder);
+        self
+    }
+
+    /// Sets what happens to the frames sent to paused clients, by default up to 1024 of them are
+    /// held until the client is resumed.
+    ///
+    /// Clients are paused with [`Server::pause_client`], or pause themselves by sending the text
+    /// frame `{"action":"pause"}` and resume with `{"action":"resume"}`, e.g. while a mobile
+    /// app is in the background.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::{PausePolicy, ServerBuilder};
+    ///
+    /// let builder = ServerBuilder::new().pause_policy(PausePolicy::Buffer { capacity: 64 });
+    /// ```
+    pub fn pause_policy(mut self, policy: PausePolicy) -> Self {
+        self.pause_policy = policy;
+        self
+    }
+
+    /// Replays the events a client missed when it reconnects shortly after disconnecting,
+    /// keeping the last `history` events published for that purpose. Disabled by default.
+    ///
+    /// Clients opt in by sending a token identifying their session in the
+    /// `X-Pushevent-Session` header of the upgrade request. When a client reconnects to the same
+    /// resource with the same token within 5 minutes, the events published to that resource
+    /// since it disconnected that are still in the history are sent before any new ones.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new()
+    ///     .replay_on_reconnect(1024)
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn replay_on_reconnect(mut self, history: usize) -> Self {
+        self.replay_history = Some(history);
+        self
+    }
+
+    /// Configures the resource or pattern `res` with `config`, overriding the settings of the
+    /// server for it. `res` may also be a pattern, `*` or a prefix ending in `/*`, like for
+    /// [`Server::set_route_qos`]. The configuration is looked up when a client connects and
+    /// when an event is published: the one of the resource itself applies, else the one of the
+    /// longest pattern matching it, and configurations aren't merged. Resources without one use
+    /// the settings of the server.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use pushevent::server::{QoS, RouteConfig, ServerBuilder};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new()
+    ///     .replay_on_reconnect(1024)
+    ///     .route(
+    ///         "/progress/*",
+    ///         RouteConfig::new()
+    ///             .coalesce(Duration::from_millis(100))
+    ///             .replay(16),
+    ///     )
+    ///     .route("/alerts", RouteConfig::new().qos(QoS::AtLeastOnce))
+    ///     .route("/public/*", RouteConfig::new().skip_auth())
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn route(mut self, res: impl Into<String>, config: RouteConfig) -> Self {
+        self.routes.insert(res.into(), config);
+        self
+    }
+
+    /// Limits the payloads of events to `bytes`, defaults to 1 MiB. Publishing a larger event
+    /// fails with [`Error::PayloadTooLarge`] before anything is queued, so that a runaway
+    /// producer can't take down the connections of every client. Events grown past the limit by
+    /// a [transform](Self::transform) are dropped before they reach any client. Routes may
+    /// override the limit with [`RouteConfig::max_payload`], see
+    /// [`Server::oversized_events`] for the number of events rejected.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::{RouteConfig, ServerBuilder};
+    /// use pushevent::{Error, Event, SerializableEvent};
+    ///
+    /// struct Blob(usize);
+    ///
+    /// impl SerializableEvent for Blob {
+    ///     fn serialize(&self) -> String {
+    ///         "x".repeat(self.0)
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .max_payload(64 * 1024)
+    ///     .route("/uploads/*", RouteConfig::new().max_payload(16 * 1024 * 1024))
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let res = tx.send(Event::new("/events", Blob(100 * 1024)));
+    /// assert!(matches!(res, Err(Error::PayloadTooLarge { .. })));
+    /// assert!(tx.send(Event::new("/uploads/42", Blob(100 * 1024))).is_ok());
+    /// # }
+    /// ```
+    pub fn max_payload(mut self, bytes: usize) -> Self {
+        self.max_payload = bytes;
+        self
+    }
+
+    /// Limits resources to `len` bytes, defaults to 1024. Clients connecting to a longer resource
+    /// are rejected with `414 URI Too Long` before the request is authenticated.
+    pub fn max_resource_len(mut self, len: usize) -> Self {
+        self.limits.max_len = len;
+        self
+    }
+
+    /// Limits the number of resources a client may be subscribed to at once, including the one
+    /// it connected to, unlimited by default. See [`Server::subscribe`].
+    pub fn max_subscriptions(mut self, max: usize) -> Self {
+        self.limits.max_subscriptions = max;
+        self
+    }
+
+    /// Lets the clients for which `f` returns `true` subscribe to the resources starting with
+    /// `/_pushevent/`, which are reserved for internal use and refused to everyone else by
+    /// default. `f` sees the [`ClientInfo::meta`] set by the authenticator, e.g. a role.
+    pub fn allow_reserved(
+        mut self,
+        f: impl Fn(&ClientInfo) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.limits.allow_reserved = Some(Arc::new(f));
+        self
+    }
+
+    /// Lets clients subscribe to patterns, `*` for every resource or a prefix ending in `/*` for
+    /// every resource below it, when connecting or through [`Server::subscribe`]. Off by
+    /// default, as a client connecting to `/*` receives every event published to the server;
+    /// such subscriptions are refused with `403 Forbidden` unless allowed here.
+    ///
+    /// Patterns go through the [validation hook](Self::validate_resource) and the
+    /// authenticator like any other resource, which should check that the client may receive
+    /// every resource the pattern matches.
+    pub fn allow_pattern_subscriptions(mut self, allow: bool) -> Self {
+        self.limits.allow_patterns = allow;
+        self
+    }
+
+    /// Sets a hook vetoing subscriptions, called with the client and the resource whenever a
+    /// client connects or is [subscribed](Server::subscribe) to another resource. Clients are
+    /// rejected with `403 Forbidden` when it returns `false`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// // Only resources below /orders/ exist.
+    /// let server = ServerBuilder::new()
+    ///     .validate_resource(|_client, res| res.starts_with("/orders/"))
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn validate_resource(
+        mut self,
+        f: impl Fn(&ClientInfo, &str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.limits.validator = Some(Arc::new(f));
+        self
+    }
+
+    /// Registers a JSON Schema for the resources matching `pattern`, a resource or a pattern such
+    /// as `/orders/*`. Events published to those resources are validated against it, after the
+    /// [transforms](Self::transform) ran, and dropped if their payload doesn't match. Several
+    /// schemas matching a resource must all be satisfied.
+    ///
+    /// The schemas are compiled when the server starts, which fails with
+    /// [`Error::InvalidSchema`] if one of them is invalid.
+    ///
+    /// Available with the `schema` feature.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    /// use serde_json::json;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new()
+    ///     .schema(
+    ///         "/prices/*",
+    ///         json!({
+    ///             "type": "object",
+    ///             "properties": { "price": { "type": "number" } },
+    ///             "required": ["price"],
+    ///         }),
+    ///     )
+    ///     .on_validation_error(|res, _payload, error| {
+    ///         eprintln!("invalid event for {}: {}", res, error)
+    ///     })
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "schema")]
+    pub fn schema(mut self, pattern: impl Into<String>, schema: serde_json::Value) -> Self {
+        self.schemas.push((pattern.into(), schema));
+        self
+    }
+
+    /// Sets a callback run on the broadcast loop with the resource, the payload and the reason
+    /// whenever an event is dropped for not matching its [schema](Self::schema).
+    ///
+    /// Available with the `schema` feature.
+    #[cfg(feature = "schema")]
+    pub fn on_validation_error(
+        mut self,
+        f: impl Fn(&str, &str, &str) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_validation_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Publishes `rps` synthetic events per second with payloads of `payload_size` bytes to
+    /// `resource` for `duration` once the server has started, then logs how many were delivered
+    /// and how long that took at the info level. Meant for profiling the server without setting
+    /// up an external load generator, see [`bench_harness::run`](crate::bench_harness::run),
+    /// which returns the report instead.
+    ///
+    /// Available with the `bench-harness` feature.
+    #[cfg(feature = "bench-harness")]
+    pub fn enable_bench_harness(
+        mut self,
+        rps: u32,
+        duration: Duration,
+        resource: &str,
+        payload_size: usize,
+    ) -> Self {
+        self.bench = Some(crate::bench_harness::BenchConfig {
+            rps,
+            duration,
+            resource: resource.to_string(),
+            payload_size,
+        });
+        self
+    }
+
+    /// Sets how long [`Server::run_until_shutdown`] gives clients to disconnect, defaults to 30
+    /// seconds.
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Sets how long [`Server::shutdown`] waits for the broadcast loop and
+    /// [workers](Self::broadcast_workers) to finish delivering the events they already took out
+    /// of the queue before closing the connections, defaults to 5 seconds.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Holds back the events published until `expected_clients` clients are connected, or
+    /// `timeout` elapsed since the server started, e.g. so that every player of a game sees its
+    /// start. The events are then delivered in the order they were published.
+    ///
+    /// The events wait in the event queue, a [capacity](Self::capacity) that is too small fails
+    /// to publish them. Events sent to a single client with [`Server::send_to`] aren't held back.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .warm_up(4, Duration::from_secs(30))
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert!(!server.is_warmed_up());
+    /// # }
+    /// ```
+    pub fn warm_up(mut self, expected_clients: usize, timeout: Duration) -> Self {
+        self.warm_up = Some((expected_clients, timeout));
+        self
+    }
+
+    /// Sets how long the broadcast loop may spend delivering a single event before
+    /// [`Server::health_report`] reports it as stuck, defaults to 5 seconds.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    /// Binds the listener, spawns the server tasks on the current tokio runtime and returns a
+    /// sender for publishing events to the connected clients.
+    ///
+    /// Fails with [`Error::NoRuntime`] when called outside a tokio runtime without setting one
+    /// with [`runtime`](Self::runtime).
+    ///
+    /// This is a shorthand for [`start`](Self::start) for servers that run for the lifetime of
+    /// the process.
+    pub async fn build(self) -> Result<EventTx, Error> {
+        self.start().await.map(|server| server.get_tx())
+    }
+
+    /// Binds the listener, spawns the server tasks on the current tokio runtime and returns a
+    /// handle to the server.
+    ///
+    /// Fails with [`Error::NoRuntime`] when called outside a tokio runtime without setting one
+    /// with [`runtime`](Self::runtime).
+    ///
+    /// # Example
+    /// ```
+    /// use futures_util::FutureExt;
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::Error;
+    ///
+    /// let res = ServerBuilder::new().start().now_or_never().unwrap();
+    /// assert!(matches!(res, Err(Error::NoRuntime)));
+    /// ```
+    pub async fn start(self) -> Result<Server, Error> {
+        let runtime = match self.runtime {
+            Some(x) => x,
+            None => Handle::try_current().map_err(|_| Error::NoRuntime)?,
+        };
+
+        let (tx, rx) = match self.capacity {
+            Some(capacity) => tx::bounded(capacity),
+            None => tx::unbounded(),
+        };
+        let routes = Arc::new(RwLock::new(self.routes));
+        let payload_limit = Arc::new(PayloadLimit::new(self.max_payload, routes.clone()));
+        rx.state().limit_payloads(payload_limit.clone());
+
+        let inner = Arc::new(ServerInner {
+            clients: Demultiplexer::new(self.shard_count),
+            authenticator: self.authenticator,
+            middleware: self.middleware,
+            on_connect: self.on_connect,
+            on_disconnect: self.on_disconnect,
+            on_message: self.on_message,
+            per_client_filter: self.per_client_filter,
+            transforms: self.transforms,
+            snapshots: self.snapshots,
+            max_protocol_version: self.max_protocol_version,
+            subprotocols: self.subprotocols,
+            reject_unsupported_subprotocols: self.reject_unsupported_subprotocols,
+            routes,
+            payload_limit,
+            batches: Batches::default(),
+            event_ids: AtomicU64::new(0),
+            acks: Acks::default(),
+            ack_timeout: self.ack_timeout,
+            write_timeout: self.write_timeout,
+            handshake_timeout: self.handshake_timeout,
+            websocket_config: self.websocket_config,
+            pending_handshakes: Arc::new(Semaphore::new(self.max_pending_handshakes)),
+            handshake_timeouts: AtomicU64::new(0),
+            refused_handshakes: AtomicU64::new(0),
+            shutdown: watch::channel(false).0,
+            ready: watch::channel(false).0,
+            warm_up: self.warm_up,
+            stall_timeout: self.stall_timeout,
+            listener: Heartbeat::new(),
+            broadcaster: Heartbeat::new(),
+            last_accepted: Timestamp::default(),
+            last_delivered: Timestamp::default(),
+            warmed_up: watch::channel(self.warm_up.is_none_or(|(x, _)| x == 0)).0,
+            closing: watch::channel(false).0,
+            disconnected: Notify::new(),
+            shutdown_grace: self.shutdown_grace,
+            running: TaskGroup::new(),
+            broadcasting: TaskGroup::new(),
+            shutdown_timeout: self.shutdown_timeout,
+            failure: Mutex::new(None),
+            restart_on_panic: self.restart_on_panic,
+            sequences: self.detect_sequence_gaps.then(Mutex::default),
+            sequence_gaps: AtomicU64::new(0),
+            channels: match self.backend {
+                BroadcastBackend::PerClient => None,
+                BroadcastBackend::TokioBroadcast { capacity } => {
+                    assert!(capacity > 0, "broadcast capacity must be greater than 0");
+                    Some(Channels::new(capacity))
+                }
+            },
+            replay: self.replay_history.map(Replay::new),
+            sessions: Mutex::default(),
+            groups: Mutex::default(),
+            paused_routes: Mutex::default(),
+            rotations: Mutex::default(),
+            partitions: Mutex::default(),
+            unassigned: Mutex::default(),
+            pause_policy: self.pause_policy,
+            local: Arc::default(),
+            taps: Arc::default(),
+            pipes: Arc::default(),
+            stats: Mutex::default(),
+            last_events: RwLock::default(),
+            limits: self.limits,
+            #[cfg(feature = "schema")]
+            schemas: Schemas::compile(self.schemas, self.on_validation_error)
+                .map_err(Error::InvalidSchema)?,
+            queue: rx.state(),
+            runtime: runtime.clone(),
+            audit: self.audit,
+            bridges: self.bridges,
+        });
+
+        // Bound on the runtime the server runs on, whose reactor the listener is registered with.
+        let bind = {
+            let socket = self.socket.clone();
+            let addr = self.addr.clone();
+            runtime.spawn(async move { socket.bind(&addr).await })
+        };
+        let listener = bind
+            .await
+            .map_err(|_| Error::NoRuntime)?
+            .map_err(Error::Bind)?;
+        let local_addr = listener.local_addr().map_err(Error::Bind)?;
+
+        // Workers that did start stop again once their senders are dropped.
+        let stack_size = self.worker_stack_size;
+        let workers = (0..self.broadcast_workers)
+            .map(|i| spawn_worker(inner.clone(), i, stack_size))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::Spawn)?;
+
+        spawn_task(
+            inner.clone(),
+            "accept loop",
+            accept_loop(inner.clone(), listener, self.socket),
+        );
+        spawn_task(
+            inner.clone(),
+            "broadcast loop",
+            broadcast_loop(inner.clone(), rx, workers),
+        );
+        if inner.replay.is_some() {
+            spawn_task(
+                inner.clone(),
+                "session cleanup",
+                expire_sessions(inner.clone()),
+            );
+        }
+        spawn_task(inner.clone(), "ack retries", resend_unacked(inner.clone()));
+        if let Some(interval) = self.ping_interval {
+            spawn_task(
+                inner.clone(),
+                "pings",
+                ping_clients(inner.clone(), interval),
+            );
+        }
+        spawn_task(inner.clone(), "batches", flush_batches(inner.clone()));
+        if let Some(shedder) = self.load_shedder {
+            spawn_task(
+                inner.clone(),
+                "load shedder",
+                shed_load(inner.clone(), shedder),
+            );
+        }
+
+        #[cfg(feature = "bench-harness")]
+        if let Some(config) = self.bench {
+            let tx = tx.clone();
+            inner.runtime.spawn(async move {
+                match crate::bench_harness::run(tx, local_addr, config).await {
+                    Ok(report) => tracing::info!("bench harness: {}", report),
+                    Err(e) => tracing::warn!("bench harness failed: {}", e),
+                }
+            });
+        }
+
+        Ok(Server {
+            sessions: Arc::new(SessionManager::new(inner.clone())),
+            inner,
+            tx,
+            local_addr,
+        })
+    }
+
+    /// Starts the server on a small single threaded runtime of its own, for applications that
+    /// don't otherwise use tokio. The runtime runs on a thread of its own until the server has
+    /// shut down.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Tick;
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("tick")
+    ///     }
+    /// }
+    ///
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start_blocking().unwrap();
+    /// let tx = server.get_tx();
+    ///
+    /// std::thread::spawn(move || tx.send(Event::new("/ticks", Tick)))
+    ///     .join()
+    ///     .unwrap()
+    ///     .unwrap();
+    /// ```
+    pub fn start_blocking(self) -> Result<Server, Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::Spawn)?;
+        let (started, start) = std::sync::mpsc::channel();
+
+        thread::Builder::new()
+            .name("pushevent-runtime".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let server = self.start().await;
+                    let running = server.as_ref().ok().cloned();
+                    let _ = started.send(server);
+
+                    if let Some(server) = running {
+                        let _ = server.join().await;
+                    }
+                })
+            })
+            .map_err(Error::Spawn)?;
+
+        // Only fails if starting the server panicked.
+        start.recv().map_err(|_| Error::TaskPanicked)?
+    }
+}
+
+impl fmt::Debug for ServerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("ServerBuilder");
+        f.field("addr", &self.addr)
+            .field("capacity", &self.capacity)
+            .field("authenticator", &self.authenticator.is_some())
+            .field("middleware", &self.middleware.len())
+            .field("on_connect", &self.on_connect.is_some())
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .field("on_message", &self.on_message.is_some())
+            .field("per_client_filter", &self.per_client_filter.is_some())
+            .field("transforms", &self.transforms.len())
+            .field("snapshots", &self.snapshots.len())
+            .field("max_protocol_version", &self.max_protocol_version)
+            .field("subprotocols", &self.subprotocols)
+            .field(
+                "reject_unsupported_subprotocols",
+                &self.reject_unsupported_subprotocols,
+            )
+            .field("ack_timeout", &self.ack_timeout)
+            .field("ping_interval", &self.ping_interval)
+            .field("write_timeout", &self.write_timeout)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("max_pending_handshakes", &self.max_pending_handshakes)
+            .field("websocket_config", &self.websocket_config)
+            .field("shutdown_grace", &self.shutdown_grace)
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field("warm_up", &self.warm_up)
+            .field("stall_timeout", &self.stall_timeout)
+            .field("socket", &self.socket)
+            .field("backend", &self.backend)
+            .field("restart_on_panic", &self.restart_on_panic)
+            .field("detect_sequence_gaps", &self.detect_sequence_gaps)
+            .field("shard_count", &self.shard_count)
+            .field("broadcast_workers", &self.broadcast_workers)
+            .field("worker_stack_size", &self.worker_stack_size)
+            .field("runtime", &self.runtime.is_some())
+            .field("audit", &self.audit.is_some())
+            .field("bridges", &self.bridges.names())
+            .field("load_shedder", &self.load_shedder)
+            .field("pause_policy", &self.pause_policy)
+            .field("replay_history", &self.replay_history)
+            .field("routes", &self.routes)
+            .field("max_payload", &self.max_payload)
+            .field("max_resource_len", &self.limits.max_len)
+            .field("max_subscriptions", &self.limits.max_subscriptions)
+            .field("allow_reserved", &self.limits.allow_reserved.is_some())
+            .field("allow_pattern_subscriptions", &self.limits.allow_patterns)
+            .field("validate_resource", &self.limits.validator.is_some());
+
+        #[cfg(feature = "schema")]
+        f.field("schemas", &self.schemas.len())
+            .field("on_validation_error", &self.on_validation_error.is_some());
+
+        f.finish()
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    /// Returns a sender for publishing events to the connected clients.
+    pub fn get_tx(&self) -> EventTx {
+        self.tx.clone()
+    }
+
+    /// Returns `n` senders like [`get_tx`](Self::get_tx), one for every thread or task of a pool
+    /// of producers. Each can be wrapped on its own, e.g. with
+    /// [`EventTxExt::meter`](crate::EventTxExt::meter) to count what every producer publishes.
+    ///
+    /// # Example
+    /// ```
+    /// use std::thread;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, EventTxExt, SerializableEvent};
+    ///
+    /// struct Tick(usize);
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    ///
+    /// let producers = server
+    ///     .clone_tx_pool(4)
+    ///     .into_iter()
+    ///     .enumerate()
+    ///     .map(|(i, tx)| {
+    ///         let tx = tx.meter(&format!("producer-{}", i));
+    ///         thread::spawn(move || {
+    ///             tx.send(Event::new("/ticks", Tick(i))).unwrap();
+    ///             tx.report()
+    ///         })
+    ///     })
+    ///     .collect::<Vec<_>>();
+    ///
+    /// for producer in producers {
+    ///     assert_eq!(producer.join().unwrap().events_sent, 1);
+    /// }
+    /// # }
+    /// ```
+    pub fn clone_tx_pool(&self, n: usize) -> Vec<EventTx> {
+        std::iter::repeat_with(|| self.get_tx()).take(n).collect()
+    }
+
+    /// Waits up to `timeout` for the server to accept connections and deliver events, and returns
+    /// the address it is listening on. Fails with [`Error::NotReady`] if it doesn't in time.
+    ///
+    /// The listener is bound by [`ServerBuilder::start`], which reports bind errors, so
+    /// connections made once it returned are queued by the OS anyway. This additionally waits
+    /// for the accept and the broadcast loop to be running, e.g. before measuring connection
+    /// latencies.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    /// let addr = server.wait_ready(Duration::from_secs(5)).await.unwrap();
+    ///
+    /// assert_eq!(addr, server.local_addr());
+    /// # }
+    /// ```
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<SocketAddr, Error> {
+        let mut ready = self.inner.ready.subscribe();
+
+        let ready = tokio::time::timeout(timeout, ready.wait_for(|x| *x))
+            .await
+            .is_ok_and(|x| x.is_ok());
+
+        match ready {
+            true => Ok(self.local_addr),
+            false => Err(Error::NotReady),
+        }
+    }
+
+    /// Returns the address the server is listening on, which is useful when it was bound to
+    /// port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the number of connected clients.
+    pub fn connection_count(&self) -> usize {
+        self.inner.clients.client_count()
+    }
+
+    /// Returns whether the server delivers the events published, `false` while it waits for
+    /// clients to connect, see [`ServerBuilder::warm_up`].
+    pub fn is_warmed_up(&self) -> bool {
+        *self.inner.warmed_up.borrow()
+    }
+
+    /// Returns the resources the client `id` is subscribed to, sorted. The list is empty if no
+    /// such client is connected.
+    pub fn list_subscriptions(&self, id: ClientId) -> Vec<String> {
+        self.inner.subscriptions_for(id)
+    }
+
+    /// Sets how the events published to `res` are delivered from now on, see [`QoS`]. `res` may
+    /// also be a pattern, `*` or a prefix ending in `/*`. If several patterns match a resource,
+    /// the longest one applies.
+    ///
+    /// Events on routes with a guarantee above [`QoS::AtMostOnce`] are sent to every client in
+    /// the version 2 envelope with an additional `id`, which the client acknowledges by sending
+    /// `{"type":"ack","id":N}`. Events not acknowledged within
+    /// [`ServerBuilder::ack_timeout`] are sent again until they are or the client disconnects.
+    /// They are delivered to every client directly, also with
+    /// [`BroadcastBackend::TokioBroadcast`].
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::{QoS, ServerBuilder};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    ///
+    /// server.set_route_qos("/alerts/*", QoS::AtLeastOnce);
+    /// server.set_route_qos("/payments", QoS::ExactlyOnce);
+    /// # }
+    /// ```
+    pub fn set_route_qos(&self, res: impl Into<String>, qos: QoS) {
+        self.inner
+            .routes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(res.into())
+            .or_default()
+            .qos = qos;
+    }
+
+    /// Sets which subscribers of `res` receive its events. `res` may also be a pattern, like
+    /// for [`set_route_qos`](Self::set_route_qos).
+    ///
+    /// With [`DeliveryMode::RoundRobin`] every event goes to one subscriber, the subscribers
+    /// taking turns, which turns the route into a work queue. Events published while nobody is
+    /// subscribed are held until a client subscribes, up to 1024 of them. On a route with a
+    /// [`QoS`] above [`QoS::AtMostOnce`], the events a subscriber didn't acknowledge before
+    /// disconnecting go to the others. Round-robin routes aren't batched, and their events are
+    /// delivered to every client directly, also with [`BroadcastBackend::TokioBroadcast`].
+    ///
+    /// Events with a [partition key](Event::with_partition_key) go to the same subscriber
+    /// while it stays subscribed, however busy it is. When subscribers come or go, only the keys
+    /// of those leaving and the keys taken over by those joining move. Clients speaking protocol
+    /// version 2 are told about it with
+    /// `{"type":"partitions","resource":"/jobs","assigned":["a"],"revoked":["b"]}`.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::{DeliveryMode, QoS, ServerBuilder};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    ///
+    /// server.set_route_qos("/jobs/*", QoS::AtLeastOnce);
+    /// server.set_route_delivery("/jobs/*", DeliveryMode::RoundRobin { max_queue_depth: 16 });
+    /// # }
+    /// ```
+    pub fn set_route_delivery(&self, res: impl Into<String>, mode: DeliveryMode) {
+        self.inner
+            .routes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(res.into())
+            .or_default()
+            .delivery = mode;
+    }
+
+    /// Batches the events published to `res` in bursts, or stops batching them with `None`.
+    /// `res` may also be a pattern, like for [`set_route_qos`](Self::set_route_qos).
+    ///
+    /// Clients speaking version 2 of the [protocol](ServerBuilder::max_protocol_version) receive
+    /// the events published within [`Batching::window`] of each other as one frame, a JSON array
+    /// of their envelopes, `[{"type":"event",...},{"type":"event",...}]`, which saves frames and
+    /// lets mobile radios sleep. An event is delayed by at most the window, and a batch holding
+    /// [`Batching::max_events`] events is sent right away. Batches still waiting are sent when
+    /// the server drains or shuts down. Other clients receive every event right away.
+    ///
+    /// Batches are shared by all clients of a resource. Routes with a [`QoS`] above
+    /// [`QoS::AtMostOnce`] aren't batched.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use pushevent::server::{Batching, ServerBuilder};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    ///
+    /// server.set_route_batching(
+    ///     "/prices/*",
+    ///     Some(Batching {
+    ///         window: Duration::from_millis(20),
+    ///         max_events: 50,
+    ///     }),
+    /// );
+    /// # }
+    /// ```
+    pub fn set_route_batching(&self, res: impl Into<String>, batching: Option<Batching>) {
+        self.inner
+            .routes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(res.into())
+            .or_default()
+            .batching = batching;
+    }
+
+    /// Returns the number of events published and not yet taken out of the queue by the
+    /// broadcast loop, see [`ServerBuilder::load_shedder`].
+    pub fn queue_depth(&self) -> usize {
+        self.inner.queue.depth()
+    }
+
+    /// Returns the number of events rejected since the server started because their payload was
+    /// larger than accepted, see [`ServerBuilder::max_payload`].
+    pub fn oversized_events(&self) -> u64 {
+        self.inner.payload_limit.rejected()
+    }
+
+    /// Returns the number of connections dropped since the server started because their
+    /// handshake didn't complete in time, see [`ServerBuilder::handshake_timeout`].
+    pub fn handshake_timeouts(&self) -> u64 {
+        self.inner.handshake_timeouts.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of connections dropped since the server started because too many
+    /// handshakes were in progress, see [`ServerBuilder::max_pending_handshakes`].
+    pub fn refused_handshakes(&self) -> u64 {
+        self.inner.refused_handshakes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times a client was found to have been skipped by the fan-out,
+    /// with [`ServerBuilder::detect_sequence_gaps`]. Always `0` otherwise.
+    pub fn sequence_gaps(&self) -> u64 {
+        self.inner.sequence_gaps.load(Ordering::Relaxed)
+    }
+
+    /// Returns every connected client.
+    ///
+    /// The shards of the registry are copied one at a time, so the snapshot doesn't block
+    /// publishing for long. A client subscribing or disconnecting meanwhile may be listed with
+    /// or without that subscription, but every client is listed once.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections = self
+            .inner
+            .clients
+            .snapshot()
+            .into_values()
+            .map(|(peer, resources)| peer.connection_info(resources))
+            .collect::<Vec<_>>();
+
+        connections.sort_unstable_by_key(|x| x.id);
+        connections
+    }
+
+    /// Returns the connected clients that receive the events published to `res`, whether
+    /// subscribed to it directly or through a pattern.
+    pub fn connections_on(&self, res: &str) -> Vec<ConnectionInfo> {
+        let peers = self
+            .inner
+            .clients
+            .read(res)
+            .subscribers(res)
+            .map(|(_, peer)| peer.clone())
+            .collect::<Vec<_>>();
+
+        let mut connections = peers
+            .into_iter()
+            .map(|peer| peer.connection_info(self.inner.clients.subscriptions(peer.info.id)))
+            .collect::<Vec<_>>();
+
+        connections.sort_unstable_by_key(|x| x.id);
+        connections
+    }
+
+    /// Sends `event` to the client `id` only, no matter which resources it is subscribed to.
+    ///
+    /// The event is queued for the client right away rather than going through the event queue,
+    /// so it may overtake events published before. The per-client filter and transforms aren't
+    /// run. Fails with [`Error::ClientNotFound`] if no such client is connected, and with
+    /// [`Error::PayloadTooLarge`] if the event is larger than accepted.
+    pub fn send_to(&self, id: ClientId, event: Event) -> Result<(), Error> {
+        self.inner.send_to(id, &event)
+    }
+
+    /// Returns a handle sending frames to the client `id` directly, e.g. typing indicators, or
+    /// `None` if no such client is connected.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::Server;
+    /// use pushevent::ClientId;
+    ///
+    /// fn show_typing(server: &Server, to: ClientId, who: &str) {
+    ///     if let Some(tx) = server.get_client_tx(to) {
+    ///         let _ = tx.send(format!(r#"{{"type":"typing","user":"{}"}}"#, who));
+    ///     }
+    /// }
+    /// ```
+    pub fn get_client_tx(&self, id: ClientId) -> Option<ClientTx> {
+        self.inner.clients.get(id).map(|peer| ClientTx {
+            peer,
+            taps: self.inner.taps.clone(),
+        })
+    }
+
+    /// Sends `event` to every client receiving the events published to `res` except `exclude`,
+    /// e.g. to relay a chat message to everyone but its sender. Returns the number of clients it
+    /// was sent to.
+    ///
+    /// Like with [`send_to`](Self::send_to), the event doesn't go through the event queue and
+    /// the transforms aren't run, but the per-client filter is and the event is counted in the
+    /// [statistics](Self::resource_stats) of `res`. Events larger than accepted, not matching the schema
+    /// of `res` or sent while `res` is [paused](Self::pause_route) aren't sent to anyone. As not
+    /// every client received it, the event doesn't become the
+    /// [last event](Self::snapshot_resource) of `res` and isn't replayed to resuming sessions.
+    ///
+    /// # Panics
+    /// Panics if `event` targets another resource than `res`.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::Server;
+    /// use pushevent::{ClientId, Event, SerializableEvent};
+    ///
+    /// struct Chat(String);
+    ///
+    /// impl SerializableEvent for Chat {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.clone()
+    ///     }
+    /// }
+    ///
+    /// fn relay(server: &Server, room: &str, sender: ClientId, text: &str) {
+    ///     let event = Event::new(room, Chat(text.to_string()));
+    ///     let sent = server.broadcast_except(room, sender, event);
+    ///     println!("relayed to {} members of {}", sent, room);
+    /// }
+    /// ```
+    pub fn broadcast_except(&self, res: &str, exclude: ClientId, event: Event) -> usize {
+        assert_eq!(event.res(), res, "event published to another resource");
+        self.inner.broadcast_except(exclude, &event)
+    }
+
+    /// Closes the connection of the client `id` with `reason`, after the frames already queued
+    /// for it. Fails with [`Error::ClientNotFound`] if no such client is connected.
+    ///
+    /// Meant for operators removing a misbehaving client at runtime, usually with
+    /// [`CloseReason::policy`].
+    pub fn disconnect(&self, id: ClientId, reason: CloseReason) -> Result<(), Error> {
+        let peer = self.inner.clients.get(id).ok_or(Error::ClientNotFound)?;
+        if !peer.send_now(reason.into_message()) {
+            return Err(Error::ClientNotFound);
+        }
+
+        // Ends the connection once the close frame is written.
+        peer.tx.close_channel();
+        Ok(())
+    }
+
+    /// Returns the sessions grouping the connections of a user.
+    pub fn sessions(&self) -> &SessionManager {
+        &self.sessions
+    }
+
+    /// Puts the client `id` in `group`, so that it receives the events published to the group
+    /// with [`EventTx::publish_group`]. Returns `false` if it already was in the group.
+    ///
+    /// Groups are managed by the application alone, e.g. from the identity of a client once it
+    /// connected, and exist as long as they have members. Clients leave every group when they
+    /// disconnect. Fails with [`Error::ClientNotFound`] if no such client is connected.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let (connected, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    /// let server = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .on_connect(move |client| {
+    ///         if client.metadata.get("role").map(String::as_str) == Some("admin") {
+    ///             let _ = connected.send(client.id);
+    ///         }
+    ///     })
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// tokio::spawn(async move {
+    ///     while let Some(id) = rx.recv().await {
+    ///         let _ = server.join_group(id, "admins");
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    pub fn join_group(&self, id: ClientId, group: &str) -> Result<bool, Error> {
+        let peer = self.inner.clients.get(id).ok_or(Error::ClientNotFound)?;
+        let mut groups = self
+            .inner
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // Checked while the groups are locked, so a client disconnecting concurrently is either
+        // seen here or removed from the group once it is gone.
+        if peer.tx.is_closed() {
+            return Err(Error::ClientNotFound);
+        }
+
+        Ok(groups.join(id, group))
+    }
+
+    /// Takes the client `id` out of `group`. Returns whether it was in the group.
+    pub fn leave_group(&self, id: ClientId, group: &str) -> bool {
+        self.inner
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .leave(id, group)
+    }
+
+    /// Returns the clients in `group`, in no particular order.
+    pub fn group_members(&self, group: &str) -> Vec<ClientId> {
+        self.inner
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .members(group)
+    }
+
+    /// Returns the groups the client `id` is in, in no particular order.
+    pub fn client_groups(&self, id: ClientId) -> Vec<String> {
+        self.inner
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .groups(id)
+    }
+
+    /// Subscribes to `res` from within the process, returning a stream of the payloads of the
+    /// events delivered to it. `res` may be a pattern like for
+    /// [`subscribe`](Self::subscribe).
+    ///
+    /// Local subscribers see events after the [transforms](ServerBuilder::transform) ran, but
+    /// aren't clients: they aren't counted or listed, and the per-client filter isn't run for
+    /// them. Up to 1024 payloads are held while the stream isn't read, newer ones are dropped
+    /// so that a stalled consumer can't hold back the clients. The stream ends once the server
+    /// shut down, dropping it unsubscribes right away.
+    ///
+    /// This is the method requested as `subscribe_async`, named after who subscribes rather
+    /// than how, and returns the nameable [`LocalSubscription`] instead of an `impl Stream`.
+    /// Local subscribers are kept apart from the clients, so that they don't show up in
+    /// [`connections`](Self::connections) and can't be [disconnected](Self::disconnect).
+    ///
+    /// # Example
+    /// ```
+    /// use futures_util::StreamExt;
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Price(u32);
+    ///
+    /// impl SerializableEvent for Price {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    /// let mut prices = server.subscribe_local("/prices/*");
+    ///
+    /// server.get_tx().send(Event::new("/prices/btc", Price(42))).unwrap();
+    /// assert_eq!(prices.next().await.as_deref(), Some("42"));
+    /// # }
+    /// ```
+    #[doc(alias = "subscribe_async")]
+    pub fn subscribe_local(&self, res: &str) -> LocalSubscription {
+        self.inner.local.subscribe(res)
+    }
+
+    /// Records the events sent to the clients matching `filter` from now on, for finding out
+    /// what a client was actually sent. The returned receiver yields a [`TapRecord`] per event
+    /// and client, also for the frames that couldn't be sent because the client was
+    /// disconnecting. Snapshots and replayed events aren't recorded.
+    ///
+    /// Up to 1024 records are held while the receiver isn't read, newer ones are dropped.
+    /// Dropping the receiver removes the tap, and recording costs nothing while no tap is
+    /// installed.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::{ServerBuilder, TapFilter};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    /// let mut records = server.tap(TapFilter::Resource("/orders/*".into()));
+    ///
+    /// tokio::spawn(async move {
+    ///     while let Some(record) = records.recv().await {
+    ///         println!("{:?} {:?}: {}", record.ts, record.client_id, record.payload_preview);
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    pub fn tap(&self, filter: TapFilter) -> mpsc::Receiver<TapRecord> {
+        self.inner.taps.add(filter)
+    }
+
+    /// Copies every event delivered to `from` to `to` until the returned handle is
+    /// [removed](PipeHandle::remove). `from` may be a pattern like for
+    /// [`subscribe`](Self::subscribe).
+    ///
+    /// The copies are published after the events they are copied from, as if they were
+    /// published to `to`: they run through its transforms and get their own id. Events that
+    /// were copied aren't copied again, so pipes can't loop.
+    ///
+    /// # Example
+    /// ```
+    /// use futures_util::StreamExt;
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Price(u32);
+    ///
+    /// impl SerializableEvent for Price {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    /// let mut prices = server.subscribe_local("/public/prices");
+    /// let pipe = server.pipe("/internal/prices", "/public/prices");
+    ///
+    /// server.get_tx().send(Event::new("/internal/prices", Price(42))).unwrap();
+    /// assert_eq!(prices.next().await.as_deref(), Some("42"));
+    ///
+    /// pipe.remove();
+    /// # }
+    /// ```
+    pub fn pipe(&self, from: &str, to: &str) -> PipeHandle {
+        self.inner.pipe(from, to, None)
+    }
+
+    /// Like [`pipe`](Self::pipe), rewriting the payloads of the copies with `transformer`, which
+    /// is given the resource the event was delivered to.
+    pub fn pipe_with(&self, from: &str, to: &str, transformer: impl Transformer) -> PipeHandle {
+        self.inner.pipe(from, to, Some(Box::new(transformer)))
+    }
+
+    /// Returns the client whose id is `id`, formatted like the `X-Pushevent-Client-Id` header of
+    /// its upgrade response, or `None` if no such client is connected to this server.
+    ///
+    /// Meant for finding a client by the header a load balancer or another node passed along,
+    /// see [`ClientId`].
+    pub fn find_client_by_id(&self, id: &str) -> Option<ClientInfo> {
+        let id = ClientId::parse(id)?;
+        self.inner
+            .clients
+            .get(id)
+            .map(|x| ClientInfo::clone(&x.info))
+    }
+
+    /// Returns the state attached to the client `id`, or `None` if no such client is connected.
+    pub fn client_meta(&self, id: ClientId) -> Option<ClientMeta> {
+        self.inner.clients.get(id).map(|x| x.info.meta.clone())
+    }
+
+    /// Tags the client `id` with `key` set to `value`, e.g. its tenant or role once the
+    /// application authenticated it, returning the previous value. Tags are the
+    /// [`ClientInfo::tags`] of the client, so the hooks and the per-client filter see them.
+    /// They are kept apart from [`ClientInfo::meta`], which tags can't overwrite.
+    ///
+    /// Fails with [`Error::ClientNotFound`] if no such client is connected.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new()
+    ///     .per_client_filter(|client, res, _payload| {
+    ///         !res.starts_with("/admin") || client.tags.get("role").as_deref() == Some("admin")
+    ///     })
+    ///     .start()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// # let id = server.connections()[0].id;
+    /// server.tag_client(id, "role", "admin").unwrap();
+    /// assert_eq!(server.get_tag(id, "role").as_deref(), Some("admin"));
+    /// # }
+    /// ```
+    pub fn tag_client(
+        &self,
+        id: ClientId,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Option<String>, Error> {
+        let peer = self.inner.clients.get(id).ok_or(Error::ClientNotFound)?;
+        Ok(peer.info.tags.insert(key, value))
+    }
+
+    /// Returns the tag `key` of the client `id`, see [`tag_client`](Self::tag_client).
+    pub fn get_tag(&self, id: ClientId, key: &str) -> Option<String> {
+        self.inner.clients.get(id)?.info.tags.get(key)
+    }
+
+    /// Removes the tag `key` from the client `id`, returning its value.
+    pub fn remove_tag(&self, id: ClientId, key: &str) -> Option<String> {
+        self.inner.clients.get(id)?.info.tags.remove(key)
+    }
+
+    /// Subscribes the client `id` to `resource` in addition to the resource it connected to.
+    /// Returns `false` if it already was subscribed.
+    ///
+    /// `resource` may be a pattern, `*` for every resource or a prefix ending in `/*` for every
+    /// resource below it, if [allowed](ServerBuilder::allow_pattern_subscriptions). A client
+    /// receives every event once, no matter through how many of its subscriptions it matches.
+    ///
+    /// Fails with [`Error::ClientNotFound`] if no such client is connected, with
+    /// [`Error::ResourceRejected`] if the resource is refused by the
+    /// [limits](ServerBuilder::max_subscriptions) or the
+    /// [validation hook](ServerBuilder::validate_resource), and with [`Error::Unsupported`] when
+    /// using [`BroadcastBackend::TokioBroadcast`], which only delivers the resource a client
+    /// connected to.
+    pub fn subscribe(&self, id: ClientId, resource: &str) -> Result<bool, Error> {
+        if self.inner.channels.is_some() {
+            return Err(Error::Unsupported);
+        }
+
+        let peer = self.inner.clients.get(id).ok_or(Error::ClientNotFound)?;
+        let subscriptions = self.inner.clients.subscriptions(id);
+        if subscriptions.iter().any(|x| x == resource) {
+            return Ok(false);
+        }
+        self.inner
+            .limits
+            .check(&peer.info, resource, subscriptions.len())
+            .map_err(Error::ResourceRejected)?;
+
+        // Held before subscribing, so that the events published meanwhile come after it.
+        let snapshot = self
+            .inner
+            .snapshot_hook(resource)
+            .cloned()
+            .map(|hook| (hook, peer.hold_for_snapshot()));
+        let subscribed = peer.clone();
+        let added = self.inner.clients.write(resource).add(resource, id, peer);
+
+        // The connection closes its queue before unsubscribing, so a client disconnecting
+        // concurrently is either seen here or unsubscribed from `resource` as well.
+        if subscribed.tx.is_closed() {
+            self.inner.clients.write(resource).remove(resource, id);
+            return Err(Error::ClientNotFound);
+        }
+
+        if !added {
+            if let Some((_, position)) = snapshot {
+                subscribed.send_snapshot(position, Vec::new());
+            }
+            return Ok(false);
+        }
+
+        let info = subscribed.info.clone();
+        self.inner.audit(AuditKind::Subscribe, &info, resource);
+        rebalance(&self.inner, Some(resource));
+        assign_unassigned(&self.inner, resource);
+
+        if let Some((hook, position)) = snapshot {
+            let inner = self.inner.clone();
+            let resource = resource.to_string();
+            self.inner.runtime.spawn(async move {
+                let frames = take_snapshot(&inner, &hook, &resource, &info).await;
+                subscribed.send_snapshot(position, frames);
+            });
+        }
+        Ok(true)
+    }
+
+    /// Pauses the route `res`: events published to it are held back instead of being delivered,
+    /// until the route is resumed with [`resume_route`](Self::resume_route). Up to 1024 events
+    /// are held, older ones are dropped. Returns `false` if the route already was paused.
+    ///
+    /// Only events published to `res` itself are held back, pausing a pattern such as
+    /// `/prices/*` doesn't pause the resources it matches.
+    pub fn pause_route(&self, res: &str) -> bool {
+        let mut paused = self
+            .inner
+            .paused_routes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match paused.get_mut(res) {
+            // Not resumed after all, the events held so far stay held.
+            Some(route) if route.resuming => {
+                route.resuming = false;
+                true
+            }
+            Some(_) => false,
+            None => {
+                paused.insert(res.to_string(), PausedRoute::default());
+                true
+            }
+        }
+    }
+
+    /// Resumes the route `res`. The events held back while it was paused are delivered by the
+    /// broadcast loop once it processed the events queued before, ahead of any event published
+    /// later. Returns `false` if the route wasn't paused, fails with [`Error::QueueFull`] if a
+    /// bounded queue is at capacity, in which case the route stays paused.
+    pub fn resume_route(&self, res: &str) -> Result<bool, Error> {
+        let mut paused = self
+            .inner
+            .paused_routes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let route = match paused.get_mut(res) {
+            Some(x) if !x.resuming => x,
+            _ => return Ok(false),
+        };
+
+        self.tx.resume_route(res)?;
+        route.resuming = true;
+        Ok(true)
+    }
+
+    /// Pauses the client `id`: the frames sent to it are held back instead of being written,
+    /// until it is resumed with [`resume_client`](Self::resume_client), or dropped depending on
+    /// the [`ServerBuilder::pause_policy`]. Returns `false` if the client already was paused.
+    ///
+    /// Meant for clients that fall behind, e.g. whose [`ConnectionInfo::queue_depth`] keeps
+    /// growing, so that their connection isn't flooded while they catch up. Close frames aren't
+    /// held back. Fails with [`Error::ClientNotFound`] if no such client is connected and with
+    /// [`Error::Unsupported`] with [`BroadcastBackend::TokioBroadcast`], where clients take the
+    /// events out of the channel themselves.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::Server;
+    ///
+    /// fn pause_laggards(server: &Server) {
+    ///     for client in server.connections() {
+    ///         if client.queue_depth > 512 {
+    ///             let _ = server.pause_client(client.id);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn pause_client(&self, id: ClientId) -> Result<bool, Error> {
+        if self.inner.channels.is_some() {
+            return Err(Error::Unsupported);
+        }
+
+        let peer = self.inner.clients.get(id).ok_or(Error::ClientNotFound)?;
+        Ok(peer.pause())
+    }
+
+    /// Resumes the client `id`, sending the frames held back while it was paused before any
+    /// sent later. Returns `false` if the client wasn't paused, fails with
+    /// [`Error::ClientNotFound`] if no such client is connected.
+    pub fn resume_client(&self, id: ClientId) -> Result<bool, Error> {
+        let peer = self.inner.clients.get(id).ok_or(Error::ClientNotFound)?;
+        Ok(peer.resume())
+    }
+
+    /// Returns the payload of the last event delivered to `res`, after the
+    /// [transforms](ServerBuilder::transform) ran, or `None` if there was none yet.
+    ///
+    /// Useful for serving the current state to clients polling over HTTP, so that they get the
+    /// same data as websocket clients.
+    pub fn snapshot_resource(&self, res: &str) -> Option<String> {
+        self.inner
+            .last_events
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(res)
+            .map(|x| x.to_string())
+    }
+
+    /// Returns the delivery statistics of `res`, or `None` if no event was published to it yet.
+    ///
+    /// Events are counted for the resource they are published to, also when they are delivered
+    /// through a pattern. With [`BroadcastBackend::TokioBroadcast`] every subscriber is assumed
+    /// to receive the event, as encoded for version 1 of the protocol.
+    pub fn resource_stats(&self, res: &str) -> Option<ResourceStats> {
+        self.inner
+            .stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(res)
+            .cloned()
+    }
+
+    /// Returns the delivery statistics of every resource an event was published to.
+    pub fn all_resource_stats(&self) -> HashMap<String, ResourceStats> {
+        self.inner
+            .stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Returns whether the route `res` is paused, see [`pause_route`](Self::pause_route).
+    pub fn is_paused(&self, res: &str) -> bool {
+        self.inner
+            .paused_routes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(res)
+            .is_some_and(|route| !route.resuming)
+    }
+
+    /// Unsubscribes the client `id` from `resource`, which must have been subscribed to with
+    /// [`subscribe`](Self::subscribe). Returns whether it was subscribed. The resource a client
+    /// connected to can't be unsubscribed from.
+    pub fn unsubscribe(&self, id: ClientId, resource: &str) -> bool {
+        let info = match self.inner.clients.get(id) {
+            Some(peer) if peer.info.resource != resource => peer.info.clone(),
+            _ => return false,
+        };
+
+        let removed = self.inner.clients.write(resource).remove(resource, id);
+        if removed {
+            if let Some(sequences) = &self.inner.sequences {
+                sequences
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .unsubscribed(id, resource);
+            }
+            rebalance(&self.inner, Some(resource));
+            self.inner.audit(AuditKind::Unsubscribe, &info, resource);
+        }
+        removed
+    }
+
+    /// Shuts the server down, giving clients up to `grace` to disconnect on their own. Meant
+    /// for rolling deploys, where clients should move to another instance before this one exits.
+    ///
+    /// The server immediately stops accepting connections and publishing events, events sent
+    /// afterwards fail with [`Error::ChannelClosed`]. Every client is sent a
+    /// `{"type":"draining","reconnect_after_ms":...}` event, if it speaks protocol version 2,
+    /// followed by a close frame with code 1012 (service restart). The reconnect delays are spread evenly over `grace` so that the
+    /// clients don't all reconnect at once.
+    ///
+    /// Resolves as soon as all clients are gone, or once `grace` has passed, at which point the
+    /// remaining connections are closed. Returns the number of connections that had to be closed.
+    /// Use [`connection_count`](Self::connection_count) to follow the progress in the meantime.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    ///
+    /// let closed = server.drain(Duration::from_secs(30)).await;
+    /// assert_eq!(closed, 0);
+    /// # }
+    /// ```
+    pub async fn drain(&self, grace: Duration) -> usize {
+        let deadline = Instant::now() + grace;
+        self.inner.flush_batches();
+
+        {
+            let shards = self.inner.clients.write_all();
+            // Set while holding the locks so that clients finishing their handshake right now are
+            // drained exactly once, either here or when they register.
+            self.inner.shutdown.send_replace(true);
+
+            let count = demux::count_clients(shards.iter().map(|x| &**x));
+            tracing::info!("draining {} connections", count);
+
+            let mut seen = HashSet::new();
+            let peers = shards
+                .iter()
+                .flat_map(|x| x.clients())
+                .filter(|(id, _)| seen.insert(*id));
+
+            for (idx, (_, peer)) in peers.enumerate() {
+                peer.drain(grace.mul_f64(idx as f64 / count as f64));
+            }
+        }
+
+        if tokio::time::timeout_at(deadline, self.disconnected())
+            .await
+            .is_ok()
+        {
+            return 0;
+        }
+
+        // Dropping the senders ends the connection tasks.
+        let remaining = self.inner.clients.take();
+        let count = demux::count_clients(&remaining);
+        tracing::info!("closing {} connections after the grace period", count);
+
+        count
+    }
+
+    /// Shuts the server down without a grace period. Stops accepting connections and
+    /// publishing events, closes every connection with 1001 (going away) and resolves once all
+    /// connection tasks have finished. Use [`drain`](Self::drain) to give clients time to
+    /// reconnect elsewhere first.
+    ///
+    /// Events the broadcast loop or its workers are delivering are handed to every recipient
+    /// before the connections are closed, waiting up to the
+    /// [shutdown timeout](ServerBuilder::shutdown_timeout). Events still queued are dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    ///
+    /// server.shutdown().await;
+    /// assert_eq!(server.connection_count(), 0);
+    /// # }
+    /// ```
+    pub async fn shutdown(&self) {
+        self.inner.flush_batches();
+        {
+            let _shards = self.inner.clients.write_all();
+            // Clients finishing their handshake right now either see the flag when they
+            // register, and are drained right away, or are still registered once the
+            // connections are closed.
+            self.inner.shutdown.send_replace(true);
+        }
+
+        let timeout = self.inner.shutdown_timeout;
+        if !self.inner.broadcasting.wait_timeout(timeout).await {
+            tracing::warn!("events were still being delivered after {:?}", timeout);
+        }
+        self.inner.closing.send_replace(true);
+
+        tracing::info!("closing {} connections", self.connection_count());
+        self.disconnected().await;
+    }
+
+    /// Resolves once no clients are connected.
+    async fn disconnected(&self) {
+        loop {
+            let disconnected = self.inner.disconnected.notified();
+            pin_mut!(disconnected);
+            disconnected.as_mut().enable();
+
+            let remaining = self.connection_count();
+            if remaining == 0 {
+                return;
+            }
+
+            tracing::debug!("waiting for {} connections to close", remaining);
+            disconnected.await;
+        }
+    }
+
+    /// Waits for SIGINT or SIGTERM (Ctrl-C on Windows), then [drains](Self::drain) the server
+    /// with the grace period set by [`ServerBuilder::shutdown_grace`]. Returns once all
+    /// connections are closed.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), pushevent::Error> {
+    /// let server = ServerBuilder::new().start().await?;
+    /// let tx = server.get_tx();
+    ///
+    /// // Hand `tx` to whatever publishes events.
+    ///
+    /// server.run_until_shutdown().await
+    /// # }
+    /// ```
+    pub async fn run_until_shutdown(&self) -> Result<(), Error> {
+        self.run_until(future::pending()).await
+    }
+
+    /// Same as [`run_until_shutdown`](Self::run_until_shutdown), but also shuts down once
+    /// `trigger` resolves. This lets the application stop the server for reasons of its own,
+    /// see [`flag_set`] for stopping on an [`AtomicBool`].
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    /// use tokio::sync::oneshot;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), pushevent::Error> {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await?;
+    /// let (stop, stopped) = oneshot::channel::<()>();
+    ///
+    /// stop.send(()).unwrap();
+    /// server
+    ///     .run_until(async {
+    ///         let _ = stopped.await;
+    ///     })
+    ///     .await
+    /// # }
+    /// ```
+    pub async fn run_until(&self, trigger: impl Future<Output = ()>) -> Result<(), Error> {
+        let signal = shutdown_requested().map_err(Error::Signal)?;
+        pin_mut!(signal, trigger);
+
+        if let future::Either::Left((Err(e), _)) = future::select(signal, trigger).await {
+            return Err(Error::Signal(e));
+        }
+
+        tracing::info!("shutting down");
+        let closed = self.drain(self.inner.shutdown_grace).await;
+        tracing::info!("shut down, {} connections were closed forcefully", closed);
+
+        Ok(())
+    }
+
+    /// Returns whether the server tasks are running as expected.
+    ///
+    /// A panic in one of the server tasks doesn't take the application down, the task stops
+    /// and the server reports [`Health::Failed`] from then on. Meant for liveness probes.
+    pub fn health(&self) -> Health {
+        match &*self
+            .inner
+            .failure
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(reason) => Health::Failed(reason.clone()),
+            None => Health::Running,
+        }
+    }
+
+    /// Returns the state of the listener and of the broadcast loop, meant for liveness and
+    /// readiness probes.
+    ///
+    /// The broadcast loop is reported as dead once it spent longer than the
+    /// [stall timeout](ServerBuilder::stall_timeout) delivering a single event, e.g. because a
+    /// hook blocks. Events handed to [broadcast workers](ServerBuilder::broadcast_workers) are
+    /// delivered by the workers and don't count.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    /// server.wait_ready(std::time::Duration::from_secs(5)).await.unwrap();
+    ///
+    /// let report = server.health_report();
+    /// assert!(report.listener_alive);
+    /// assert_eq!(report.event_queue_depth, 0);
+    /// assert_eq!(report.last_accepted_at, None);
+    /// # }
+    /// ```
+    pub fn health_report(&self) -> HealthReport {
+        let inner = &self.inner;
+
+        HealthReport {
+            accepting: !*inner.shutdown.borrow(),
+            listener_alive: inner.listener.is_alive(inner.stall_timeout),
+            broadcast_loop_alive: inner.broadcaster.is_alive(inner.stall_timeout),
+            event_queue_depth: inner.queue.depth(),
+            last_accepted_at: inner.last_accepted.get(),
+            last_delivered_at: inner.last_delivered.get(),
+        }
+    }
+
+    /// Returns whether the server tasks have finished, which happens once the server has shut
+    /// down. Doesn't wait.
+    pub fn try_join(&self) -> bool {
+        self.inner.running.is_empty()
+    }
+
+    /// Waits for the server tasks to finish. Fails with [`Error::TaskPanicked`] if one of them
+    /// panicked, rather than propagating the panic.
+    pub async fn join(&self) -> Result<(), Error> {
+        self.inner.running.wait().await;
+
+        match self.health() {
+            Health::Failed(_) => Err(Error::TaskPanicked),
+            Health::Running => Ok(()),
+        }
+    }
+
+    /// Same as [`join`](Self::join), but gives up with [`Error::JoinTimeout`] once `timeout`
+    /// has passed.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::Error;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+    ///
+    /// // The server runs until it is shut down.
+    /// let res = server.join_timeout(Duration::from_millis(10)).await;
+    /// assert!(matches!(res, Err(Error::JoinTimeout)));
+    /// # }
+    /// ```
+    pub async fn join_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        tokio::time::timeout(timeout, self.join())
+            .await
+            .map_err(|_| Error::JoinTimeout)?
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("local_addr", &self.local_addr)
+            .field("connections", &self.connection_count())
+            .finish()
+    }
+}
+
+/// Resolves once `flag` is set, checking it every 100 milliseconds. Meant as the trigger of
+/// [`Server::run_until`] in applications that signal shutdown through a flag.
+///
+/// # Example
+/// ```no_run
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::sync::Arc;
+///
+/// use pushevent::server::{self, ServerBuilder};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), pushevent::Error> {
+/// let stop = Arc::new(AtomicBool::new(false));
+/// let server = ServerBuilder::new().start().await?;
+///
+/// // Elsewhere: stop.store(true, Ordering::Relaxed);
+///
+/// server.run_until(server::flag_set(stop)).await
+/// # }
+/// ```
+pub async fn flag_set(flag: Arc<AtomicBool>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+
+    while !flag.load(Ordering::Relaxed) {
+        interval.tick().await;
+    }
+}
+
+/// Installs the shutdown signal handlers and returns a future resolving once one of the signals
+/// is received. Installing the handlers up front means a signal arriving before the future is
+/// polled isn't missed.
+#[cfg(unix)]
+fn shutdown_requested() -> io::Result<impl Future<Output = io::Result<()>>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    Ok(async move {
+        let interrupt = interrupt.recv();
+        let terminate = terminate.recv();
+        pin_mut!(interrupt, terminate);
+        future::select(interrupt, terminate).await;
+
+        Ok(())
+    })
+}
+
+#[cfg(not(unix))]
+fn shutdown_requested() -> io::Result<impl Future<Output = io::Result<()>>> {
+    Ok(tokio::signal::ctrl_c())
+}
+
+/// Spawns a server task, recording it as failed if it panics.
+fn spawn_task(
+    inner: Arc<ServerInner>,
+    name: &'static str,
+    task: impl Future<Output = ()> + Send + 'static,
+) {
+    let running = inner.running.enter();
+    let runtime = inner.runtime.clone();
+    runtime.spawn(async move {
+        if let Err(payload) = AssertUnwindSafe(task).catch_unwind().await {
+            inner.fail(format!("{} panicked: {}", name, panic_message(&*payload)));
+        }
+
+        drop(running);
+    });
+}
+
+/// Returns the message a panic was raised with.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Unsubscribes a client when dropped, which also happens when its connection task panics.
+struct Subscription<'a> {
+    inner: &'a ServerInner,
+    info: &'a ClientInfo,
+    session: Option<&'a str>,
+    /// Why the connection ended, for the audit log.
+    reason: String,
+    connected_at: Instant,
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        // Before unsubscribing, so that events published in between may be sent twice but are
+        // never lost.
+        let id = self.info.id;
+        let resource = self.info.resource.as_str();
+
+        if let (Some(replay), Some(token)) = (&self.inner.replay, self.session) {
+            replay.disconnected(token.to_string(), resource);
+        }
+
+        self.inner.clients.remove_client(id);
+        let unacked = self.inner.acks.remove_client(id);
+        self.inner
+            .sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .detach(id);
+        self.inner
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove_client(id);
+        if let Some(sequences) = &self.inner.sequences {
+            sequences
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove_client(id);
+        }
+
+        if let Some(channels) = &self.inner.channels {
+            channels.release(resource);
+        }
+
+        self.inner.disconnected.notify_waiters();
+
+        // A panicking hook would abort the process while unwinding.
+        if let Some(on_disconnect) = self
+            .inner
+            .on_disconnect
+            .as_ref()
+            .filter(|_| !thread::panicking())
+        {
+            on_disconnect(self.info);
+        }
+        if !thread::panicking() {
+            self.inner
+                .middleware
+                .on_disconnect(self.info, self.connected_at.elapsed());
+
+            let reason = std::mem::take(&mut self.reason);
+            self.inner.audit(
+                AuditKind::Disconnect { reason },
+                self.info,
+                &self.info.resource,
+            );
+
+            rebalance(self.inner, None);
+            // Only events of round-robin routes are handed to someone else.
+            for msg in unacked {
+                reassign(self.inner, msg);
+            }
+        }
+    }
+}
+
+/// Periodically forgets the sessions that can no longer be resumed, until the server shuts down.
+async fn expire_sessions(inner: Arc<ServerInner>) {
+    let shutdown = shutdown_signal(inner.shutdown.subscribe());
+    pin_mut!(shutdown);
+
+    let mut interval = tokio::time::interval(replay::SESSION_TTL / 10);
+    loop {
+        let tick = interval.tick();
+        pin_mut!(tick);
+
+        if let future::Either::Right(_) = future::select(tick, shutdown.as_mut()).await {
+            break;
+        }
+
+        if let Some(replay) = &inner.replay {
+            replay.expire();
+        }
+    }
+}
+
+/// Periodically checks the depth of the event queue, dropping new events while it is too deep,
+/// until the server shuts down.
+async fn shed_load(inner: Arc<ServerInner>, shedder: LoadShedder) {
+    let shutdown = shutdown_signal(inner.shutdown.subscribe());
+    pin_mut!(shutdown);
+
+    let mut shedding = false;
+    let mut interval = tokio::time::interval(shedder.check_interval);
+    loop {
+        let tick = interval.tick();
+        pin_mut!(tick);
+
+        if let future::Either::Right(_) = future::select(tick, shutdown.as_mut()).await {
+            break;
+        }
+
+        let depth = inner.queue.depth();
+        if (depth > shedder.max_queue_depth) == shedding {
+            continue;
+        }
+
+        shedding = !shedding;
+        let dropped = inner.queue.set_shedding(shedding);
+        if shedding {
+            tracing::warn!(
+                "{} events queued, dropping new events until there are at most {}",
+                depth,
+                shedder.max_queue_depth
+            );
+        } else {
+            tracing::warn!(
+                "{} events queued, no longer dropping new events after dropping {}",
+                depth,
+                dropped
+            );
+        }
+    }
+
+    // Events published while draining are delivered.
+    inner.queue.set_shedding(false);
+}
+
+/// Periodically sends the events clients didn't acknowledge in time again, until the server
+/// shuts down.
+async fn resend_unacked(inner: Arc<ServerInner>) {
+    let shutdown = shutdown_signal(inner.shutdown.subscribe());
+    pin_mut!(shutdown);
+
+    let mut interval = tokio::time::interval((inner.ack_timeout / 4).max(Duration::from_millis(1)));
+    loop {
+        let tick = interval.tick();
+        pin_mut!(tick);
+
+        if let future::Either::Right(_) = future::select(tick, shutdown.as_mut()).await {
+            break;
+        }
+
+        for (id, event_id, event, qos) in inner.acks.expired(inner.ack_timeout) {
+            // Acknowledged since it expired.
+            if qos == QoS::ExactlyOnce && inner.acks.is_acked(id, event_id) {
+                continue;
+            }
+
+            if let Some(peer) = inner.clients.get(id) {
+                let ack = Ack {
+                    dup: qos == QoS::ExactlyOnce,
+                };
+                let sent = peer.send(protocol::encode_with(&peer.info, &event, Some(ack)));
+                inner
+                    .taps
+                    .record(&peer.info, event.res(), event.payload(), sent);
+            }
+        }
+    }
+}
+
+/// Pings the clients that didn't ping the server within `interval`, until the server shuts down.
+async fn ping_clients(inner: Arc<ServerInner>, interval: Duration) {
+    let shutdown = shutdown_signal(inner.shutdown.subscribe());
+    pin_mut!(shutdown);
+
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        let tick = ticks.tick();
+        pin_mut!(tick);
+
+        if let future::Either::Right(_) = future::select(tick, shutdown.as_mut()).await {
+            break;
+        }
+
+        for peer in inner.clients.all() {
+            let pinged = peer
+                .last_ping
+                .get()
+                .and_then(|x| x.elapsed().ok())
+                .is_some_and(|x| x < interval);
+
+            // Sent even to paused clients, which are still connected.
+            if !pinged {
+                peer.send_now(Message::Ping(Default::default()));
+            }
+        }
+    }
+}
+
+/// Sends every batch once its window ends, until the server shuts down.
+async fn flush_batches(inner: Arc<ServerInner>) {
+    let shutdown = shutdown_signal(inner.shutdown.subscribe());
+    pin_mut!(shutdown);
+
+    loop {
+        let deadline = inner.batches.next_deadline();
+        let due = async move {
+            match deadline {
+                Some(x) => tokio::time::sleep_until(x).await,
+                None => future::pending().await,
+            }
+        };
+        // A batch starting now may be due before the others.
+        let started = inner.batches.started();
+        pin_mut!(due, started);
+
+        let wake = future::select(due, started);
+        if let future::Either::Right(_) = future::select(wake, shutdown.as_mut()).await {
+            break;
+        }
+
+        for (res, events) in inner.batches.expired(Instant::now()) {
+            deliver_batch(&inner, &res, events);
+        }
+    }
+
+    // Batched while draining.
+    inner.flush_batches();
+}
+
+/// Resolves once the server starts shutting down.
+async fn shutdown_signal(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|x| *x).await;
+}
+
+async fn accept_loop(inner: Arc<ServerInner>, listener: TcpListener, socket: SocketOptions) {
+    let shutdown = shutdown_signal(inner.shutdown.subscribe());
+    pin_mut!(shutdown);
+    let _running = inner.listener.run();
+    inner.loop_started();
+
+    loop {
+        let accept = listener.accept();
+        pin_mut!(accept);
+
+        match future::select(accept, shutdown.as_mut()).await {
+            future::Either::Left((Ok((stream, addr)), _)) => {
+                inner.last_accepted.set_now();
+                // IPv4 clients of a dual-stack listener show up with their IPv4 address.
+                let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+                if let Err(e) = socket.configure(&stream) {
+                    tracing::debug!("{}: failed to set socket options: {}", addr, e);
+                }
+
+                let permit = match inner.pending_handshakes.clone().try_acquire_owned() {
+                    Ok(x) => x,
+                    Err(_) => {
+                        tracing::debug!("{}: dropped, too many handshakes in progress", addr);
+                        inner.refused_handshakes.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                };
+
+                inner
+                    .runtime
+                    .spawn(handle_connection(inner.clone(), stream, addr, permit));
+            }
+            _ => break,
+        }
+    }
+}
+
+/// Starts the broadcast worker `index`, which delivers the events it is sent until the sender is
+/// dropped.
+fn spawn_worker(
+    inner: Arc<ServerInner>,
+    index: usize,
+    stack_size: Option<usize>,
+) -> io::Result<std::sync::mpsc::Sender<Work>> {
+    let (tx, rx) = std::sync::mpsc::channel::<Work>();
+
+    let mut builder = thread::Builder::new().name(format!("pushevent-broadcast-{}", index));
+    if let Some(size) = stack_size {
+        builder = builder.stack_size(size);
+    }
+
+    let guards = (inner.running.enter(), inner.broadcasting.enter());
+    let worker = inner.clone();
+    let spawned = builder.spawn(move || {
+        let delivered = panic::catch_unwind(AssertUnwindSafe(|| {
+            for work in rx {
+                work.run(&worker);
+            }
+        }));
+
+        if let Err(payload) = delivered {
+            worker.fail(format!(
+                "broadcast worker {} panicked: {}",
+                index,
+                panic_message(&*payload)
+            ));
+        }
+
+        drop(guards);
+    });
+
+    // The guards were dropped with the closure if the thread didn't start.
+    spawned?;
+    Ok(tx)
+}
+
+/// Delivers the events taken out of `rx`, on the broadcast loop itself or by handing them to
+/// `workers` by resource.
+async fn broadcast_loop(
+    inner: Arc<ServerInner>,
+    mut rx: EventRx,
+    workers: Vec<std::sync::mpsc::Sender<Work>>,
+) {
+    let shutdown = shutdown_signal(inner.shutdown.subscribe());
+    pin_mut!(shutdown);
+    let _running = inner.broadcaster.run();
+    let _broadcasting = inner.broadcasting.enter();
+    inner.loop_started();
+
+    if let Some((expected_clients, timeout)) = inner.warm_up {
+        let mut warmed_up = inner.warmed_up.subscribe();
+        let warm_up = tokio::time::timeout(timeout, warmed_up.wait_for(|x| *x));
+        pin_mut!(warm_up);
+
+        if let future::Either::Left((Err(_), _)) = future::select(warm_up, shutdown.as_mut()).await
+        {
+            tracing::info!(
+                "warm-up timed out with {} of {} clients connected",
+                inner.clients.client_count(),
+                expected_clients
+            );
+        }
+        inner.warmed_up.send_replace(true);
+    }
+
+    let dispatch = |work: Work| {
+        if workers.is_empty() {
+            return work.run(&inner);
+        }
+
+        let mut hasher = FxHasher::default();
+        work.res().hash(&mut hasher);
+        let worker = &workers[hasher.finish() as usize % workers.len()];
+
+        if worker.send(work).is_err() {
+            tracing::error!("dropping event, its broadcast worker stopped");
+        }
+    };
+
+    loop {
+        let recv = rx.recv();
+        pin_mut!(recv);
+
+        let queued = match future::select(recv, shutdown.as_mut()).await {
+            future::Either::Left((Some(x), _)) => x,
+            _ => break,
+        };
+
+        let _busy = inner.broadcaster.busy();
+        match queued {
+            Queued::One(msg) => dispatch(Work::Deliver(msg)),
+            Queued::All(msg) => deliver_caught(&inner, msg, deliver_all),
+            Queued::Group(group, msg) => {
+                deliver_caught(&inner, msg, |inner, msg| deliver_group(inner, &group, msg))
+            }
+            Queued::Batch(msgs) => msgs.into_iter().map(Work::Deliver).for_each(dispatch),
+            Queued::Resume(res) => dispatch(Work::Resume(res)),
+        }
+    }
+
+    // The workers deliver the events they were sent and stop.
+    drop(workers);
+    inner.local.close();
+}
+
+/// What the broadcast loop hands to the worker delivering a resource.
+enum Work {
+    Deliver(Event),
+    /// Delivers the events held for a paused route and resumes it, see [`Server::resume_route`].
+    Resume(String),
+}
+
+impl Work {
+    fn res(&self) -> &str {
+        match self {
+            Self::Deliver(msg) => msg.res(),
+            Self::Resume(res) => res,
+        }
+    }
+
+    fn run(self, inner: &ServerInner) {
+        match self {
+            Self::Deliver(msg) => deliver_caught(inner, msg, deliver),
+            Self::Resume(res) => resume(inner, &res),
+        }
+    }
+}
+
+/// Resumes the paused route `res` and delivers the events held for it, unless it was paused
+/// again since it was resumed.
+fn resume(inner: &ServerInner, res: &str) {
+    let route = {
+        let mut paused = inner
+            .paused_routes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match paused.get(res) {
+            Some(route) if route.resuming => paused.remove(res),
+            _ => None,
+        }
+    };
+
+    // Published once the routes are unlocked, publishing checks them again for the copies made
+    // by pipes.
+    for msg in route.into_iter().flat_map(|route| route.held) {
+        deliver_caught(inner, msg, publish);
+    }
+}
+
+/// Delivers `msg`, dropping it if a hook panics unless the server shouldn't restart on panics.
+fn deliver_caught(inner: &ServerInner, msg: Event, deliver: impl FnOnce(&ServerInner, Event)) {
+    let res = msg.get_res();
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| deliver(inner, msg))) {
+        if !inner.restart_on_panic {
+            panic::resume_unwind(payload);
+        }
+
+        tracing::error!(
+            "dropping event for {} after a panic: {}",
+            res,
+            panic_message(&*payload)
+        );
+    }
+}
+
+/// Transforms `msg` and hands it to its subscribers, unless its route is paused.
+fn deliver(inner: &ServerInner, msg: Event) {
+    if let Some(msg) = admit(inner, msg) {
+        publish(inner, msg);
+    }
+}
+
+/// Transforms and checks `msg`. Returns `None` if it was dropped, or held because its route is
+/// paused.
+fn admit(inner: &ServerInner, msg: Event) -> Option<Event> {
+    let msg = transform::apply(&inner.transforms, msg)?;
+
+    #[cfg(feature = "schema")]
+    if !inner.schemas.check(&msg) {
+        return None;
+    }
+
+    // Checked when the event was published, but transforms may have grown it since.
+    if let Err(e) = inner.payload_limit.check(&msg) {
+        tracing::warn!("dropping event for {}: {}", msg.res(), e);
+        return None;
+    }
+
+    // Also held while the route is resuming, these events were queued before it was resumed.
+    if let Some(route) = inner
+        .paused_routes
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_mut(msg.res())
+    {
+        if route.held.len() == PAUSED_ROUTE_CAPACITY {
+            tracing::warn!(
+                "dropping the oldest event held for paused route {}",
+                msg.res()
+            );
+            route.held.pop_front();
+        }
+        route.held.push_back(msg);
+        return None;
+    }
+
+    Some(msg)
+}
+
+/// Forwards the already transformed `msg` to the bridges and hands it to its subscribers, then
+/// delivers its copies to the routes it is piped to.
+fn publish(inner: &ServerInner, msg: Event) {
+    inner.bridges.forward(&msg);
+    let piped = inner.pipes.pipe(&msg);
+    fan_out(inner, msg);
+
+    // The copies are published without being piped again, so that pipes can't loop.
+    for msg in piped {
+        if let Some(msg) = admit(inner, msg) {
+            fan_out(inner, msg);
+        }
+    }
+}
+
+/// Hands the already transformed `msg` to its subscribers.
+fn fan_out(inner: &ServerInner, msg: Event) {
+    let id = inner.next_event_id();
+    let msg = msg.with_id(id);
+
+    inner
+        .last_events
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(msg.get_res(), protocol::payload(&msg));
+    inner.local.publish(&msg);
+
+    let peers = inner.clients.read(msg.res());
+
+    // Recorded while the shard is locked, so that a client resuming its session either finds
+    // the event in the history or receives it when it is delivered, never both.
+    if let Some(replay) = &inner.replay {
+        replay.record(&msg);
+    }
+
+    let route = inner.route(msg.res());
+    if let DeliveryMode::RoundRobin { max_queue_depth } = route.delivery {
+        drop(peers);
+        assign(inner, msg, route.qos, max_queue_depth);
+        return;
+    }
+
+    let mut stats = Delivery::default();
+    let qos = route.qos;
+    // Nothing sends the batches anymore once the server shuts down.
+    let batching = route
+        .batching
+        .filter(|_| qos == QoS::AtMostOnce && !*inner.shutdown.borrow());
+
+    if let Some(channels) = inner
+        .channels
+        .as_ref()
+        .filter(|_| qos == QoS::AtMostOnce && batching.is_none())
+    {
+        // Each subscriber takes the event out of the channel, assuming it was accepted.
+        stats.subscribers = peers.subscribers(msg.res()).count();
+        stats.sent = stats.subscribers as u64;
+        stats.bytes = (msg.payload().len() * stats.subscribers) as u64;
+        drop(peers);
+
+        let res = msg.get_res();
+        channels.publish(msg);
+        inner.record(&res, stats);
+        return;
+    }
+
+    let ack = match qos {
+        QoS::AtMostOnce => None,
+        _ => Some(Ack { dup: false }),
+    };
+    let mut frames = protocol::Frames::new(&msg, ack);
+    let mut sequences = inner
+        .sequences
+        .as_ref()
+        .map(|x| x.lock().unwrap_or_else(PoisonError::into_inner));
+    let previous = sequences.as_mut().and_then(|x| x.published(msg.res(), id));
+
+    for (_, recp) in peers.subscribers(msg.res()) {
+        stats.subscribers += 1;
+        if let Some(gap) = sequences
+            .as_mut()
+            .and_then(|x| x.offered(recp.info.id, msg.res(), previous, id))
+        {
+            inner.sequence_gaps.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                "{}: skipped events of {} after {} up to {}",
+                recp.info.addr,
+                gap.resource,
+                gap.from,
+                gap.to
+            );
+        }
+
+        if !inner.accepts(&recp.info, &msg) {
+            continue;
+        }
+
+        // Sent with the batch.
+        if batching.is_some() && protocol::receives_batches(&recp.info) {
+            continue;
+        }
+
+        // Recorded before sending, so that an acknowledgement can't arrive first.
+        if ack.is_some() {
+            inner.acks.sent(recp.info.id, id, msg.clone(), qos);
+        }
+
+        let frame = frames.get(&recp.info);
+        let len = frame.len() as u64;
+        let sent = recp.send(frame);
+        inner
+            .taps
+            .record(&recp.info, msg.res(), msg.payload(), sent);
+        if sent {
+            stats.sent += 1;
+            stats.bytes += len;
+        } else {
+            stats.failed += 1;
+            if ack.is_some() {
+                inner.acks.ack(recp.info.id, id);
+            }
+        }
+    }
+
+    drop(peers);
+    inner.record(msg.res(), stats);
+
+    if let Some(batching) = batching {
+        if let Some(events) = inner.batches.push(msg, batching) {
+            let res = events[0].get_res();
+            deliver_batch(inner, &res, events);
+        }
+    }
+}
+
+/// Sends `msg`, which already has its id, to the subscriber whose turn it is on its round-robin
+/// route, see [`DeliveryMode::RoundRobin`]. Holds it until a client subscribes if nobody took
+/// it.
+fn assign(inner: &ServerInner, msg: Event, qos: QoS, max_queue_depth: usize) {
+    let id = msg.id().unwrap_or_default();
+    let ack = match qos {
+        QoS::AtMostOnce => None,
+        _ => Some(Ack { dup: false }),
+    };
+
+    let peers = inner.clients.read(msg.res());
+    let mut candidates: Vec<&Peer> = peers
+        .subscribers(msg.res())
+        .map(|(_, x)| x)
+        .filter(|x| inner.accepts(&x.info, &msg))
+        .collect();
+    let mut stats = Delivery {
+        subscribers: candidates.len(),
+        ..Delivery::default()
+    };
+
+    let mut rotations = inner
+        .rotations
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let next = |candidates: &[&Peer], last| match msg.partition_key() {
+        Some(key) => next_in_partition(candidates, key),
+        None => next_in_rotation(candidates, last, max_queue_depth),
+    };
+
+    while let Some(i) = next(&candidates, rotations.get(msg.res()).copied()) {
+        let recp = candidates.swap_remove(i);
+        match msg.partition_key() {
+            Some(key) => inner
+                .partitions
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .assigned(msg.res(), key, recp.info.id),
+            None => {
+                rotations.insert(msg.get_res(), recp.info.id);
+            }
+        }
+
+        // Recorded before sending, so that an acknowledgement can't arrive first.
+        if ack.is_some() {
+            inner.acks.sent(recp.info.id, id, msg.clone(), qos);
+        }
+
+        let frame = protocol::Frames::new(&msg, ack).get(&recp.info);
+        let len = frame.len() as u64;
+        let sent = recp.send(frame);
+        inner
+            .taps
+            .record(&recp.info, msg.res(), msg.payload(), sent);
+        if sent {
+            stats.sent += 1;
+            stats.bytes += len;
+            break;
+        }
+
+        // Disconnecting, the next one gets it.
+        stats.failed += 1;
+        if ack.is_some() {
+            inner.acks.ack(recp.info.id, id);
+        }
+    }
+
+    drop(rotations);
+    drop(peers);
+    let taken = stats.sent > 0;
+    inner.record(msg.res(), stats);
+
+    if !taken {
+        let mut unassigned = inner
+            .unassigned
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let buffer = unassigned.entry(msg.get_res()).or_default();
+        if buffer.len() == UNASSIGNED_CAPACITY {
+            tracing::warn!(
+                "dropping the oldest event held for round-robin route {}",
+                msg.res()
+            );
+            buffer.pop_front();
+        }
+        buffer.push_back(msg);
+    }
+}
+
+/// Returns the index of the subscriber whose turn it is after `last`: the next one by id with
+/// at most `max_queue_depth` frames waiting, else the one with the fewest.
+fn next_in_rotation(
+    candidates: &[&Peer],
+    last: Option<ClientId>,
+    max_queue_depth: usize,
+) -> Option<usize> {
+    let queued = |x: &Peer| x.queued.load(Ordering::Relaxed);
+    let idle = || {
+        candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| queued(x) <= max_queue_depth)
+    };
+
+    idle()
+        .filter(|(_, x)| Some(x.info.id) > last)
+        .min_by_key(|(_, x)| x.info.id)
+        .or_else(|| idle().min_by_key(|(_, x)| x.info.id))
+        .or_else(|| candidates.iter().enumerate().min_by_key(|(_, x)| queued(x)))
+        .map(|(i, _)| i)
+}
+
+/// Returns the index of the subscriber the events with `key` go to, see
+/// [`Event::with_partition_key`]. Keys stick to a subscriber however busy it is.
+fn next_in_partition(candidates: &[&Peer], key: &str) -> Option<usize> {
+    candidates
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, x)| partition::score(key, x.info.id))
+        .map(|(i, _)| i)
+}
+
+/// Moves the partition keys of the round-robin resources matching `pattern`, or of every one
+/// with `None`, to the clients subscribed now. The clients speaking protocol version 2 are
+/// told which keys they were assigned or lost.
+fn rebalance(inner: &ServerInner, pattern: Option<&str>) {
+    let resources: Vec<String> = inner
+        .partitions
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .resources()
+        .filter(|res| pattern.is_none_or(|x| crate::pattern::matches(x, res)))
+        .map(str::to_string)
+        .collect();
+
+    for res in resources {
+        // Not locked together with the partitions, which the fan-out locks the other way round.
+        let clients: Vec<ClientId> = inner
+            .clients
+            .read(&res)
+            .subscribers(&res)
+            .map(|(id, _)| id)
+            .collect();
+        let moves = inner
+            .partitions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .rebalance(&res, &clients);
+
+        announce_moves(inner, &res, moves);
+    }
+}
+
+/// Tells the clients of `res` which of its partition keys they were assigned or lost.
+fn announce_moves(inner: &ServerInner, res: &str, moves: Vec<Move>) {
+    let mut changes: HashMap<ClientId, (Vec<String>, Vec<String>)> = HashMap::new();
+    for x in moves {
+        if let Some(to) = x.to {
+            changes.entry(to).or_default().0.push(x.key.clone());
+        }
+        changes.entry(x.from).or_default().1.push(x.key);
+    }
+
+    for (id, (assigned, revoked)) in changes {
+        let peer = match inner.clients.get(id) {
+            Some(x) if protocol::receives_notices(&x.info) => x,
+            _ => continue,
+        };
+
+        let notice = pushevent_core::format::partitions(res, &assigned, &revoked);
+        peer.send(Message::Text(notice.into()));
+    }
+}
+
+/// Hands `msg` to another subscriber if it was published to a round-robin route, e.g. because
+/// the one it was sent to disconnected without acknowledging it.
+fn reassign(inner: &ServerInner, msg: Event) {
+    let route = inner.route(msg.res());
+    if let DeliveryMode::RoundRobin { max_queue_depth } = route.delivery {
+        assign(inner, msg, route.qos, max_queue_depth);
+    }
+}
+
+/// Hands the events held for round-robin routes without subscribers to a client that just
+/// subscribed to `resource`, which may be a pattern.
+fn assign_unassigned(inner: &ServerInner, resource: &str) {
+    let mut held = Vec::new();
+    inner
+        .unassigned
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|res, events| {
+            if !crate::pattern::matches(resource, res) {
+                return true;
+            }
+            held.extend(events.drain(..));
+            false
+        });
+
+    for msg in held {
+        reassign(inner, msg);
+    }
+}
+
+/// Hands `msg` to every connected client once, see [`EventTx::publish_all`].
+fn deliver_all(inner: &ServerInner, msg: Event) {
+    let msg = msg.with_id(inner.next_event_id());
+    let mut frames = protocol::Frames::new(&msg, None);
+    let mut stats = Delivery::default();
+
+    for peer in inner.clients.all() {
+        stats.subscribers += 1;
+        if !inner.accepts(&peer.info, &msg) {
+            continue;
+        }
+
+        let frame = frames.get(&peer.info);
+        let len = frame.len() as u64;
+        let sent = peer.send(frame);
+        inner
+            .taps
+            .record(&peer.info, msg.res(), msg.payload(), sent);
+        if sent {
+            stats.sent += 1;
+            stats.bytes += len;
+        } else {
+            stats.failed += 1;
+        }
+    }
+
+    inner.record(msg.res(), stats);
+}
+
+/// Hands `msg` to every client in `group` once, see [`EventTx::publish_group`].
+fn deliver_group(inner: &ServerInner, group: &str, msg: Event) {
+    let members = inner
+        .groups
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .members(group);
+    if members.is_empty() {
+        return;
+    }
+
+    let msg = msg.with_id(inner.next_event_id());
+    let mut frames = protocol::Frames::new(&msg, None);
+    let mut stats = Delivery::default();
+
+    for peer in members.into_iter().filter_map(|id| inner.clients.get(id)) {
+        stats.subscribers += 1;
+        if !inner.accepts(&peer.info, &msg) {
+            continue;
+        }
+
+        let frame = frames.get(&peer.info);
+        let len = frame.len() as u64;
+        let sent = peer.send(frame);
+        inner
+            .taps
+            .record(&peer.info, msg.res(), msg.payload(), sent);
+        if sent {
+            stats.sent += 1;
+            stats.bytes += len;
+        } else {
+            stats.failed += 1;
+        }
+    }
+
+    inner.record(msg.res(), stats);
+}
+
+/// Sends a batch of `events` published to `res` to the clients receiving batches, each in one
+/// frame.
+fn deliver_batch(inner: &ServerInner, res: &str, events: Vec<Event>) {
+    let peers = inner.clients.read(res);
+    let mut stats = Delivery::default();
+    let mut frame = None;
+
+    for (_, recp) in peers.subscribers(res) {
+        if !protocol::receives_batches(&recp.info) {
+            continue;
+        }
+        stats.subscribers += 1;
+
+        let accepted: Vec<_> = events
+            .iter()
+            .filter(|x| inner.accepts(&recp.info, x))
+            .collect();
+        // Clients accepting every event share the frame.
+        let frame = match accepted.len() {
+            0 => continue,
+            n if n == events.len() => frame
+                .get_or_insert_with(|| protocol::encode_batch(&events))
+                .clone(),
+            _ => protocol::encode_batch(accepted.iter().copied()),
+        };
+
+        let len = frame.len() as u64;
+        let sent = recp.send(frame);
+        for event in &accepted {
+            inner
+                .taps
+                .record(&recp.info, event.res(), event.payload(), sent);
+        }
+        if sent {
+            stats.sent += accepted.len() as u64;
+            stats.bytes += len;
+        } else {
+            stats.failed += accepted.len() as u64;
+        }
+    }
+
+    drop(peers);
+    inner.record(res, stats);
+}
+
+/// Writes `frames` to a client until either ends, giving up on a client that a frame can't be
+/// written to within `timeout`. Returns why the connection ended if writing to it failed, and
+/// records the close frame sent to the client in `closed_with`.
+async fn write_frames<S>(
+    frames: impl Stream<Item = Message>,
+    mut outgoing: S,
+    timeout: Option<Duration>,
+    addr: SocketAddr,
+    closed_with: &Mutex<Option<String>>,
+) -> Option<String>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: fmt::Display,
+{
+    pin_mut!(frames);
+
+    while let Some(frame) = frames.next().await {
+        if let Message::Close(Some(close)) = &frame {
+            let mut reason = format!("closed by the server with {}", u16::from(close.code));
+            if !close.reason.is_empty() {
+                reason = format!("{}: {}", reason, close.reason);
+            }
+            *closed_with.lock().unwrap_or_else(PoisonError::into_inner) = Some(reason);
+        }
+
+        let written = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, outgoing.send(frame)).await {
+                Ok(x) => x,
+                Err(_) => {
+                    tracing::warn!("{}: disconnecting slow consumer", addr);
+                    // The socket is most likely still full, don't wait for it to take this.
+                    let close = CloseReason::slow_consumer().into_message();
+                    let _ = outgoing.send(close).now_or_never();
+                    return Some("slow consumer".to_string());
+                }
+            },
+            None => outgoing.send(frame).await,
+        };
+
+        if let Err(e) = written {
+            return Some(format!("write failed: {}", e));
+        }
+    }
+
+    match timeout {
+        Some(timeout) => {
+            let _ = tokio::time::timeout(timeout, outgoing.close()).await;
+        }
+        None => {
+            let _ = outgoing.close().await;
+        }
+    }
+
+    None
+}
+
+/// Calls the snapshot hook of `res` for `client` and returns the frames to send it.
+async fn take_snapshot(
+    inner: &ServerInner,
+    hook: &SnapshotHook,
+    res: &str,
+    client: &ClientInfo,
+) -> Vec<Message> {
+    hook(res, client)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|event| protocol::encode(client, &event.with_id(inner.next_event_id())))
+        .collect()
+}
+
+/// What delivering a single event did, see [`ResourceStats`].
+#[derive(Default)]
+struct Delivery {
+    subscribers: usize,
+    sent: u64,
+    bytes: u64,
+    failed: u64,
+}
+
+async fn handle_connection(
+    inner: Arc<ServerInner>,
+    raw_stream: TcpStream,
+    addr: SocketAddr,
+    permit: OwnedSemaphorePermit,
+) {
+    let mut client = Client::new(addr);
+    let callback = OnRequest {
+        client: &mut client,
+        server: &inner,
+    };
+
+    let handshake = tokio::time::timeout(
+        inner.handshake_timeout,
+        tokio_tungstenite::accept_hdr_async_with_config(
+            raw_stream,
+            callback,
+            inner.websocket_config,
+        ),
+    );
+    let ws_stream = match handshake.await {
+        Ok(Ok(x)) => x,
+        // Either a broken handshake or a rejected client, in both cases there is nothing to
+        // clean up.
+        Ok(Err(e)) => {
+            tracing::debug!("{}: {}", addr, Error::handshake(e));
+            return;
+        }
+        Err(_) => {
+            tracing::debug!("{}: handshake timed out", addr);
+            inner.handshake_timeouts.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    drop(permit);
+
+    // Insert the write part of this peer to the peer map.
+    let (tx, rx) = unbounded();
+    let info = Arc::new(client.info());
+    let queued = Arc::new(AtomicUsize::new(0));
+    let snapshot = inner.snapshot_hook(&client.resource).cloned();
+    let peer = Peer {
+        tx,
+        info: info.clone(),
+        queued: queued.clone(),
+        connected_at: SystemTime::now(),
+        hold: Arc::new(Hold::new(inner.pause_policy)),
+        last_ping: Arc::default(),
+    };
+    let last_ping = peer.last_ping.clone();
+    // The clients of the broadcast channels take the events out of them only once the snapshot
+    // was sent, the others hold the events sent to them until then.
+    let held = match (&snapshot, &inner.channels) {
+        (Some(_), None) => Some((peer.clone(), peer.hold_for_snapshot())),
+        _ => None,
+    };
+
+    let events = {
+        let mut clients = inner.clients.write(&client.resource);
+        // The server started draining while this client was in the handshake.
+        if *inner.shutdown.borrow() {
+            peer.drain(Duration::ZERO);
+        }
+
+        let mut replayed = match (&inner.replay, &client.session) {
+            (Some(replay), Some(token)) => replay.resume(token, &client.resource),
+            _ => Vec::new(),
+        };
+        if let Some(max) = inner.route(&client.resource).replay {
+            replayed.drain(..replayed.len().saturating_sub(max));
+        }
+        let replayed = replayed
+            .into_iter()
+            .filter(|event| inner.accepts(&info, event))
+            .map(|event| protocol::encode(&info, &event));
+
+        // Subscribed while the shard is locked, like the client is registered, so that no event
+        // is missed or replayed and delivered twice.
+        let events = match &inner.channels {
+            Some(channels) => {
+                let rx = channels.subscribe(&client.resource);
+                stream::iter(replayed.collect::<Vec<_>>())
+                    .chain(fanout::frames(rx, inner.clone(), info.clone()))
+                    .left_stream()
+            }
+            None => {
+                for frame in replayed {
+                    let _ = peer.send(frame);
+                }
+                stream::empty().right_stream()
+            }
+        };
+
+        clients.add(&client.resource, client.id, peer);
+        events
+    };
+
+    if let Some((expected_clients, _)) = inner.warm_up {
+        if inner.clients.client_count() >= expected_clients {
+            inner
+                .warmed_up
+                .send_if_modified(|x| !std::mem::replace(x, true));
+        }
+    }
+
+    let mut subscription = Subscription {
+        inner: &inner,
+        info: &info,
+        session: client.session.as_deref(),
+        reason: "closed by the server".to_string(),
+        connected_at: Instant::now(),
+    };
+
+    let mut snapshot_frames = Vec::new();
+    match (snapshot, held) {
+        // Taken in the background, the frames are held until then and the connection already
+        // writes pings and close frames.
+        (Some(hook), Some((peer, position))) => {
+            let (inner, info) = (inner.clone(), info.clone());
+            inner.clone().runtime.spawn(async move {
+                let frames = take_snapshot(&inner, &hook, &info.resource, &info).await;
+                peer.send_snapshot(position, frames);
+            });
+        }
+        (Some(hook), None) => {
+            snapshot_frames = take_snapshot(&inner, &hook, &info.resource, &info).await;
+        }
+        (None, _) => {}
+    }
+    let events = stream::iter(snapshot_frames).chain(events);
+
+    inner.audit(AuditKind::HandshakeAccepted, &info, &info.resource);
+    if let Some(on_connect) = &inner.on_connect {
+        on_connect(&info);
+    }
+    inner.middleware.on_connect(&info);
+    rebalance(&inner, Some(&info.resource));
+    assign_unassigned(&inner, &info.resource);
+
+    let (outgoing, incoming) = ws_stream.split();
+
+    let broadcast_incoming = incoming.try_for_each(|frame| {
+        // Answered by the websocket itself.
+        if frame.is_ping() {
+            last_ping.set_now();
+            return future::ok(());
+        }
+
+        if let Some(id) = frame.to_text().ok().and_then(qos::parse_ack) {
+            inner.acks.ack(info.id, id);
+            return future::ok(());
+        }
+
+        if let Some(control) = frame.to_text().ok().and_then(protocol::parse_control) {
+            // Clients take the events out of the broadcast channels themselves.
+            let peer = inner
+                .clients
+                .get(info.id)
+                .filter(|_| inner.channels.is_none());
+            match (control, peer) {
+                (Control::Pause, Some(peer)) => {
+                    peer.pause();
+                }
+                (Control::Resume, Some(peer)) => {
+                    peer.resume();
+                }
+                (_, None) => tracing::debug!("{}: ignoring {:?}", addr, control),
+            }
+            return future::ok(());
+        }
+
+        if let Some(on_message) = &inner.on_message {
+            if let Some(payload) = Payload::from_message(frame) {
+                on_message(&info, payload);
+            }
+        }
+
+        future::ok(())
+    });
+
+    // Closes the connection once the server shuts down.
+    let closing = stream::once(shutdown_signal(inner.closing.subscribe()))
+        .flat_map(|_| stream::iter([Some(CloseReason::going_away().into_message()), None]));
+
+    // Frames queued for this client directly, followed by a marker ending the connection once
+    // the queue is closed, interleaved with the events of the broadcast channel if there is one.
+    // The close frame sent on shutdown comes after the frames already queued.
+    let frames = stream::select_with_strategy(
+        stream::select(
+            rx.inspect(|_| {
+                queued.fetch_sub(1, Ordering::Relaxed);
+            })
+            .map(Some)
+            .chain(stream::once(future::ready(None))),
+            events.map(Some),
+        ),
+        closing,
+        |_: &mut ()| PollNext::Left,
+    )
+    .take_while(|x| future::ready(x.is_some()))
+    .filter_map(future::ready);
+
+    let closed_with = Mutex::new(None);
+    {
+        let receive_from_others =
+            write_frames(frames, outgoing, inner.write_timeout, addr, &closed_with);
+
+        pin_mut!(broadcast_incoming, receive_from_others);
+        let closed_by_server = || {
+            closed_with
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take()
+        };
+
+        subscription.reason = match future::select(broadcast_incoming, receive_from_others).await {
+            future::Either::Left((Ok(()), _)) => {
+                closed_by_server().unwrap_or_else(|| "closed by the client".to_string())
+            }
+            future::Either::Left((Err(e), _)) => format!("connection error: {}", e),
+            future::Either::Right((Some(reason), _)) => reason,
+            future::Either::Right((None, _)) => {
+                closed_by_server().unwrap_or_else(|| "closed by the server".to_string())
+            }
+        };
+    }
+}
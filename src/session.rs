@@ -0,0 +1,202 @@
+//! Groups the connections of one user, such as several browser tabs, so that they can be sent
+//! events together.
+//!
+//! A session is opened with a token identifying the user, e.g. taken from a cookie in the
+//! authenticator, and clients are attached to it once connected. Clients are detached
+//! automatically when they disconnect, the session itself stays open until it is closed.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, PoisonError},
+};
+
+use crate::client::ClientId;
+use crate::server::ServerInner;
+use crate::{Error, Event};
+
+/// Opaque identifier of a session, see [`SessionManager::open_session`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SessionId(u64);
+
+/// The sessions of a server, stored in its shared state.
+#[derive(Default)]
+pub(crate) struct Sessions {
+    next: u64,
+    tokens: HashMap<String, SessionId>,
+    /// session -> attached clients, in the order they were attached.
+    clients: HashMap<SessionId, Vec<ClientId>>,
+    /// client -> the session it is attached to.
+    attached: HashMap<ClientId, SessionId>,
+}
+
+impl Sessions {
+    /// Detaches `id` from its session. Returns the session it was attached to.
+    pub(crate) fn detach(&mut self, id: ClientId) -> Option<SessionId> {
+        let session = self.attached.remove(&id)?;
+        if let Some(clients) = self.clients.get_mut(&session) {
+            clients.retain(|x| *x != id);
+        }
+
+        Some(session)
+    }
+}
+
+/// Manages the sessions of a server, returned by
+/// [`Server::sessions`](crate::server::Server::sessions).
+///
+/// # Example
+/// ```
+/// use pushevent::server::ServerBuilder;
+/// use pushevent::{Event, SerializableEvent};
+///
+/// struct Logout;
+///
+/// impl SerializableEvent for Logout {
+///     fn serialize(&self) -> String {
+///         r#"{"type":"logout"}"#.to_string()
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let server = ServerBuilder::new().addr("127.0.0.1:0").start().await.unwrap();
+/// let sessions = server.sessions();
+///
+/// let session = sessions.open_session("user-42".to_string());
+/// assert_eq!(sessions.open_session("user-42".to_string()), session);
+///
+/// // Clients are attached with `sessions.attach(session, id)` once connected, e.g. from the
+/// // `on_connect` hook. Nobody is attached yet, so nobody is logged out.
+/// let sent = sessions.broadcast_to_session(session, Event::new("/account", Logout));
+/// assert_eq!(sent.unwrap(), 0);
+/// # }
+/// ```
+pub struct SessionManager {
+    inner: Arc<ServerInner>,
+}
+
+impl SessionManager {
+    pub(crate) fn new(inner: Arc<ServerInner>) -> Self {
+        Self { inner }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Sessions) -> R) -> R {
+        f(&mut self
+            .inner
+            .sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Returns the session identified by `session_token`, opening it if there is none.
+    pub fn open_session(&self, session_token: String) -> SessionId {
+        self.with(|sessions| {
+            if let Some(id) = sessions.tokens.get(&session_token) {
+                return *id;
+            }
+
+            let id = SessionId(sessions.next);
+            sessions.next += 1;
+            sessions.tokens.insert(session_token, id);
+            sessions.clients.insert(id, Vec::new());
+
+            id
+        })
+    }
+
+    /// Attaches the client `client_id` to `session_id`, detaching it from the session it was
+    /// attached to before.
+    ///
+    /// Fails with [`Error::SessionNotFound`] if the session isn't open and with
+    /// [`Error::ClientNotFound`] if no such client is connected.
+    pub fn attach(&self, session_id: SessionId, client_id: ClientId) -> Result<(), Error> {
+        let peer = self
+            .inner
+            .clients
+            .get(client_id)
+            .ok_or(Error::ClientNotFound)?;
+
+        self.with(|sessions| {
+            if !sessions.clients.contains_key(&session_id) {
+                return Err(Error::SessionNotFound);
+            }
+
+            sessions.detach(client_id);
+            // Checked while the sessions are locked, so a client disconnecting concurrently is
+            // either seen here or detached once it is gone.
+            if peer.tx.is_closed() {
+                return Err(Error::ClientNotFound);
+            }
+
+            sessions.attached.insert(client_id, session_id);
+            sessions
+                .clients
+                .entry(session_id)
+                .or_default()
+                .push(client_id);
+
+            Ok(())
+        })
+    }
+
+    /// Detaches the client `client_id` from its session. Returns the session it was attached to.
+    pub fn detach(&self, client_id: ClientId) -> Option<SessionId> {
+        self.with(|sessions| sessions.detach(client_id))
+    }
+
+    /// Detaches every client from `session_id` and closes it, returning the clients that were
+    /// attached.
+    pub fn close_session(&self, session_id: SessionId) -> Vec<ClientId> {
+        self.with(|sessions| {
+            sessions.tokens.retain(|_, x| *x != session_id);
+
+            let clients = sessions.clients.remove(&session_id).unwrap_or_default();
+            for id in &clients {
+                sessions.attached.remove(id);
+            }
+
+            clients
+        })
+    }
+
+    /// Returns the clients attached to `session_id`, in the order they were attached.
+    pub fn clients(&self, session_id: SessionId) -> Vec<ClientId> {
+        self.with(|sessions| {
+            sessions
+                .clients
+                .get(&session_id)
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+
+    /// Sends `event` to every client attached to `session_id` with
+    /// [`Server::send_to`](crate::server::Server::send_to), returning the number of clients it
+    /// was sent to.
+    ///
+    /// Fails with [`Error::SessionNotFound`] if the session isn't open.
+    pub fn broadcast_to_session(
+        &self,
+        session_id: SessionId,
+        event: Event,
+    ) -> Result<usize, Error> {
+        let clients = self.with(|sessions| sessions.clients.get(&session_id).cloned());
+
+        Ok(clients
+            .ok_or(Error::SessionNotFound)?
+            .into_iter()
+            .filter(|id| self.inner.send_to(*id, &event).is_ok())
+            .count())
+    }
+}
+
+impl fmt::Debug for SessionManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sessions = self.with(|sessions| sessions.clients.len());
+
+        f.debug_struct("SessionManager")
+            .field("sessions", &sessions)
+            .finish()
+    }
+}
@@ -0,0 +1,133 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+
+/// Options applied to the listener and to every accepted connection.
+#[derive(Debug, Clone)]
+pub(crate) struct SocketOptions {
+    pub(crate) reuse_addr: bool,
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    pub(crate) reuse_port: bool,
+    pub(crate) nodelay: bool,
+    pub(crate) keepalive: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            reuse_addr: true,
+            #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+            reuse_port: false,
+            nodelay: true,
+            keepalive: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Binds a listener to the first address `addr` resolves to that can be bound, the same way
+    /// [`TcpListener::bind`] does.
+    pub(crate) async fn bind(&self, addr: &str) -> io::Result<TcpListener> {
+        let mut last_err = None;
+
+        for addr in lookup_host(addr).await? {
+            match self.bind_addr(addr) {
+                Ok(x) => return Ok(x),
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    fn bind_addr(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+        // On Windows SO_REUSEADDR allows binding to a port another socket is actively listening
+        // on, rebinding while old connections linger works without it.
+        #[cfg(not(windows))]
+        socket.set_reuse_address(self.reuse_addr)?;
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(self.reuse_port)?;
+
+        // Linux accepts IPv4 clients on `[::]` by default, Windows and the BSDs don't.
+        if addr.is_ipv6() && addr.ip().is_unspecified() {
+            socket.set_only_v6(false)?;
+        }
+
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+
+        TcpListener::from_std(socket.into())
+    }
+
+    /// Applies the per-connection options to an accepted stream.
+    pub(crate) fn configure(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket = SockRef::from(stream);
+
+        socket.set_tcp_nodelay(self.nodelay)?;
+
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn options_are_applied() {
+        let options = SocketOptions {
+            keepalive: Some(Duration::from_secs(42)),
+            ..SocketOptions::default()
+        };
+
+        let listener = options.bind("127.0.0.1:0").await.unwrap();
+        #[cfg(not(windows))]
+        assert!(SockRef::from(&listener).reuse_address().unwrap());
+
+        let (accepted, client) = tokio::join!(
+            listener.accept(),
+            TcpStream::connect(listener.local_addr().unwrap())
+        );
+        let (stream, _) = accepted.unwrap();
+        let _client = client.unwrap();
+
+        options.configure(&stream).unwrap();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.tcp_nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        #[cfg(not(any(windows, target_os = "openbsd")))]
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(42)
+        );
+    }
+
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    #[tokio::test]
+    async fn reuse_port_allows_several_listeners() {
+        let options = SocketOptions {
+            reuse_port: true,
+            ..SocketOptions::default()
+        };
+
+        let first = options.bind("127.0.0.1:0").await.unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = options.bind(&addr.to_string()).await.unwrap();
+
+        assert!(SockRef::from(&second).reuse_port().unwrap());
+    }
+}
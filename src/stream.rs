@@ -0,0 +1,51 @@
+use std::fmt;
+
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+
+use crate::Event;
+
+/// An event whose payload is produced piece by piece, such as the progress of a long running
+/// operation or a large payload split into chunks. Every item of the stream is sent to the
+/// subscribers as a frame of its own, see [`EventTx::send_stream`](crate::EventTx::send_stream).
+///
+/// The stream is consumed once and every item is shared by the subscribers, like the payload of
+/// an [`Event`]. Clients subscribing while the stream is being sent only receive the items
+/// after that.
+pub struct StreamEvent {
+    res: String,
+    chunks: BoxStream<'static, String>,
+}
+
+impl StreamEvent {
+    /// Returns a StreamEvent sending every item of `chunks` to `res`.
+    pub fn new(
+        res: impl Into<String>,
+        chunks: impl Stream<Item = String> + Send + 'static,
+    ) -> Self {
+        Self {
+            res: res.into(),
+            chunks: chunks.boxed(),
+        }
+    }
+
+    /// Returns the resource this event targets.
+    pub fn res(&self) -> &str {
+        &self.res
+    }
+
+    /// Returns the items as events published to the resource.
+    pub(crate) fn into_events(self) -> impl Stream<Item = Event> {
+        let res = self.res;
+
+        self.chunks
+            .map(move |chunk| Event::from_string(res.clone(), chunk))
+    }
+}
+
+impl fmt::Debug for StreamEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamEvent")
+            .field("res", &self.res)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        PoisonError, RwLock,
+    },
+    time::SystemTime,
+};
+
+use tokio::sync::mpsc;
+
+use crate::client::{ClientId, ClientInfo};
+use crate::pattern;
+
+/// How many records a tap holds while it isn't read, newer ones are dropped.
+const TAP_CAPACITY: usize = 1024;
+
+/// How many bytes of the payload a [`TapRecord`] keeps.
+const PREVIEW_LEN: usize = 64;
+
+/// Which frames a tap records, see [`Server::tap`](crate::server::Server::tap).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TapFilter {
+    /// Every frame sent to a client.
+    All,
+    /// The frames sent to one client.
+    Client(ClientId),
+    /// The frames of the events published to a resource, which may be a pattern like
+    /// `/prices/*`.
+    Resource(String),
+}
+
+impl TapFilter {
+    fn matches(&self, client: &ClientInfo, res: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Client(id) => client.id == *id,
+            Self::Resource(pattern) => pattern::matches(pattern, res),
+        }
+    }
+}
+
+/// What happened to a frame, see [`TapRecord`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TapOutcome {
+    /// The frame was queued for the connection, or held back while the client is paused.
+    Sent,
+    /// The client was disconnecting, so the frame was dropped.
+    Failed,
+}
+
+/// A frame the server sent to a client, as recorded by
+/// [`Server::tap`](crate::server::Server::tap).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TapRecord {
+    /// When the frame was sent.
+    pub ts: SystemTime,
+    /// The client the frame was sent to.
+    pub client_id: ClientId,
+    /// The resource of the event, or the resource the client connected to for frames sent
+    /// through a [`ClientTx`](crate::server::ClientTx).
+    pub resource: String,
+    /// The first 64 bytes of the payload.
+    pub payload_preview: String,
+    /// What happened to the frame.
+    pub outcome: TapOutcome,
+}
+
+/// The taps installed on a server.
+#[derive(Default)]
+pub(crate) struct Taps {
+    /// Whether any tap is installed, checked before anything else so that recording costs
+    /// nothing without taps.
+    active: AtomicBool,
+    taps: RwLock<Vec<(TapFilter, mpsc::Sender<TapRecord>)>>,
+}
+
+impl Taps {
+    pub(crate) fn add(&self, filter: TapFilter) -> mpsc::Receiver<TapRecord> {
+        let (tx, rx) = mpsc::channel(TAP_CAPACITY);
+        let mut taps = self.taps.write().unwrap_or_else(PoisonError::into_inner);
+        taps.push((filter, tx));
+        self.active.store(true, Ordering::Relaxed);
+
+        rx
+    }
+
+    /// Records that a frame of `payload` published to `res` was sent to `client`.
+    pub(crate) fn record(&self, client: &ClientInfo, res: &str, payload: &str, sent: bool) {
+        if self.active.load(Ordering::Relaxed) {
+            self.record_slow(client, res, payload, sent);
+        }
+    }
+
+    #[cold]
+    fn record_slow(&self, client: &ClientInfo, res: &str, payload: &str, sent: bool) {
+        let record = TapRecord {
+            ts: SystemTime::now(),
+            client_id: client.id,
+            resource: res.to_string(),
+            payload_preview: preview(payload).to_string(),
+            outcome: if sent {
+                TapOutcome::Sent
+            } else {
+                TapOutcome::Failed
+            },
+        };
+
+        let mut closed = false;
+        for (_, tx) in self
+            .taps
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(filter, _)| filter.matches(client, res))
+        {
+            closed |= tx.is_closed();
+            // Full while the tap isn't read.
+            let _ = tx.try_send(record.clone());
+        }
+
+        if closed {
+            let mut taps = self.taps.write().unwrap_or_else(PoisonError::into_inner);
+            taps.retain(|(_, tx)| !tx.is_closed());
+            self.active.store(!taps.is_empty(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the first bytes of `payload`, cut at a character boundary.
+fn preview(payload: &str) -> &str {
+    let mut end = payload.len().min(PREVIEW_LEN);
+    while !payload.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &payload[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_end_at_a_character_boundary() {
+        assert_eq!(preview("hello"), "hello");
+        assert_eq!(preview(&"x".repeat(100)), "x".repeat(64));
+        assert_eq!(preview(&format!("{}é", "x".repeat(63))), "x".repeat(63));
+    }
+}
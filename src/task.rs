@@ -0,0 +1,48 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+
+/// Counts the tasks or threads of a kind that are still running, so that they can be waited
+/// for.
+#[derive(Clone)]
+pub(crate) struct TaskGroup {
+    running: Arc<watch::Sender<usize>>,
+}
+
+impl TaskGroup {
+    pub(crate) fn new() -> Self {
+        Self {
+            running: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Counts a task until the returned guard is dropped, also when it panics.
+    pub(crate) fn enter(&self) -> TaskGuard {
+        self.running.send_modify(|x| *x += 1);
+        TaskGuard(self.clone())
+    }
+
+    /// Returns whether every task has finished. Doesn't wait.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self.running.borrow() == 0
+    }
+
+    /// Resolves once every task has finished.
+    pub(crate) async fn wait(&self) {
+        let _ = self.running.subscribe().wait_for(|x| *x == 0).await;
+    }
+
+    /// Same as [`wait`](Self::wait), but gives up once `timeout` has passed. Returns whether
+    /// every task has finished.
+    pub(crate) async fn wait_timeout(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.wait()).await.is_ok()
+    }
+}
+
+pub(crate) struct TaskGuard(TaskGroup);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.running.send_modify(|x| *x -= 1);
+    }
+}
@@ -0,0 +1,39 @@
+//! Helpers for testing code that publishes events, enabled with the `test-utils` feature.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::server::ServerBuilder;
+use crate::EventTx;
+
+/// Starts a server on an ephemeral port of `127.0.0.1` and returns its sender and the address it
+/// is listening on. The server accepts connections when this returns, so clients can connect
+/// right away.
+///
+/// # Panics
+/// If the listener can't be bound or the server doesn't become
+/// [ready](crate::server::Server::wait_ready) within 5 seconds.
+///
+/// # Example
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let (tx, addr) = pushevent::test_utils::spawn_test_server().await;
+///
+/// assert_ne!(addr.port(), 0);
+/// assert!(!tx.is_closed());
+/// # }
+/// ```
+pub async fn spawn_test_server() -> (EventTx, SocketAddr) {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .expect("failed to start test server");
+    let addr = server
+        .wait_ready(Duration::from_secs(5))
+        .await
+        .expect("test server never became ready");
+
+    (server.get_tx(), addr)
+}
@@ -0,0 +1,141 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use crate::{pattern, Event};
+
+/// Rewrites or drops the payload of events before they are delivered, registered per resource
+/// pattern with [`ServerBuilder::transform`](crate::server::ServerBuilder::transform).
+///
+/// Transforms run once per event on the broadcast loop, before the event is encoded for the
+/// clients, so they must be cheap. Closures taking the resource and payload implement this
+/// trait.
+///
+/// # Example
+/// ```
+/// use pushevent::Transform;
+///
+/// /// Drops payloads that aren't JSON objects.
+/// struct OnlyObjects;
+///
+/// impl Transform for OnlyObjects {
+///     fn transform(&self, _res: &str, payload: String) -> Option<String> {
+///         payload.starts_with('{').then_some(payload)
+///     }
+/// }
+///
+/// assert_eq!(OnlyObjects.transform("/events", "[]".to_string()), None);
+/// ```
+pub trait Transform: Send + Sync + 'static {
+    /// Returns the new payload of an event published to `res`, or `None` to drop the event.
+    fn transform(&self, res: &str, payload: String) -> Option<String>;
+
+    /// Like [`transform`](Self::transform), with the event being delivered, e.g. to look at its
+    /// [origin](Event::origin). `payload` is the one returned by the previous transforms.
+    fn transform_event(&self, event: &Event, payload: String) -> Option<String> {
+        self.transform(event.res(), payload)
+    }
+}
+
+impl<F> Transform for F
+where
+    F: Fn(&str, String) -> Option<String> + Send + Sync + 'static,
+{
+    fn transform(&self, res: &str, payload: String) -> Option<String> {
+        self(res, payload)
+    }
+}
+
+/// Rewrites the payload of events before they are delivered, registered per resource pattern
+/// with [`ServerBuilder::transformer`](crate::server::ServerBuilder::transformer).
+///
+/// Unlike a [`Transform`] a transformer can't drop events. Closures taking the resource and
+/// payload implement this trait.
+///
+/// # Example
+/// ```
+/// use pushevent::Transformer;
+///
+/// /// Escapes the HTML in chat messages.
+/// struct EscapeHtml;
+///
+/// impl Transformer for EscapeHtml {
+///     fn transform(&self, _res: &str, payload: String) -> String {
+///         payload.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+///     }
+/// }
+///
+/// assert_eq!(EscapeHtml.transform("/chat", "<b>hi</b>".to_string()), "&lt;b&gt;hi&lt;/b&gt;");
+/// ```
+pub trait Transformer: Send + Sync + 'static {
+    /// Returns the new payload of an event published to `res`.
+    fn transform(&self, res: &str, payload: String) -> String;
+
+    /// Like [`transform`](Self::transform), with the event being delivered, e.g. to look at its
+    /// [origin](Event::origin). `payload` is the one returned by the previous transforms.
+    fn transform_event(&self, event: &Event, payload: String) -> String {
+        self.transform(event.res(), payload)
+    }
+}
+
+impl<F> Transformer for F
+where
+    F: Fn(&str, String) -> String + Send + Sync + 'static,
+{
+    fn transform(&self, res: &str, payload: String) -> String {
+        self(res, payload)
+    }
+}
+
+/// Runs a [`Transformer`] as a [`Transform`] that keeps every event.
+pub(crate) struct Infallible<T>(pub(crate) T);
+
+impl<T: Transformer> Transform for Infallible<T> {
+    fn transform(&self, res: &str, payload: String) -> Option<String> {
+        Some(self.0.transform(res, payload))
+    }
+
+    fn transform_event(&self, event: &Event, payload: String) -> Option<String> {
+        Some(self.0.transform_event(event, payload))
+    }
+}
+
+/// The transforms of a server with the patterns they are registered for, in registration order.
+pub(crate) type Transforms = Vec<(String, Arc<dyn Transform>)>;
+
+/// Runs the transforms matching the resource of `event` in order. Returns `None` if one of them
+/// dropped the event or panicked.
+pub(crate) fn apply(transforms: &Transforms, event: Event) -> Option<Event> {
+    let res = event.res();
+    let mut matching = transforms
+        .iter()
+        .filter(|(pattern, _)| pattern::matches(pattern, res))
+        .peekable();
+
+    // Events without transforms keep their payload as is.
+    if matching.peek().is_none() {
+        return Some(event);
+    }
+
+    let mut payload = event.payload().to_string();
+    for (pattern, transform) in matching {
+        match panic::catch_unwind(AssertUnwindSafe(|| {
+            transform.transform_event(&event, payload)
+        })) {
+            Ok(Some(x)) => payload = x,
+            Ok(None) => {
+                tracing::debug!("transform for {} dropped an event for {}", pattern, res);
+                return None;
+            }
+            Err(_) => {
+                tracing::error!(
+                    "transform for {} panicked, dropping event for {}",
+                    pattern,
+                    res
+                );
+                return None;
+            }
+        }
+    }
+
+    Some(event.with_payload(payload))
+}
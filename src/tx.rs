@@ -0,0 +1,926 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, PoisonError,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+    time::Duration,
+};
+
+use futures_util::{future::BoxFuture, pin_mut, ready, FutureExt, Sink, Stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::limits::PayloadLimit;
+use crate::{BufferedEventTx, Error, Event, MeteredEventTx, RetryingEventTx, StreamEvent};
+
+/// Sending half of the event channel returned by [`build`](crate::build) and
+/// [`build_bounded`](crate::build_bounded).
+///
+/// The sender is cheap to clone and can be moved freely across threads. Depending on how the
+/// server was built the underlying queue is either unbounded or has a fixed capacity.
+///
+/// `EventTx` also implements [`Sink`], which waits for room in a bounded queue instead of
+/// failing with [`Error::QueueFull`], so that a producing stream slows down to the pace of the
+/// server. See [`send_all`](Self::send_all).
+pub struct EventTx {
+    inner: Inner,
+    /// Called in order with every event before it is queued, see [`EventTxExt::observe`].
+    observers: Arc<Vec<Observer>>,
+    /// The slot in a bounded queue reserved by [`Sink::poll_ready`]. Not shared between clones.
+    reserved: Mutex<Option<Reservation>>,
+    /// Shared with the receiving half.
+    state: Arc<QueueState>,
+}
+
+enum Reservation {
+    Pending(BoxFuture<'static, Result<mpsc::OwnedPermit<Queued>, mpsc::error::SendError<()>>>),
+    Ready(mpsc::OwnedPermit<Queued>),
+}
+
+type Observer = Arc<dyn Fn(&Event) + Send + Sync>;
+
+#[derive(Clone)]
+enum Inner {
+    Unbounded(mpsc::UnboundedSender<Queued>),
+    Bounded(mpsc::Sender<Queued>),
+    /// Discards every event, see [`noop::sink_tx`](crate::noop::sink_tx).
+    Sink,
+}
+
+/// An entry of the event channel. A batch takes up a single slot of a bounded channel.
+pub(crate) enum Queued {
+    One(Event),
+    Batch(Vec<Event>),
+    /// An event for every connected client, see [`EventTx::publish_all`].
+    All(Event),
+    /// An event for the members of a group, see [`EventTx::publish_group`].
+    Group(String, Event),
+    /// Resumes a paused route once the events queued before were processed, see
+    /// [`Server::resume_route`](crate::server::Server::resume_route).
+    Resume(String),
+}
+
+impl Queued {
+    /// Returns the number of events in the entry.
+    fn len(&self) -> usize {
+        match self {
+            Self::One(_) | Self::All(_) | Self::Group(..) => 1,
+            Self::Batch(x) => x.len(),
+            Self::Resume(_) => 0,
+        }
+    }
+}
+
+/// Receiving half of the event channel, consumed by the broadcast loop.
+pub(crate) struct EventRx {
+    rx: Receiver,
+    state: Arc<QueueState>,
+}
+
+enum Receiver {
+    Unbounded(mpsc::UnboundedReceiver<Queued>),
+    Bounded(mpsc::Receiver<Queued>),
+}
+
+/// The state of an event channel shared by its two halves.
+#[derive(Default)]
+pub(crate) struct QueueState {
+    /// The number of events queued and not yet taken out by the broadcast loop.
+    depth: AtomicUsize,
+    /// Whether new events are dropped instead of queued, see
+    /// [`LoadShedder`](crate::server::LoadShedder).
+    shedding: AtomicBool,
+    /// The number of events dropped while shedding load.
+    shed: AtomicU64,
+    /// Set by the server once it starts, see
+    /// [`ServerBuilder::max_payload`](crate::server::ServerBuilder::max_payload).
+    payload_limit: OnceLock<Arc<PayloadLimit>>,
+}
+
+impl QueueState {
+    /// Returns the number of events queued and not yet taken out by the broadcast loop.
+    pub(crate) fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops dropping new events. Returns the number of events dropped since the last
+    /// call.
+    pub(crate) fn set_shedding(&self, shedding: bool) -> u64 {
+        self.shedding.store(shedding, Ordering::Relaxed);
+        self.shed.swap(0, Ordering::Relaxed)
+    }
+
+    /// Rejects the events whose payload exceeds `limit` from now on.
+    pub(crate) fn limit_payloads(&self, limit: Arc<PayloadLimit>) {
+        let _ = self.payload_limit.set(limit);
+    }
+
+    /// Checks the payload of `event` before it is queued.
+    fn check_payload(&self, event: &Event) -> Result<(), Error> {
+        match self.payload_limit.get() {
+            Some(limit) => limit.check(event),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns whether `len` new events should be dropped, counting them if so.
+    fn shed(&self, len: usize) -> bool {
+        if !self.shedding.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        self.shed.fetch_add(len as u64, Ordering::Relaxed);
+        true
+    }
+}
+
+impl EventTx {
+    /// Queues an event for broadcast without blocking.
+    ///
+    /// Unbounded senders only fail with [`Error::ChannelClosed`] once the server has been dropped,
+    /// bounded senders additionally fail with [`Error::QueueFull`] when the queue is at capacity.
+    /// Events larger than the server accepts fail with [`Error::PayloadTooLarge`], see
+    /// [`ServerBuilder::max_payload`](crate::server::ServerBuilder::max_payload), and events
+    /// whose payload isn't valid UTF-8 with [`Error::Serialization`], see
+    /// [`Event::payload_error`].
+    pub fn send(&self, event: Event) -> Result<(), Error> {
+        self.state.check_payload(&event)?;
+        for observer in self.observers.iter() {
+            observer(&event);
+        }
+
+        self.queue(Queued::One(event))
+    }
+
+    /// Queues an event for broadcast like [`send`](Self::send), but waits for room instead of
+    /// failing with [`Error::QueueFull`] when a bounded queue is at capacity.
+    pub async fn publish_async(&self, event: Event) -> Result<(), Error> {
+        self.state.check_payload(&event)?;
+        for observer in self.observers.iter() {
+            observer(&event);
+        }
+
+        let tx = match &self.inner {
+            Inner::Bounded(tx) => tx,
+            _ => return self.queue(Queued::One(event)),
+        };
+        let permit = tx.reserve().await.map_err(|_| Error::ChannelClosed)?;
+
+        // Dropping the permit releases the slot.
+        if self.state.shed(1) {
+            return Ok(());
+        }
+
+        self.state.depth.fetch_add(1, Ordering::Relaxed);
+        permit.send(Queued::One(event));
+        Ok(())
+    }
+
+    /// Queues an event for broadcast like [`publish_async`](Self::publish_async), parking the
+    /// calling thread while a bounded queue is at capacity.
+    ///
+    /// Meant for producers running on plain threads, which don't need to enter a tokio runtime
+    /// to call this. Async code should use [`publish_async`](Self::publish_async) instead, as
+    /// parking a thread of a runtime keeps it from running other tasks, possibly the broadcast
+    /// loop this waits for.
+    ///
+    /// # Example
+    /// ```
+    /// use std::thread;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Frame(u64);
+    ///
+    /// impl SerializableEvent for Frame {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// let server = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .capacity(16)
+    ///     .start_blocking()
+    ///     .unwrap();
+    /// let tx = server.get_tx();
+    ///
+    /// let transcoder = thread::spawn(move || {
+    ///     for n in 0..100 {
+    ///         tx.publish_blocking(Event::new("/transcodes/42", Frame(n)))?;
+    ///     }
+    ///     Ok::<_, pushevent::Error>(())
+    /// });
+    /// transcoder.join().unwrap().unwrap();
+    /// ```
+    pub fn publish_blocking(&self, event: Event) -> Result<(), Error> {
+        block_on(self.publish_async(event), None).unwrap_or(Err(Error::QueueFull))
+    }
+
+    /// Same as [`publish_blocking`](Self::publish_blocking), but gives up with
+    /// [`Error::QueueFull`] once the queue had no room for `timeout`.
+    pub fn publish_blocking_timeout(&self, event: Event, timeout: Duration) -> Result<(), Error> {
+        let deadline = std::time::Instant::now() + timeout;
+
+        block_on(self.publish_async(event), Some(deadline)).unwrap_or(Err(Error::QueueFull))
+    }
+
+    /// Queues several events at once. The events are broadcast in order, in a single pass of
+    /// the broadcast loop, and take up a single slot of a bounded queue. Queuing an empty batch
+    /// does nothing. If the payload of any of the events is too large, none of them is queued.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Progress(u8);
+    ///
+    /// impl SerializableEvent for Progress {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .capacity(1)
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let batch = (0..=100).map(|x| Event::new("/progress", Progress(x))).collect();
+    /// tx.publish_batch(batch).unwrap();
+    /// # }
+    /// ```
+    pub fn publish_batch(&self, events: Vec<Event>) -> Result<(), Error> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        for event in &events {
+            self.state.check_payload(event)?;
+        }
+
+        for event in &events {
+            for observer in self.observers.iter() {
+                observer(event);
+            }
+        }
+
+        self.queue(Queued::Batch(events))
+    }
+
+    /// Queues an event for every connected client, whatever resources they are subscribed to,
+    /// e.g. to announce maintenance. Every client receives it once, also when subscribed to
+    /// several resources, encoded like any other event for it. The per-client filter is run and
+    /// paused clients receive it once resumed, but no transforms or route configuration apply,
+    /// since the event isn't published to a route.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Announcement(&'static str);
+    ///
+    /// impl SerializableEvent for Announcement {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// let event = Event::new("/announcements", Announcement("going down in 5 minutes"));
+    /// tx.publish_all(event).unwrap();
+    /// # }
+    /// ```
+    pub fn publish_all(&self, event: Event) -> Result<(), Error> {
+        self.state.check_payload(&event)?;
+        for observer in self.observers.iter() {
+            observer(&event);
+        }
+
+        self.queue(Queued::All(event))
+    }
+
+    /// Queues an event for the clients in `group`, whatever resources they are subscribed to.
+    /// Clients are put in groups by the application with
+    /// [`Server::join_group`](crate::server::Server::join_group), e.g. from their identity once
+    /// they connected, so they don't need to know the groups they are in.
+    ///
+    /// Like [`publish_all`](Self::publish_all), every member receives the event once, the
+    /// per-client filter is run, and no transforms or route configuration apply. Events for a
+    /// group without members are dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Alert(&'static str);
+    ///
+    /// impl SerializableEvent for Alert {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// tx.publish_group("admins", Event::new("/alerts", Alert("disk almost full")))
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn publish_group(&self, group: &str, event: Event) -> Result<(), Error> {
+        self.state.check_payload(&event)?;
+        for observer in self.observers.iter() {
+            observer(&event);
+        }
+
+        self.queue(Queued::Group(group.to_string(), event))
+    }
+
+    /// Queues resuming the paused route `res` behind the events already queued. Never shed,
+    /// since it doesn't add events.
+    pub(crate) fn resume_route(&self, res: &str) -> Result<(), Error> {
+        self.push(Queued::Resume(res.to_string()))
+    }
+
+    fn queue(&self, queued: Queued) -> Result<(), Error> {
+        if matches!(self.inner, Inner::Sink) || self.state.shed(queued.len()) {
+            return Ok(());
+        }
+
+        self.push(queued)
+    }
+
+    fn push(&self, queued: Queued) -> Result<(), Error> {
+        let len = queued.len();
+
+        // Counted before queuing, so that the broadcast loop never takes out more than was
+        // counted.
+        self.state.depth.fetch_add(len, Ordering::Relaxed);
+        let queued = match &self.inner {
+            Inner::Unbounded(tx) => tx.send(queued).map_err(|_| Error::ChannelClosed),
+            Inner::Bounded(tx) => tx.try_send(queued).map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => Error::QueueFull,
+                mpsc::error::TrySendError::Closed(_) => Error::ChannelClosed,
+            }),
+            Inner::Sink => Ok(()),
+        };
+
+        if queued.is_err() {
+            self.state.depth.fetch_sub(len, Ordering::Relaxed);
+        }
+
+        queued
+    }
+
+    /// Publishes every event of `events` in order, waiting for room whenever a bounded queue is
+    /// full. Stops at the first event that fails to queue.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use futures_util::stream::{self, StreamExt};
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, SerializableEvent};
+    ///
+    /// struct Tick(u64);
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         self.0.to_string()
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), pushevent::Error> {
+    /// let tx = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .capacity(16)
+    ///     .build()
+    ///     .await?;
+    ///
+    /// let interval = tokio::time::interval(Duration::from_millis(10));
+    /// let ticks = stream::unfold((interval, 0), |(mut interval, n)| async move {
+    ///     interval.tick().await;
+    ///     Some((Event::new("/ticks", Tick(n)), (interval, n + 1)))
+    /// });
+    ///
+    /// tx.send_all(ticks.take(5)).await
+    /// # }
+    /// ```
+    pub async fn send_all(&self, events: impl Stream<Item = Event>) -> Result<(), Error> {
+        events.map(Ok).forward(self.clone()).await
+    }
+
+    /// Publishes every item of `event` as it is produced, each as a frame of its own. Stops at
+    /// the first item that fails to queue, see [`send_all`](Self::send_all).
+    ///
+    /// # Example
+    /// ```
+    /// use futures_util::{stream, StreamExt};
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::StreamEvent;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), pushevent::Error> {
+    /// let tx = ServerBuilder::new().addr("127.0.0.1:0").build().await?;
+    ///
+    /// let progress = stream::iter((0..=100).step_by(25)).map(|x| format!("{}%", x));
+    /// tx.send_stream(StreamEvent::new("/jobs/42", progress)).await
+    /// # }
+    /// ```
+    pub async fn send_stream(&self, event: StreamEvent) -> Result<(), Error> {
+        self.send_all(event.into_events()).await
+    }
+
+    /// Returns whether the receiving end has been dropped.
+    pub fn is_closed(&self) -> bool {
+        match &self.inner {
+            Inner::Unbounded(tx) => tx.is_closed(),
+            Inner::Bounded(tx) => tx.is_closed(),
+            Inner::Sink => false,
+        }
+    }
+}
+
+impl EventRx {
+    pub(crate) async fn recv(&mut self) -> Option<Queued> {
+        let queued = match &mut self.rx {
+            Receiver::Unbounded(rx) => rx.recv().await,
+            Receiver::Bounded(rx) => rx.recv().await,
+        }?;
+
+        self.state.depth.fetch_sub(queued.len(), Ordering::Relaxed);
+        Some(queued)
+    }
+
+    /// Returns the state shared with the sending half.
+    pub(crate) fn state(&self) -> Arc<QueueState> {
+        self.state.clone()
+    }
+}
+
+/// Polls `future` on the calling thread, parking it in between, until it completes or
+/// `deadline` passes.
+fn block_on<F: Future>(future: F, deadline: Option<std::time::Instant>) -> Option<F::Output> {
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    pin_mut!(future);
+
+    loop {
+        if let Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+            return Some(x);
+        }
+
+        match deadline {
+            Some(deadline) => {
+                let now = std::time::Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                thread::park_timeout(deadline - now);
+            }
+            None => thread::park(),
+        }
+    }
+}
+
+/// Creates a new unbounded event channel.
+pub(crate) fn unbounded() -> (EventTx, EventRx) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let state = Arc::new(QueueState::default());
+    (
+        EventTx {
+            inner: Inner::Unbounded(tx),
+            observers: Arc::default(),
+            reserved: Mutex::default(),
+            state: state.clone(),
+        },
+        EventRx {
+            rx: Receiver::Unbounded(rx),
+            state,
+        },
+    )
+}
+
+/// Creates a new event channel that holds at most `capacity` queued events.
+pub(crate) fn bounded(capacity: usize) -> (EventTx, EventRx) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let state = Arc::new(QueueState::default());
+    (
+        EventTx {
+            inner: Inner::Bounded(tx),
+            observers: Arc::default(),
+            reserved: Mutex::default(),
+            state: state.clone(),
+        },
+        EventRx {
+            rx: Receiver::Bounded(rx),
+            state,
+        },
+    )
+}
+
+/// Creates a sender that accepts and discards every event.
+pub(crate) fn sink() -> EventTx {
+    EventTx {
+        inner: Inner::Sink,
+        observers: Arc::default(),
+        reserved: Mutex::default(),
+        state: Arc::default(),
+    }
+}
+
+impl Clone for EventTx {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            observers: self.observers.clone(),
+            reserved: Mutex::default(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Sink<Event> for EventTx {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        let tx = match &this.inner {
+            Inner::Bounded(tx) => tx,
+            _ if this.is_closed() => return Poll::Ready(Err(Error::ChannelClosed)),
+            _ => return Poll::Ready(Ok(())),
+        };
+
+        let reserved = this
+            .reserved
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        loop {
+            match reserved {
+                Some(Reservation::Ready(_)) => return Poll::Ready(Ok(())),
+                Some(Reservation::Pending(x)) => match ready!(x.poll_unpin(cx)) {
+                    Ok(permit) => *reserved = Some(Reservation::Ready(permit)),
+                    Err(_) => {
+                        *reserved = None;
+                        return Poll::Ready(Err(Error::ChannelClosed));
+                    }
+                },
+                None => *reserved = Some(Reservation::Pending(tx.clone().reserve_owned().boxed())),
+            }
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, event: Event) -> Result<(), Error> {
+        let this = self.get_mut();
+        let permit = match this
+            .reserved
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            Some(Reservation::Ready(permit)) => permit,
+            // Not reserved through `poll_ready`, which only happens for unbounded queues unless
+            // the caller broke the `Sink` contract.
+            _ => return this.send(event),
+        };
+
+        // Dropping the permit releases the slot.
+        this.state.check_payload(&event)?;
+        for observer in this.observers.iter() {
+            observer(&event);
+        }
+
+        // Dropping the permit releases the slot.
+        if this.state.shed(1) {
+            return Ok(());
+        }
+
+        this.state.depth.fetch_add(1, Ordering::Relaxed);
+        permit.send(Queued::One(event));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        // Queued events are as flushed as they get, the broadcast loop takes it from there.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl fmt::Debug for EventTx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventTx")
+            .field("bounded", &matches!(self.inner, Inner::Bounded(_)))
+            .field("closed", &self.is_closed())
+            .field("observers", &self.observers.len())
+            .finish()
+    }
+}
+
+/// Convenience adapters for event senders.
+pub trait EventTxExt {
+    /// Attempts to queue `event`, returning `true` on success.
+    ///
+    /// If the channel is at capacity (or closed) the event is dropped, a warning is logged and
+    /// `false` is returned. This lets producers observe backpressure without ever blocking.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pushevent::{Event, EventTxExt, SerializableEvent};
+    ///
+    /// struct Tick;
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("tick")
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = pushevent::build_bounded(64).await.unwrap();
+    ///
+    /// if !tx.try_send_or_drop(Event::new("/ticks", Tick)) {
+    ///     // Clients are falling behind, skip the expensive work for the next tick.
+    /// }
+    /// # }
+    /// ```
+    fn try_send_or_drop(&self, event: Event) -> bool;
+
+    /// Returns a new sender that calls `observer` with every event before forwarding it to this
+    /// sender. Observers can be chained and are called in the order they were added, the sender
+    /// this was called on is unaffected.
+    ///
+    /// This is meant for logging, metrics and tracing without touching the production code path.
+    /// The observer runs synchronously on whatever thread or task sends the event, so it must be
+    /// cheap and must never block.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, EventTxExt, SerializableEvent};
+    ///
+    /// struct Tick;
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("tick")
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new().addr("127.0.0.1:0").build().await.unwrap();
+    ///
+    /// let count = Arc::new(AtomicUsize::new(0));
+    /// let counter = count.clone();
+    /// let observed = tx
+    ///     .observe(|event| println!("publishing {:?}", event))
+    ///     .observe(move |_| {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     });
+    ///
+    /// observed.send(Event::new("/ticks", Tick)).unwrap();
+    /// observed.send(Event::new("/ticks", Tick)).unwrap();
+    /// tx.send(Event::new("/ticks", Tick)).unwrap();
+    ///
+    /// assert_eq!(count.load(Ordering::Relaxed), 2);
+    /// # }
+    /// ```
+    fn observe(&self, observer: impl Fn(&Event) + Send + Sync + 'static) -> EventTx;
+
+    /// Returns a sender that collects events for `window` after the first one arrives and then
+    /// publishes them together with [`EventTx::publish_batch`], so that a producer sending
+    /// bursts of events doesn't wake the broadcast loop for every single one.
+    ///
+    /// Events are delayed by at most `window`. Events still collected when the last clone of the
+    /// returned sender is dropped are published right away. Must be called from within a tokio
+    /// runtime, which the collecting task is spawned on.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, EventTxExt, SerializableEvent};
+    ///
+    /// struct Tick;
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("tick")
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new().addr("127.0.0.1:0").build().await.unwrap();
+    /// let buffered = tx.buffered(Duration::from_millis(20));
+    ///
+    /// for _ in 0..1000 {
+    ///     buffered.send(Event::new("/ticks", Tick)).unwrap();
+    /// }
+    /// # }
+    /// ```
+    fn buffered(&self, window: Duration) -> BufferedEventTx;
+
+    /// Returns a sender that counts the events published through it and their size, for
+    /// visibility into what a producer generates without external instrumentation. `name`
+    /// tells the reports of several producers apart.
+    ///
+    /// # Example
+    /// ```
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, EventTxExt, SerializableEvent};
+    ///
+    /// struct Tick;
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("tick")
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new().addr("127.0.0.1:0").build().await.unwrap();
+    /// let metered = tx.meter("ticker");
+    ///
+    /// metered.send(Event::new("/ticks", Tick)).unwrap();
+    ///
+    /// let report = metered.report();
+    /// assert_eq!(report.events_sent, 1);
+    /// assert_eq!(report.bytes_sent, 4);
+    /// assert_eq!(report.to_string(), "ticker: sent 1 events (4 bytes), 0 failed");
+    /// # }
+    /// ```
+    fn meter(&self, name: &str) -> MeteredEventTx;
+
+    /// Returns a sender that retries events the queue is full for up to `max_retries` times,
+    /// waiting `base_delay` before the first retry and twice as long before every following one.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use pushevent::server::ServerBuilder;
+    /// use pushevent::{Event, EventTxExt, SerializableEvent};
+    ///
+    /// struct Tick;
+    ///
+    /// impl SerializableEvent for Tick {
+    ///     fn serialize(&self) -> String {
+    ///         String::from("tick")
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let tx = ServerBuilder::new()
+    ///     .addr("127.0.0.1:0")
+    ///     .capacity(64)
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// let retrying = tx.retrying(5, Duration::from_millis(10));
+    ///
+    /// if let Err(e) = retrying.send_with_retry(Event::new("/ticks", Tick)).await {
+    ///     eprintln!("dropping tick: {}", e);
+    /// }
+    /// # }
+    /// ```
+    fn retrying(&self, max_retries: u32, base_delay: Duration) -> RetryingEventTx;
+}
+
+impl EventTxExt for EventTx {
+    fn observe(&self, observer: impl Fn(&Event) + Send + Sync + 'static) -> EventTx {
+        let mut observers = Vec::clone(&self.observers);
+        observers.push(Arc::new(observer));
+
+        EventTx {
+            inner: self.inner.clone(),
+            observers: Arc::new(observers),
+            reserved: Mutex::default(),
+            state: self.state.clone(),
+        }
+    }
+
+    fn buffered(&self, window: Duration) -> BufferedEventTx {
+        BufferedEventTx::new(self.clone(), window)
+    }
+
+    fn meter(&self, name: &str) -> MeteredEventTx {
+        MeteredEventTx::new(self.clone(), name)
+    }
+
+    fn retrying(&self, max_retries: u32, base_delay: Duration) -> RetryingEventTx {
+        RetryingEventTx::new(self.clone(), max_retries, base_delay)
+    }
+
+    fn try_send_or_drop(&self, event: Event) -> bool {
+        match self.send(event) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("dropping event: {}", e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures_util::stream;
+
+    use super::*;
+    use crate::SerializableEvent;
+
+    struct Tick;
+
+    impl SerializableEvent for Tick {
+        fn serialize(&self) -> String {
+            String::from("tick")
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_waits_for_room_in_a_bounded_queue() {
+        let (tx, mut rx) = bounded(2);
+        let pulled = Arc::new(AtomicUsize::new(0));
+
+        let counter = pulled.clone();
+        let events = stream::iter(0..10).map(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Event::new("/ticks", Tick)
+        });
+        let task = tokio::spawn(async move { tx.send_all(events).await });
+
+        // Two events are queued and a third one waits for room.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(pulled.load(Ordering::SeqCst), 3);
+
+        rx.recv().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(pulled.load(Ordering::SeqCst), 4);
+
+        for _ in 0..9 {
+            rx.recv().await.unwrap();
+        }
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sink_fails_once_the_server_is_gone() {
+        let (tx, rx) = bounded(2);
+        drop(rx);
+
+        let events = stream::iter([Event::new("/ticks", Tick)]);
+        assert!(matches!(
+            tx.send_all(events).await,
+            Err(Error::ChannelClosed)
+        ));
+    }
+}
@@ -0,0 +1,61 @@
+#![cfg(feature = "actix")]
+
+mod common;
+
+use std::time::Duration;
+
+use actix::{Actor, Context, Handler};
+use common::Text;
+use pushevent::actix_adapter::{EventActor, PushEventMessage};
+use pushevent::server::ServerBuilder;
+use pushevent::Event;
+use tokio::sync::mpsc;
+
+/// Forwards the payloads it receives to a channel.
+struct Forward(mpsc::UnboundedSender<String>);
+
+impl Actor for Forward {
+    type Context = Context<Self>;
+}
+
+impl Handler<PushEventMessage> for Forward {
+    type Result = ();
+
+    fn handle(&mut self, msg: PushEventMessage, _: &mut Context<Self>) {
+        let _ = self.0.send(msg.0);
+    }
+}
+
+#[actix::test]
+async fn actors_receive_events_as_messages() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let subscription = EventActor::new(&server).subscribe("/prices/*", Forward(tx).start());
+
+    let events = server.get_tx();
+    events
+        .send(Event::new("/news", Text("ignored".into())))
+        .unwrap();
+    events
+        .send(Event::new("/prices/btc", Text("42".into())))
+        .unwrap();
+
+    let received = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap();
+    assert_eq!(received.as_deref(), Some("42"));
+
+    server.shutdown().await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !subscription.is_finished() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
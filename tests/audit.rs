@@ -0,0 +1,112 @@
+#![cfg(feature = "audit-jsonl")]
+
+mod common;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pushevent::audit::{JsonLinesSink, IDENTITY_KEY};
+use pushevent::auth::{Authenticator, Rejection, Request};
+use pushevent::server::ServerBuilder;
+use serde_json::Value;
+
+/// A writer whose output the test can read.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Waits for `n` lines to be written and returns them.
+    async fn lines(&self, n: usize) -> Vec<Value> {
+        for _ in 0..500 {
+            let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            if output.lines().count() >= n {
+                return output
+                    .lines()
+                    .map(|x| serde_json::from_str(x).unwrap())
+                    .collect();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("fewer than {} lines were written", n);
+    }
+}
+
+struct User;
+
+impl Authenticator for User {
+    fn authenticate(&self, req: &Request) -> Result<(), Rejection> {
+        let user = req
+            .header("x-user")
+            .ok_or_else(|| Rejection::unauthorized("who are you"))?;
+        req.meta().insert(IDENTITY_KEY, user);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn sessions_are_written_as_json_lines() {
+    let output = Shared::default();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .authenticator(User)
+        .audit(JsonLinesSink::from_writer(output.clone()).unwrap())
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    assert!(
+        tokio_tungstenite::connect_async(format!("ws://{}/orders", addr))
+            .await
+            .is_err()
+    );
+    let mut client = common::connect_with_header(&addr, "/orders", "x-user", "alice").await;
+    while server.connection_count() < 1 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let id = server.connections()[0].id;
+    assert!(server.subscribe(id, "/invoices").unwrap());
+    assert!(server.unsubscribe(id, "/invoices"));
+    client.close(None).await.unwrap();
+
+    let lines = output.lines(5).await;
+    let field = |line: &Value, name: &str| line[name].as_str().map(str::to_string);
+    assert_eq!(
+        lines
+            .iter()
+            .map(|x| (field(x, "event").unwrap(), field(x, "resource").unwrap()))
+            .collect::<Vec<_>>(),
+        [
+            ("handshake_rejected", "/orders"),
+            ("handshake_accepted", "/orders"),
+            ("subscribe", "/invoices"),
+            ("unsubscribe", "/invoices"),
+            ("disconnect", "/orders"),
+        ]
+        .map(|(a, b)| (a.to_string(), b.to_string()))
+    );
+
+    assert_eq!(lines[0]["status"], 401);
+    assert_eq!(lines[0]["reason"], "who are you");
+    assert_eq!(lines[0]["client_id"], Value::Null);
+    assert_eq!(lines[0]["identity"], Value::Null);
+
+    for line in &lines[1..] {
+        assert_eq!(field(line, "client_id"), Some(id.to_string()));
+        assert_eq!(field(line, "identity").as_deref(), Some("alice"));
+        assert!(line["timestamp_ms"].as_u64().unwrap() > 0);
+        assert!(line["addr"].as_str().unwrap().starts_with("127.0.0.1:"));
+    }
+    assert_eq!(lines[4]["reason"], "closed by the client");
+}
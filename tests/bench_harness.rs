@@ -0,0 +1,30 @@
+#![cfg(feature = "bench-harness")]
+
+use std::time::Duration;
+
+use pushevent::bench_harness::{self, BenchConfig};
+use pushevent::server::ServerBuilder;
+
+#[tokio::test]
+async fn measures_delivered_events() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+
+    let config = BenchConfig {
+        rps: 200,
+        duration: Duration::from_millis(250),
+        resource: "/bench".to_string(),
+        payload_size: 64,
+    };
+    let report = bench_harness::run(server.get_tx(), server.local_addr(), config)
+        .await
+        .unwrap();
+
+    assert!(report.sent > 0);
+    assert_eq!(report.delivered, report.sent);
+    assert_eq!(report.dropped, 0);
+    assert!(report.p99_latency > Duration::ZERO);
+}
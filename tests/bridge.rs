@@ -0,0 +1,118 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::Text;
+use pushevent::server::{Server, ServerBuilder};
+use pushevent::{Error, Event, EventTx, Origin, PublishTarget};
+use tokio::sync::mpsc;
+
+/// One direction of a fake broker between two servers, tagging the events it carries with the
+/// name of the server they come from.
+#[derive(Clone, Default)]
+struct Link {
+    from: &'static str,
+    to: Arc<Mutex<Option<EventTx>>>,
+    carried: Arc<AtomicUsize>,
+}
+
+impl Link {
+    fn new(from: &'static str) -> Self {
+        Self {
+            from,
+            ..Self::default()
+        }
+    }
+
+    fn carried(&self) -> usize {
+        self.carried.load(Ordering::SeqCst)
+    }
+}
+
+impl PublishTarget for Link {
+    fn publish(&self, event: Event) -> Result<(), Error> {
+        self.carried.fetch_add(1, Ordering::SeqCst);
+        let tx = self
+            .to
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(Error::ChannelClosed)?;
+        tx.send(event.with_origin(Origin::Bridge(self.from.to_string())))
+    }
+}
+
+async fn start(name: &'static str, link: Link, log: Link) -> (Server, mpsc::UnboundedReceiver<()>) {
+    let (connected, rx) = mpsc::unbounded_channel();
+    let other = if name == "a" { "b" } else { "a" };
+
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .bridge(other, link)
+        .bridge("log", log)
+        .on_connect(move |_| {
+            let _ = connected.send(());
+        })
+        .per_client_event_filter(|client, event| {
+            !client.metadata.contains_key("local_only") || *event.origin() == Origin::Local
+        })
+        .start()
+        .await
+        .unwrap();
+
+    (server, rx)
+}
+
+#[tokio::test]
+async fn events_make_one_circuit_between_bridged_servers() {
+    let (a_to_b, b_to_a) = (Link::new("a"), Link::new("b"));
+    let (a_log, b_log) = (Link::new("a"), Link::new("b"));
+    let (a, mut a_connected) = start("a", a_to_b.clone(), a_log.clone()).await;
+    let (b, mut b_connected) = start("b", b_to_a.clone(), b_log.clone()).await;
+    *a_to_b.to.lock().unwrap() = Some(b.get_tx());
+    *b_to_a.to.lock().unwrap() = Some(a.get_tx());
+
+    let mut on_a = common::connect(&a.local_addr().to_string(), "/prices").await;
+    let mut on_b = common::connect(&b.local_addr().to_string(), "/prices").await;
+    let mut local_on_b = common::connect(&b.local_addr().to_string(), "/prices?local_only=1").await;
+    a_connected.recv().await.unwrap();
+    for _ in 0..2 {
+        b_connected.recv().await.unwrap();
+    }
+
+    a.get_tx()
+        .send(Event::new("/prices", Text("from a".to_string())))
+        .unwrap();
+    for client in [&mut on_a, &mut on_b] {
+        assert_eq!(
+            common::recv(client, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some("from a")
+        );
+    }
+
+    b.get_tx()
+        .send(Event::new("/prices", Text("from b".to_string())))
+        .unwrap();
+    for client in [&mut on_a, &mut on_b, &mut local_on_b] {
+        assert_eq!(
+            common::recv(client, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some("from b")
+        );
+    }
+
+    // Nothing came back through the bridge it was received from.
+    for client in [&mut on_a, &mut on_b, &mut local_on_b] {
+        assert_eq!(common::recv(client, Duration::from_millis(100)).await, None);
+    }
+    assert_eq!(a_to_b.carried(), 1);
+    assert_eq!(b_to_a.carried(), 1);
+    // Events received from a bridge still go to the other bridges.
+    assert_eq!(a_log.carried(), 2);
+    assert_eq!(b_log.carried(), 2);
+}
@@ -0,0 +1,113 @@
+#![cfg(feature = "cbor")]
+
+mod common;
+
+use common::Text;
+use pushevent::server::ServerBuilder;
+use pushevent::Event;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn cbor_clients_receive_binary_envelopes() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+
+    let (mut cbor, selected) =
+        common::connect_with_protocols(&addr, "/sensors", "pushevent.unknown, pushevent.cbor")
+            .await;
+    assert_eq!(selected.as_deref(), Some("pushevent.cbor"));
+    let (mut json, selected) =
+        common::connect_with_protocols(&addr, "/sensors", "pushevent-v2").await;
+    assert_eq!(selected.as_deref(), Some("pushevent-v2"));
+    let (mut unknown, selected) =
+        common::connect_with_protocols(&addr, "/sensors", "pushevent.unknown").await;
+    assert_eq!(selected, None);
+
+    let publish = || {
+        tx.send(Event::new("/sensors", Text(r#"{"temp":21.5}"#.to_string())))
+            .unwrap();
+    };
+
+    let frame = common::publish_until_received_binary(&mut cbor, publish).await;
+    let mut decoded: Value = ciborium::from_reader(frame.as_slice()).unwrap();
+    assert!(decoded["id"].is_u64());
+    decoded.as_object_mut().unwrap().remove("id");
+    assert_eq!(
+        decoded,
+        json!({ "type": "event", "resource": "/sensors", "payload": { "temp": 21.5 } })
+    );
+
+    let frame = common::publish_until_received(&mut json, publish).await;
+    let envelope: Value = serde_json::from_str(&frame).unwrap();
+    let payload: Value = serde_json::from_str(envelope["payload"].as_str().unwrap()).unwrap();
+    assert_eq!(decoded["resource"], envelope["resource"]);
+    assert_eq!(decoded["payload"], payload);
+
+    assert_eq!(
+        common::publish_until_received(&mut unknown, publish).await,
+        r#"{"temp":21.5}"#
+    );
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn the_first_offered_encoding_is_selected() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let (_, selected) =
+        common::connect_with_protocols(&addr, "/", "pushevent.cbor, pushevent.msgpack").await;
+    assert_eq!(selected.as_deref(), Some("pushevent.cbor"));
+
+    let (_, selected) =
+        common::connect_with_protocols(&addr, "/", "pushevent.msgpack, pushevent.cbor").await;
+    assert_eq!(selected.as_deref(), Some("pushevent.msgpack"));
+}
+
+#[tokio::test]
+async fn clients_accepting_cbor_receive_binary_envelopes() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+
+    let mut cbor = common::connect_with_header(
+        &addr,
+        "/sensors",
+        "accept",
+        "text/plain;q=0.5, application/cbor",
+    )
+    .await;
+    let mut refused =
+        common::connect_with_header(&addr, "/sensors", "accept", "application/cbor;q=0").await;
+    let mut json = common::connect(&addr, "/sensors").await;
+
+    let publish = || {
+        tx.send(Event::new("/sensors", Text(r#"{"temp":21.5}"#.to_string())))
+            .unwrap();
+    };
+
+    let frame = common::publish_until_received_binary(&mut cbor, publish).await;
+    let decoded: Value = ciborium::from_reader(frame.as_slice()).unwrap();
+    assert_eq!(decoded["payload"], json!({ "temp": 21.5 }));
+
+    for client in [&mut refused, &mut json] {
+        assert_eq!(
+            common::publish_until_received(client, publish).await,
+            r#"{"temp":21.5}"#
+        );
+    }
+}
@@ -0,0 +1,151 @@
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use pushevent::SerializableEvent;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::protocol::Role;
+use tungstenite::Message;
+
+pub type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Event payload that is sent verbatim.
+pub struct Text(pub String);
+
+impl SerializableEvent for Text {
+    fn serialize(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// Connects a websocket client to `res` on the server listening at `addr`.
+pub async fn connect(addr: &str, res: &str) -> Client {
+    let (ws, _) = connect_async(format!("ws://{}{}", addr, res))
+        .await
+        .expect("failed to connect");
+    ws
+}
+
+/// Connects like [`connect`], sending the header `name` with `value` in the upgrade request.
+pub async fn connect_with_header(addr: &str, res: &str, name: &'static str, value: &str) -> Client {
+    let mut req = format!("ws://{}{}", addr, res)
+        .into_client_request()
+        .unwrap();
+    req.headers_mut().insert(name, value.parse().unwrap());
+
+    let (ws, _) = connect_async(req).await.expect("failed to connect");
+    ws
+}
+
+/// Connects like [`connect`], offering `protocols` in `Sec-WebSocket-Protocol`. Returns the
+/// protocol selected by the server, if any.
+///
+/// The handshake is done by hand, since tungstenite refuses servers that select none of the
+/// offered protocols, which browsers accept.
+pub async fn connect_with_protocols(
+    addr: &str,
+    res: &str,
+    protocols: &str,
+) -> (Client, Option<String>) {
+    let mut stream = TcpStream::connect(addr).await.expect("failed to connect");
+    let req = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Protocol: {}\r\n\r\n",
+        res, addr, protocols
+    );
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    // Read byte by byte, so that no frame sent right after the response is consumed.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        if stream.read(&mut byte).await.unwrap() == 0 {
+            break;
+        }
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8(response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 101"),
+        "failed to connect: {}",
+        response
+    );
+    let selected = response.lines().find_map(|x| {
+        let (name, value) = x.split_once(':')?;
+        name.eq_ignore_ascii_case("sec-websocket-protocol")
+            .then(|| value.trim().to_string())
+    });
+
+    let ws =
+        WebSocketStream::from_raw_socket(MaybeTlsStream::Plain(stream), Role::Client, None).await;
+    (ws, selected)
+}
+
+/// Returns a version 2 `frame` without the id of the event, which depends on how many events were
+/// published before.
+pub fn strip_id(frame: &str) -> String {
+    match frame.strip_prefix(r#"{"type":"event","id":"#) {
+        Some(rest) => {
+            let (_, rest) = rest.split_once(',').unwrap();
+            format!(r#"{{"type":"event",{}"#, rest)
+        }
+        None => frame.to_string(),
+    }
+}
+
+/// Returns the next text frame received by `client`, or `None` if nothing arrives in time.
+pub async fn recv(client: &mut Client, timeout: Duration) -> Option<String> {
+    loop {
+        match tokio::time::timeout(timeout, client.next()).await {
+            Ok(Some(Ok(Message::Text(x)))) => return Some(x.to_string()),
+            Ok(Some(Ok(_))) => continue,
+            _ => return None,
+        }
+    }
+}
+
+/// Calls `publish` until `client` receives a frame and returns it.
+///
+/// The server registers a client only after its handshake completes, so an event published right
+/// after `connect` returns may race the registration.
+pub async fn publish_until_received(client: &mut Client, publish: impl Fn()) -> String {
+    for _ in 0..100 {
+        publish();
+
+        if let Some(x) = recv(client, Duration::from_millis(50)).await {
+            return x;
+        }
+    }
+
+    panic!("client never received an event");
+}
+
+/// Returns the next binary frame received by `client`, or `None` if nothing arrives in time.
+pub async fn recv_binary(client: &mut Client, timeout: Duration) -> Option<Vec<u8>> {
+    loop {
+        match tokio::time::timeout(timeout, client.next()).await {
+            Ok(Some(Ok(Message::Binary(x)))) => return Some(x.to_vec()),
+            Ok(Some(Ok(_))) => continue,
+            _ => return None,
+        }
+    }
+}
+
+/// Like [`publish_until_received`] for clients receiving binary frames.
+pub async fn publish_until_received_binary(client: &mut Client, publish: impl Fn()) -> Vec<u8> {
+    for _ in 0..100 {
+        publish();
+
+        if let Some(x) = recv_binary(client, Duration::from_millis(50)).await {
+            return x;
+        }
+    }
+
+    panic!("client never received an event");
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::time::Duration;
+
+use common::Text;
+use pushevent::server::{RouteConfig, ServerBuilder};
+use pushevent::{Error, Event};
+
+#[tokio::test]
+async fn bind_to_used_address() {
+    let addr = "127.0.0.1:30401";
+    let _tx = ServerBuilder::new().addr(addr).build().await.unwrap();
+
+    let err = ServerBuilder::new().addr(addr).build().await.unwrap_err();
+    assert!(matches!(err, Error::Bind(_)), "{:?}", err);
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn send_to_full_queue() {
+    let tx = ServerBuilder::new()
+        .addr("127.0.0.1:30402")
+        .capacity(1)
+        .build()
+        .await
+        .unwrap();
+
+    // The broadcast loop can't run before we yield, so the first event occupies the only slot.
+    tx.send(Event::new("/a", Text("1".into()))).unwrap();
+
+    let err = tx.send(Event::new("/a", Text("2".into()))).unwrap_err();
+    assert!(matches!(err, Error::QueueFull), "{:?}", err);
+}
+
+#[test]
+fn send_after_server_is_gone() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let tx = rt
+        .block_on(ServerBuilder::new().addr("127.0.0.1:30403").build())
+        .unwrap();
+
+    // Dropping the runtime tears down the server tasks.
+    drop(rt);
+
+    let err = tx.send(Event::new("/a", Text("1".into()))).unwrap_err();
+    assert!(matches!(err, Error::ChannelClosed), "{:?}", err);
+}
+
+#[tokio::test]
+async fn payloads_above_the_limit_are_rejected() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_payload(16)
+        .route("/uploads/*", RouteConfig::new().max_payload(32))
+        .transform("/grows", |_: &str, payload: String| Some(payload.repeat(2)))
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+    let event = |res: &str, len: usize| Event::new(res, Text("x".repeat(len)));
+
+    let mut client = common::connect(&addr, "/events").await;
+    let mut grows = common::connect(&addr, "/grows").await;
+    common::publish_until_received(&mut client, || tx.send(event("/events", 1)).unwrap()).await;
+    common::publish_until_received(&mut grows, || tx.send(event("/grows", 1)).unwrap()).await;
+    for client in [&mut client, &mut grows] {
+        while common::recv(client, Duration::from_millis(100))
+            .await
+            .is_some()
+        {}
+    }
+
+    assert!(tx.send(event("/events", 16)).is_ok());
+    let err = tx.send(event("/events", 17)).unwrap_err();
+    assert!(
+        matches!(err, Error::PayloadTooLarge { size: 17, max: 16 }),
+        "{:?}",
+        err
+    );
+
+    assert!(tx.send(event("/uploads/42", 32)).is_ok());
+    let err = tx.send(event("/uploads/42", 33)).unwrap_err();
+    assert!(
+        matches!(err, Error::PayloadTooLarge { size: 33, max: 32 }),
+        "{:?}",
+        err
+    );
+
+    // Nothing of a batch holding an oversized event is queued.
+    let batch = vec![event("/events", 1), event("/events", 17)];
+    assert!(matches!(
+        tx.publish_batch(batch),
+        Err(Error::PayloadTooLarge { .. })
+    ));
+    tx.send(event("/events", 3)).unwrap();
+
+    // Grown past the limit by the transform after it was published.
+    tx.send(event("/grows", 10)).unwrap();
+    tx.send(event("/grows", 2)).unwrap();
+
+    let timeout = Duration::from_secs(5);
+    assert_eq!(common::recv(&mut client, timeout).await.unwrap().len(), 16);
+    assert_eq!(common::recv(&mut client, timeout).await.unwrap().len(), 3);
+    assert_eq!(common::recv(&mut grows, timeout).await.unwrap().len(), 4);
+    assert_eq!(server.oversized_events(), 4);
+}
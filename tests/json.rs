@@ -0,0 +1,72 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use pushevent::event;
+use pushevent::server::ServerBuilder;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ScanDone {
+    kind: String,
+    library: u64,
+}
+
+#[tokio::test]
+async fn publish_json_roundtrip() {
+    let addr = "127.0.0.1:30201";
+    let tx = ServerBuilder::new().addr(addr).build().await.unwrap();
+    let mut client = common::connect(addr, "/library").await;
+
+    let sent = ScanDone {
+        kind: "scan_done".into(),
+        library: 5,
+    };
+    let frame = common::publish_until_received(&mut client, || {
+        tx.publish_json("/library", &sent).unwrap();
+    })
+    .await;
+
+    assert_eq!(serde_json::from_str::<ScanDone>(&frame).unwrap(), sent);
+}
+
+#[tokio::test]
+async fn event_macro_roundtrip() {
+    let addr = "127.0.0.1:30202";
+    let tx = ServerBuilder::new().addr(addr).build().await.unwrap();
+    let mut client = common::connect(addr, "/library").await;
+
+    let library = 7;
+    let frame = common::publish_until_received(&mut client, || {
+        let _ = tx.send(event!("/library", { "kind": "scan_done", "library": library }));
+    })
+    .await;
+
+    assert_eq!(
+        serde_json::from_str::<ScanDone>(&frame).unwrap(),
+        ScanDone {
+            kind: "scan_done".into(),
+            library: 7,
+        }
+    );
+}
+
+#[tokio::test]
+async fn publish_json_serialization_error() {
+    let tx = ServerBuilder::new()
+        .addr("127.0.0.1:30203")
+        .build()
+        .await
+        .unwrap();
+
+    // JSON object keys must be strings.
+    let mut value = std::collections::HashMap::new();
+    value.insert(vec![1u8], 1);
+
+    let err = tx.publish_json("/library", &value).unwrap_err();
+    assert!(
+        matches!(err, pushevent::Error::Serialization(_)),
+        "{:?}",
+        err
+    );
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "loadtest")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::Text;
+use pushevent::loadtest::LoadTest;
+use pushevent::server::ServerBuilder;
+use pushevent::Event;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn load_test_counts_connections_and_events() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let publisher = tokio::spawn(async move {
+        for x in 0.. {
+            for res in 0..5 {
+                let _ = tx.send(Event::new(format!("/events/{}", res), Text(x.to_string())));
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    });
+
+    let report = LoadTest::new(format!("ws://{}", server.local_addr()))
+        .connections(100)
+        .resources("/events/{}")
+        .resource_count(5)
+        .ramp(Duration::from_millis(200))
+        .duration(Duration::from_secs(1))
+        .run()
+        .await;
+    publisher.abort();
+
+    assert_eq!(report.connected, 100);
+    assert_eq!(report.failed, 0);
+    assert!(report.messages > 0 && report.messages_per_sec > 0.0);
+    assert!(report.handshake_p50 <= report.handshake_p99);
+    assert_eq!(report.missed, 0);
+}
+
+#[tokio::test]
+async fn connections_to_nothing_fail() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let report = LoadTest::new(format!("ws://{}", addr))
+        .connections(5)
+        .duration(Duration::ZERO)
+        .run()
+        .await;
+
+    assert_eq!(
+        (report.connected, report.failed, report.messages),
+        (0, 5, 0)
+    );
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "msgpack")]
+
+mod common;
+
+use common::Text;
+use pushevent::server::ServerBuilder;
+use pushevent::Event;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn msgpack_clients_receive_binary_envelopes() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+
+    let (mut msgpack, selected) =
+        common::connect_with_protocols(&addr, "/prices", "pushevent.msgpack, pushevent-v2").await;
+    assert_eq!(selected.as_deref(), Some("pushevent.msgpack"));
+    let (mut json, selected) =
+        common::connect_with_protocols(&addr, "/prices", "pushevent-v2").await;
+    assert_eq!(selected.as_deref(), Some("pushevent-v2"));
+    let (mut unknown, selected) =
+        common::connect_with_protocols(&addr, "/prices", "pushevent.yaml").await;
+    assert_eq!(selected, None);
+
+    let publish = |payload: &str| {
+        tx.send(Event::new("/prices", Text(payload.to_string())))
+            .unwrap();
+    };
+
+    let frame =
+        common::publish_until_received_binary(&mut msgpack, || publish(r#"{"price":10}"#)).await;
+    let mut decoded: Value = rmp_serde::from_slice(&frame).unwrap();
+    assert!(decoded["id"].is_u64());
+    decoded.as_object_mut().unwrap().remove("id");
+    assert_eq!(
+        decoded,
+        json!({ "type": "event", "resource": "/prices", "payload": { "price": 10 } })
+    );
+
+    let frame = common::publish_until_received(&mut json, || publish(r#"{"price":10}"#)).await;
+    let envelope: Value = serde_json::from_str(&frame).unwrap();
+    let payload: Value = serde_json::from_str(envelope["payload"].as_str().unwrap()).unwrap();
+    assert_eq!(decoded["resource"], envelope["resource"]);
+    assert_eq!(decoded["payload"], payload);
+
+    // Unknown subprotocols fall back to JSON.
+    assert_eq!(
+        common::publish_until_received(&mut unknown, || publish(r#"{"price":10}"#)).await,
+        r#"{"price":10}"#
+    );
+    publish("plain");
+
+    // Payloads that aren't JSON are sent as strings.
+    let frame = loop {
+        let frame = common::recv_binary(&mut msgpack, std::time::Duration::from_secs(5))
+            .await
+            .unwrap();
+        let decoded: Value = rmp_serde::from_slice(&frame).unwrap();
+        if decoded["payload"] != json!({ "price": 10 }) {
+            break decoded;
+        }
+    };
+    assert_eq!(frame["payload"], "plain");
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::Text;
+use pushevent::server::ServerBuilder;
+use pushevent::{Error, Event, MultiPublisher, PublishTarget};
+
+struct Closed;
+
+impl PublishTarget for Closed {
+    fn publish(&self, _: Event) -> Result<(), Error> {
+        Err(Error::ChannelClosed)
+    }
+}
+
+#[tokio::test]
+async fn publishes_to_every_server() {
+    let public = "127.0.0.1:30501";
+    let internal = "127.0.0.1:30502";
+
+    let publisher = MultiPublisher::new()
+        .with_target(ServerBuilder::new().addr(public).build().await.unwrap())
+        .with_target(ServerBuilder::new().addr(internal).build().await.unwrap());
+
+    let mut a = common::connect(public, "/events").await;
+    let mut b = common::connect(internal, "/events").await;
+
+    let publish = || {
+        publisher
+            .publish(Event::new("/events", Text("hello".to_string())))
+            .unwrap();
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut a, publish).await,
+        "hello"
+    );
+    assert_eq!(
+        common::publish_until_received(&mut b, publish).await,
+        "hello"
+    );
+}
+
+#[tokio::test]
+async fn failing_target_does_not_stop_the_others() {
+    let addr = "127.0.0.1:30503";
+
+    let publisher = MultiPublisher::new()
+        .with_target(Closed)
+        .with_target(ServerBuilder::new().addr(addr).build().await.unwrap())
+        .with_target(Closed);
+
+    let mut client = common::connect(addr, "/events").await;
+
+    let publish = || {
+        let err = publisher
+            .publish(Event::new("/events", Text("hello".to_string())))
+            .unwrap_err();
+
+        let failed = err.errors().iter().map(|(idx, _)| *idx).collect::<Vec<_>>();
+        assert_eq!(failed, [0, 2]);
+        assert!(matches!(err.errors()[0].1, Error::ChannelClosed));
+        assert!(err
+            .to_string()
+            .starts_with("failed to publish to 2 of 3 targets"));
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut client, publish).await,
+        "hello"
+    );
+}
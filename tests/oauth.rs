@@ -0,0 +1,82 @@
+#![cfg(feature = "oauth")]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{encode, EncodingKey, Header};
+use pushevent::auth::{Authenticator, Request};
+use pushevent::oauth::OAuthAuthenticator;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const SECRET: &[u8] = b"correct horse battery staple";
+// base64url("correct horse battery staple")
+const JWKS: &str = r#"{"keys":[{"kty":"oct","kid":"k1","alg":"HS256","k":"Y29ycmVjdCBob3JzZSBiYXR0ZXJ5IHN0YXBsZQ"}]}"#;
+
+/// Serves `JWKS` to every request and returns the url it is reachable at.
+async fn serve_jwks() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await;
+            let res = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                JWKS.len(),
+                JWKS
+            );
+            let _ = stream.write_all(res.as_bytes()).await;
+        }
+    });
+
+    format!("http://{}/jwks.json", addr)
+}
+
+fn token(aud: &str, iss: &str, exp_offset: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let claims = serde_json::json!({ "aud": aud, "iss": iss, "exp": now + exp_offset });
+    let header = Header {
+        kid: Some("k1".into()),
+        ..Header::default()
+    };
+
+    encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+}
+
+fn request(token: Option<&str>) -> Request {
+    let req = Request::new("/events");
+
+    match token {
+        Some(token) => req.with_header("authorization", format!("Bearer {}", token)),
+        None => req,
+    }
+}
+
+#[tokio::test]
+async fn validates_bearer_tokens() {
+    let auth = OAuthAuthenticator::new(serve_jwks().await, "pushevent", "issuer")
+        .await
+        .unwrap();
+
+    let valid = token("pushevent", "issuer", 300);
+    assert!(auth.authenticate(&request(Some(&valid))).is_ok());
+    // Served from the cache the second time around.
+    assert!(auth.authenticate(&request(Some(&valid))).is_ok());
+
+    for invalid in [
+        token("someone-else", "issuer", 300),
+        token("pushevent", "evil", 300),
+        token("pushevent", "issuer", -300),
+        "garbage".to_string(),
+    ] {
+        let err = auth.authenticate(&request(Some(&invalid))).unwrap_err();
+        assert_eq!(err.status(), 401);
+    }
+
+    let err = auth.authenticate(&request(None)).unwrap_err();
+    assert_eq!(err.status(), 401);
+}
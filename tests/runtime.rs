@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use futures_util::FutureExt;
+use pushevent::server::{Server, ServerBuilder};
+use pushevent::{Error, Event};
+use tokio::runtime::{Builder, Runtime};
+
+mod common;
+use common::Text;
+
+fn current_thread() -> Runtime {
+    Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+/// Connects a client to `server` and checks that it receives the events published to it.
+async fn receives_events(server: &Server) {
+    let tx = server.get_tx();
+    let mut client = common::connect(&server.local_addr().to_string(), "/ticks").await;
+
+    let received = common::publish_until_received(&mut client, || {
+        tx.send(Event::new("/ticks", Text("tick".into()))).unwrap();
+    })
+    .await;
+    assert_eq!(received, "tick");
+}
+
+#[test]
+fn starting_outside_a_runtime_fails() {
+    let res = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .build()
+        .now_or_never()
+        .expect("fails without waiting");
+
+    assert!(matches!(res, Err(Error::NoRuntime)));
+}
+
+#[test]
+fn servers_run_on_the_given_runtime() {
+    let io = Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    // The runtime starting the server is gone once it started.
+    let server = current_thread().block_on(
+        ServerBuilder::new()
+            .addr("127.0.0.1:0")
+            .runtime(io.handle().clone())
+            .start(),
+    );
+    let server = server.unwrap();
+    assert!(io.metrics().num_alive_tasks() > 0);
+
+    current_thread().block_on(async {
+        receives_events(&server).await;
+        server.shutdown().await;
+        server.join_timeout(Duration::from_secs(5)).await.unwrap();
+    });
+}
+
+#[test]
+fn blocking_servers_run_without_a_runtime() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start_blocking()
+        .unwrap();
+
+    current_thread().block_on(async {
+        receives_events(&server).await;
+        server.shutdown().await;
+        server.join_timeout(Duration::from_secs(5)).await.unwrap();
+    });
+    assert!(server.try_join());
+}
+
+#[test]
+fn blocking_servers_report_errors_starting() {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let res = ServerBuilder::new()
+        .addr(taken.local_addr().unwrap().to_string())
+        .reuse_addr(false)
+        .start_blocking();
+
+    assert!(matches!(res, Err(Error::Bind(_))));
+}
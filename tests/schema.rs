@@ -0,0 +1,78 @@
+#![cfg(feature = "schema")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::Text;
+use pushevent::server::ServerBuilder;
+use pushevent::{Error, Event};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+#[tokio::test]
+async fn drops_events_not_matching_the_schema() {
+    let (tx, mut errors) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .schema(
+            "/prices/*",
+            json!({
+                "type": "object",
+                "properties": { "price": { "type": "number" } },
+                "required": ["price"],
+            }),
+        )
+        .on_validation_error(move |res, payload, _error| {
+            let _ = tx.send((res.to_string(), payload.to_string()));
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let mut client = common::connect(&addr, "/prices/btc").await;
+    let events = server.get_tx();
+
+    let payload = common::publish_until_received(&mut client, || {
+        let _ = events.send(Event::new(
+            "/prices/btc",
+            Text(r#"{"price":"high"}"#.into()),
+        ));
+        let _ = events.send(Event::new("/prices/btc", Text("not json".into())));
+        let _ = events.send(Event::new("/prices/btc", Text(r#"{"price":1}"#.into())));
+    })
+    .await;
+    assert_eq!(payload, r#"{"price":1}"#);
+
+    let timeout = Duration::from_secs(5);
+    let mut invalid = Vec::new();
+    for _ in 0..2 {
+        let (res, payload) = tokio::time::timeout(timeout, errors.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(res, "/prices/btc");
+        invalid.push(payload);
+    }
+    assert_eq!(invalid, [r#"{"price":"high"}"#, "not json"]);
+
+    // Resources without a schema aren't validated.
+    let mut other = common::connect(&addr, "/news").await;
+    let payload = common::publish_until_received(&mut other, || {
+        let _ = events.send(Event::new("/news", Text("not json".into())));
+    })
+    .await;
+    assert_eq!(payload, "not json");
+}
+
+#[tokio::test]
+async fn invalid_schemas_fail_to_start() {
+    let res = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .schema("/prices", json!({ "type": "not a type" }))
+        .start()
+        .await;
+
+    assert!(matches!(res, Err(Error::InvalidSchema(_))));
+}
@@ -0,0 +1,3389 @@
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use common::Text;
+use futures_util::{SinkExt, StreamExt};
+use pushevent::audit;
+use pushevent::auth::{Authenticator, Rejection};
+use pushevent::middleware::{
+    CorsMiddleware, LoggingMiddleware, RateLimitMiddleware, RequestMiddleware, Response,
+};
+use pushevent::server::{
+    self, Batching, BroadcastBackend, DeliveryMode, Health, LoadShedder, PausePolicy, QoS,
+    RouteConfig, ServerBuilder, TapFilter, TapOutcome,
+};
+use pushevent::{CloseReason, Error, Event, Payload, Request, StreamEvent};
+use tokio::sync::mpsc;
+use tokio_tungstenite::connect_async;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::Message;
+
+#[tokio::test]
+async fn on_connect_receives_query_metadata() {
+    let addr = "127.0.0.1:30301";
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let _events = ServerBuilder::new()
+        .addr(addr)
+        .on_connect(move |client| {
+            let _ = tx.send(client.clone());
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let _client = common::connect(addr, "/events?user_id=42&org=acme%20inc").await;
+
+    let info = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(info.resource, "/events");
+    assert_eq!(info.metadata.len(), 2);
+    assert_eq!(info.metadata["user_id"], "42");
+    assert_eq!(info.metadata["org"], "acme inc");
+}
+
+#[tokio::test]
+async fn on_connect_receives_user_agent() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = tx.send(client.user_agent.clone());
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let _client =
+        common::connect_with_header(&addr, "/events", "user-agent", "pushevent-tests/1.0").await;
+    let _anonymous = common::connect(&addr, "/events").await;
+
+    let mut agents = Vec::new();
+    for _ in 0..2 {
+        let agent = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        agents.push(agent);
+    }
+
+    assert_eq!(agents, [Some("pushevent-tests/1.0".to_string()), None]);
+}
+
+/// Stores the `X-User` header as the `user` of the connection.
+struct UserHeader;
+
+impl Authenticator for UserHeader {
+    fn authenticate(&self, req: &Request) -> Result<(), Rejection> {
+        let user = req
+            .header("x-user")
+            .ok_or_else(|| Rejection::unauthorized("missing user"))?;
+        req.meta().insert("user", user);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn client_meta_is_shared_by_the_hooks() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .authenticator(UserHeader)
+        .on_connect(move |client| {
+            let tenant = match client.meta.get("user").as_deref() {
+                Some("alice") => "acme",
+                _ => "initech",
+            };
+            client.meta.insert("tenant", tenant);
+            let _ = tx.send(client.id);
+        })
+        .per_client_filter(|client, _res, payload| {
+            client
+                .meta
+                .get("tenant")
+                .is_some_and(|tenant| payload.starts_with(&tenant))
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let mut alice = common::connect_with_header(&addr, "/events", "x-user", "alice").await;
+    let id = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let meta = server.client_meta(id).unwrap();
+    assert_eq!(meta.get("user").as_deref(), Some("alice"));
+    assert_eq!(meta.get("tenant").as_deref(), Some("acme"));
+
+    let tx = server.get_tx();
+    let payload = common::publish_until_received(&mut alice, || {
+        let _ = tx.send(Event::new("/events", Text("initech: hidden".into())));
+        let _ = tx.send(Event::new("/events", Text("acme: visible".into())));
+    })
+    .await;
+    assert_eq!(payload, "acme: visible");
+
+    drop(alice);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.client_meta(id).is_some() {
+        assert!(
+            Instant::now() < deadline,
+            "metadata outlived the connection"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn per_client_filter_sees_tags() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            client.meta.insert(audit::IDENTITY_KEY, "alice");
+            let _ = ids.send(client.id);
+        })
+        .per_client_filter(|client, _res, _payload| {
+            client.tags.get("role").as_deref() == Some("admin")
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut admin = common::connect(&addr, "/admin").await;
+    let mut viewer = common::connect(&addr, "/admin?viewer=1").await;
+    for _ in 0..2 {
+        connected.recv().await.unwrap();
+    }
+    let clients = server.connections_on("/admin");
+    let (admin_id, viewer_id) = match clients[0].metadata.contains_key("viewer") {
+        true => (clients[1].id, clients[0].id),
+        false => (clients[0].id, clients[1].id),
+    };
+
+    assert_eq!(server.tag_client(admin_id, "role", "admin").unwrap(), None);
+    assert_eq!(server.tag_client(viewer_id, "role", "admin").unwrap(), None);
+    assert_eq!(
+        server.remove_tag(viewer_id, "role").as_deref(),
+        Some("admin")
+    );
+    assert_eq!(server.get_tag(admin_id, "role").as_deref(), Some("admin"));
+    assert_eq!(server.get_tag(viewer_id, "role"), None);
+
+    // Tags don't touch the state set by the hooks.
+    assert_eq!(
+        server
+            .tag_client(admin_id, audit::IDENTITY_KEY, "mallory")
+            .unwrap(),
+        None
+    );
+    let meta = server.client_meta(admin_id).unwrap();
+    assert_eq!(meta.get(audit::IDENTITY_KEY).as_deref(), Some("alice"));
+    assert_eq!(meta.get("role"), None);
+
+    server
+        .get_tx()
+        .send(Event::new("/admin", Text("audit".to_string())))
+        .unwrap();
+    assert_eq!(next_frame(&mut admin).await, "audit");
+    assert_eq!(
+        common::recv(&mut viewer, Duration::from_millis(100)).await,
+        None
+    );
+
+    drop(viewer);
+    while server.connection_count() > 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(matches!(
+        server.tag_client(viewer_id, "role", "admin"),
+        Err(Error::ClientNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn connections_lists_every_client_once() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .allow_pattern_subscriptions(true)
+        .on_connect(move |client| {
+            client.meta.insert("role", "viewer");
+            let _ = tx.send((client.resource.clone(), client.id));
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let _a = common::connect(&addr, "/a?user_id=1").await;
+    let _b = common::connect(&addr, "/b").await;
+    let _library = common::connect(&addr, "/library/*").await;
+
+    let mut ids = HashMap::new();
+    for _ in 0..3 {
+        let (res, id) = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        ids.insert(res, id);
+    }
+    assert!(server.subscribe(ids["/a"], "/b").unwrap());
+
+    let connections = server.connections();
+    assert_eq!(
+        connections
+            .iter()
+            .map(|x| (x.id, x.resources.clone()))
+            .collect::<Vec<_>>(),
+        [
+            (ids["/a"], vec!["/a".to_string(), "/b".to_string()]),
+            (ids["/b"], vec!["/b".to_string()]),
+            (ids["/library/*"], vec!["/library/*".to_string()]),
+        ]
+    );
+
+    let a = &connections[0];
+    assert_eq!(a.metadata.get("user_id").map(String::as_str), Some("1"));
+    assert_eq!(a.meta.get("role").map(String::as_str), Some("viewer"));
+    assert!(a.addr.ip().is_loopback());
+    assert!(a.connected_at <= SystemTime::now());
+
+    let on = |res: &str| {
+        server
+            .connections_on(res)
+            .into_iter()
+            .map(|x| x.id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(on("/b"), [ids["/a"], ids["/b"]]);
+    assert_eq!(on("/library/books"), [ids["/library/*"]]);
+    assert!(on("/c").is_empty());
+}
+
+#[tokio::test]
+async fn sessions_group_the_connections_of_a_user() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = tx.send((client.resource.clone(), client.id));
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let mut tab1 = common::connect(&addr, "/alice/1").await;
+    let mut tab2 = common::connect(&addr, "/alice/2").await;
+    let mut bob = common::connect(&addr, "/bob").await;
+
+    let mut ids = HashMap::new();
+    for _ in 0..3 {
+        let (res, id) = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        ids.insert(res, id);
+    }
+
+    let sessions = server.sessions();
+    let alice = sessions.open_session("alice".to_string());
+    assert_eq!(sessions.open_session("alice".to_string()), alice);
+    assert_ne!(sessions.open_session("bob".to_string()), alice);
+
+    sessions.attach(alice, ids["/alice/1"]).unwrap();
+    sessions.attach(alice, ids["/alice/2"]).unwrap();
+    assert_eq!(sessions.clients(alice), [ids["/alice/1"], ids["/alice/2"]]);
+
+    let event = Event::new("/account", Text("logout".into()));
+    assert_eq!(sessions.broadcast_to_session(alice, event).unwrap(), 2);
+
+    let timeout = Duration::from_secs(5);
+    assert_eq!(common::recv(&mut tab1, timeout).await.unwrap(), "logout");
+    assert_eq!(common::recv(&mut tab2, timeout).await.unwrap(), "logout");
+    assert_eq!(
+        common::recv(&mut bob, Duration::from_millis(100)).await,
+        None
+    );
+
+    // Disconnected clients are detached.
+    drop(tab1);
+    let deadline = Instant::now() + timeout;
+    while sessions.clients(alice).len() > 1 {
+        assert!(Instant::now() < deadline, "client was never detached");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(sessions.clients(alice), [ids["/alice/2"]]);
+
+    assert_eq!(sessions.close_session(alice), [ids["/alice/2"]]);
+    assert!(matches!(
+        sessions.attach(alice, ids["/alice/2"]),
+        Err(Error::SessionNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn paused_routes_hold_events_until_resumed() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let mut prices = common::connect(&addr, "/prices").await;
+    let mut other = common::connect(&addr, "/other").await;
+    let tx = server.get_tx();
+
+    common::publish_until_received(&mut prices, || {
+        let _ = tx.send(Event::new("/prices", Text("ready".into())));
+    })
+    .await;
+    common::publish_until_received(&mut other, || {
+        let _ = tx.send(Event::new("/other", Text("ready".into())));
+    })
+    .await;
+    // Drain the leftovers of waiting for the subscriptions.
+    while common::recv(&mut prices, Duration::from_millis(100))
+        .await
+        .is_some()
+    {}
+
+    assert!(server.pause_route("/prices"));
+    assert!(!server.pause_route("/prices"));
+    assert!(server.is_paused("/prices"));
+
+    tx.send(Event::new("/prices", Text("one".into()))).unwrap();
+    tx.send(Event::new("/prices", Text("two".into()))).unwrap();
+    // Events are delivered in order, so the held events were processed once this arrives.
+    tx.send(Event::new("/other", Text("marker".into())))
+        .unwrap();
+    let timeout = Duration::from_secs(5);
+    while common::recv(&mut other, timeout).await.unwrap() != "marker" {}
+    assert_eq!(
+        common::recv(&mut prices, Duration::from_millis(100)).await,
+        None
+    );
+
+    assert!(server.resume_route("/prices").unwrap());
+    assert!(!server.resume_route("/prices").unwrap());
+    assert!(!server.is_paused("/prices"));
+    tx.send(Event::new("/prices", Text("three".into())))
+        .unwrap();
+
+    for expected in ["one", "two", "three"] {
+        assert_eq!(common::recv(&mut prices, timeout).await.unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn resumed_routes_stay_in_order_on_broadcast_workers() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .broadcast_workers(4)
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let mut prices = common::connect(&addr, "/prices").await;
+    let tx = server.get_tx();
+    common::publish_until_received(&mut prices, || {
+        let _ = tx.send(Event::new("/prices", Text("ready".into())));
+    })
+    .await;
+    while common::recv(&mut prices, Duration::from_millis(100))
+        .await
+        .is_some()
+    {}
+
+    assert!(server.pause_route("/prices"));
+    for i in 0..100 {
+        tx.send(Event::new("/prices", Text(i.to_string()))).unwrap();
+    }
+    // Some of these may still be queued or on their way to the worker, they are held anyway.
+    assert!(server.resume_route("/prices").unwrap());
+    assert!(!server.is_paused("/prices"));
+    for i in 100..200 {
+        tx.send(Event::new("/prices", Text(i.to_string()))).unwrap();
+    }
+
+    let timeout = Duration::from_secs(5);
+    for i in 0..200 {
+        assert_eq!(
+            common::recv(&mut prices, timeout).await.unwrap(),
+            i.to_string()
+        );
+    }
+}
+
+#[tokio::test]
+async fn resuming_a_piped_route_delivers_the_copies() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut internal = common::connect(&addr, "/internal/prices").await;
+    let mut public = common::connect(&addr, "/public/prices").await;
+    for _ in 0..2 {
+        connected.recv().await.unwrap();
+    }
+
+    let _pipe = server.pipe("/internal/prices", "/public/prices");
+    assert!(server.pause_route("/internal/prices"));
+
+    let tx = server.get_tx();
+    tx.send(Event::new("/internal/prices", Text("42".into())))
+        .unwrap();
+    // Events are delivered in order, so the held event was processed once this arrives.
+    tx.send(Event::new("/public/prices", Text("marker".into())))
+        .unwrap();
+    let timeout = Duration::from_secs(5);
+    assert_eq!(common::recv(&mut public, timeout).await.unwrap(), "marker");
+
+    // Publishing the held event pipes it, which checks the paused routes again.
+    assert!(server.resume_route("/internal/prices").unwrap());
+    assert_eq!(common::recv(&mut internal, timeout).await.unwrap(), "42");
+    assert_eq!(common::recv(&mut public, timeout).await.unwrap(), "42");
+
+    // The paused routes are still usable.
+    assert!(server.pause_route("/public/prices"));
+    assert!(server.resume_route("/public/prices").unwrap());
+}
+
+/// Stores the `X-Role` header as the `role` of the connection.
+struct RoleHeader;
+
+impl Authenticator for RoleHeader {
+    fn authenticate(&self, req: &Request) -> Result<(), Rejection> {
+        if let Some(role) = req.header("x-role") {
+            req.meta().insert("role", role);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the HTTP status `res` is rejected with in the handshake, or `None` if it's accepted.
+async fn handshake_status(addr: &str, res: &str, role: Option<&str>) -> Option<u16> {
+    let mut req = format!("ws://{}{}", addr, res)
+        .into_client_request()
+        .unwrap();
+    if let Some(role) = role {
+        req.headers_mut().insert("x-role", role.parse().unwrap());
+    }
+
+    match connect_async(req).await {
+        Ok(_) => None,
+        Err(tungstenite::Error::Http(res)) => Some(res.status().as_u16()),
+        Err(e) => panic!("handshake failed: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn resource_limits_reject_subscriptions() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .authenticator(RoleHeader)
+        .max_resource_len(64)
+        .max_subscriptions(2)
+        .allow_reserved(|client| client.meta.get("role").as_deref() == Some("admin"))
+        .validate_resource(|_client, res| !res.starts_with("/forbidden"))
+        .on_connect(move |client| {
+            let _ = tx.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let long = format!("/{}", "x".repeat(64));
+
+    assert_eq!(handshake_status(&addr, &long, None).await, Some(414));
+    assert_eq!(
+        handshake_status(&addr, "/_pushevent/stats", None).await,
+        Some(403)
+    );
+    assert_eq!(
+        handshake_status(&addr, "/_pushevent/stats", Some("viewer")).await,
+        Some(403)
+    );
+    assert_eq!(
+        handshake_status(&addr, "/_pushevent/stats", Some("admin")).await,
+        None
+    );
+    assert_eq!(handshake_status(&addr, "/forbidden", None).await, Some(403));
+    // Patterns are refused unless allowed.
+    assert_eq!(handshake_status(&addr, "/*", None).await, Some(403));
+    assert_eq!(handshake_status(&addr, "/a/*", None).await, Some(403));
+
+    let _client = common::connect(&addr, "/a").await;
+    // The admin connected above, and possibly disconnected already.
+    let mut id = None;
+    while let Ok(Some(x)) = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
+        if server.list_subscriptions(x) == ["/a"] {
+            id = Some(x);
+            break;
+        }
+    }
+    let id = id.unwrap();
+
+    let status = |res: &str| match server.subscribe(id, res) {
+        Err(Error::ResourceRejected(rejection)) => Some(rejection.status()),
+        x => {
+            x.unwrap();
+            None
+        }
+    };
+
+    assert_eq!(status(&long), Some(414));
+    assert_eq!(status("/_pushevent/stats"), Some(403));
+    assert_eq!(status("/forbidden/b"), Some(403));
+    assert_eq!(status("*"), Some(403));
+    assert_eq!(status("/b"), None);
+    // Subscribing twice doesn't count against the limit.
+    assert!(!server.subscribe(id, "/b").unwrap());
+    assert_eq!(status("/c"), Some(429));
+    assert_eq!(server.list_subscriptions(id), ["/a", "/b"]);
+}
+
+#[tokio::test]
+async fn hooks_receive_the_resource_of_the_client() {
+    let (messages_tx, mut messages) = mpsc::unbounded_channel();
+    let (disconnects_tx, mut disconnects) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_message(move |client, payload| {
+            let _ = messages_tx.send((client.resource.clone(), payload));
+        })
+        .on_disconnect(move |client| {
+            let _ = disconnects_tx.send(client.resource.clone());
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let mut orders = common::connect(&addr, "/orders?user_id=1").await;
+    let news = common::connect(&addr, "/news").await;
+    let timeout = Duration::from_secs(5);
+
+    orders.send(Message::Text("ping".into())).await.unwrap();
+    let message = tokio::time::timeout(timeout, messages.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        message,
+        ("/orders".to_string(), Payload::Text("ping".into()))
+    );
+
+    drop(news);
+    let resource = tokio::time::timeout(timeout, disconnects.recv())
+        .await
+        .unwrap();
+    assert_eq!(resource.as_deref(), Some("/news"));
+
+    orders.close(None).await.unwrap();
+    let resource = tokio::time::timeout(timeout, disconnects.recv())
+        .await
+        .unwrap();
+    assert_eq!(resource.as_deref(), Some("/orders"));
+}
+
+#[tokio::test]
+async fn snapshot_resource_returns_the_last_event() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    assert_eq!(server.snapshot_resource("/prices"), None);
+
+    tx.send(Event::new("/prices", Text("1".into()))).unwrap();
+    tx.send(Event::new("/prices", Text("2".into()))).unwrap();
+    tx.send(Event::new("/news", Text("hello".into()))).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.snapshot_resource("/news").is_none() {
+        assert!(Instant::now() < deadline, "events were never delivered");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(server.snapshot_resource("/prices").as_deref(), Some("2"));
+    assert_eq!(server.snapshot_resource("/news").as_deref(), Some("hello"));
+    assert_eq!(server.snapshot_resource("/other"), None);
+}
+
+#[tokio::test]
+async fn clients_connect_right_after_wait_ready() {
+    for _ in 0..1000 {
+        let server = ServerBuilder::new()
+            .addr("127.0.0.1:0")
+            .start()
+            .await
+            .unwrap();
+        let addr = server.wait_ready(Duration::from_secs(5)).await.unwrap();
+
+        let client = connect_async(format!("ws://{}/events", addr)).await;
+        assert!(client.is_ok());
+
+        drop(client);
+        server.shutdown().await;
+    }
+}
+
+#[tokio::test]
+async fn resource_stats_count_deliveries() {
+    let (tx, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |_| {
+            let _ = tx.send(());
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let mut first = common::connect(&addr, "/prices").await;
+    let mut second = common::connect(&addr, "/prices").await;
+    for _ in 0..2 {
+        connected.recv().await.unwrap();
+    }
+
+    let events = server.get_tx();
+    events
+        .send(Event::new("/prices", Text("abc".into())))
+        .unwrap();
+    events
+        .send(Event::new("/prices", Text("defg".into())))
+        .unwrap();
+    for client in [&mut first, &mut second] {
+        for expected in ["abc", "defg"] {
+            let payload = common::recv(client, Duration::from_secs(5)).await;
+            assert_eq!(payload.as_deref(), Some(expected));
+        }
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let stats = loop {
+        match server.resource_stats("/prices") {
+            Some(x) if x.events_sent == 4 => break x,
+            _ => assert!(Instant::now() < deadline, "deliveries were never counted"),
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+
+    assert_eq!(stats.bytes_sent, 14);
+    assert_eq!(stats.failed_sends, 0);
+    assert_eq!(stats.subscriber_high_water, 2);
+    assert!(stats.last_event_at.is_some());
+
+    assert_eq!(server.resource_stats("/news"), None);
+    assert_eq!(
+        server.all_resource_stats().into_keys().collect::<Vec<_>>(),
+        ["/prices"]
+    );
+}
+
+#[tokio::test]
+async fn broadcast_workers_keep_resources_ordered() {
+    let (tx, mut connected) = mpsc::unbounded_channel();
+    let threads = Arc::new(Mutex::new(HashSet::new()));
+    let seen = threads.clone();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .broadcast_workers(4)
+        .on_connect(move |_| {
+            let _ = tx.send(());
+        })
+        .per_client_filter(move |_, _, _| {
+            let name = std::thread::current().name().map(str::to_string);
+            seen.lock().unwrap().insert(name);
+            true
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr().to_string();
+    let resources = ["/a", "/b", "/c", "/d", "/e", "/f"];
+    let mut clients = Vec::new();
+    for res in resources {
+        clients.push(common::connect(&addr, res).await);
+        connected.recv().await.unwrap();
+    }
+
+    let events = server.get_tx();
+    for i in 0..50 {
+        for res in resources {
+            events.send(Event::new(res, Text(i.to_string()))).unwrap();
+        }
+    }
+
+    for client in &mut clients {
+        for i in 0..50 {
+            let payload = common::recv(client, Duration::from_secs(5)).await;
+            assert_eq!(payload, Some(i.to_string()));
+        }
+    }
+
+    let threads = threads.lock().unwrap().clone();
+    assert!(threads.len() > 1);
+    assert!(threads.iter().all(|name| name
+        .as_deref()
+        .is_some_and(|x| x.starts_with("pushevent-broadcast-"))));
+
+    server.shutdown().await;
+    server.join_timeout(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn middleware_runs_in_the_handshake() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .middleware(CorsMiddleware::new(["https://example.com"]))
+        .middleware(RateLimitMiddleware::new(2, Duration::from_secs(60)))
+        .start()
+        .await
+        .unwrap();
+
+    let request = |origin: &str| {
+        let mut req = format!("ws://{}/events", server.local_addr())
+            .into_client_request()
+            .unwrap();
+        req.headers_mut().insert("origin", origin.parse().unwrap());
+        req
+    };
+
+    let (_client, res) = connect_async(request("https://example.com")).await.unwrap();
+    assert_eq!(
+        res.headers()["access-control-allow-origin"],
+        "https://example.com"
+    );
+
+    match connect_async(request("https://evil.example")).await {
+        Err(tungstenite::Error::Http(res)) => assert_eq!(res.status(), 403),
+        x => panic!(
+            "expected the origin to be rejected, got {:?}",
+            x.map(|_| ())
+        ),
+    }
+
+    // Rejected before reaching the rate limit, which allows one more connection.
+    let _second = connect_async(request("https://example.com")).await.unwrap();
+    match connect_async(request("https://example.com")).await {
+        Err(tungstenite::Error::Http(res)) => assert_eq!(res.status(), 429),
+        x => panic!(
+            "expected the client to be rate limited, got {:?}",
+            x.map(|_| ())
+        ),
+    }
+}
+
+/// Records the connections and broadcasts it is told about.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl RequestMiddleware for Recorder {
+    fn handle(
+        &self,
+        req: &Request,
+        next: &dyn Fn(&Request) -> Result<Response, Rejection>,
+    ) -> Result<Response, Rejection> {
+        next(req)
+    }
+
+    fn on_connect(&self, client: &pushevent::ClientInfo) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("connect {}", client.resource));
+    }
+
+    fn on_disconnect(&self, client: &pushevent::ClientInfo, connected_for: Duration) {
+        assert!(connected_for > Duration::ZERO);
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("disconnect {}", client.resource));
+    }
+
+    fn on_broadcast(&self, res: &str, subscribers: usize, bytes: u64) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("broadcast {} {} {}", res, subscribers, bytes));
+    }
+}
+
+#[tokio::test]
+async fn middleware_sees_connections_and_broadcasts() {
+    let recorder = Recorder::default();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .middleware(LoggingMiddleware::new())
+        .middleware(recorder.clone())
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut client = common::connect(&addr, "/events").await;
+    let publish = || {
+        let _ = server
+            .get_tx()
+            .send(Event::new("/events", Text("hello".to_string())));
+    };
+    common::publish_until_received(&mut client, publish).await;
+    drop(client);
+    while server.connection_count() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let seen = recorder.0.lock().unwrap().clone();
+    assert_eq!(seen.first().map(String::as_str), Some("connect /events"));
+    assert_eq!(seen.last().map(String::as_str), Some("disconnect /events"));
+    assert!(
+        seen.contains(&"broadcast /events 1 5".to_string()),
+        "{:?}",
+        seen
+    );
+}
+
+#[tokio::test]
+async fn per_client_filter_skips_clients() {
+    let addr = "127.0.0.1:30302";
+    let tx = ServerBuilder::new()
+        .addr(addr)
+        .per_client_filter(|client, res, payload| {
+            assert_eq!(res, "/orders");
+            client.metadata.get("user") == Some(&payload.to_string())
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let mut alice = common::connect(addr, "/orders?user=alice").await;
+    let mut bob = common::connect(addr, "/orders?user=bob").await;
+
+    let publish = |user: &'static str| {
+        let tx = tx.clone();
+        move || {
+            let _ = tx.send(Event::new("/orders", Text(user.to_string())));
+        }
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut alice, publish("alice")).await,
+        "alice"
+    );
+    assert_eq!(
+        common::publish_until_received(&mut bob, publish("bob")).await,
+        "bob"
+    );
+
+    // Everything alice got is hers, including the events published while waiting for bob.
+    while let Some(x) = common::recv(&mut alice, Duration::from_millis(100)).await {
+        assert_eq!(x, "alice");
+    }
+}
+
+#[tokio::test]
+async fn transforms_rewrite_and_drop_events() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .transform("*", move |_: &str, payload: String| {
+            if payload == "delivered" {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            (payload != "invalid").then_some(payload)
+        })
+        .transform("/orders/*", |res: &str, payload: String| {
+            Some(format!("{} for {}", payload, res))
+        })
+        .transform("/orders/*", |_: &str, payload: String| {
+            assert_ne!(payload, "boom for /orders/1");
+            Some(format!("{} in eu-west", payload))
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+    let mut orders = common::connect(&addr, "/orders/1").await;
+    let mut news = common::connect(&addr, "/news").await;
+
+    let send = |res: &str, payload: &str| {
+        tx.send(Event::new(res, Text(payload.to_string()))).unwrap();
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut orders, || send("/orders/1", "shipped")).await,
+        "shipped for /orders/1 in eu-west"
+    );
+    assert_eq!(
+        common::publish_until_received(&mut news, || send("/news", "hello")).await,
+        "hello"
+    );
+
+    send("/orders/1", "invalid");
+    send("/orders/1", "boom");
+    send("/orders/1", "delivered");
+
+    // Skip the events published while waiting for the clients to subscribe.
+    let next = loop {
+        let frame = common::recv(&mut orders, Duration::from_secs(5))
+            .await
+            .unwrap();
+        if frame != "shipped for /orders/1 in eu-west" {
+            break frame;
+        }
+    };
+    assert_eq!(next, "delivered for /orders/1 in eu-west");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn transformers_chain_before_the_filter() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .transformer("/prices", |_: &str, price: String| format!("${}", price))
+        .transformer("/prices", |_: &str, price: String| format!("{} USD", price))
+        .transformer("/chat", |_: &str, text: String| {
+            text.replace('&', "&amp;").replace('<', "&lt;")
+        })
+        // Only sees the transformed payloads.
+        .per_client_filter(|_, _, payload| !payload.starts_with("1") && !payload.contains('<'))
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+    let mut prices = common::connect(&addr, "/prices").await;
+    let mut chat = common::connect(&addr, "/chat").await;
+
+    let send = |res: &str, payload: &str| {
+        tx.send(Event::new(res, Text(payload.to_string()))).unwrap();
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut prices, || send("/prices", "10")).await,
+        "$10 USD"
+    );
+    assert_eq!(
+        common::publish_until_received(&mut chat, || send("/chat", "<b>a & b</b>")).await,
+        "&lt;b>a &amp; b&lt;/b>"
+    );
+}
+
+#[tokio::test]
+async fn protocol_version_is_negotiated() {
+    let addr = "127.0.0.1:30303";
+    let tx = ServerBuilder::new()
+        .addr(addr)
+        .max_protocol_version(2)
+        .build()
+        .await
+        .unwrap();
+
+    let (mut v2, selected) =
+        common::connect_with_protocols(addr, "/events", "pushevent-v1, pushevent-v2").await;
+    assert_eq!(selected.as_deref(), Some("pushevent-v2"));
+
+    let (mut v1, selected) = common::connect_with_protocols(addr, "/events", "pushevent-v1").await;
+    assert_eq!(selected.as_deref(), Some("pushevent-v1"));
+
+    let mut plain = common::connect(addr, "/events").await;
+
+    let publish = || {
+        let _ = tx.send(Event::new("/events", Text(r#"{"id":"1"}"#.to_string())));
+    };
+
+    assert_eq!(
+        common::strip_id(&common::publish_until_received(&mut v2, publish).await),
+        r#"{"type":"event","resource":"/events","payload":"{\"id\":\"1\"}"}"#
+    );
+    assert_eq!(
+        common::publish_until_received(&mut v1, publish).await,
+        r#"{"id":"1"}"#
+    );
+    assert_eq!(
+        common::publish_until_received(&mut plain, publish).await,
+        r#"{"id":"1"}"#
+    );
+}
+
+#[tokio::test]
+async fn protocol_version_is_capped_by_server() {
+    let addr = "127.0.0.1:30304";
+    let _tx = ServerBuilder::new().addr(addr).build().await.unwrap();
+
+    let (_client, selected) = common::connect_with_protocols(addr, "/events", "pushevent-v2").await;
+    assert_eq!(selected, None);
+}
+
+#[tokio::test]
+async fn v1_and_v2_clients_receive_their_own_format() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut v1 = common::connect(&addr, "/events").await;
+    let mut v2_query = common::connect(&addr, "/events?protocol=2").await;
+    let (mut v2, selected) = common::connect_with_protocols(&addr, "/events", "pushevent.v2").await;
+    assert_eq!(selected.as_deref(), Some("pushevent.v2"));
+
+    // Ids set by the publisher are replaced by the ones the server assigns.
+    let publish = || {
+        let _ = tx.send(Event::new("/events", Text("hello".to_string())).with_id(u64::MAX));
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut v1, publish).await,
+        "hello"
+    );
+    for client in [&mut v2_query, &mut v2] {
+        let frame = common::publish_until_received(client, publish).await;
+        assert!(frame.starts_with(r#"{"type":"event","id":"#));
+        assert!(!frame.contains(&u64::MAX.to_string()));
+        assert_eq!(
+            common::strip_id(&frame),
+            r#"{"type":"event","resource":"/events","payload":"hello"}"#
+        );
+    }
+
+    // Only version 2 clients are told why the connection closes.
+    server.drain(Duration::ZERO).await;
+    let mut notices = Vec::new();
+    for client in [&mut v1, &mut v2_query, &mut v2] {
+        let mut count = 0;
+        while let Ok(Some(Ok(frame))) =
+            tokio::time::timeout(Duration::from_secs(5), client.next()).await
+        {
+            if matches!(frame, Message::Text(x) if x.contains("draining")) {
+                count += 1;
+            }
+        }
+        notices.push(count);
+    }
+    assert_eq!(notices, [0, 1, 1]);
+}
+
+#[tokio::test]
+async fn drain_notifies_clients_and_resolves_once_they_leave() {
+    let addr = "127.0.0.1:30305";
+    let server = ServerBuilder::new()
+        .addr(addr)
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    let (mut client, _) = common::connect_with_protocols(addr, "/events", "pushevent-v2").await;
+    common::publish_until_received(&mut client, || {
+        let _ = tx.send(Event::new("/events", Text("hello".to_string())));
+    })
+    .await;
+
+    let start = Instant::now();
+    let drain = tokio::spawn({
+        let server = server.clone();
+        async move { server.drain(Duration::from_secs(30)).await }
+    });
+
+    assert_eq!(
+        common::recv(&mut client, Duration::from_secs(5)).await,
+        Some(r#"{"type":"draining","reconnect_after_ms":0}"#.to_string())
+    );
+
+    match tokio::time::timeout(Duration::from_secs(5), client.next()).await {
+        Ok(Some(Ok(Message::Close(Some(frame))))) => {
+            assert_eq!(u16::from(frame.code), 1012);
+            assert_eq!(frame.reason, "service restart");
+        }
+        x => panic!("expected a close frame, got {:?}", x),
+    }
+
+    // The client library answers the close frame, after which the server is empty.
+    while let Some(Ok(_)) = client.next().await {}
+
+    assert_eq!(drain.await.unwrap(), 0);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(server.connection_count(), 0);
+
+    assert!(connect_async(format!("ws://{}/events", addr))
+        .await
+        .is_err());
+    assert!(matches!(
+        tx.send(Event::new("/events", Text("late".to_string()))),
+        Err(Error::ChannelClosed)
+    ));
+}
+
+#[tokio::test]
+async fn drain_closes_lingering_clients_after_grace() {
+    let addr = "127.0.0.1:30306";
+    let server = ServerBuilder::new().addr(addr).start().await.unwrap();
+
+    // Never polled, so the close frame is never answered.
+    let _client = common::connect(addr, "/events").await;
+
+    while server.connection_count() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(server.drain(Duration::from_millis(200)).await, 1);
+    assert_eq!(server.connection_count(), 0);
+}
+
+#[tokio::test]
+async fn list_subscriptions_of_client() {
+    let addr = "127.0.0.1:30307";
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let server = ServerBuilder::new()
+        .addr(addr)
+        .on_connect(move |client| {
+            let _ = tx.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+
+    let client = common::connect(addr, "/library/movies").await;
+
+    let id = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(server.list_subscriptions(id), ["/library/movies"]);
+
+    drop(client);
+    while server.connection_count() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert!(server.list_subscriptions(id).is_empty());
+}
+
+#[tokio::test]
+async fn run_until_drains_once_triggered() {
+    let addr = "127.0.0.1:30308";
+    let server = ServerBuilder::new()
+        .addr(addr)
+        .shutdown_grace(Duration::from_secs(5))
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+
+    let (mut client, _) = common::connect_with_protocols(addr, "/events", "pushevent-v2").await;
+    while server.connection_count() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let run = tokio::spawn({
+        let server = server.clone();
+        let stop = stop.clone();
+        async move { server.run_until(server::flag_set(stop)).await }
+    });
+
+    assert_eq!(
+        common::recv(&mut client, Duration::from_millis(300)).await,
+        None
+    );
+    stop.store(true, Ordering::Relaxed);
+
+    assert!(common::recv(&mut client, Duration::from_secs(5))
+        .await
+        .unwrap()
+        .contains("draining"));
+    while let Some(Ok(_)) = client.next().await {}
+
+    tokio::time::timeout(Duration::from_secs(5), run)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(server.connection_count(), 0);
+}
+
+#[tokio::test]
+async fn restart_on_same_port() {
+    let addr = "127.0.0.1:30309";
+
+    for _ in 0..2 {
+        let server = ServerBuilder::new().addr(addr).start().await.unwrap();
+        let tx = server.get_tx();
+        let mut client = common::connect(addr, "/events").await;
+
+        common::publish_until_received(&mut client, || {
+            let _ = tx.send(Event::new("/events", Text("hello".to_string())));
+        })
+        .await;
+
+        // Closing the connections from the server side leaves them in TIME_WAIT.
+        server.drain(Duration::ZERO).await;
+        while let Some(Ok(_)) = client.next().await {}
+    }
+}
+
+#[tokio::test]
+async fn tokio_broadcast_backend_delivers_per_resource() {
+    let addr = "127.0.0.1:30310";
+    let server = ServerBuilder::new()
+        .addr(addr)
+        .broadcast_backend(BroadcastBackend::TokioBroadcast { capacity: 16 })
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    let mut movies = common::connect(addr, "/movies").await;
+    let (mut movies_v2, _) = common::connect_with_protocols(addr, "/movies", "pushevent-v2").await;
+    let mut shows = common::connect(addr, "/shows").await;
+
+    let publish = || {
+        let _ = tx.send(Event::new("/movies", Text("movie".to_string())));
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut movies, publish).await,
+        "movie"
+    );
+    assert_eq!(
+        common::strip_id(&common::publish_until_received(&mut movies_v2, publish).await),
+        r#"{"type":"event","resource":"/movies","payload":"movie"}"#
+    );
+    assert_eq!(
+        common::recv(&mut shows, Duration::from_millis(100)).await,
+        None
+    );
+
+    // Connections still end once the server closes them.
+    server.drain(Duration::from_millis(100)).await;
+    let closed = async { while let Some(Ok(_)) = movies.next().await {} };
+    tokio::time::timeout(Duration::from_secs(5), closed)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn tokio_broadcast_notifies_lagging_clients() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .broadcast_backend(BroadcastBackend::TokioBroadcast { capacity: 4 })
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let (mut client, _) =
+        common::connect_with_protocols(&server.local_addr().to_string(), "/feed", "pushevent-v2")
+            .await;
+
+    let publish = || {
+        let _ = tx.send(Event::new("/feed", Text("hello".to_string())));
+    };
+    common::publish_until_received(&mut client, publish).await;
+
+    // Publishing faster than the connection writes to a client that isn't reading makes the
+    // connection fall behind.
+    let large = "x".repeat(64 * 1024);
+    for _ in 0..500 {
+        tx.send(Event::new("/feed", Text(large.clone()))).unwrap();
+    }
+
+    let lagged = async {
+        loop {
+            let frame = common::recv(&mut client, Duration::from_secs(5))
+                .await
+                .unwrap();
+            if !frame.starts_with(r#"{"type":"event","#) {
+                return frame;
+            }
+        }
+    };
+    let notice = tokio::time::timeout(Duration::from_secs(10), lagged)
+        .await
+        .unwrap();
+
+    assert!(notice.starts_with(r#"{"type":"lagged","missed":"#));
+}
+
+#[tokio::test]
+async fn replay_on_reconnect_sends_missed_events() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .replay_on_reconnect(16)
+        .route("/progress", RouteConfig::new().replay(1))
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+    let send = |payload: &str| {
+        let _ = tx.send(Event::new("/feed", Text(payload.to_string())));
+        let _ = tx.send(Event::new("/progress", Text(payload.to_string())));
+    };
+
+    let mut client = common::connect_with_header(&addr, "/feed", "x-pushevent-session", "s1").await;
+    let mut progress =
+        common::connect_with_header(&addr, "/progress", "x-pushevent-session", "s2").await;
+    common::publish_until_received(&mut client, || send("live")).await;
+    common::publish_until_received(&mut progress, || send("live")).await;
+    drop((client, progress));
+
+    while server.connection_count() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    send("missed 1");
+    send("missed 2");
+    tx.send(Event::new("/other", Text("other".to_string())))
+        .unwrap();
+
+    // Only the last missed event is replayed on this route.
+    let mut progress =
+        common::connect_with_header(&addr, "/progress", "x-pushevent-session", "s2").await;
+    assert_eq!(
+        common::recv(&mut progress, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("missed 2")
+    );
+
+    let mut anonymous = common::connect(&addr, "/feed").await;
+    let mut client = common::connect_with_header(&addr, "/feed", "x-pushevent-session", "s1").await;
+
+    for expected in ["missed 1", "missed 2"] {
+        assert_eq!(
+            common::recv(&mut client, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some(expected)
+        );
+    }
+    assert_eq!(
+        common::recv(&mut anonymous, Duration::from_millis(100)).await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn broadcast_except_skips_the_sender() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send((client.metadata["user"].clone(), client.id));
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut clients = HashMap::new();
+    for user in ["alice", "bob", "carol"] {
+        let client = common::connect(&addr, &format!("/chat?user={}", user)).await;
+        clients.insert(user.to_string(), client);
+    }
+    let mut ids = HashMap::new();
+    while ids.len() < 3 {
+        let (user, id) = connected.recv().await.unwrap();
+        ids.insert(user, id);
+    }
+
+    let event = Event::new("/chat", Text("hi from alice".to_string()));
+    assert_eq!(server.broadcast_except("/chat", ids["alice"], event), 2);
+
+    for user in ["bob", "carol"] {
+        let client = clients.get_mut(user).unwrap();
+        assert_eq!(
+            common::recv(client, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some("hi from alice")
+        );
+    }
+    let alice = clients.get_mut("alice").unwrap();
+    assert_eq!(common::recv(alice, Duration::from_millis(100)).await, None);
+
+    let stats = server.resource_stats("/chat").unwrap();
+    assert_eq!(stats.events_sent, 2);
+    assert_eq!(stats.subscriber_high_water, 2);
+
+    assert!(server.pause_route("/chat"));
+    let event = Event::new("/chat", Text("while paused".to_string()));
+    assert_eq!(server.broadcast_except("/chat", ids["alice"], event), 0);
+    server.resume_route("/chat").unwrap();
+    let bob = clients.get_mut("bob").unwrap();
+    assert_eq!(common::recv(bob, Duration::from_millis(100)).await, None);
+}
+
+#[tokio::test]
+async fn paused_clients_receive_the_held_events_once_resumed() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut paused = common::connect(&addr, "/feed").await;
+    let id = connected.recv().await.unwrap();
+    let mut other = common::connect(&addr, "/feed").await;
+    connected.recv().await.unwrap();
+
+    assert!(server.pause_client(id).unwrap());
+    assert!(!server.pause_client(id).unwrap());
+    for x in ["a", "b"] {
+        tx.send(Event::new("/feed", Text(x.to_string()))).unwrap();
+    }
+
+    for x in ["a", "b"] {
+        assert_eq!(
+            common::recv(&mut other, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some(x)
+        );
+    }
+    assert_eq!(
+        common::recv(&mut paused, Duration::from_millis(100)).await,
+        None
+    );
+
+    assert!(server.resume_client(id).unwrap());
+    assert!(!server.resume_client(id).unwrap());
+    tx.send(Event::new("/feed", Text("c".to_string()))).unwrap();
+    for x in ["a", "b", "c"] {
+        assert_eq!(
+            common::recv(&mut paused, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some(x)
+        );
+    }
+
+    drop(paused);
+    while server.connection_count() > 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(matches!(
+        server.pause_client(id),
+        Err(Error::ClientNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn clients_pause_themselves_with_control_frames() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .pause_policy(PausePolicy::Buffer { capacity: 2 })
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut paused = common::connect(&addr, "/feed").await;
+    let id = connected.recv().await.unwrap();
+    let mut other = common::connect(&addr, "/feed").await;
+    connected.recv().await.unwrap();
+
+    paused
+        .send(Message::Text(r#"{"action":"pause"}"#.into()))
+        .await
+        .unwrap();
+    wait_until_paused(&server, id, true).await;
+
+    // The oldest frame is dropped once the buffer is full.
+    for x in ["a", "b", "c"] {
+        tx.send(Event::new("/feed", Text(x.to_string()))).unwrap();
+    }
+    for x in ["a", "b", "c"] {
+        assert_eq!(
+            common::recv(&mut other, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some(x)
+        );
+    }
+    assert_eq!(
+        common::recv(&mut paused, Duration::from_millis(100)).await,
+        None
+    );
+
+    paused
+        .send(Message::Text(r#"{"action":"resume"}"#.into()))
+        .await
+        .unwrap();
+    wait_until_paused(&server, id, false).await;
+    tx.send(Event::new("/feed", Text("d".to_string()))).unwrap();
+    for x in ["b", "c", "d"] {
+        assert_eq!(
+            common::recv(&mut paused, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some(x)
+        );
+    }
+}
+
+#[tokio::test]
+async fn paused_clients_miss_events_when_dropping() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .pause_policy(PausePolicy::Drop)
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&addr, "/feed").await;
+    let id = connected.recv().await.unwrap();
+    let mut other = common::connect(&addr, "/feed").await;
+    connected.recv().await.unwrap();
+
+    assert!(server.pause_client(id).unwrap());
+    tx.send(Event::new("/feed", Text("missed".to_string())))
+        .unwrap();
+    // Delivered to both clients at once.
+    assert_eq!(
+        common::recv(&mut other, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("missed")
+    );
+
+    assert!(server.resume_client(id).unwrap());
+    tx.send(Event::new("/feed", Text("seen".to_string())))
+        .unwrap();
+    assert_eq!(
+        common::recv(&mut client, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("seen")
+    );
+}
+
+/// Waits until the client `id` is `paused` according to [`server::Server::connections`].
+async fn wait_until_paused(server: &server::Server, id: pushevent::ClientId, paused: bool) {
+    while !server
+        .connections()
+        .iter()
+        .any(|x| x.id == id && x.paused == paused)
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn pipes_copy_events_until_removed() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut internal = common::connect(&addr, "/internal/prices").await;
+    let mut public = common::connect(&addr, "/public/prices").await;
+    for _ in 0..2 {
+        connected.recv().await.unwrap();
+    }
+
+    let pipe = server.pipe_with("/internal/*", "/public/prices", |res: &str, payload| {
+        format!("{}={}", res, payload)
+    });
+    // Pipes can't loop, the copies aren't piped back.
+    let back = server.pipe("/public/prices", "/internal/prices");
+
+    let tx = server.get_tx();
+    tx.send(Event::new("/internal/prices", Text("42".to_string())))
+        .unwrap();
+    assert_eq!(
+        common::recv(&mut internal, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("42")
+    );
+    assert_eq!(
+        common::recv(&mut public, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("/internal/prices=42")
+    );
+    assert_eq!(
+        common::recv(&mut internal, Duration::from_millis(100)).await,
+        None
+    );
+
+    pipe.remove();
+    back.remove();
+    tx.send(Event::new("/internal/prices", Text("43".to_string())))
+        .unwrap();
+    assert_eq!(
+        common::recv(&mut internal, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("43")
+    );
+    assert_eq!(
+        common::recv(&mut public, Duration::from_millis(100)).await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn publish_all_reaches_every_client_once() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .per_client_filter(|client, _res, _payload| !client.metadata.contains_key("muted"))
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut clients = Vec::new();
+    for res in ["/a", "/b", "/c"] {
+        clients.push(common::connect(&addr, res).await);
+    }
+    let mut muted = common::connect(&addr, "/a?muted=1").await;
+    for _ in 0..4 {
+        connected.recv().await.unwrap();
+    }
+    let both = connected_id(&server, "/c");
+    assert!(server.subscribe(both, "/a").unwrap());
+
+    let event = Event::new("/announcements", Text("going down".to_string()));
+    server.get_tx().publish_all(event).unwrap();
+
+    for client in clients.iter_mut() {
+        assert_eq!(
+            common::recv(client, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some("going down")
+        );
+        assert_eq!(common::recv(client, Duration::from_millis(100)).await, None);
+    }
+    assert_eq!(
+        common::recv(&mut muted, Duration::from_millis(100)).await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn taps_record_sent_and_failed_frames() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let mut everything = server.tap(TapFilter::All);
+    let mut orders = server.tap(TapFilter::Resource("/orders/*".into()));
+
+    let mut alice = common::connect(&addr, "/orders/1").await;
+    let bob = common::connect(&addr, "/news").await;
+    for _ in 0..2 {
+        connected.recv().await.unwrap();
+    }
+    let (alice_id, bob_id) = (
+        connected_id(&server, "/orders/1"),
+        connected_id(&server, "/news"),
+    );
+    let mut bobs = server.tap(TapFilter::Client(bob_id));
+
+    server
+        .get_tx()
+        .send(Event::new("/orders/1", Text("shipped".to_string())))
+        .unwrap();
+    assert_eq!(next_frame(&mut alice).await, "shipped");
+
+    // Bob is gone by the time this is sent.
+    let bob_tx = server.get_client_tx(bob_id).unwrap();
+    drop(bob);
+    while server.connection_count() > 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(bob_tx.send("late".to_string()).is_err());
+
+    let summary = |x: server::TapRecord| (x.client_id, x.resource, x.payload_preview, x.outcome);
+    assert_eq!(
+        summary(everything.recv().await.unwrap()),
+        (
+            alice_id,
+            "/orders/1".to_string(),
+            "shipped".to_string(),
+            TapOutcome::Sent
+        )
+    );
+    assert_eq!(
+        summary(everything.recv().await.unwrap()),
+        (
+            bob_id,
+            "/news".to_string(),
+            "late".to_string(),
+            TapOutcome::Failed
+        )
+    );
+    assert_eq!(orders.recv().await.unwrap().client_id, alice_id);
+    assert_eq!(bobs.recv().await.unwrap().outcome, TapOutcome::Failed);
+    for tap in [&mut everything, &mut orders, &mut bobs] {
+        assert!(tap.try_recv().is_err());
+    }
+}
+
+#[tokio::test]
+async fn group_events_follow_group_membership() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .per_client_filter(|client, _res, _payload| !client.metadata.contains_key("muted"))
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut alice = common::connect(&addr, "/alice").await;
+    let mut bob = common::connect(&addr, "/bob").await;
+    let mut muted = common::connect(&addr, "/muted?muted=1").await;
+    for _ in 0..3 {
+        connected.recv().await.unwrap();
+    }
+    let (alice_id, bob_id) = (
+        connected_id(&server, "/alice"),
+        connected_id(&server, "/bob"),
+    );
+    let muted_id = connected_id(&server, "/muted");
+
+    assert!(server.join_group(alice_id, "admins").unwrap());
+    assert!(!server.join_group(alice_id, "admins").unwrap());
+    assert!(server.join_group(muted_id, "admins").unwrap());
+    assert!(server.join_group(bob_id, "players").unwrap());
+
+    let event = |x: &str| Event::new("/notices", Text(x.to_string()));
+    tx.publish_group("admins", event("for admins")).unwrap();
+    assert_eq!(next_frame(&mut alice).await, "for admins");
+    // Group events still go through the per-client filter.
+    assert_eq!(
+        common::recv(&mut muted, Duration::from_millis(100)).await,
+        None
+    );
+
+    // Alice moves to the players and Bob to the admins.
+    assert!(server.leave_group(alice_id, "admins"));
+    assert!(server.join_group(alice_id, "players").unwrap());
+    assert!(server.leave_group(bob_id, "players"));
+    assert!(server.join_group(bob_id, "admins").unwrap());
+    assert_eq!(server.client_groups(alice_id), ["players"]);
+
+    tx.publish_group("admins", event("for admins again"))
+        .unwrap();
+    tx.publish_group("players", event("for players")).unwrap();
+    assert_eq!(next_frame(&mut bob).await, "for admins again");
+    assert_eq!(next_frame(&mut alice).await, "for players");
+    for client in [&mut alice, &mut bob] {
+        assert_eq!(common::recv(client, Duration::from_millis(100)).await, None);
+    }
+
+    // Clients leave their groups when they disconnect.
+    drop(muted);
+    while server.connection_count() > 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.group_members("admins"), [bob_id]);
+    assert!(matches!(
+        server.join_group(muted_id, "admins"),
+        Err(Error::ClientNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn regular_delivery_has_no_sequence_gaps() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .allow_pattern_subscriptions(true)
+        .detect_sequence_gaps(true)
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .per_client_filter(|client, _res, payload| {
+            !client.metadata.contains_key("muted") || payload != "1"
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut orders = common::connect(&addr, "/orders/*").await;
+    let mut muted = common::connect(&addr, "/orders/1?muted=1").await;
+    let mut other = common::connect(&addr, "/other").await;
+    for _ in 0..3 {
+        connected.recv().await.unwrap();
+    }
+    let other_id = connected_id(&server, "/other");
+
+    // Every step waits for the event to be delivered, so that it happens in between events.
+    let publish = |x: u32| {
+        tx.send(Event::new("/orders/1", Text(x.to_string())))
+            .unwrap();
+    };
+    for x in 0..5 {
+        publish(x);
+        assert_eq!(next_frame(&mut orders).await, x.to_string());
+        match x {
+            0 => assert!(server.subscribe(other_id, "/orders/1").unwrap()),
+            2 => assert!(server.unsubscribe(other_id, "/orders/1")),
+            3 => assert!(server.subscribe(other_id, "/orders/*").unwrap()),
+            _ => {}
+        }
+    }
+
+    for x in [0, 2, 3, 4] {
+        assert_eq!(next_frame(&mut muted).await, x.to_string());
+    }
+    for x in [1, 2, 4] {
+        assert_eq!(next_frame(&mut other).await, x.to_string());
+    }
+    assert_eq!(server.sequence_gaps(), 0);
+}
+
+/// Returns the id of the only client connected to `res`.
+fn connected_id(server: &server::Server, res: &str) -> pushevent::ClientId {
+    let clients = server.connections_on(res);
+    assert_eq!(clients.len(), 1);
+    clients[0].id
+}
+
+#[tokio::test]
+async fn subscribing_twice_delivers_events_once() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&server.local_addr().to_string(), "/a").await;
+    let id = connected.recv().await.unwrap();
+
+    assert!(server.subscribe(id, "/b").unwrap());
+    assert!(!server.subscribe(id, "/b").unwrap());
+    assert_eq!(server.list_subscriptions(id), ["/a", "/b"]);
+
+    tx.send(Event::new("/b", Text("once".into()))).unwrap();
+    assert_eq!(
+        common::recv(&mut client, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("once")
+    );
+    assert_eq!(
+        common::recv(&mut client, Duration::from_millis(100)).await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn overlapping_subscriptions_receive_events_once() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .allow_pattern_subscriptions(true)
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+
+    let mut client = common::connect(&addr, "/library/5").await;
+    let id = connected.recv().await.unwrap();
+    let mut wildcard = common::connect(&addr, "/library/*").await;
+    connected.recv().await.unwrap();
+
+    assert!(server.subscribe(id, "/library/*").unwrap());
+    assert!(server.subscribe(id, "*").unwrap());
+    assert!(!server.subscribe(id, "/library/5").unwrap());
+    assert_eq!(
+        server.list_subscriptions(id),
+        ["*", "/library/*", "/library/5"]
+    );
+
+    for res in ["/library/5", "/library/6", "/news"] {
+        tx.send(Event::new(res, Text(res.to_string()))).unwrap();
+    }
+
+    for expected in ["/library/5", "/library/6", "/news"] {
+        assert_eq!(
+            common::recv(&mut client, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some(expected)
+        );
+    }
+    for expected in ["/library/5", "/library/6"] {
+        assert_eq!(
+            common::recv(&mut wildcard, Duration::from_secs(5))
+                .await
+                .as_deref(),
+            Some(expected)
+        );
+    }
+    assert_eq!(
+        common::recv(&mut client, Duration::from_millis(100)).await,
+        None
+    );
+    assert_eq!(
+        common::recv(&mut wildcard, Duration::from_millis(100)).await,
+        None
+    );
+
+    assert!(server.unsubscribe(id, "*"));
+    assert!(!server.unsubscribe(id, "/library/5"));
+    tx.send(Event::new("/news", Text("/news".to_string())))
+        .unwrap();
+    tx.send(Event::new("/library/5", Text("/library/5".to_string())))
+        .unwrap();
+    assert_eq!(
+        common::recv(&mut client, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("/library/5")
+    );
+}
+
+#[tokio::test]
+async fn tokio_broadcast_delivers_wildcard_subscriptions() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .allow_pattern_subscriptions(true)
+        .broadcast_backend(BroadcastBackend::TokioBroadcast { capacity: 16 })
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&server.local_addr().to_string(), "/library/*").await;
+    let id = connected.recv().await.unwrap();
+
+    tx.send(Event::new("/news", Text("news".to_string())))
+        .unwrap();
+    tx.send(Event::new("/library/5", Text("book".to_string())))
+        .unwrap();
+    assert_eq!(
+        common::recv(&mut client, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("book")
+    );
+
+    assert!(matches!(
+        server.subscribe(id, "/news"),
+        Err(Error::Unsupported)
+    ));
+}
+
+#[tokio::test]
+async fn join_after_shutdown() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+
+    assert!(!server.try_join());
+    assert!(matches!(
+        server.join_timeout(Duration::from_millis(50)).await,
+        Err(Error::JoinTimeout)
+    ));
+
+    server.drain(Duration::ZERO).await;
+
+    server.join_timeout(Duration::from_secs(5)).await.unwrap();
+    assert!(server.try_join());
+}
+
+#[tokio::test]
+async fn panicking_filter_does_not_take_the_server_down() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .per_client_filter(|_, _, payload| {
+            assert_ne!(payload, "boom");
+            true
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let mut client = common::connect(&server.local_addr().to_string(), "/events").await;
+
+    let publish = || {
+        let _ = tx.send(Event::new("/events", Text("boom".to_string())));
+        let _ = tx.send(Event::new("/events", Text("ok".to_string())));
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut client, publish).await,
+        "ok"
+    );
+    assert_eq!(server.health(), Health::Running);
+}
+
+#[tokio::test]
+async fn panicking_filter_fails_the_server_without_restart() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .per_client_filter(|_, _, _| panic!("broken filter"))
+        .restart_on_panic(false)
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let _client = common::connect(&server.local_addr().to_string(), "/events").await;
+
+    while server.connection_count() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    tx.send(Event::new("/events", Text("hello".to_string())))
+        .unwrap();
+
+    while server.health() == Health::Running {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(
+        server.health(),
+        Health::Failed("broadcast loop panicked: broken filter".to_string())
+    );
+
+    // The accept loop keeps running until shutdown.
+    server.drain(Duration::ZERO).await;
+    assert!(matches!(
+        server.join_timeout(Duration::from_secs(5)).await,
+        Err(Error::TaskPanicked)
+    ));
+}
+
+#[tokio::test]
+async fn shutdown_closes_idle_connections() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut clients = Vec::new();
+    for _ in 0..100 {
+        clients.push(common::connect(&addr, "/idle").await);
+    }
+
+    while server.connection_count() < 100 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    tokio::time::timeout(Duration::from_secs(5), server.shutdown())
+        .await
+        .expect("shutdown took too long");
+    assert_eq!(server.connection_count(), 0);
+
+    for mut client in clients {
+        match client.next().await {
+            Some(Ok(Message::Close(Some(frame)))) => assert_eq!(u16::from(frame.code), 1001),
+            x => panic!("expected a close frame, got {:?}", x),
+        }
+    }
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_events_being_delivered() {
+    let (delivering, mut started) = mpsc::unbounded_channel();
+    let (connected, mut ids) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .broadcast_workers(1)
+        .on_connect(move |client| {
+            let _ = connected.send(client.id);
+        })
+        // Keeps the worker busy with the event while the server shuts down.
+        .transform("/slow", move |_: &str, payload: String| {
+            let _ = delivering.send(());
+            std::thread::sleep(Duration::from_millis(300));
+            Some(payload)
+        })
+        .start()
+        .await
+        .unwrap();
+    let mut client = common::connect(&server.local_addr().to_string(), "/slow").await;
+    ids.recv().await.unwrap();
+
+    server
+        .get_tx()
+        .send(Event::new("/slow", Text("last words".to_string())))
+        .unwrap();
+    started.recv().await.unwrap();
+    server.shutdown().await;
+
+    assert_eq!(next_frame(&mut client).await, "last words");
+    match client.next().await {
+        Some(Ok(Message::Close(Some(frame)))) => assert_eq!(u16::from(frame.code), 1001),
+        x => panic!("expected a close frame, got {:?}", x),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn load_shedder_drops_events_while_the_queue_is_deep() {
+    let stalled = Arc::new(AtomicBool::new(false));
+    let stall = stalled.clone();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        // Stalls the broadcast loop so that events pile up.
+        .transform("/load", move |_: &str, payload: String| {
+            while stall.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Some(payload)
+        })
+        .load_shedder(LoadShedder {
+            // Reached by the last queued event only.
+            max_queue_depth: 19,
+            check_interval: Duration::from_millis(10),
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+    let mut client = common::connect(&addr, "/load").await;
+
+    let send = |payload: &str| {
+        tx.send(Event::new("/load", Text(payload.to_string())))
+            .unwrap();
+    };
+    common::publish_until_received(&mut client, || send("ready")).await;
+    while common::recv(&mut client, Duration::from_millis(50))
+        .await
+        .is_some()
+    {}
+
+    stalled.store(true, Ordering::SeqCst);
+    send("stall");
+    // The stalling event is taken out of the queue.
+    while server.queue_depth() > 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    for _ in 0..20 {
+        send("queued");
+    }
+    assert_eq!(server.queue_depth(), 20);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for _ in 0..5 {
+        send("dropped");
+    }
+    assert_eq!(server.queue_depth(), 20);
+
+    stalled.store(false, Ordering::SeqCst);
+    let mut received = Vec::new();
+    while let Some(frame) = common::recv(&mut client, Duration::from_millis(200)).await {
+        received.push(frame);
+    }
+    assert_eq!(received.len(), 21);
+    assert!(received.iter().all(|x| x != "dropped"));
+    assert_eq!(server.queue_depth(), 0);
+
+    // Events are delivered again once the queue drained.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        common::publish_until_received(&mut client, || send("recovered")).await,
+        "recovered"
+    );
+}
+
+#[tokio::test]
+async fn subprotocols_are_negotiated_on_both_backends() {
+    let backends = [
+        BroadcastBackend::PerClient,
+        BroadcastBackend::TokioBroadcast { capacity: 16 },
+    ];
+
+    for backend in backends {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let server = ServerBuilder::new()
+            .addr("127.0.0.1:0")
+            .broadcast_backend(backend)
+            .subprotocols(["chat.v2", "chat.v1"])
+            .on_connect(move |client| {
+                let _ = tx.send(client.subprotocol.clone());
+            })
+            .start()
+            .await
+            .unwrap();
+        let events = server.get_tx();
+        let addr = server.local_addr().to_string();
+
+        // The preference of the client wins.
+        let (mut v1, selected) =
+            common::connect_with_protocols(&addr, "/chat", "chat.v1, chat.v2").await;
+        assert_eq!(selected.as_deref(), Some("chat.v1"));
+        assert_eq!(rx.recv().await.unwrap().as_deref(), Some("chat.v1"));
+
+        let (mut unknown, selected) =
+            common::connect_with_protocols(&addr, "/chat", "chat.v3").await;
+        assert_eq!(selected, None);
+        assert_eq!(rx.recv().await.unwrap(), None);
+
+        // The pushevent protocols take precedence.
+        let (_, selected) =
+            common::connect_with_protocols(&addr, "/chat", "chat.v2, pushevent-v1").await;
+        assert_eq!(selected.as_deref(), Some("pushevent-v1"));
+
+        let publish = || {
+            events.send(Event::new("/chat", Text("hi".into()))).unwrap();
+        };
+        assert_eq!(common::publish_until_received(&mut v1, publish).await, "hi");
+        assert_eq!(
+            common::publish_until_received(&mut unknown, publish).await,
+            "hi"
+        );
+    }
+}
+
+#[tokio::test]
+async fn unsupported_subprotocols_can_be_rejected() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .subprotocols(["chat.v1"])
+        .reject_unsupported_subprotocols(true)
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut req = format!("ws://{}/chat", addr).into_client_request().unwrap();
+    req.headers_mut()
+        .insert("sec-websocket-protocol", "chat.v3".parse().unwrap());
+    match connect_async(req).await {
+        Err(tungstenite::Error::Http(res)) => assert_eq!(res.status().as_u16(), 400),
+        _ => panic!("the handshake should have been rejected"),
+    }
+
+    let (_, selected) = common::connect_with_protocols(&addr, "/chat", "chat.v3, chat.v1").await;
+    assert_eq!(selected.as_deref(), Some("chat.v1"));
+    // Clients offering nothing are accepted.
+    common::connect(&addr, "/chat").await;
+}
+
+/// Acknowledges every event `client` receives until none arrives for a while, returning the
+/// last one.
+async fn ack_all(client: &mut common::Client) -> Option<serde_json::Value> {
+    let mut last = None;
+    while let Some(frame) = common::recv(client, Duration::from_millis(300)).await {
+        let event: serde_json::Value = serde_json::from_str(&frame).unwrap();
+        let ack = format!(r#"{{"type":"ack","id":{}}}"#, event["id"]);
+        client.send(Message::Text(ack.into())).await.unwrap();
+        last = Some(event);
+    }
+
+    last
+}
+
+#[tokio::test]
+async fn qos_routes_resend_unacknowledged_events() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .ack_timeout(Duration::from_millis(100))
+        .start()
+        .await
+        .unwrap();
+    server.set_route_qos("/alerts/*", QoS::AtLeastOnce);
+    server.set_route_qos("/payments", QoS::ExactlyOnce);
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+
+    for (res, dup) in [("/alerts/disk", None), ("/payments", Some(true))] {
+        let mut client = common::connect(&addr, res).await;
+        let send = |payload: &str| {
+            tx.send(Event::new(res, Text(payload.to_string()))).unwrap();
+        };
+
+        common::publish_until_received(&mut client, || send("ready")).await;
+        ack_all(&mut client).await;
+
+        send("important");
+        let first = common::recv(&mut client, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let first: serde_json::Value = serde_json::from_str(&first).unwrap();
+        assert_eq!(first["type"], "event");
+        assert_eq!(first["resource"], res);
+        assert_eq!(first["payload"], "important");
+        assert_eq!(first.get("dup"), None);
+
+        // Not acknowledged, so it is sent again with the same id.
+        let again = ack_all(&mut client).await.unwrap();
+        assert_eq!(again["id"], first["id"]);
+        assert_eq!(again["payload"], "important");
+        assert_eq!(again.get("dup").and_then(|x| x.as_bool()), dup);
+
+        assert_eq!(
+            common::recv(&mut client, Duration::from_millis(300)).await,
+            None
+        );
+    }
+
+    // Other routes are unaffected.
+    let mut client = common::connect(&addr, "/news").await;
+    assert_eq!(
+        common::publish_until_received(&mut client, || {
+            tx.send(Event::new("/news", Text("plain".into()))).unwrap();
+        })
+        .await,
+        "plain"
+    );
+}
+
+/// Receives the next job sent to `worker`, returning its envelope.
+async fn next_job(worker: &mut common::Client) -> serde_json::Value {
+    let frame = common::recv(worker, Duration::from_secs(5)).await.unwrap();
+    serde_json::from_str(&frame).unwrap()
+}
+
+#[tokio::test]
+async fn round_robin_routes_hand_each_event_to_one_worker() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .ack_timeout(Duration::from_secs(60))
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    server.set_route_qos("/jobs", QoS::AtLeastOnce);
+    server.set_route_delivery(
+        "/jobs",
+        DeliveryMode::RoundRobin {
+            max_queue_depth: 16,
+        },
+    );
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+    let publish = |x: u32| {
+        tx.send(Event::new("/jobs", Text(x.to_string()))).unwrap();
+    };
+
+    // Held until a worker shows up.
+    publish(0);
+    while server.resource_stats("/jobs").is_none() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let mut first = common::connect(&addr, "/jobs").await;
+    connected.recv().await.unwrap();
+    let job = next_job(&mut first).await;
+    assert_eq!(job["payload"], "0");
+    let ack = format!(r#"{{"type":"ack","id":{}}}"#, job["id"]);
+    first.send(Message::Text(ack.into())).await.unwrap();
+
+    let mut second = common::connect(&addr, "/jobs").await;
+    let mut third = common::connect(&addr, "/jobs").await;
+    for _ in 0..2 {
+        connected.recv().await.unwrap();
+    }
+
+    for x in 1..=6 {
+        publish(x);
+    }
+    let mut handed_out = HashSet::new();
+    let mut unacked = Vec::new();
+    for worker in [&mut first, &mut second, &mut third] {
+        for _ in 0..2 {
+            let job = next_job(worker).await;
+            assert!(handed_out.insert(job["payload"].as_str().unwrap().to_string()));
+            unacked.push(job);
+        }
+        assert_eq!(common::recv(worker, Duration::from_millis(100)).await, None);
+    }
+    assert_eq!(handed_out.len(), 6);
+
+    // The first worker quits without acknowledging its jobs, which go to the others.
+    let quitter = unacked[..2]
+        .iter()
+        .map(|x| x["payload"].as_str().unwrap().to_string())
+        .collect::<HashSet<_>>();
+    drop(first);
+    let mut reassigned = HashSet::new();
+    for worker in [&mut second, &mut third] {
+        let job = next_job(worker).await;
+        assert!(reassigned.insert(job["payload"].as_str().unwrap().to_string()));
+    }
+    assert_eq!(reassigned, quitter);
+}
+
+/// What a worker received until it went quiet.
+#[derive(Default)]
+struct Received {
+    /// The payloads of the jobs.
+    jobs: HashSet<String>,
+    /// The partition keys it was assigned or lost.
+    assigned: HashSet<String>,
+    revoked: HashSet<String>,
+}
+
+async fn receive_all(worker: &mut common::Client) -> Received {
+    let mut received = Received::default();
+    while let Some(frame) = common::recv(worker, Duration::from_millis(300)).await {
+        let frame: serde_json::Value = serde_json::from_str(&frame).unwrap();
+        let strings = |x: &serde_json::Value| {
+            x.as_array()
+                .unwrap()
+                .iter()
+                .map(|x| x.as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        match frame["type"].as_str().unwrap() {
+            "event" => {
+                received
+                    .jobs
+                    .insert(frame["payload"].as_str().unwrap().to_string());
+            }
+            "partitions" => {
+                assert_eq!(frame["resource"], "/jobs");
+                received.assigned.extend(strings(&frame["assigned"]));
+                received.revoked.extend(strings(&frame["revoked"]));
+            }
+            other => panic!("unexpected frame {}", other),
+        }
+    }
+
+    received
+}
+
+#[tokio::test]
+async fn partition_keys_stick_to_a_worker() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_protocol_version(2)
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    server.set_route_delivery(
+        "/jobs",
+        DeliveryMode::RoundRobin {
+            max_queue_depth: 16,
+        },
+    );
+    let tx = server.get_tx();
+    let addr = server.local_addr().to_string();
+
+    let keys: Vec<String> = (0..40).map(|x| format!("library-{}", x)).collect();
+    // The payload of every job is its key.
+    let publish_all = |rounds: usize| {
+        for _ in 0..rounds {
+            for key in &keys {
+                let event = Event::new("/jobs", Text(key.clone())).with_partition_key(key.as_str());
+                tx.send(event).unwrap();
+            }
+        }
+    };
+
+    let mut workers = Vec::new();
+    for _ in 0..3 {
+        workers.push(common::connect(&addr, "/jobs?protocol=2").await);
+        connected.recv().await.unwrap();
+    }
+
+    // Every key goes to one worker, however many events it has.
+    publish_all(5);
+    let mut owned = Vec::new();
+    for worker in &mut workers {
+        let received = receive_all(worker).await;
+        assert!(received.assigned.is_empty() && received.revoked.is_empty());
+        owned.push(received.jobs);
+    }
+    assert_eq!(owned.iter().map(HashSet::len).sum::<usize>(), keys.len());
+
+    // A fourth worker only takes keys, which the others are told they lost.
+    workers.push(common::connect(&addr, "/jobs?protocol=2").await);
+    connected.recv().await.unwrap();
+    let mut moved = Vec::new();
+    for worker in &mut workers {
+        let received = receive_all(worker).await;
+        assert!(received.jobs.is_empty());
+        moved.push(received);
+    }
+    let taken = moved[3].assigned.clone();
+    assert!(!taken.is_empty() && moved[3].revoked.is_empty());
+    for (owned, moved) in owned.iter_mut().zip(&moved[..3]) {
+        assert!(moved.assigned.is_empty());
+        assert!(moved.revoked.is_subset(owned));
+        owned.retain(|x| !moved.revoked.contains(x));
+    }
+    owned.push(taken);
+
+    publish_all(1);
+    for (worker, owned) in workers.iter_mut().zip(&owned) {
+        assert_eq!(&receive_all(worker).await.jobs, owned);
+    }
+
+    // The keys of a worker leaving are spread over the others, the rest stays.
+    drop(workers.remove(0));
+    let lost = owned.remove(0);
+    while server.connection_count() > 3 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let mut reassigned = HashSet::new();
+    for (worker, owned) in workers.iter_mut().zip(&mut owned) {
+        let received = receive_all(worker).await;
+        assert!(received.revoked.is_empty());
+        assert!(received.assigned.is_disjoint(&reassigned));
+        reassigned.extend(received.assigned.iter().cloned());
+        owned.extend(received.assigned);
+    }
+    assert_eq!(reassigned, lost);
+
+    publish_all(1);
+    for (worker, owned) in workers.iter_mut().zip(&owned) {
+        assert_eq!(&receive_all(worker).await.jobs, owned);
+    }
+}
+
+/// Returns the payloads of the events in a batch.
+fn batch_payloads(frame: &str) -> Vec<String> {
+    let events: Vec<serde_json::Value> = serde_json::from_str(frame).unwrap();
+    events
+        .iter()
+        .map(|x| x["payload"].as_str().unwrap().to_string())
+        .collect()
+}
+
+async fn next_frame(client: &mut common::Client) -> String {
+    // The clock is paused, so this only runs out if the test is stuck.
+    common::recv(client, Duration::from_secs(60)).await.unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn batched_routes_coalesce_events_for_v2_clients() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_protocol_version(2)
+        .start()
+        .await
+        .unwrap();
+    let window = Duration::from_millis(20);
+    server.set_route_batching(
+        "/feed",
+        Some(Batching {
+            window,
+            max_events: 3,
+        }),
+    );
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut v1 = common::connect(&addr, "/feed").await;
+    let (mut v2, _) = common::connect_with_protocols(&addr, "/feed", "pushevent-v2").await;
+    while server.connection_count() < 2 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let publish = |x: &str| tx.send(Event::new("/feed", Text(x.to_string()))).unwrap();
+
+    // A full batch is sent on its own, the rest once the window ends.
+    let start = tokio::time::Instant::now();
+    for x in ["a", "b", "c", "d"] {
+        publish(x);
+    }
+    assert_eq!(batch_payloads(&next_frame(&mut v2).await), ["a", "b", "c"]);
+    assert_eq!(batch_payloads(&next_frame(&mut v2).await), ["d"]);
+    assert!(start.elapsed() >= window);
+
+    // Version 1 clients receive every event right away.
+    for x in ["a", "b", "c", "d"] {
+        assert_eq!(next_frame(&mut v1).await, x);
+    }
+
+    // A lone event waits for the window, not for more events.
+    let start = tokio::time::Instant::now();
+    publish("e");
+    assert_eq!(batch_payloads(&next_frame(&mut v2).await), ["e"]);
+    assert!(start.elapsed() >= window);
+    assert_eq!(next_frame(&mut v1).await, "e");
+
+    // Draining sends the batch still waiting before telling the client to leave.
+    publish("f");
+    assert_eq!(next_frame(&mut v1).await, "f");
+    let drain = tokio::spawn({
+        let server = server.clone();
+        async move { server.drain(Duration::from_secs(1)).await }
+    });
+
+    assert_eq!(batch_payloads(&next_frame(&mut v2).await), ["f"]);
+    assert!(next_frame(&mut v2).await.contains("draining"));
+    drop((v1, v2));
+    drain.await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn routes_follow_their_own_config() {
+    let window = Duration::from_millis(20);
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .max_protocol_version(2)
+        .authenticator(UserHeader)
+        .route(
+            "/progress/*",
+            RouteConfig::new().coalesce(window).skip_auth(),
+        )
+        .route("/public/*", RouteConfig::new().skip_auth())
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    assert!(connect_async(format!("ws://{}/private", addr))
+        .await
+        .is_err());
+    let (mut progress, _) =
+        common::connect_with_protocols(&addr, "/progress/42", "pushevent-v2").await;
+    let (mut public, _) =
+        common::connect_with_protocols(&addr, "/public/news", "pushevent-v2").await;
+    while server.connection_count() < 2 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    for x in ["a", "b"] {
+        tx.send(Event::new("/progress/42", Text(x.to_string())))
+            .unwrap();
+        tx.send(Event::new("/public/news", Text(x.to_string())))
+            .unwrap();
+    }
+
+    let start = tokio::time::Instant::now();
+    assert_eq!(batch_payloads(&next_frame(&mut progress).await), ["a", "b"]);
+    assert!(start.elapsed() >= window);
+
+    for x in ["a", "b"] {
+        let envelope: serde_json::Value =
+            serde_json::from_str(&next_frame(&mut public).await).unwrap();
+        assert_eq!(envelope["payload"], x);
+    }
+}
+
+#[tokio::test]
+async fn stream_events_send_every_item_as_it_is_produced() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&addr, "/jobs/42").await;
+    common::publish_until_received(&mut client, || {
+        tx.send(Event::new("/jobs/42", Text("queued".into())))
+            .unwrap();
+    })
+    .await;
+    while common::recv(&mut client, Duration::from_millis(100))
+        .await
+        .is_some()
+    {}
+
+    let (progress, rx) = mpsc::unbounded_channel::<String>();
+    let chunks =
+        futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|x| (x, rx)) });
+    let sending = tokio::spawn({
+        let tx = tx.clone();
+        async move { tx.send_stream(StreamEvent::new("/jobs/42", chunks)).await }
+    });
+
+    // Every item reaches the client while the stream is still running.
+    let timeout = Duration::from_secs(5);
+    for x in ["25%", "50%", "75%"] {
+        progress.send(x.to_string()).unwrap();
+        assert_eq!(common::recv(&mut client, timeout).await.unwrap(), x);
+    }
+    assert!(!sending.is_finished());
+
+    drop(progress);
+    sending.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn upgrade_responses_carry_the_client_id() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr();
+
+    let (_client, response) = connect_async(format!("ws://{}/feed", addr)).await.unwrap();
+    let header = response.headers()["x-pushevent-client-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    while server.connection_count() < 1 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let client = server.find_client_by_id(&header).unwrap();
+    assert_eq!(client.id.to_string(), header);
+    assert_eq!(client.resource, "/feed");
+    assert_eq!(server.connections()[0].id, client.id);
+
+    assert!(server.find_client_by_id("not-a-uuid").is_none());
+    assert!(server
+        .find_client_by_id("00000000-0000-7000-8000-000000000000")
+        .is_none());
+}
+
+#[tokio::test]
+async fn both_backends_publish_the_same_event() {
+    let event = Event::new("/news", Text("extra".into()));
+
+    for backend in [
+        BroadcastBackend::PerClient,
+        BroadcastBackend::TokioBroadcast { capacity: 16 },
+    ] {
+        let server = ServerBuilder::new()
+            .addr("127.0.0.1:0")
+            .broadcast_backend(backend)
+            .start()
+            .await
+            .unwrap();
+        let tx = server.get_tx();
+        let mut client = common::connect(&server.local_addr().to_string(), "/news").await;
+
+        let received =
+            common::publish_until_received(&mut client, || tx.send(event.clone()).unwrap()).await;
+        assert_eq!(received, event.payload());
+    }
+}
+
+#[tokio::test]
+async fn clients_that_stop_reading_are_disconnected() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .write_timeout(Duration::from_millis(200))
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    // Never reads, so that the socket buffers fill up.
+    let _stalled = common::connect(&addr, "/video").await;
+    let mut reader = common::connect(&addr, "/video").await;
+    while server.connection_count() < 2 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let reading = tokio::spawn(async move { while let Some(Ok(_)) = reader.next().await {} });
+
+    let frame = Event::new("/video", Text("x".repeat(1024 * 1024)));
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while server.connection_count() > 1 {
+        assert!(
+            Instant::now() < deadline,
+            "the stalled client is still connected"
+        );
+        tx.send(frame.clone()).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The client that keeps up stays connected.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(server.connection_count(), 1);
+    assert!(!reading.is_finished());
+}
+
+#[tokio::test]
+async fn disconnected_clients_receive_the_close_reason() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let (mut kicked, response) = connect_async(format!("ws://{}/chat", addr)).await.unwrap();
+    let id = response.headers()["x-pushevent-client-id"]
+        .to_str()
+        .unwrap();
+    let id = server.find_client_by_id(id).unwrap().id;
+    let mut other = common::connect(&addr, "/chat").await;
+    while server.connection_count() < 2 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    server.disconnect(id, CloseReason::policy("spam")).unwrap();
+    let frame = tokio::time::timeout(Duration::from_secs(5), kicked.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    match frame {
+        Message::Close(Some(x)) => {
+            assert_eq!(u16::from(x.code), 1008);
+            assert_eq!(x.reason.as_str(), "spam");
+        }
+        x => panic!("expected a close frame, got {:?}", x),
+    }
+
+    while server.connection_count() > 1 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert!(matches!(
+        server.disconnect(id, CloseReason::normal()),
+        Err(Error::ClientNotFound)
+    ));
+
+    // Other clients are unaffected.
+    common::publish_until_received(&mut other, || {
+        tx.send(Event::new("/chat", Text("hi".into()))).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn dual_stack_listeners_accept_ipv4_and_ipv6_clients() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("[::]:0")
+        .on_connect(move |client| {
+            let _ = tx.send(client.addr);
+        })
+        .start()
+        .await
+        .unwrap();
+    let port = server.local_addr().port();
+    assert!(server.local_addr().is_ipv6());
+
+    let _v4 = common::connect(&format!("127.0.0.1:{}", port), "/events").await;
+    let v4 = rx.recv().await.unwrap();
+    assert_eq!(v4.ip(), "127.0.0.1".parse::<std::net::IpAddr>().unwrap());
+
+    let _v6 = common::connect(&format!("[::1]:{}", port), "/events").await;
+    let v6 = rx.recv().await.unwrap();
+    assert_eq!(v6.ip(), "::1".parse::<std::net::IpAddr>().unwrap());
+}
+
+#[tokio::test]
+async fn events_wait_for_the_expected_clients() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .warm_up(2, Duration::from_secs(30))
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut first = common::connect(&addr, "/game").await;
+    for x in ["ready", "go"] {
+        tx.send(Event::new("/game", Text(x.to_string()))).unwrap();
+    }
+    assert_eq!(
+        common::recv(&mut first, Duration::from_millis(100)).await,
+        None
+    );
+    assert!(!server.is_warmed_up());
+
+    let mut second = common::connect(&addr, "/game").await;
+    for client in [&mut first, &mut second] {
+        for x in ["ready", "go"] {
+            assert_eq!(
+                common::recv(client, Duration::from_secs(5))
+                    .await
+                    .as_deref(),
+                Some(x)
+            );
+        }
+    }
+    assert!(server.is_warmed_up());
+}
+
+#[tokio::test]
+async fn warm_up_ends_after_the_timeout() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .warm_up(10, Duration::from_millis(200))
+        .start()
+        .await
+        .unwrap();
+
+    let mut client = common::connect(&server.local_addr().to_string(), "/game").await;
+    server
+        .get_tx()
+        .send(Event::new("/game", Text("go".to_string())))
+        .unwrap();
+
+    assert_eq!(
+        common::recv(&mut client, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some("go")
+    );
+    assert!(server.is_warmed_up());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_blocked_broadcast_loop_is_reported_as_dead() {
+    let (release, released) = std::sync::mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .stall_timeout(Duration::from_millis(100))
+        .transform("/stuck", move |_: &str, payload: String| {
+            let _ = released.lock().unwrap().recv();
+            Some(payload)
+        })
+        .start()
+        .await
+        .unwrap();
+    server.wait_ready(Duration::from_secs(5)).await.unwrap();
+
+    let report = server.health_report();
+    assert!(report.is_ready());
+    assert_eq!(report.last_delivered_at, None);
+
+    let tx = server.get_tx();
+    tx.send(Event::new("/stuck", Text("x".to_string())))
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.health_report().broadcast_loop_alive {
+        assert!(
+            Instant::now() < deadline,
+            "the broadcast loop wasn't reported as stuck"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let report = server.health_report();
+    assert!(report.listener_alive);
+    assert!(!report.is_ready());
+
+    release.send(()).unwrap();
+    while !server.health_report().broadcast_loop_alive {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(server.health_report().last_delivered_at.is_some());
+
+    server.shutdown().await;
+    let report = server.health_report();
+    assert!(!report.accepting);
+}
+
+#[tokio::test]
+async fn client_tx_sends_to_one_client() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut target = common::connect(&addr, "/chat").await;
+    let id = connected.recv().await.unwrap();
+    let mut other = common::connect(&addr, "/chat").await;
+    connected.recv().await.unwrap();
+
+    let tx = server.get_client_tx(id).unwrap();
+    assert_eq!(tx.id(), id);
+    tx.send(r#"{"type":"typing"}"#.to_string()).unwrap();
+    assert_eq!(
+        common::recv(&mut target, Duration::from_secs(5))
+            .await
+            .as_deref(),
+        Some(r#"{"type":"typing"}"#)
+    );
+    assert_eq!(
+        common::recv(&mut other, Duration::from_millis(100)).await,
+        None
+    );
+
+    drop(target);
+    while server.connection_count() > 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(tx.is_closed());
+    assert_eq!(tx.send("late".to_string()), Err(server::ClientGone));
+    assert!(server.get_client_tx(id).is_none());
+}
+
+#[tokio::test]
+async fn stalled_handshakes_are_dropped() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .handshake_timeout(Duration::from_millis(200))
+        .max_pending_handshakes(1)
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr();
+
+    let mut stalled = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stalled
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n")
+        .await
+        .unwrap();
+
+    // The only handshake slot is taken.
+    let mut refused = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0; 64];
+    let read = tokio::time::timeout(Duration::from_secs(5), refused.read(&mut buf))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert_eq!(server.refused_handshakes(), 1);
+
+    let read = tokio::time::timeout(Duration::from_secs(5), stalled.read(&mut buf))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert_eq!(server.handshake_timeouts(), 1);
+
+    // The slot is free again.
+    let mut client = common::connect(&addr.to_string(), "/events").await;
+    let tx = server.get_tx();
+    let received = common::publish_until_received(&mut client, || {
+        tx.send(Event::new("/events", Text("hello".to_string())))
+            .unwrap();
+    })
+    .await;
+    assert_eq!(received, "hello");
+}
+
+/// Returns the next control frame received by `client` within `timeout`.
+async fn next_control_frame(client: &mut common::Client, timeout: Duration) -> Option<Message> {
+    loop {
+        match tokio::time::timeout(timeout, client.next()).await {
+            Ok(Some(Ok(x @ (Message::Ping(_) | Message::Pong(_))))) => return Some(x),
+            Ok(Some(Ok(_))) => continue,
+            _ => return None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn clients_that_ping_are_answered_and_not_pinged() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let interval = Duration::from_millis(200);
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .ping_interval(interval)
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+
+    let mut silent = common::connect(&addr, "/events").await;
+    connected.recv().await.unwrap();
+    assert!(matches!(
+        next_control_frame(&mut silent, Duration::from_secs(5)).await,
+        Some(Message::Ping(_))
+    ));
+
+    let mut pinging = common::connect(&addr, "/events").await;
+    let id = connected.recv().await.unwrap();
+    for _ in 0..4 {
+        pinging
+            .send(Message::Ping(b"still here".to_vec().into()))
+            .await
+            .unwrap();
+        match next_control_frame(&mut pinging, interval).await {
+            Some(Message::Pong(x)) => assert_eq!(&x[..], b"still here"),
+            // Pinged before its first ping arrived.
+            Some(Message::Ping(_)) => {}
+            x => panic!("expected a pong, got {:?}", x),
+        }
+        tokio::time::sleep(interval / 4).await;
+    }
+    // Its pings are recent enough, the server doesn't ping it.
+    while let Some(frame) = next_control_frame(&mut pinging, interval / 2).await {
+        assert!(matches!(frame, Message::Pong(_)));
+    }
+
+    let connection = server
+        .connections()
+        .into_iter()
+        .find(|x| x.id == id)
+        .unwrap();
+    assert!(connection.last_ping_at.is_some());
+}
+
+#[tokio::test]
+async fn snapshots_come_before_live_events() {
+    for backend in [
+        BroadcastBackend::PerClient,
+        BroadcastBackend::TokioBroadcast { capacity: 16 },
+    ] {
+        let (called, mut snapshotting) = mpsc::unbounded_channel();
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let open = gate.clone();
+        let server = ServerBuilder::new()
+            .addr("127.0.0.1:0")
+            .broadcast_backend(backend)
+            .snapshot_async("/queue/*", move |res, _client| {
+                let _ = called.send(());
+                let (gate, res) = (gate.clone(), res.to_string());
+                async move {
+                    let _ = gate.acquire().await.unwrap();
+                    let snapshot = ["snap-1", "snap-2"].map(|x| Event::from_string(&res, x.into()));
+                    Some(snapshot.to_vec())
+                }
+            })
+            .start()
+            .await
+            .unwrap();
+        let addr = server.local_addr().to_string();
+        let tx = server.get_tx();
+
+        let mut early = common::connect(&addr, "/queue/songs").await;
+        snapshotting.recv().await.unwrap();
+        open.add_permits(1);
+        for x in ["snap-1", "snap-2"] {
+            assert_eq!(next_frame(&mut early).await, x);
+        }
+
+        let mut late = common::connect(&addr, "/queue/songs").await;
+        snapshotting.recv().await.unwrap();
+        // Published while the snapshot of the late client is still being taken.
+        tx.send(Event::new("/queue/songs", Text("live-1".to_string())))
+            .unwrap();
+        assert_eq!(next_frame(&mut early).await, "live-1");
+
+        open.add_permits(1);
+        tx.send(Event::new("/queue/songs", Text("live-2".to_string())))
+            .unwrap();
+        for x in ["snap-1", "snap-2", "live-1", "live-2"] {
+            assert_eq!(next_frame(&mut late).await, x, "{:?}", backend);
+        }
+    }
+}
+
+#[tokio::test]
+async fn snapshots_are_sent_on_subscribe() {
+    let (called, mut snapshotting) = mpsc::unbounded_channel();
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let gate = Arc::new(tokio::sync::Semaphore::new(0));
+    let open = gate.clone();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .snapshot_async("/queue/*", move |res, _client| {
+            let _ = called.send(res.to_string());
+            let (gate, res) = (gate.clone(), res.to_string());
+            async move {
+                let _ = gate.acquire().await.unwrap();
+                Some(vec![Event::from_string(&res, "snap".into())])
+            }
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&server.local_addr().to_string(), "/player").await;
+    let id = connected.recv().await.unwrap();
+
+    assert!(server.subscribe(id, "/queue/songs").unwrap());
+    assert_eq!(snapshotting.recv().await.unwrap(), "/queue/songs");
+    // Published while the snapshot is still being taken.
+    tx.send(Event::new("/queue/songs", Text("live-1".to_string())))
+        .unwrap();
+
+    open.add_permits(1);
+    for x in ["snap", "live-1"] {
+        assert_eq!(next_frame(&mut client).await, x);
+    }
+}
+
+#[tokio::test]
+async fn pausing_during_a_snapshot_keeps_it_first() {
+    let (ids, mut connected) = mpsc::unbounded_channel();
+    let gate = Arc::new(tokio::sync::Semaphore::new(0));
+    let open = gate.clone();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .on_connect(move |client| {
+            let _ = ids.send(client.id);
+        })
+        .snapshot_async("/queue", move |res, _client| {
+            let (gate, res) = (gate.clone(), res.to_string());
+            async move {
+                let _ = gate.acquire().await.unwrap();
+                Some(vec![Event::from_string(&res, "snap".into())])
+            }
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&server.local_addr().to_string(), "/queue").await;
+    let id = connected.recv().await.unwrap();
+    tx.send(Event::new("/queue", Text("live-1".to_string())))
+        .unwrap();
+
+    // Resuming a client that isn't paused lets nothing through ahead of the snapshot.
+    client
+        .send(Message::Text(r#"{"action":"resume"}"#.into()))
+        .await
+        .unwrap();
+    assert!(!server.resume_client(id).unwrap());
+    assert!(server.pause_client(id).unwrap());
+    assert!(server.resume_client(id).unwrap());
+    assert_eq!(
+        common::recv(&mut client, Duration::from_millis(200)).await,
+        None
+    );
+
+    // A client paused when the snapshot is sent stays paused.
+    assert!(server.pause_client(id).unwrap());
+    open.add_permits(1);
+    tx.send(Event::new("/queue", Text("live-2".to_string())))
+        .unwrap();
+    assert_eq!(
+        common::recv(&mut client, Duration::from_millis(200)).await,
+        None
+    );
+
+    assert!(server.resume_client(id).unwrap());
+    for x in ["snap", "live-1", "live-2"] {
+        assert_eq!(next_frame(&mut client).await, x);
+    }
+}
+
+#[tokio::test]
+async fn clients_are_disconnected_when_too_much_waits_for_their_snapshot() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .snapshot_async("/queue", |_res, _client| std::future::pending())
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&server.local_addr().to_string(), "/queue").await;
+    while server.connection_count() < 1 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    for i in 0..5000 {
+        tx.send(Event::new("/queue", Text(i.to_string()))).unwrap();
+    }
+
+    let frame = tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    match frame {
+        Message::Close(Some(x)) => assert_eq!(u16::from(x.code), 1013),
+        x => panic!("expected a close frame, got {:?}", x),
+    }
+}
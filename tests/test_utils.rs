@@ -0,0 +1,22 @@
+#![cfg(feature = "test-utils")]
+
+mod common;
+
+use common::Text;
+use pushevent::test_utils::spawn_test_server;
+use pushevent::Event;
+
+#[tokio::test]
+async fn test_server_delivers_events() {
+    let (tx, addr) = spawn_test_server().await;
+    let mut client = common::connect(&addr.to_string(), "/events").await;
+
+    let publish = || {
+        let _ = tx.send(Event::new("/events", Text("hello".to_string())));
+    };
+
+    assert_eq!(
+        common::publish_until_received(&mut client, publish).await,
+        "hello"
+    );
+}
@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use pushevent::server::ServerBuilder;
+use pushevent::{noop, Error, Event, EventTxExt, SerializableEvent};
+
+mod common;
+use common::Text;
+
+struct Tick;
+
+impl SerializableEvent for Tick {
+    fn serialize(&self) -> String {
+        String::from("tick")
+    }
+}
+
+#[tokio::test]
+async fn buffered_events_are_published_after_the_window() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = count.clone();
+    let tx = noop::sink_tx().observe(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let buffered = tx.buffered(Duration::from_millis(200));
+    for _ in 0..10 {
+        buffered.send(Event::new("/ticks", Tick)).unwrap();
+    }
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(count.load(Ordering::Relaxed), 0);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(count.load(Ordering::Relaxed), 10);
+}
+
+#[tokio::test]
+async fn buffered_events_are_flushed_on_drop() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = count.clone();
+    let tx = noop::sink_tx().observe(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let buffered = tx.buffered(Duration::from_secs(60));
+    buffered.send(Event::new("/ticks", Tick)).unwrap();
+    buffered.send(Event::new("/ticks", Tick)).unwrap();
+    drop(buffered);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(count.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn metered_senders_count_events() {
+    let server = pushevent::server::ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .capacity(2)
+        .start()
+        .await
+        .unwrap();
+    let metered = server.get_tx().meter("ticker");
+    let clone = metered.clone();
+
+    // The broadcast loop doesn't run before the test yields, so the queue fills up.
+    metered.send(Event::new("/ticks", Tick)).unwrap();
+    clone
+        .publish_batch(vec![Event::new("/ticks", Tick), Event::new("/ticks", Tick)])
+        .unwrap();
+    assert!(metered.send(Event::new("/ticks", Tick)).is_err());
+
+    let report = metered.report();
+    assert_eq!(report.name, "ticker");
+    assert_eq!(report.events_sent, 3);
+    assert_eq!(report.bytes_sent, 12);
+    assert_eq!(report.events_failed, 1);
+    assert_eq!(
+        report.to_string(),
+        "ticker: sent 3 events (12 bytes), 1 failed"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn blocking_publishers_park_while_the_queue_is_full() {
+    // Holds up the broadcast loop while set, so that the queue fills up.
+    let hold = Arc::new(AtomicBool::new(false));
+    let held = hold.clone();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .capacity(1)
+        .per_client_filter(move |_, _, _| {
+            while held.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            true
+        })
+        .start()
+        .await
+        .unwrap();
+    let tx = server.get_tx();
+    let event = |x: &str| Event::new("/frames", Text(x.to_string()));
+
+    let mut client = common::connect(&server.local_addr().to_string(), "/frames").await;
+    common::publish_until_received(&mut client, || {
+        let _ = tx.send(event("ready"));
+    })
+    .await;
+    while common::recv(&mut client, Duration::from_millis(100))
+        .await
+        .is_some()
+    {}
+
+    hold.store(true, Ordering::SeqCst);
+    tx.send(event("a")).unwrap();
+    while server.queue_depth() > 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    tx.send(event("b")).unwrap();
+    assert!(matches!(tx.send(event("x")), Err(Error::QueueFull)));
+
+    let producer = tx.clone();
+    let timed_out = thread::spawn(move || {
+        producer.publish_blocking_timeout(event("x"), Duration::from_millis(50))
+    });
+    assert!(matches!(timed_out.join().unwrap(), Err(Error::QueueFull)));
+
+    let producer = tx.clone();
+    let parked = thread::spawn(move || producer.publish_blocking(event("c")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!parked.is_finished());
+
+    hold.store(false, Ordering::SeqCst);
+    tx.publish_async(event("d")).await.unwrap();
+    parked.join().unwrap().unwrap();
+
+    let timeout = Duration::from_secs(5);
+    for x in ["a", "b", "c", "d"] {
+        assert_eq!(common::recv(&mut client, timeout).await.unwrap(), x);
+    }
+}
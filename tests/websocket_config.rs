@@ -0,0 +1,39 @@
+#![cfg(feature = "unstable-websocket-config")]
+
+mod common;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use pushevent::server::{ServerBuilder, WebSocketConfig};
+use tokio::sync::mpsc;
+use tungstenite::Message;
+
+#[tokio::test]
+async fn clients_sending_too_large_messages_are_disconnected() {
+    let (messages, mut received) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .websocket_config(WebSocketConfig::default().max_message_size(Some(16)))
+        .on_message(move |_, payload| {
+            let _ = messages.send(payload);
+        })
+        .start()
+        .await
+        .unwrap();
+    let mut client = common::connect(&server.local_addr().to_string(), "/events").await;
+
+    client.send(Message::text("small")).await.unwrap();
+    received.recv().await.unwrap();
+
+    client.send(Message::text("x".repeat(64))).await.unwrap();
+    let closed = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(frame)) = client.next().await {
+            if frame.is_close() {
+                break;
+            }
+        }
+    });
+    closed.await.expect("the connection wasn't closed");
+    assert!(received.try_recv().is_err());
+}
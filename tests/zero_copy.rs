@@ -0,0 +1,168 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use futures_util::future;
+use pushevent::server::ServerBuilder;
+use pushevent::{Error, Event, SerializableEvent};
+use tokio::io::AsyncReadExt;
+use tokio_tungstenite::MaybeTlsStream;
+
+mod common;
+use common::Text;
+
+/// Counts the allocations of the whole test binary, which is why these tests live on their own.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Publishes a `payload` bytes large event to `subscribers` clients and returns the number of
+/// allocations and allocated bytes it took until every client read it. The clients read the raw
+/// socket into a fixed buffer, so that only the allocations of the server are counted.
+async fn publish_to(subscribers: usize, payload: usize) -> (usize, usize) {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut clients = Vec::new();
+    for _ in 0..subscribers {
+        let client = common::connect(&addr, "/thumbnails").await;
+        clients.push((client, vec![0; 64 * 1024]));
+    }
+    while server.connection_count() < subscribers {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let event = Event::new("/thumbnails", Text("x".repeat(payload)));
+
+    // The first event grows the write buffers of the connections, which are then reused.
+    let mut measured = (0, 0);
+    for round in 0..2 {
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let allocated = ALLOCATED.load(Ordering::Relaxed);
+
+        tx.send(event.clone()).unwrap();
+        let reads = clients.iter_mut().map(|(client, buf)| async move {
+            let stream = match client.get_mut() {
+                MaybeTlsStream::Plain(x) => x,
+                _ => unreachable!(),
+            };
+
+            // Frames this large have a 10 byte header.
+            let mut read = 0;
+            while read < payload + 10 {
+                read += stream.read(buf).await.unwrap();
+            }
+        });
+        future::join_all(reads).await;
+
+        if round == 1 {
+            measured = (
+                ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+                ALLOCATED.load(Ordering::Relaxed) - allocated,
+            );
+        }
+    }
+
+    measured
+}
+
+#[tokio::test]
+async fn payloads_are_not_copied_per_subscriber() {
+    const SUBSCRIBERS: usize = 100;
+    const PAYLOAD: usize = 1024 * 1024;
+
+    let (_, allocated) = publish_to(SUBSCRIBERS, PAYLOAD).await;
+
+    // Copying the payload for every subscriber would allocate 100 MiB.
+    assert!(
+        allocated < SUBSCRIBERS * PAYLOAD / 10,
+        "allocated {} bytes",
+        allocated
+    );
+}
+
+/// A payload shared through a buffer that isn't valid UTF-8, e.g. a PNG.
+struct Thumbnail(Bytes);
+
+impl SerializableEvent for Thumbnail {
+    fn serialize(&self) -> String {
+        unreachable!("serialized through serialize_bytes")
+    }
+
+    fn serialize_bytes(&self) -> Bytes {
+        self.0.clone()
+    }
+}
+
+#[tokio::test]
+async fn binary_payloads_are_refused_instead_of_mangled() {
+    let server = ServerBuilder::new()
+        .addr("127.0.0.1:0")
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().to_string();
+    let tx = server.get_tx();
+
+    let mut client = common::connect(&addr, "/thumbnails").await;
+    while server.connection_count() < 1 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let png = Bytes::from_static(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+    let event = Event::new("/thumbnails", Thumbnail(png));
+    assert!(event.payload_error().is_some());
+    assert!(matches!(
+        tx.send(event.clone()),
+        Err(Error::Serialization(_))
+    ));
+
+    // Nothing reached the client before the next valid event.
+    tx.send(Event::new("/thumbnails", Text("ok".into())))
+        .unwrap();
+    assert_eq!(
+        common::recv(&mut client, std::time::Duration::from_secs(5)).await,
+        Some("ok".to_string())
+    );
+}
+
+/// Run with `cargo test --release --test zero_copy -- --ignored --nocapture`.
+#[tokio::test]
+#[ignore]
+async fn bench_allocations_per_publish() {
+    const SUBSCRIBERS: usize = 1000;
+    const PAYLOAD: usize = 1024 * 1024;
+
+    let (allocations, allocated) = publish_to(SUBSCRIBERS, PAYLOAD).await;
+    println!(
+        "a {} byte event to {} subscribers: {} allocations, {} bytes allocated",
+        PAYLOAD, SUBSCRIBERS, allocations, allocated
+    );
+}